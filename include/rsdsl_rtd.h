@@ -0,0 +1,44 @@
+/*
+ * C interface of rtd for validating and applying single entries.
+ *
+ * Build the library with
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type staticlib
+ *
+ * and link against target/release/librsdsl_rtd.a.
+ *
+ * Entries are lines in the format of the rtd configuration files
+ * (routes, rules and neighbors). Placeholders are expanded using
+ * their current values. Error messages returned through `err`
+ * must be freed with rtd_free_string.
+ */
+
+#ifndef RSDSL_RTD_H
+#define RSDSL_RTD_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct RtdEntry RtdEntry;
+
+/* Parses a line, returning NULL and setting *err on failure. */
+RtdEntry *rtd_parse(const char *line, char **err);
+
+/*
+ * Installs an entry, replacing an existing one, or removes it for del lines.
+ * Returns 0 on success and -1 on failure, setting *err in that case.
+ */
+int rtd_apply(const RtdEntry *entry, char **err);
+
+/* Frees a parsed entry. Does nothing if entry is NULL. */
+void rtd_free(RtdEntry *entry);
+
+/* Frees an error message. Does nothing if msg is NULL. */
+void rtd_free_string(char *msg);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,361 @@
+//! Routes with conditions (`schedule`, `when-exists`, `ttl`), which are
+//! only installed while the conditions hold and removed otherwise.
+//! Routes with a `ttl` are removed for good once it has expired.
+//!
+//! The deadlines are kept in `/data/rtd.expiry` so that a restart
+//! neither reinstalls expired routes nor starts their `ttl` over.
+//! Changing the line of a route gives it a fresh `ttl`, removing
+//! it from the configuration forgets its deadline.
+//!
+//! The directories of trigger files are watched via inotify so that
+//! creating or removing one takes effect right away. Schedules
+//! and directories that can't be watched are checked periodically.
+//!
+//! Placeholders, peer and source addresses of conditional routes are resolved
+//! whenever they are installed rather than kept up to date by `reload`.
+
+use crate::audit::Source;
+use crate::{lock, log, resolve, resolve_peer, resolve_src, status};
+use crate::{outcome, report};
+
+use rsdsl_rtd::{Backend, Route};
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+const EXPIRY_PATH: &str = "/data/rtd.expiry";
+
+/// The deadlines of the time-to-live of routes as of the start, by route.
+static DEADLINES: OnceLock<HashMap<String, SystemTime>> = OnceLock::new();
+
+/// Reports whether the time-to-live of a route ran out before the restart.
+pub fn expired(route: &Route) -> bool {
+    route.ttl.is_some()
+        && deadlines()
+            .get(&key(route))
+            .is_some_and(|deadline| *deadline <= SystemTime::now())
+}
+
+/// Installs or removes the given routes whenever their conditions change.
+/// `installed` tells whether each route is currently installed.
+pub fn watch(backend: &dyn Backend, routes: Vec<(Source, Route, bool)>) {
+    let routes: Vec<_> = routes
+        .into_iter()
+        .filter(|(_, route, _)| route.is_conditional())
+        .collect();
+
+    if routes.is_empty() {
+        save(&[], &[]);
+        return;
+    }
+
+    let backend = match backend.connect() {
+        Ok(backend) => backend,
+        Err(e) => {
+            log::error!(Netlink, "connect for conditional routes: {}", e);
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        let dirs: HashSet<PathBuf> = routes
+            .iter()
+            .filter_map(|(_, route, _)| route.when_exists.as_deref())
+            .map(|path| match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                _ => PathBuf::from("."),
+            })
+            .collect();
+        let inotify = match watch_dirs(&dirs) {
+            Ok(inotify) => inotify,
+            Err(e) => {
+                log::warn!(General, "watch trigger files: {}", e);
+                None
+            }
+        };
+
+        // The time-to-live of routes installed right away has started already,
+        // that of routes from before the restart carries on.
+        let mut entries: Vec<Entry> = routes
+            .into_iter()
+            .map(|(source, route, installed)| {
+                let key = key(&route);
+                Entry {
+                    expires: deadlines().get(&key).copied().or_else(|| {
+                        route
+                            .ttl
+                            .filter(|_| installed)
+                            .map(|ttl| SystemTime::now() + Duration::from_secs(ttl))
+                    }),
+                    key,
+                    source,
+                    route,
+                    installed,
+                }
+            })
+            .collect();
+
+        // Expired routes stay gone after restarts.
+        let mut expired = Vec::new();
+        save(&entries, &expired);
+
+        while !entries.is_empty() {
+            let timeout = entries
+                .iter()
+                .filter_map(|entry| entry.expires)
+                .map(left)
+                .fold(POLL_INTERVAL, Duration::min);
+            wait(inotify.as_ref(), timeout);
+
+            let mut changed = false;
+
+            // Expired routes are removed for good.
+            entries.retain_mut(|entry| {
+                if let Some(expires) = entry.expires.filter(|expires| left(*expires).is_zero()) {
+                    entry.expire(&*backend);
+                    expired.push((entry.key.clone(), expires));
+                    changed = true;
+
+                    return false;
+                }
+
+                changed |= entry.update(&*backend);
+                true
+            });
+
+            if changed {
+                save(&entries, &expired);
+                status::applied();
+            }
+        }
+    });
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Identifies the route across restarts, see `key`.
+    key: String,
+    source: Source,
+    route: Route,
+    installed: bool,
+    expires: Option<SystemTime>,
+}
+
+impl Entry {
+    /// Installs or removes the route if its conditions changed,
+    /// reporting whether it did.
+    fn update(&mut self, backend: &dyn Backend) -> bool {
+        let Self {
+            key: _,
+            source,
+            route,
+            installed,
+            expires,
+        } = self;
+
+        let active = route.is_active();
+        if active == *installed {
+            return false;
+        }
+
+        if active {
+            log::info!(General, "{} of {} holds, add", route.condition(), source);
+
+            if let Some(template) = &route.template {
+                match resolve::<Route>(*source, template) {
+                    Ok(resolved) => *route = route.resolved(resolved),
+                    Err(e) => {
+                        log::error!(Parser, "resolve {}: {}", template, e);
+                        status::set(*source, status::State::Failed(e.to_string()));
+                        return true;
+                    }
+                }
+            }
+
+            if route.via_peer {
+                *route = resolve_peer(*source, route.clone());
+            }
+            if route.src_auto {
+                *route = resolve_src(*source, route.clone());
+            }
+
+            let res = report(*source, "add", route, route.add(backend));
+            status::set(*source, outcome(res, status::State::Applied));
+
+            if expires.is_none() {
+                *expires = route
+                    .ttl
+                    .map(|ttl| SystemTime::now() + Duration::from_secs(ttl));
+            }
+        } else {
+            log::info!(
+                General,
+                "{} of {} no longer holds, remove",
+                route.condition(),
+                source
+            );
+
+            let res = report(*source, "del", route, backend.del_route(&route.def));
+            let inactive = status::State::Inactive(route.condition());
+            status::set(*source, outcome(res, inactive));
+        }
+
+        *installed = active;
+        true
+    }
+
+    /// Removes the route after its time-to-live has expired.
+    fn expire(&self, backend: &dyn Backend) {
+        log::info!(General, "ttl of {} expired, remove", self.source);
+
+        let res = if self.installed {
+            report(
+                self.source,
+                "del",
+                &self.route,
+                backend.del_route(&self.route.def),
+            )
+        } else {
+            Ok(())
+        };
+
+        let expired = status::State::Inactive(format!("{} expired", self.route.condition()));
+        status::set(self.source, outcome(res, expired));
+    }
+}
+
+/// Returns the time left until a deadline, zero if it has passed.
+fn left(deadline: SystemTime) -> Duration {
+    deadline
+        .duration_since(SystemTime::now())
+        .unwrap_or_default()
+}
+
+/// Identifies a route by its configuration but not its line,
+/// which changes whenever lines are added or removed above it.
+fn key(route: &Route) -> String {
+    match &route.template {
+        Some(template) => template.clone(),
+        None => format!("{:#}", route),
+    }
+}
+
+fn deadlines() -> &'static HashMap<String, SystemTime> {
+    DEADLINES.get_or_init(|| match fs::read_to_string(EXPIRY_PATH) {
+        Ok(deadlines) => deadlines
+            .lines()
+            .filter_map(|line| {
+                let (secs, key) = line.split_once(' ')?;
+                let deadline = UNIX_EPOCH + Duration::from_secs(secs.parse().ok()?);
+                Some((key.to_string(), deadline))
+            })
+            .collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+        Err(e) => {
+            log::warn!(General, "read ttl deadlines ({}): {}", EXPIRY_PATH, e);
+            HashMap::new()
+        }
+    })
+}
+
+/// Stores the deadlines of the current and expired routes,
+/// one per line: `<seconds since the epoch> <route>`.
+fn save(entries: &[Entry], expired: &[(String, SystemTime)]) {
+    let deadlines = entries
+        .iter()
+        .filter_map(|entry| Some((&entry.key, entry.expires?)))
+        .chain(expired.iter().map(|(key, deadline)| (key, *deadline)));
+
+    let mut content = String::new();
+    for (key, deadline) in deadlines {
+        let secs = deadline
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        content += &format!("{} {}\n", secs, key);
+    }
+
+    let res = if content.is_empty() {
+        match fs::remove_file(EXPIRY_PATH) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        }
+    } else {
+        lock::write_atomic(Path::new(EXPIRY_PATH), content.as_bytes())
+    };
+    if let Err(e) = res {
+        log::warn!(General, "write ttl deadlines ({}): {}", EXPIRY_PATH, e);
+    }
+}
+
+/// Sets up an inotify instance reporting files being created or removed
+/// in the given directories, `None` if there are none.
+fn watch_dirs(dirs: &HashSet<PathBuf>) -> io::Result<Option<OwnedFd>> {
+    if dirs.is_empty() {
+        return Ok(None);
+    }
+
+    // SAFETY: Plain inotify_init1(2) call, the result is checked below.
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: fd is a freshly created inotify instance that nothing else owns.
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mask = libc::IN_CREATE | libc::IN_DELETE | libc::IN_MOVED_FROM | libc::IN_MOVED_TO;
+    for dir in dirs {
+        let path = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        // SAFETY: path is a valid NUL-terminated string.
+        if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), mask) } < 0 {
+            // The directory may only be created later, it is polled until then.
+            let e = io::Error::last_os_error();
+            log::debug!(General, "watch {}: {}", dir.display(), e);
+        }
+    }
+
+    Ok(Some(fd))
+}
+
+/// Waits for inotify events or the timeout, whichever comes first.
+fn wait(inotify: Option<&OwnedFd>, timeout: Duration) {
+    let Some(fd) = inotify else {
+        thread::sleep(timeout);
+        return;
+    };
+
+    let mut pfd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    // SAFETY: pfd is a valid pollfd and the count matches.
+    let n = unsafe { libc::poll(&mut pfd, 1, timeout.as_millis() as i32) };
+    if n <= 0 {
+        return;
+    }
+
+    // The events themselves don't matter, all conditions are checked anyway.
+    let mut buf = [0u8; 4096];
+    // SAFETY: buf is a valid, writable buffer of the given length.
+    unsafe {
+        libc::read(
+            fd.as_raw_fd(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+        )
+    };
+}
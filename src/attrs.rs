@@ -0,0 +1,83 @@
+//! Route attributes rsdsl_netlinklib can't set, i.e. the preferred source
+//! address (`src`) and the encapsulation (`encap`). Routes using them
+//! are installed with requests of rtd's own.
+
+use crate::{rtnl, Encap, RouteDef, SetupError};
+
+use std::net::IpAddr;
+
+/// The attributes of a route beyond its [`RouteDef`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RouteAttrs {
+    /// The source address of packets the host itself sends along the route.
+    pub src: Option<IpAddr>,
+    pub encap: Option<Encap>,
+}
+
+impl RouteAttrs {
+    /// Installs the route with the attributes through the link with the given index.
+    pub fn blocking_add(&self, def: &RouteDef, index: u32) -> Result<(), SetupError> {
+        def.check_gateway()?;
+
+        let req = self.request(def, index);
+        crate::retry(|| Ok(rtnl::Socket::new()?.add_route(&req)?))
+    }
+
+    /// Builds the `RTM_NEWROUTE` request for the route through the given link.
+    fn request(&self, def: &RouteDef, index: u32) -> Vec<u8> {
+        let family = if def.dst().is_ipv4() {
+            libc::AF_INET
+        } else {
+            libc::AF_INET6
+        } as u8;
+        let flags = if def.on_link() {
+            rtnl::RTNH_F_ONLINK
+        } else {
+            0
+        };
+        let mut req = rtnl::rtmsg(
+            family,
+            def.prefix_len(),
+            0,
+            0,
+            rtnl::RTN_UNICAST,
+            flags.into(),
+        );
+        req[5] = rtnl::RTPROT_STATIC;
+        // Like ip(8), routes without a gateway only reach the link itself.
+        if def.rtr().is_none() && family == libc::AF_INET as u8 {
+            req[6] = rtnl::RT_SCOPE_LINK;
+        }
+
+        rtnl::put_addr(&mut req, rtnl::RTA_DST, def.dst());
+        rtnl::put_attr(&mut req, rtnl::RTA_TABLE, &def.table().to_ne_bytes());
+        if let Some(metric) = def.metric() {
+            rtnl::put_attr(&mut req, rtnl::RTA_PRIORITY, &metric.to_ne_bytes());
+        }
+        if let Some(rtr) = def.rtr() {
+            rtnl::put_addr(&mut req, rtnl::RTA_GATEWAY, rtr);
+        }
+        rtnl::put_attr(&mut req, rtnl::RTA_OIF, &index.to_ne_bytes());
+        if let Some(src) = self.src {
+            rtnl::put_addr(&mut req, rtnl::RTA_PREFSRC, src);
+        }
+        if let Some(encap) = &self.encap {
+            encap.put(&mut req);
+        }
+
+        req
+    }
+
+    /// Returns the arguments of ip(8) for the attributes.
+    pub(crate) fn ip_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(src) = self.src {
+            args.extend(["src".to_string(), src.to_string()]);
+        }
+        if let Some(encap) = &self.encap {
+            args.extend(encap.ip_args());
+        }
+
+        args
+    }
+}
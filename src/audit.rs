@@ -0,0 +1,109 @@
+//! Append-only record of every change rtd makes to the routing configuration.
+
+use crate::log;
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+
+const AUDIT_PATH: &str = "/data/rtd.audit";
+
+static AUDIT: OnceLock<Option<Mutex<File>>> = OnceLock::new();
+
+/// Where a change originated from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Config {
+        path: &'static str,
+        line: usize,
+    },
+    /// A copy of a route with a link pattern, see `wildcard`.
+    Copy {
+        path: &'static str,
+        line: usize,
+        slot: u32,
+    },
+    /// A command run on the command line, e.g. `rollback`.
+    Cli {
+        command: &'static str,
+    },
+    /// A request made over the control socket, see `control`.
+    Client {
+        request: &'static str,
+    },
+    /// The revert of a configuration that wasn't confirmed in time, see `confirm`.
+    Revert,
+}
+
+impl Source {
+    /// Returns the file the entry is configured in,
+    /// or what made the change if it doesn't come from a file.
+    pub fn path(&self) -> &'static str {
+        match self {
+            Self::Config { path, .. } | Self::Copy { path, .. } => path,
+            Self::Cli { command } => command,
+            Self::Client { request } => request,
+            Self::Revert => "revert",
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Config { path, line } => write!(f, "{}:{}", path, line),
+            Self::Copy { path, line, slot } => write!(f, "{}:{}[{}]", path, line, slot),
+            Self::Cli { command } => write!(f, "cli:{}", command),
+            Self::Client { request } => write!(f, "socket:{}", request),
+            Self::Revert => write!(f, "revert"),
+        }
+    }
+}
+
+fn audit() -> Option<&'static Mutex<File>> {
+    AUDIT
+        .get_or_init(|| {
+            match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(AUDIT_PATH)
+            {
+                Ok(file) => Some(Mutex::new(file)),
+                Err(e) => {
+                    log::error!(General, "open audit log ({}): {}", AUDIT_PATH, e);
+                    None
+                }
+            }
+        })
+        .as_ref()
+}
+
+/// Appends a change and its outcome to the audit log.
+pub fn record(
+    source: Source,
+    action: &str,
+    entry: &dyn fmt::Display,
+    result: Result<(), &dyn fmt::Display>,
+) {
+    let Some(audit) = audit() else {
+        return;
+    };
+
+    let line = match result {
+        Ok(()) => format!("{} {} {} {}: ok\n", log::timestamp(), source, action, entry),
+        Err(e) => format!(
+            "{} {} {} {}: failed: {}\n",
+            log::timestamp(),
+            source,
+            action,
+            entry,
+            e
+        ),
+    };
+
+    let mut file = audit.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(e) = file.write_all(line.as_bytes()) {
+        log::error!(General, "write audit log ({}): {}", AUDIT_PATH, e);
+    }
+}
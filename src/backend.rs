@@ -0,0 +1,948 @@
+//! The netlink operations the apply pipeline is built on.
+//!
+//! The kernel is reached through rsdsl_netlinklib normally, `Iproute2`
+//! runs ip(8) instead where rsdsl_netlinklib falls short. Entries
+//! rsdsl_netlinklib doesn't know (VRFs, neighbors, reject routes,
+//! multipath routes, the kernel's built-in rules and the rules
+//! of isolation presets) use requests of rtd's own either way.
+//! Multicast routes aren't netlink entries, they stay outside of backends.
+//! `Mock` keeps everything in memory and records what was asked of it.
+//! It works without root and can simulate failures.
+
+use crate::{
+    rtnl, Balance, Blackhole, Isolate, KernelRule, Neighbor, RouteAttrs, RouteDef, Rule,
+    RuleAction, RuleVersion, SetupError, Sysctl, Vrf,
+};
+
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::process::Command;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use rsdsl_netlinklib::blocking::Connection;
+
+/// A way to install routes, rules and the other entries of the configuration.
+pub trait Backend {
+    fn add_route(&self, route: &RouteDef) -> Result<(), SetupError>;
+    /// Adds a route with attributes beyond its definition.
+    /// It is removed like any other.
+    fn add_route_attrs(&self, route: &RouteDef, attrs: &RouteAttrs) -> Result<(), SetupError>;
+    fn del_route(&self, route: &RouteDef) -> Result<(), SetupError>;
+    fn add_rule(&self, rule: &Rule) -> Result<(), SetupError>;
+    fn del_rule(&self, rule: &Rule) -> Result<(), SetupError>;
+
+    /// Installs the multipath route of a balance group with the given
+    /// members, replacing the one in place.
+    fn replace_balance(&self, balance: &Balance) -> Result<(), SetupError>;
+    /// Removes the multipath route of a balance group, whatever its members.
+    fn del_balance(&self, balance: &Balance) -> Result<(), SetupError>;
+
+    /// Moves or removes one of the kernel's built-in rules.
+    fn apply_kernel_rule(&self, kernel_rule: &KernelRule) -> Result<(), SetupError>;
+    /// Puts one of the kernel's built-in rules back in place.
+    fn restore_kernel_rule(&self, kernel_rule: &KernelRule) -> Result<(), SetupError>;
+
+    /// Adds a route rejecting packets, e.g. an unreachable one.
+    fn add_reject(&self, route: &Blackhole) -> Result<(), SetupError>;
+    fn del_reject(&self, route: &Blackhole) -> Result<(), SetupError>;
+    /// Adds the rules directing the traffic of the guest interface
+    /// of an isolation preset to its table.
+    fn add_isolate_rules(&self, isolate: &Isolate) -> Result<(), SetupError>;
+    /// Removes the rules of an isolation preset, ignoring ones that don't exist.
+    fn del_isolate_rules(&self, isolate: &Isolate) -> Result<(), SetupError>;
+
+    /// Adds a neighbor entry, replacing any existing one for the address.
+    fn add_neighbor(&self, neighbor: &Neighbor) -> Result<(), SetupError>;
+    fn del_neighbor(&self, neighbor: &Neighbor) -> Result<(), SetupError>;
+
+    /// Creates the VRF device unless it exists and brings it up.
+    fn add_vrf(&self, vrf: &Vrf) -> Result<(), SetupError>;
+    /// Binds an interface to a VRF.
+    fn bind_vrf(&self, vrf: &Vrf, member: &str) -> Result<(), SetupError>;
+    /// Removes the VRF device unless it is gone already, releasing its members.
+    fn del_vrf(&self, vrf: &Vrf) -> Result<(), SetupError>;
+
+    /// Writes the settings of a `sysctl` line.
+    fn apply_sysctl(&self, sysctl: &Sysctl) -> Result<(), SetupError>;
+
+    /// Reports whether the link exists right now.
+    fn link_exists(&self, link: &str) -> Result<bool, SetupError>;
+    /// Blocks until the link exists.
+    fn link_wait_exists(&self, link: &str) -> Result<(), SetupError>;
+    /// Blocks until the link is up.
+    fn link_wait_up(&self, link: &str) -> Result<(), SetupError>;
+
+    /// Returns all routes of the given address family (`AF_UNSPEC` for all).
+    fn dump_routes(&self, family: u8) -> Result<Vec<rtnl::RouteMsg>, SetupError>;
+    /// Returns all rules of the given address family (`AF_UNSPEC` for all).
+    fn dump_rules(&self, family: u8) -> Result<Vec<rtnl::RuleMsg>, SetupError>;
+
+    /// Opens another connection of the same kind for concurrent requests.
+    fn connect(&self) -> Result<Box<dyn Backend + Send>, SetupError>;
+}
+
+impl Backend for Connection {
+    fn add_route(&self, route: &RouteDef) -> Result<(), SetupError> {
+        route.clone().blocking_add(self)
+    }
+
+    // The link is looked up through the connection like for the other routes.
+    fn add_route_attrs(&self, route: &RouteDef, attrs: &RouteAttrs) -> Result<(), SetupError> {
+        let index = Connection::link_index(self, route.link().to_string())?;
+        attrs.blocking_add(route, index)
+    }
+
+    fn del_route(&self, route: &RouteDef) -> Result<(), SetupError> {
+        route.clone().blocking_del(self)
+    }
+
+    fn add_rule(&self, rule: &Rule) -> Result<(), SetupError> {
+        rule.clone().blocking_add(self)
+    }
+
+    fn del_rule(&self, rule: &Rule) -> Result<(), SetupError> {
+        rule.clone().blocking_del(self)
+    }
+
+    fn replace_balance(&self, balance: &Balance) -> Result<(), SetupError> {
+        balance.blocking_replace()
+    }
+
+    fn del_balance(&self, balance: &Balance) -> Result<(), SetupError> {
+        balance.blocking_del()
+    }
+
+    fn apply_kernel_rule(&self, kernel_rule: &KernelRule) -> Result<(), SetupError> {
+        kernel_rule.blocking_apply()
+    }
+
+    fn restore_kernel_rule(&self, kernel_rule: &KernelRule) -> Result<(), SetupError> {
+        kernel_rule.blocking_restore()
+    }
+
+    fn add_reject(&self, route: &Blackhole) -> Result<(), SetupError> {
+        route.blocking_add()
+    }
+
+    fn del_reject(&self, route: &Blackhole) -> Result<(), SetupError> {
+        route.blocking_del()
+    }
+
+    fn add_isolate_rules(&self, isolate: &Isolate) -> Result<(), SetupError> {
+        isolate.blocking_add_rules()
+    }
+
+    fn del_isolate_rules(&self, isolate: &Isolate) -> Result<(), SetupError> {
+        isolate.blocking_del_rules()
+    }
+
+    fn add_neighbor(&self, neighbor: &Neighbor) -> Result<(), SetupError> {
+        neighbor.blocking_add()
+    }
+
+    fn del_neighbor(&self, neighbor: &Neighbor) -> Result<(), SetupError> {
+        neighbor.blocking_del()
+    }
+
+    fn add_vrf(&self, vrf: &Vrf) -> Result<(), SetupError> {
+        vrf.blocking_add()
+    }
+
+    fn bind_vrf(&self, vrf: &Vrf, member: &str) -> Result<(), SetupError> {
+        vrf.blocking_bind(member)
+    }
+
+    fn del_vrf(&self, vrf: &Vrf) -> Result<(), SetupError> {
+        vrf.blocking_del()
+    }
+
+    fn apply_sysctl(&self, sysctl: &Sysctl) -> Result<(), SetupError> {
+        sysctl.blocking_apply()
+    }
+
+    fn link_exists(&self, link: &str) -> Result<bool, SetupError> {
+        Ok(Connection::link_exists(self, link.to_string())?)
+    }
+
+    fn link_wait_exists(&self, link: &str) -> Result<(), SetupError> {
+        Ok(Connection::link_wait_exists(self, link.to_string())?)
+    }
+
+    fn link_wait_up(&self, link: &str) -> Result<(), SetupError> {
+        Ok(Connection::link_wait_up(self, link.to_string())?)
+    }
+
+    // Dumps go through a socket of the calling thread's network namespace.
+    fn dump_routes(&self, family: u8) -> Result<Vec<rtnl::RouteMsg>, SetupError> {
+        Ok(rtnl::Socket::new()?.dump_routes(family)?)
+    }
+
+    fn dump_rules(&self, family: u8) -> Result<Vec<rtnl::RuleMsg>, SetupError> {
+        Ok(rtnl::Socket::new()?.dump_rules(family)?)
+    }
+
+    fn connect(&self) -> Result<Box<dyn Backend + Send>, SetupError> {
+        Ok(Box::new(Connection::new()?))
+    }
+}
+
+/// How often `Iproute2` checks the links it waits for.
+const LINK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A backend running ip(8) of iproute2, an escape hatch for kernels
+/// rsdsl_netlinklib misbehaves on. The commands run in the network namespace
+/// of the calling thread. Dumps and the entries rsdsl_netlinklib doesn't
+/// know use rtd's own netlink socket.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Iproute2;
+
+impl Iproute2 {
+    /// Runs ip(8) with the given arguments, returning its output.
+    fn ip(args: &[String]) -> Result<String, SetupError> {
+        let out = Command::new("ip").args(args).output()?;
+        if !out.status.success() {
+            let e = String::from_utf8_lossy(&out.stderr).trim().to_string();
+            return Err(SetupError::Ip(e));
+        }
+
+        Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+    }
+
+    fn route(cmd: &str, route: &RouteDef, attrs: Option<&RouteAttrs>) -> Result<(), SetupError> {
+        let family = if route.dst().is_ipv4() { "-4" } else { "-6" };
+
+        let mut args = vec![
+            family.to_string(),
+            "route".to_string(),
+            cmd.to_string(),
+            format!("{}/{}", route.dst(), route.prefix_len()),
+        ];
+        if let Some(rtr) = route.rtr() {
+            args.extend(["via".to_string(), rtr.to_string()]);
+        }
+        if route.on_link() {
+            args.push("onlink".to_string());
+        }
+        args.extend(["table".to_string(), route.table().to_string()]);
+        if let Some(metric) = route.metric() {
+            args.extend(["metric".to_string(), metric.to_string()]);
+        }
+        args.extend(["dev".to_string(), route.link().to_string()]);
+        if let Some(attrs) = attrs {
+            args.extend(attrs.ip_args());
+        }
+
+        crate::retry(|| Self::ip(&args).map(drop))
+    }
+
+    /// Adds or removes one address family of a rule.
+    fn rule(cmd: &str, family: &str, rule: &Rule) -> Result<(), SetupError> {
+        let mut args = vec![family.to_string(), "rule".to_string(), cmd.to_string()];
+        if rule.invert {
+            args.push("not".to_string());
+        }
+        if let Some(fwmark) = rule.fwmark {
+            args.extend(["fwmark".to_string(), format!("{:#x}", fwmark)]);
+        }
+        if let Some((addr, len)) = rule.dst {
+            args.extend(["to".to_string(), format!("{}/{}", addr, len)]);
+        }
+        if let Some((addr, len)) = rule.src {
+            args.extend(["from".to_string(), format!("{}/{}", addr, len)]);
+        }
+        match rule.action {
+            RuleAction::Blackhole => args.push("blackhole".to_string()),
+            RuleAction::Unreachable => args.push("unreachable".to_string()),
+            RuleAction::Prohibit => args.push("prohibit".to_string()),
+            _ => args.extend(["table".to_string(), rule.table.to_string()]),
+        }
+        // Removal matches rules of any protocol, like netlinklib's.
+        if cmd == "add" && rule.protocol != rtnl::RTPROT_UNSPEC {
+            args.extend(["protocol".to_string(), rule.protocol.to_string()]);
+        }
+
+        crate::retry(|| Self::ip(&args).map(drop))
+    }
+
+    /// Returns the flags of a link, e.g. `UP`, or `None` if it doesn't exist.
+    fn link_flags(link: &str) -> Option<Vec<String>> {
+        let args = ["-o", "link", "show", "dev", link].map(str::to_string);
+        let out = Self::ip(&args).ok()?;
+
+        let flags = out.split_once('<')?.1.split_once('>')?.0;
+        Some(flags.split(',').map(str::to_string).collect())
+    }
+}
+
+impl Backend for Iproute2 {
+    fn add_route(&self, route: &RouteDef) -> Result<(), SetupError> {
+        route.check_gateway()?;
+        Self::route("add", route, None)
+    }
+
+    fn add_route_attrs(&self, route: &RouteDef, attrs: &RouteAttrs) -> Result<(), SetupError> {
+        route.check_gateway()?;
+        Self::route("add", route, Some(attrs))
+    }
+
+    fn del_route(&self, route: &RouteDef) -> Result<(), SetupError> {
+        Self::route("del", route, None)
+    }
+
+    /// Both halves of a protocol-agnostic rule are installed or neither is.
+    fn add_rule(&self, rule: &Rule) -> Result<(), SetupError> {
+        match rule.version {
+            RuleVersion::Both => {
+                Self::rule("add", "-4", rule)?;
+
+                // Never leave half a policy behind.
+                if let Err(e) = Self::rule("add", "-6", rule) {
+                    return match Self::rule("del", "-4", rule) {
+                        Ok(()) => Err(e),
+                        Err(_) => Err(SetupError::HalfApplied(Box::new(e))),
+                    };
+                }
+
+                Ok(())
+            }
+            RuleVersion::Ipv4 => Self::rule("add", "-4", rule),
+            RuleVersion::Ipv6 => Self::rule("add", "-6", rule),
+        }
+    }
+
+    fn del_rule(&self, rule: &Rule) -> Result<(), SetupError> {
+        match rule.version {
+            // Remove whatever half exists.
+            RuleVersion::Both => {
+                let v4 = Self::rule("del", "-4", rule);
+                let v6 = Self::rule("del", "-6", rule);
+                v4.and(v6)
+            }
+            RuleVersion::Ipv4 => Self::rule("del", "-4", rule),
+            RuleVersion::Ipv6 => Self::rule("del", "-6", rule),
+        }
+    }
+
+    fn replace_balance(&self, balance: &Balance) -> Result<(), SetupError> {
+        balance.blocking_replace()
+    }
+
+    fn del_balance(&self, balance: &Balance) -> Result<(), SetupError> {
+        balance.blocking_del()
+    }
+
+    fn apply_kernel_rule(&self, kernel_rule: &KernelRule) -> Result<(), SetupError> {
+        kernel_rule.blocking_apply()
+    }
+
+    fn restore_kernel_rule(&self, kernel_rule: &KernelRule) -> Result<(), SetupError> {
+        kernel_rule.blocking_restore()
+    }
+
+    fn add_reject(&self, route: &Blackhole) -> Result<(), SetupError> {
+        route.blocking_add()
+    }
+
+    fn del_reject(&self, route: &Blackhole) -> Result<(), SetupError> {
+        route.blocking_del()
+    }
+
+    fn add_isolate_rules(&self, isolate: &Isolate) -> Result<(), SetupError> {
+        isolate.blocking_add_rules()
+    }
+
+    fn del_isolate_rules(&self, isolate: &Isolate) -> Result<(), SetupError> {
+        isolate.blocking_del_rules()
+    }
+
+    fn add_neighbor(&self, neighbor: &Neighbor) -> Result<(), SetupError> {
+        neighbor.blocking_add()
+    }
+
+    fn del_neighbor(&self, neighbor: &Neighbor) -> Result<(), SetupError> {
+        neighbor.blocking_del()
+    }
+
+    fn add_vrf(&self, vrf: &Vrf) -> Result<(), SetupError> {
+        vrf.blocking_add()
+    }
+
+    fn bind_vrf(&self, vrf: &Vrf, member: &str) -> Result<(), SetupError> {
+        vrf.blocking_bind(member)
+    }
+
+    fn del_vrf(&self, vrf: &Vrf) -> Result<(), SetupError> {
+        vrf.blocking_del()
+    }
+
+    fn apply_sysctl(&self, sysctl: &Sysctl) -> Result<(), SetupError> {
+        sysctl.blocking_apply()
+    }
+
+    fn link_exists(&self, link: &str) -> Result<bool, SetupError> {
+        Ok(Self::link_flags(link).is_some())
+    }
+
+    fn link_wait_exists(&self, link: &str) -> Result<(), SetupError> {
+        while Self::link_flags(link).is_none() {
+            thread::sleep(LINK_POLL_INTERVAL);
+        }
+
+        Ok(())
+    }
+
+    fn link_wait_up(&self, link: &str) -> Result<(), SetupError> {
+        while !Self::link_flags(link).is_some_and(|flags| flags.iter().any(|flag| flag == "UP")) {
+            thread::sleep(LINK_POLL_INTERVAL);
+        }
+
+        Ok(())
+    }
+
+    fn dump_routes(&self, family: u8) -> Result<Vec<rtnl::RouteMsg>, SetupError> {
+        Ok(rtnl::Socket::new()?.dump_routes(family)?)
+    }
+
+    fn dump_rules(&self, family: u8) -> Result<Vec<rtnl::RuleMsg>, SetupError> {
+        Ok(rtnl::Socket::new()?.dump_rules(family)?)
+    }
+
+    fn connect(&self) -> Result<Box<dyn Backend + Send>, SetupError> {
+        Ok(Box::new(*self))
+    }
+}
+
+/// A request made to a `Mock`.
+#[derive(Clone, Debug)]
+pub enum Op {
+    AddRoute(RouteDef),
+    DelRoute(RouteDef),
+    AddRule(Rule),
+    DelRule(Rule),
+    ReplaceBalance(Balance),
+    DelBalance(Balance),
+    ApplyKernelRule(KernelRule),
+    RestoreKernelRule(KernelRule),
+    AddReject(Blackhole),
+    DelReject(Blackhole),
+    AddIsolateRules(Isolate),
+    DelIsolateRules(Isolate),
+    AddNeighbor(Neighbor),
+    DelNeighbor(Neighbor),
+    AddVrf(Vrf),
+    BindVrf(Vrf, String),
+    DelVrf(Vrf),
+    ApplySysctl(Sysctl),
+    LinkExists(String),
+    LinkWaitExists(String),
+    LinkWaitUp(String),
+    DumpRoutes(u8),
+    DumpRules(u8),
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AddRoute(route) => write!(f, "add {}", route)?,
+            Self::DelRoute(route) => write!(f, "del {}", route)?,
+            Self::AddRule(rule) => write!(f, "add {}", rule)?,
+            Self::DelRule(rule) => write!(f, "del {}", rule)?,
+            Self::ReplaceBalance(balance) => write!(f, "replace {}", balance)?,
+            Self::DelBalance(balance) => write!(f, "del {}", balance)?,
+            Self::ApplyKernelRule(kernel_rule) => write!(f, "apply {}", kernel_rule)?,
+            Self::RestoreKernelRule(kernel_rule) => write!(f, "restore {}", kernel_rule)?,
+            Self::AddReject(route) => write!(f, "add {}", route)?,
+            Self::DelReject(route) => write!(f, "del {}", route)?,
+            Self::AddIsolateRules(isolate) => write!(f, "add rules of {}", isolate)?,
+            Self::DelIsolateRules(isolate) => write!(f, "del rules of {}", isolate)?,
+            Self::AddNeighbor(neighbor) => write!(f, "add {}", neighbor)?,
+            Self::DelNeighbor(neighbor) => write!(f, "del {}", neighbor)?,
+            Self::AddVrf(vrf) => write!(f, "add {}", vrf)?,
+            Self::BindVrf(vrf, member) => write!(f, "bind {} to vrf {}", member, vrf.name)?,
+            Self::DelVrf(vrf) => write!(f, "del {}", vrf)?,
+            Self::ApplySysctl(sysctl) => write!(f, "set {}", sysctl)?,
+            Self::LinkExists(link) => write!(f, "look up link {}", link)?,
+            Self::LinkWaitExists(link) => write!(f, "wait for link {}", link)?,
+            Self::LinkWaitUp(link) => write!(f, "wait for link {} up", link)?,
+            Self::DumpRoutes(family) => write!(f, "dump routes of family {}", family)?,
+            Self::DumpRules(family) => write!(f, "dump rules of family {}", family)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Decides whether a request fails and with which error code.
+type Failure = Box<dyn Fn(&Op) -> Option<i32> + Send>;
+
+#[derive(Default)]
+struct State {
+    ops: Vec<Op>,
+    routes: Vec<RouteDef>,
+    rules: Vec<Rule>,
+    rejects: Vec<Blackhole>,
+    /// The guest interfaces and tables of the isolation rules.
+    isolate_rules: Vec<(String, u32)>,
+    neighbors: Vec<Neighbor>,
+    /// The known links, their index is their position plus one.
+    links: Vec<String>,
+    missing_links: Vec<String>,
+    failures: Vec<Failure>,
+}
+
+/// An in-memory backend recording all requests.
+///
+/// It behaves like the kernel as far as rtd is concerned: adding an entry
+/// twice fails with `EEXIST`, removing a missing one with `ESRCH`.
+/// Links exist and are up unless removed with `set_link`, waiting for
+/// a missing link fails with `ENODEV` rather than blocking forever.
+/// VRF devices are links like any other, the multipath route of a balance
+/// group is kept as that of its first member. Neither the values of sysctls
+/// nor the kernel's built-in rules are kept.
+/// Connections opened with `connect` share the state.
+#[derive(Clone, Default)]
+pub struct Mock {
+    state: Arc<Mutex<State>>,
+}
+
+impl Mock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the requests made so far, oldest first.
+    pub fn ops(&self) -> Vec<Op> {
+        self.state().ops.clone()
+    }
+
+    /// Returns the installed routes.
+    pub fn routes(&self) -> Vec<RouteDef> {
+        self.state().routes.clone()
+    }
+
+    /// Returns the installed rules.
+    pub fn rules(&self) -> Vec<Rule> {
+        self.state().rules.clone()
+    }
+
+    /// Returns the installed neighbor entries.
+    pub fn neighbors(&self) -> Vec<Neighbor> {
+        self.state().neighbors.clone()
+    }
+
+    /// Makes a link appear or disappear.
+    pub fn set_link(&self, link: &str, exists: bool) {
+        let mut state = self.state();
+
+        state.missing_links.retain(|missing| missing != link);
+        if !exists {
+            state.missing_links.push(link.to_string());
+        }
+    }
+
+    /// Makes the requests `f` returns an error code for fail,
+    /// e.g. `mock.fail(|op| matches!(op, Op::AddRule(_)).then_some(libc::EPERM))`.
+    /// Failed requests are recorded but change nothing.
+    pub fn fail(&self, f: impl Fn(&Op) -> Option<i32> + Send + 'static) {
+        self.state().failures.push(Box::new(f));
+    }
+
+    /// Records a request, returning the state to carry it out on
+    /// unless it is to fail.
+    fn request(&self, op: Op) -> Result<MutexGuard<'_, State>, SetupError> {
+        let mut state = self.state();
+
+        let errno = state.failures.iter().find_map(|failure| failure(&op));
+        state.ops.push(op);
+        match errno {
+            Some(errno) => Err(io::Error::from_raw_os_error(errno).into()),
+            None => Ok(state),
+        }
+    }
+}
+
+impl fmt::Debug for Mock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state();
+
+        f.debug_struct("Mock")
+            .field("ops", &state.ops)
+            .field("routes", &state.routes)
+            .field("rules", &state.rules)
+            .field("rejects", &state.rejects)
+            .field("isolate_rules", &state.isolate_rules)
+            .field("neighbors", &state.neighbors)
+            .field("missing_links", &state.missing_links)
+            .field("failures", &state.failures.len())
+            .finish()
+    }
+}
+
+impl Backend for Mock {
+    fn add_route(&self, route: &RouteDef) -> Result<(), SetupError> {
+        let mut state = self.request(Op::AddRoute(route.clone()))?;
+
+        if state.missing_links.iter().any(|link| link == route.link()) {
+            return Err(io::Error::from_raw_os_error(libc::ENODEV).into());
+        }
+        if state.routes.iter().any(|other| other.same_dst(route)) {
+            return Err(io::Error::from_raw_os_error(libc::EEXIST).into());
+        }
+
+        state.routes.push(route.clone());
+        Ok(())
+    }
+
+    // The attributes aren't simulated, the route is recorded like any other.
+    fn add_route_attrs(&self, route: &RouteDef, _attrs: &RouteAttrs) -> Result<(), SetupError> {
+        self.add_route(route)
+    }
+
+    fn del_route(&self, route: &RouteDef) -> Result<(), SetupError> {
+        let mut state = self.request(Op::DelRoute(route.clone()))?;
+
+        match state.routes.iter().position(|other| other.same_dst(route)) {
+            Some(i) => {
+                state.routes.remove(i);
+                Ok(())
+            }
+            None => Err(io::Error::from_raw_os_error(libc::ESRCH).into()),
+        }
+    }
+
+    fn add_rule(&self, rule: &Rule) -> Result<(), SetupError> {
+        let mut state = self.request(Op::AddRule(rule.clone()))?;
+
+        if state.rules.iter().any(|other| same_rule(other, rule)) {
+            return Err(io::Error::from_raw_os_error(libc::EEXIST).into());
+        }
+
+        state.rules.push(rule.clone());
+        Ok(())
+    }
+
+    fn del_rule(&self, rule: &Rule) -> Result<(), SetupError> {
+        let mut state = self.request(Op::DelRule(rule.clone()))?;
+
+        match state.rules.iter().position(|other| same_rule(other, rule)) {
+            Some(i) => {
+                state.rules.remove(i);
+                Ok(())
+            }
+            None => Err(io::Error::from_raw_os_error(libc::ENOENT).into()),
+        }
+    }
+
+    fn replace_balance(&self, balance: &Balance) -> Result<(), SetupError> {
+        let mut state = self.request(Op::ReplaceBalance(balance.clone()))?;
+
+        let Some(first) = balance.members.first() else {
+            return Ok(());
+        };
+        let missing = balance.members.iter().any(|member| {
+            state
+                .missing_links
+                .iter()
+                .any(|link| link == member.def.link())
+        });
+        if missing {
+            return Err(io::Error::from_raw_os_error(libc::ENODEV).into());
+        }
+
+        state.routes.retain(|other| !other.same_dst(&first.def));
+        state.routes.push(first.def.clone());
+        Ok(())
+    }
+
+    fn del_balance(&self, balance: &Balance) -> Result<(), SetupError> {
+        let mut state = self.request(Op::DelBalance(balance.clone()))?;
+
+        let Some(first) = balance.members.first() else {
+            return Ok(());
+        };
+        match state
+            .routes
+            .iter()
+            .position(|other| other.same_dst(&first.def))
+        {
+            Some(i) => {
+                state.routes.remove(i);
+                Ok(())
+            }
+            None => Err(io::Error::from_raw_os_error(libc::ESRCH).into()),
+        }
+    }
+
+    fn apply_kernel_rule(&self, kernel_rule: &KernelRule) -> Result<(), SetupError> {
+        self.request(Op::ApplyKernelRule(kernel_rule.clone()))
+            .map(drop)
+    }
+
+    fn restore_kernel_rule(&self, kernel_rule: &KernelRule) -> Result<(), SetupError> {
+        self.request(Op::RestoreKernelRule(kernel_rule.clone()))
+            .map(drop)
+    }
+
+    fn add_reject(&self, route: &Blackhole) -> Result<(), SetupError> {
+        let mut state = self.request(Op::AddReject(route.clone()))?;
+
+        if state.rejects.iter().any(|other| same_reject(other, route)) {
+            return Err(io::Error::from_raw_os_error(libc::EEXIST).into());
+        }
+
+        state.rejects.push(route.clone());
+        Ok(())
+    }
+
+    fn del_reject(&self, route: &Blackhole) -> Result<(), SetupError> {
+        let mut state = self.request(Op::DelReject(route.clone()))?;
+
+        match state
+            .rejects
+            .iter()
+            .position(|other| same_reject(other, route))
+        {
+            Some(i) => {
+                state.rejects.remove(i);
+                Ok(())
+            }
+            None => Err(io::Error::from_raw_os_error(libc::ESRCH).into()),
+        }
+    }
+
+    fn add_isolate_rules(&self, isolate: &Isolate) -> Result<(), SetupError> {
+        let mut state = self.request(Op::AddIsolateRules(isolate.clone()))?;
+
+        let rules = (isolate.link.clone(), isolate.table);
+        if state.isolate_rules.contains(&rules) {
+            return Err(io::Error::from_raw_os_error(libc::EEXIST).into());
+        }
+
+        state.isolate_rules.push(rules);
+        Ok(())
+    }
+
+    fn del_isolate_rules(&self, isolate: &Isolate) -> Result<(), SetupError> {
+        let mut state = self.request(Op::DelIsolateRules(isolate.clone()))?;
+
+        state
+            .isolate_rules
+            .retain(|(link, table)| *link != isolate.link || *table != isolate.table);
+        Ok(())
+    }
+
+    fn add_neighbor(&self, neighbor: &Neighbor) -> Result<(), SetupError> {
+        let mut state = self.request(Op::AddNeighbor(neighbor.clone()))?;
+
+        if state.missing_links.contains(&neighbor.link) {
+            return Err(io::Error::from_raw_os_error(libc::ENODEV).into());
+        }
+
+        state
+            .neighbors
+            .retain(|other| !same_neighbor(other, neighbor));
+        state.neighbors.push(neighbor.clone());
+        Ok(())
+    }
+
+    fn del_neighbor(&self, neighbor: &Neighbor) -> Result<(), SetupError> {
+        let mut state = self.request(Op::DelNeighbor(neighbor.clone()))?;
+
+        match state
+            .neighbors
+            .iter()
+            .position(|other| same_neighbor(other, neighbor))
+        {
+            Some(i) => {
+                state.neighbors.remove(i);
+                Ok(())
+            }
+            None => Err(io::Error::from_raw_os_error(libc::ENOENT).into()),
+        }
+    }
+
+    fn add_vrf(&self, vrf: &Vrf) -> Result<(), SetupError> {
+        let mut state = self.request(Op::AddVrf(vrf.clone()))?;
+
+        state.missing_links.retain(|missing| *missing != vrf.name);
+        Ok(())
+    }
+
+    fn bind_vrf(&self, vrf: &Vrf, member: &str) -> Result<(), SetupError> {
+        let state = self.request(Op::BindVrf(vrf.clone(), member.to_string()))?;
+
+        match state
+            .missing_links
+            .iter()
+            .any(|missing| *missing == vrf.name || missing == member)
+        {
+            true => Err(io::Error::from_raw_os_error(libc::ENODEV).into()),
+            false => Ok(()),
+        }
+    }
+
+    fn del_vrf(&self, vrf: &Vrf) -> Result<(), SetupError> {
+        let mut state = self.request(Op::DelVrf(vrf.clone()))?;
+
+        if !state.missing_links.contains(&vrf.name) {
+            state.missing_links.push(vrf.name.clone());
+        }
+        Ok(())
+    }
+
+    fn apply_sysctl(&self, sysctl: &Sysctl) -> Result<(), SetupError> {
+        let state = self.request(Op::ApplySysctl(sysctl.clone()))?;
+
+        match state.missing_links.contains(&sysctl.link) {
+            true => Err(io::Error::from_raw_os_error(libc::ENOENT).into()),
+            false => Ok(()),
+        }
+    }
+
+    fn link_exists(&self, link: &str) -> Result<bool, SetupError> {
+        let state = self.request(Op::LinkExists(link.to_string()))?;
+
+        Ok(!state.missing_links.iter().any(|missing| missing == link))
+    }
+
+    fn link_wait_exists(&self, link: &str) -> Result<(), SetupError> {
+        let state = self.request(Op::LinkWaitExists(link.to_string()))?;
+
+        match state.missing_links.iter().any(|missing| missing == link) {
+            true => Err(io::Error::from_raw_os_error(libc::ENODEV).into()),
+            false => Ok(()),
+        }
+    }
+
+    fn link_wait_up(&self, link: &str) -> Result<(), SetupError> {
+        let state = self.request(Op::LinkWaitUp(link.to_string()))?;
+
+        match state.missing_links.iter().any(|missing| missing == link) {
+            true => Err(io::Error::from_raw_os_error(libc::ENODEV).into()),
+            false => Ok(()),
+        }
+    }
+
+    fn dump_routes(&self, family: u8) -> Result<Vec<rtnl::RouteMsg>, SetupError> {
+        let mut state = self.request(Op::DumpRoutes(family))?;
+
+        let routes = state.routes.clone();
+        Ok(routes
+            .iter()
+            .map(|route| rtnl::RouteMsg {
+                family: family_of(route.dst()),
+                dst_len: route.prefix_len(),
+                table: route.table(),
+                protocol: rtnl::RTPROT_STATIC,
+                ty: rtnl::RTN_UNICAST,
+                on_link: route.on_link(),
+                dst: Some(route.dst()),
+                gateway: route.rtr(),
+                prefsrc: None,
+                oif: Some(state.link_index(route.link())),
+                metric: route.metric(),
+            })
+            .filter(|msg| family == libc::AF_UNSPEC as u8 || msg.family == family)
+            .collect())
+    }
+
+    fn dump_rules(&self, family: u8) -> Result<Vec<rtnl::RuleMsg>, SetupError> {
+        let state = self.request(Op::DumpRules(family))?;
+
+        let families = [libc::AF_INET as u8, libc::AF_INET6 as u8];
+        Ok(state
+            .rules
+            .iter()
+            .flat_map(|rule| {
+                families
+                    .into_iter()
+                    .filter(|family| match rule.version {
+                        RuleVersion::Both => true,
+                        RuleVersion::Ipv4 => *family == libc::AF_INET as u8,
+                        RuleVersion::Ipv6 => *family == libc::AF_INET6 as u8,
+                    })
+                    .map(|family| rtnl::RuleMsg {
+                        family,
+                        dst_len: rule.dst.map(|(_, len)| len).unwrap_or_default(),
+                        src_len: rule.src.map(|(_, len)| len).unwrap_or_default(),
+                        action: action_type(rule.action),
+                        flags: if rule.invert {
+                            rtnl::FIB_RULE_INVERT
+                        } else {
+                            0
+                        },
+                        table: rule.table,
+                        protocol: rule.protocol,
+                        fwmark: rule.fwmark,
+                        dst: rule.dst.map(|(addr, _)| addr),
+                        src: rule.src.map(|(addr, _)| addr),
+                        ..Default::default()
+                    })
+            })
+            .filter(|msg| family == libc::AF_UNSPEC as u8 || msg.family == family)
+            .collect())
+    }
+
+    fn connect(&self) -> Result<Box<dyn Backend + Send>, SetupError> {
+        Ok(Box::new(self.clone()))
+    }
+}
+
+impl State {
+    fn link_index(&mut self, link: &str) -> u32 {
+        let i = match self.links.iter().position(|known| known == link) {
+            Some(i) => i,
+            None => {
+                self.links.push(link.to_string());
+                self.links.len() - 1
+            }
+        };
+
+        i as u32 + 1
+    }
+}
+
+/// Reports whether the kernel would consider two rules the same.
+fn same_rule(a: &Rule, b: &Rule) -> bool {
+    a.version == b.version
+        && a.invert == b.invert
+        && a.fwmark == b.fwmark
+        && a.dst == b.dst
+        && a.src == b.src
+        && a.action == b.action
+        && a.table == b.table
+}
+
+/// Reports whether two reject routes have the same destination in the same table.
+fn same_reject(a: &Blackhole, b: &Blackhole) -> bool {
+    a.dst == b.dst && a.prefix_len == b.prefix_len && a.table == b.table && a.metric == b.metric
+}
+
+/// Reports whether two neighbor entries are for the same address on the same link.
+fn same_neighbor(a: &Neighbor, b: &Neighbor) -> bool {
+    a.addr == b.addr && a.link == b.link && a.proxy == b.proxy
+}
+
+pub(crate) fn action_type(action: RuleAction) -> u8 {
+    match action {
+        RuleAction::ToTable => rtnl::FR_ACT_TO_TBL,
+        RuleAction::Blackhole => rtnl::FR_ACT_BLACKHOLE,
+        RuleAction::Unreachable => rtnl::FR_ACT_UNREACHABLE,
+        RuleAction::Prohibit => rtnl::FR_ACT_PROHIBIT,
+        _ => rtnl::FR_ACT_NOP,
+    }
+}
+
+fn family_of(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => libc::AF_INET as u8,
+        IpAddr::V6(_) => libc::AF_INET6 as u8,
+    }
+}
@@ -0,0 +1,82 @@
+//! Balance groups: weighted multipath routes whose members
+//! can be taken out of rotation by the gateway health checks.
+
+use crate::audit::Source;
+use crate::status;
+use crate::{outcome, report};
+
+use rsdsl_rtd::{Backend, Balance};
+
+/// A balance group along with the members that are currently installed.
+#[derive(Debug)]
+pub struct Group {
+    balance: Balance,
+    sources: Vec<Source>,
+    up: Vec<bool>,
+}
+
+impl Group {
+    pub fn new(balance: Balance, sources: Vec<Source>) -> Self {
+        let up = vec![true; balance.members.len()];
+
+        Self {
+            balance,
+            sources,
+            up,
+        }
+    }
+
+    pub fn balance(&self) -> &Balance {
+        &self.balance
+    }
+
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    /// Installs the multipath route with the members that are up,
+    /// withdrawing it entirely if none are.
+    pub fn apply(&self, backend: &dyn Backend) {
+        let current = self.balance.subset(&self.up);
+
+        let res = if current.members.is_empty() {
+            report(
+                self.sources[0],
+                "withdraw",
+                &self.balance,
+                backend.del_balance(&self.balance),
+            )
+        } else {
+            report(
+                self.sources[0],
+                "replace",
+                &current,
+                backend.replace_balance(&current),
+            )
+        };
+        let state = outcome(res, status::State::Applied);
+
+        for ((source, member), up) in self.sources.iter().zip(&self.balance.members).zip(&self.up) {
+            if *up {
+                status::set(*source, state.clone());
+            } else {
+                let probe = member
+                    .probe
+                    .as_ref()
+                    .map(|p| p.to_string())
+                    .unwrap_or_default();
+                status::set(*source, status::State::Withdrawn(probe));
+            }
+        }
+    }
+
+    /// Takes a member out of or back into rotation, rebalancing the rest.
+    pub fn set(&mut self, backend: &dyn Backend, member: usize, up: bool) {
+        if self.up.get(member) == Some(&up) {
+            return;
+        }
+
+        self.up[member] = up;
+        self.apply(backend);
+    }
+}
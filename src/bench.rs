@@ -0,0 +1,360 @@
+//! `bench`: measures how fast routes can be installed and removed.
+//!
+//! Synthetic /24 routes are added to a scratch table and removed again,
+//! optionally over several netlink connections. The throughput and
+//! the latencies of the individual requests are logged for either step.
+//!
+//! `bench parse` measures the parsers instead, using a generated
+//! configuration of the given number of routes and as many rules.
+//! The time taken and the peak memory usage of the process are logged.
+
+use crate::log;
+
+use rsdsl_rtd::rtnl;
+use rsdsl_rtd::{RouteBuilder, RouteDef, Routes, Rules, SetupError};
+
+use std::fmt;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rsdsl_netlinklib::blocking::Connection;
+
+const DEFAULT_ROUTES: usize = 1000;
+/// Generated configurations are about as large as those of big sites.
+const DEFAULT_PARSE_ROUTES: usize = 50000;
+const DEFAULT_TABLE: u32 = 4242;
+const DEFAULT_LINK: &str = "lo";
+/// The number of distinct /24 prefixes below 10.0.0.0/8.
+const MAX_ROUTES: usize = 1 << 16;
+
+#[derive(Debug)]
+pub enum BenchError {
+    InvalidAttr(String),
+    InvalidParseAttr(String),
+    Netlink(io::Error),
+    NoAttrValue(String),
+    NoConnections,
+    NoRoutes,
+    Parse(String),
+    ParseInt(std::num::ParseIntError),
+    Setup(SetupError),
+    TableInUse(u32),
+    TooManyRoutes(usize),
+}
+
+impl fmt::Display for BenchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidAttr(a) => write!(
+                f,
+                "invalid attribute {} (want \"routes\", \"table\", \"dev\" or \"connections\")",
+                a
+            )?,
+            Self::InvalidParseAttr(a) => write!(f, "invalid attribute {} (want \"routes\")", a)?,
+            Self::Netlink(e) => write!(f, "netlink: {}", e)?,
+            Self::Parse(e) => write!(f, "parse generated configuration: {}", e)?,
+            Self::NoAttrValue(a) => write!(f, "missing value for attribute {}", a)?,
+            Self::NoConnections => write!(f, "need at least one connection")?,
+            Self::NoRoutes => write!(f, "need at least one route")?,
+            Self::ParseInt(e) => write!(f, "parse integer: {}", e)?,
+            Self::Setup(e) => write!(f, "{}", e)?,
+            Self::TableInUse(table) => write!(
+                f,
+                "scratch table {} isn't empty (pick another one with \"table\")",
+                table
+            )?,
+            Self::TooManyRoutes(n) => {
+                write!(f, "too many routes: {} (maximum is {})", n, MAX_ROUTES)?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl From<io::Error> for BenchError {
+    fn from(e: io::Error) -> BenchError {
+        BenchError::Netlink(e)
+    }
+}
+
+impl From<std::num::ParseIntError> for BenchError {
+    fn from(e: std::num::ParseIntError) -> BenchError {
+        BenchError::ParseInt(e)
+    }
+}
+
+impl From<SetupError> for BenchError {
+    fn from(e: SetupError) -> BenchError {
+        BenchError::Setup(e)
+    }
+}
+
+impl std::error::Error for BenchError {}
+
+pub fn bench(args: &[String]) -> Result<(), BenchError> {
+    if args.first().is_some_and(|arg| arg == "parse") {
+        return bench_parse(&args[1..]);
+    }
+
+    let mut words = args.iter().map(String::as_str);
+
+    let mut routes = DEFAULT_ROUTES;
+    let mut table = DEFAULT_TABLE;
+    let mut link = DEFAULT_LINK;
+    let mut connections = 1;
+    while let Some(attr) = words.next() {
+        let value = words
+            .next()
+            .ok_or_else(|| BenchError::NoAttrValue(attr.to_string()))?;
+        match attr {
+            "routes" => routes = value.parse()?,
+            "table" => table = value.parse()?,
+            "dev" => link = value,
+            "connections" => connections = value.parse()?,
+            _ => return Err(BenchError::InvalidAttr(attr.to_string())),
+        }
+    }
+
+    if routes == 0 {
+        return Err(BenchError::NoRoutes);
+    }
+    if routes > MAX_ROUTES {
+        return Err(BenchError::TooManyRoutes(routes));
+    }
+    if connections == 0 {
+        return Err(BenchError::NoConnections);
+    }
+
+    // Never touch routes that don't belong to the benchmark.
+    let in_use = rtnl::Socket::new()?
+        .dump_routes(libc::AF_UNSPEC as u8)?
+        .iter()
+        .any(|route| route.table == table);
+    if in_use {
+        return Err(BenchError::TableInUse(table));
+    }
+
+    let defs: Vec<RouteDef> = (0..routes)
+        .map(|i| {
+            RouteBuilder::v4()
+                .dst(Ipv4Addr::new(10, (i >> 8) as u8, i as u8, 0), 24)
+                .table(table)
+                .dev(link)
+                .build()
+                .map(|route| route.def)
+                .expect("synthetic routes are valid")
+        })
+        .collect();
+
+    log::info!(
+        General,
+        "benchmark {} routes in table {} via {} over {} connections",
+        routes,
+        table,
+        link,
+        connections
+    );
+
+    let add = run(&defs, connections, |conn, def| {
+        def.clone().blocking_add(conn)
+    });
+    let del = run(&defs, connections, |conn, def| {
+        def.clone().blocking_del(conn)
+    });
+
+    // Don't leave anything behind if removing some of them failed.
+    if del.is_err() || del.as_ref().is_ok_and(|del| del.failed > 0) {
+        let _ = rtnl::Socket::new().and_then(|mut sock| sock.flush_routes(|r| r.table == table));
+    }
+
+    log::info!(General, "add: {}", add?);
+    log::info!(General, "del: {}", del?);
+    Ok(())
+}
+
+fn bench_parse(args: &[String]) -> Result<(), BenchError> {
+    let mut words = args.iter().map(String::as_str);
+
+    let mut routes = DEFAULT_PARSE_ROUTES;
+    while let Some(attr) = words.next() {
+        let value = words
+            .next()
+            .ok_or_else(|| BenchError::NoAttrValue(attr.to_string()))?;
+        match attr {
+            "routes" => routes = value.parse()?,
+            _ => return Err(BenchError::InvalidParseAttr(attr.to_string())),
+        }
+    }
+
+    if routes == 0 {
+        return Err(BenchError::NoRoutes);
+    }
+    if routes > MAX_ROUTES {
+        return Err(BenchError::TooManyRoutes(routes));
+    }
+
+    let (routes_file, rules_file) = generate(routes);
+    log::info!(
+        General,
+        "benchmark parsing {} routes and {} rules ({} KiB)",
+        routes,
+        routes,
+        (routes_file.len() + rules_file.len()) / 1024
+    );
+
+    let start = Instant::now();
+    let parsed: Routes = routes_file
+        .parse()
+        .map_err(|e: rsdsl_rtd::RouteParseError| BenchError::Parse(e.to_string()))?;
+    let routes_time = start.elapsed();
+
+    let start = Instant::now();
+    let rules: Rules = rules_file
+        .parse()
+        .map_err(|e: rsdsl_rtd::RuleParseError| BenchError::Parse(e.to_string()))?;
+    let rules_time = start.elapsed();
+
+    log::info!(
+        General,
+        "routes: {} in {:.3} s, rules: {} in {:.3} s, peak memory {} KiB",
+        parsed.routes.len(),
+        routes_time.as_secs_f64(),
+        rules.rules.len(),
+        rules_time.as_secs_f64(),
+        peak_memory().unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// Generates a route and a rule configuration of the given size, with
+/// gateways, comments and metrics to exercise what real ones use.
+fn generate(n: usize) -> (String, String) {
+    let mut routes = String::new();
+    let mut rules = String::new();
+    for i in 0..n {
+        let (a, b) = ((i >> 8) as u8, i as u8);
+        let link = ["eth0", "eth1", "wg0", "ppp0"][i % 4];
+        let _ = writeln!(
+            routes,
+            "route4 add to 10.{}.{}.0/24 via 192.0.2.{} dev {} table {} metric {} # site {}",
+            a,
+            b,
+            i % 250 + 1,
+            link,
+            100 + i % 8,
+            i % 16,
+            i
+        );
+        let _ = writeln!(
+            rules,
+            "rule4 add fwmark {} src 10.{}.{}.0/24 lookup {}",
+            i + 1,
+            a,
+            b,
+            100 + i % 8
+        );
+    }
+
+    (routes, rules)
+}
+
+/// Returns the peak resident set size of the process in KiB.
+fn peak_memory() -> Option<usize> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// The measurements of one step.
+#[derive(Debug)]
+struct Stats {
+    total: Duration,
+    latencies: Vec<Duration>,
+    failed: usize,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = self.latencies.len();
+        let percentile = |p: usize| self.latencies[(n * p / 100).min(n - 1)];
+        let avg = self.latencies.iter().sum::<Duration>() / n as u32;
+
+        write!(
+            f,
+            "{} routes in {:.3} s ({:.0}/s), latency min {:?} avg {:?} p50 {:?} p99 {:?} max {:?}",
+            n,
+            self.total.as_secs_f64(),
+            n as f64 / self.total.as_secs_f64(),
+            self.latencies[0],
+            avg,
+            percentile(50),
+            percentile(99),
+            self.latencies[n - 1]
+        )?;
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Performs an operation on all routes, spreading them across the connections.
+fn run<F>(defs: &[RouteDef], connections: usize, op: F) -> Result<Stats, BenchError>
+where
+    F: Fn(&Connection, &RouteDef) -> Result<(), SetupError> + Sync,
+{
+    let conns = (0..connections)
+        .map(|_| Connection::new())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(SetupError::from)?;
+
+    let next = AtomicUsize::new(0);
+    let latencies = Mutex::new(Vec::with_capacity(defs.len()));
+    let failed = AtomicUsize::new(0);
+
+    let start = Instant::now();
+    thread::scope(|s| {
+        for conn in &conns {
+            s.spawn(|| {
+                let mut own = Vec::new();
+                while let Some(def) = defs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let t = Instant::now();
+                    if let Err(e) = op(conn, def) {
+                        log::debug!(Netlink, "{}: {}", def, e);
+                        failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                    own.push(t.elapsed());
+                }
+
+                latencies
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .extend(own);
+            });
+        }
+    });
+    let total = start.elapsed();
+
+    let mut latencies = latencies.into_inner().unwrap_or_else(|e| e.into_inner());
+    latencies.sort();
+
+    Ok(Stats {
+        total,
+        latencies,
+        failed: failed.into_inner(),
+    })
+}
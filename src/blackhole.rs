@@ -0,0 +1,386 @@
+//! Routes discarding traffic: remote-triggered blackholing
+//! via prefix lists and the bogon preset.
+//!
+//! A prefix list is a plain file with one prefix (or address) per line.
+//! Empty lines and everything after a `#` are ignored.
+
+use crate::{rtnl, RouteParseError, SetupError};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// A `rtbh` line of the route configuration.
+#[derive(Clone, Debug)]
+pub struct PrefixList {
+    pub delete: bool,
+    pub path: PathBuf,
+    pub table: Option<u32>,
+    pub line: usize,
+}
+
+impl PrefixList {
+    /// Reads the blackhole routes from the list file.
+    pub fn load(&self) -> io::Result<Vec<Blackhole>> {
+        let s = fs::read_to_string(&self.path)?;
+
+        let mut blackholes = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let prefix = line.split('#').next().unwrap_or_default().trim();
+            if prefix.is_empty() {
+                continue;
+            }
+
+            let blackhole = parse_prefix(prefix).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: invalid prefix {}", i + 1, prefix),
+                )
+            })?;
+
+            blackholes.push(Blackhole {
+                table: self.table,
+                ..blackhole
+            });
+        }
+
+        Ok(blackholes)
+    }
+}
+
+impl fmt::Display for PrefixList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rtbh file {}", self.path.display())?;
+        if let Some(table) = self.table {
+            write!(f, " table {}", table)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for PrefixList {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = crate::lowercase_keywords(s, 2);
+        let mut words = s.split_whitespace();
+
+        let version_str = words.next().ok_or(RouteParseError::NoVersion)?;
+        if version_str != "rtbh" {
+            return Err(RouteParseError::InvalidVersion(version_str.to_string()));
+        }
+
+        let cmd = words.next().ok_or(RouteParseError::NoCmd)?;
+        let delete = match cmd {
+            "add" => false,
+            "del" => true,
+            _ => return Err(RouteParseError::InvalidCmd(cmd.to_string())),
+        };
+
+        let attrs = crate::attr_pairs(words, |attr| attr)?;
+
+        let mut list = PrefixList {
+            delete,
+            path: PathBuf::new(),
+            table: None,
+            line: 0,
+        };
+
+        for (attr, value) in attrs {
+            match attr {
+                "file" => list.path = PathBuf::from(value),
+                "table" => list.table = Some(crate::parse_table(value)?),
+                _ => return Err(RouteParseError::InvalidAttr(attr.to_string())),
+            }
+        }
+
+        if list.path.as_os_str().is_empty() {
+            return Err(RouteParseError::NoFile);
+        }
+
+        Ok(list)
+    }
+}
+
+/// A `bogons` line of the route configuration: rejects traffic to address
+/// space that isn't routed on the internet instead of sending it to the WAN.
+///
+/// The routes are less specific than those of the local networks and use
+/// the highest metric so they only catch what would take the default route.
+#[derive(Clone, Debug)]
+pub struct Bogons {
+    pub delete: bool,
+    pub kind: RejectKind,
+    pub table: Option<u32>,
+    pub line: usize,
+}
+
+impl Bogons {
+    /// The metric of the routes, lowest priority to never shadow configured routes.
+    pub const METRIC: u32 = u32::MAX;
+
+    /// Returns the routes of the preset.
+    pub fn routes(&self) -> Vec<Blackhole> {
+        let v4 = BOGONS_V4
+            .iter()
+            .map(|(addr, len)| (IpAddr::V4(Ipv4Addr::from(*addr)), *len));
+        let v6 = BOGONS_V6
+            .iter()
+            .map(|(addr, len)| (IpAddr::V6(Ipv6Addr::from(*addr)), *len));
+
+        v4.chain(v6)
+            .map(|(dst, prefix_len)| Blackhole {
+                dst,
+                prefix_len,
+                kind: self.kind,
+                table: self.table,
+                metric: Some(Self::METRIC),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Bogons {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bogons type {}", self.kind)?;
+        if let Some(table) = self.table {
+            write!(f, " table {}", table)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Bogons {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = crate::lowercase_keywords(s, 2);
+        let mut words = s.split_whitespace();
+
+        let version_str = words.next().ok_or(RouteParseError::NoVersion)?;
+        if version_str != "bogons" {
+            return Err(RouteParseError::InvalidVersion(version_str.to_string()));
+        }
+
+        let cmd = words.next().ok_or(RouteParseError::NoCmd)?;
+        let delete = match cmd {
+            "add" => false,
+            "del" => true,
+            _ => return Err(RouteParseError::InvalidCmd(cmd.to_string())),
+        };
+
+        let attrs = crate::attr_pairs(words, |attr| attr)?;
+
+        let mut bogons = Bogons {
+            delete,
+            kind: RejectKind::Unreachable,
+            table: None,
+            line: 0,
+        };
+
+        for (attr, value) in attrs {
+            match attr {
+                "type" => bogons.kind = value.parse()?,
+                "table" => bogons.table = Some(crate::parse_table(value)?),
+                _ => return Err(RouteParseError::InvalidAttr(attr.to_string())),
+            }
+        }
+
+        Ok(bogons)
+    }
+}
+
+/// Special-purpose IPv4 space (RFC 6890) that is never routed on the internet.
+/// Loopback and multicast are left alone, the kernel handles them separately.
+const BOGONS_V4: &[([u8; 4], u8)] = &[
+    ([0, 0, 0, 0], 8),
+    ([10, 0, 0, 0], 8),
+    ([100, 64, 0, 0], 10),
+    ([169, 254, 0, 0], 16),
+    ([172, 16, 0, 0], 12),
+    ([192, 0, 0, 0], 24),
+    ([192, 0, 2, 0], 24),
+    ([192, 168, 0, 0], 16),
+    ([198, 18, 0, 0], 15),
+    ([198, 51, 100, 0], 24),
+    ([203, 0, 113, 0], 24),
+    ([240, 0, 0, 0], 4),
+];
+
+/// Special-purpose IPv6 space (RFC 6890) that is never routed on the internet.
+/// Link-local and multicast are left alone, the kernel handles them separately.
+const BOGONS_V6: &[([u8; 16], u8)] = &[
+    ([0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 64),
+    (
+        [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        32,
+    ),
+    ([0x3f, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 20),
+    ([0xfc, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 7),
+    ([0xfe, 0xc0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 10),
+];
+
+/// How a route discards traffic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RejectKind {
+    /// Drop silently.
+    Blackhole,
+    /// Reply with ICMP destination unreachable.
+    Unreachable,
+    /// Reply with ICMP administratively prohibited.
+    Prohibit,
+}
+
+impl RejectKind {
+    fn rtn(self) -> u8 {
+        match self {
+            Self::Blackhole => rtnl::RTN_BLACKHOLE,
+            Self::Unreachable => rtnl::RTN_UNREACHABLE,
+            Self::Prohibit => rtnl::RTN_PROHIBIT,
+        }
+    }
+}
+
+impl fmt::Display for RejectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Blackhole => write!(f, "blackhole"),
+            Self::Unreachable => write!(f, "unreachable"),
+            Self::Prohibit => write!(f, "prohibit"),
+        }
+    }
+}
+
+impl FromStr for RejectKind {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blackhole" => Ok(Self::Blackhole),
+            "unreachable" => Ok(Self::Unreachable),
+            "prohibit" => Ok(Self::Prohibit),
+            _ => Err(RouteParseError::InvalidType(s.to_string())),
+        }
+    }
+}
+
+/// A route discarding all traffic to a prefix.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Blackhole {
+    pub dst: IpAddr,
+    pub prefix_len: u8,
+    pub kind: RejectKind,
+    pub table: Option<u32>,
+    pub metric: Option<u32>,
+}
+
+impl Blackhole {
+    /// Installs the route.
+    pub fn blocking_add(&self) -> Result<(), SetupError> {
+        crate::retry(|| {
+            Ok(rtnl::Socket::new()?.add_reject(
+                self.dst,
+                self.prefix_len,
+                self.table,
+                self.metric,
+                self.kind.rtn(),
+            )?)
+        })
+    }
+
+    /// Removes the route.
+    pub fn blocking_del(&self) -> Result<(), SetupError> {
+        crate::retry(|| {
+            Ok(rtnl::Socket::new()?.del_route(
+                self.dst,
+                self.prefix_len,
+                self.table,
+                self.metric,
+            )?)
+        })
+    }
+}
+
+impl fmt::Display for Blackhole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.dst {
+            IpAddr::V4(_) => write!(f, "route4 {} {}/{}", self.kind, self.dst, self.prefix_len)?,
+            IpAddr::V6(_) => write!(f, "route6 {} {}/{}", self.kind, self.dst, self.prefix_len)?,
+        }
+        if let Some(table) = self.table {
+            write!(f, " table {}", table)?;
+        }
+        if let Some(metric) = self.metric {
+            write!(f, " metric {}", metric)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a prefix or a single address of a prefix list.
+fn parse_prefix(s: &str) -> Option<Blackhole> {
+    let (dst, prefix_len) = match s.split_once('/') {
+        Some((addr, len)) => (addr.parse::<IpAddr>().ok()?, len.parse().ok()?),
+        None => {
+            let dst = s.parse::<IpAddr>().ok()?;
+            (dst, if dst.is_ipv4() { 32 } else { 128 })
+        }
+    };
+
+    let max_len = if dst.is_ipv4() { 32 } else { 128 };
+    if prefix_len > max_len {
+        return None;
+    }
+
+    Some(Blackhole {
+        dst,
+        prefix_len,
+        kind: RejectKind::Blackhole,
+        table: None,
+        metric: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::round_trip;
+
+    #[test]
+    fn prefix_lists() {
+        assert_eq!(
+            round_trip::<PrefixList>("rtbh add file /data/rtbh.txt"),
+            "rtbh file /data/rtbh.txt"
+        );
+        assert_eq!(
+            round_trip::<PrefixList>("rtbh add file /data/rtbh.txt table 100"),
+            "rtbh file /data/rtbh.txt table 100"
+        );
+        assert!(matches!(
+            "rtbh add table 100".parse::<PrefixList>(),
+            Err(RouteParseError::NoFile)
+        ));
+    }
+
+    #[test]
+    fn bogons() {
+        assert_eq!(
+            round_trip::<Bogons>("bogons add"),
+            "bogons type unreachable"
+        );
+        assert_eq!(
+            round_trip::<Bogons>("bogons add type prohibit table 100"),
+            "bogons type prohibit table 100"
+        );
+        assert!(matches!(
+            "bogons add type drop".parse::<Bogons>(),
+            Err(RouteParseError::InvalidType(kind)) if kind == "drop"
+        ));
+    }
+}
@@ -0,0 +1,137 @@
+//! VPN bypass (`bypass` lines of the route configuration).
+//!
+//! Traffic carrying the mark looks up a table of its own whose default
+//! routes use the WAN directly instead of the VPN. Replies arrive on the WAN
+//! while the main table points to the VPN, so reverse path filtering
+//! is relaxed on the WAN to keep strict filtering from dropping them.
+
+use crate::{
+    rtnl, RouteDef, RouteParseError, Rule, RuleAction, RuleVersion, Sysctl, SysctlKey, DEFAULT_WAN,
+};
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A `bypass` line of the route configuration.
+#[derive(Clone, Debug)]
+pub struct Bypass {
+    pub delete: bool,
+    pub fwmark: u32,
+    pub table: u32,
+    /// The interface of the default routes.
+    pub wan: String,
+    pub line: usize,
+}
+
+impl Bypass {
+    /// Returns the rule directing marked traffic to the bypass table.
+    pub fn rule(&self) -> Rule {
+        Rule {
+            delete: self.delete,
+            version: RuleVersion::Both,
+            invert: false,
+            fwmark: Some(self.fwmark),
+            dst: None,
+            src: None,
+            action: RuleAction::ToTable,
+            table: self.table,
+            netns: None,
+            protocol: rtnl::RTPROT_RTD,
+            drift: None,
+            managed: true,
+            line: self.line,
+            template: None,
+            comment: None,
+        }
+    }
+
+    /// Returns the default routes of the bypass table.
+    pub fn default_routes(&self) -> [RouteDef; 2] {
+        RouteDef::defaults(&self.wan, self.table)
+    }
+
+    /// Returns the loose reverse path filtering setting of the WAN.
+    pub fn sysctl(&self) -> Sysctl {
+        Sysctl {
+            link: self.wan.clone(),
+            settings: vec![(SysctlKey::RpFilter, 2)],
+            line: self.line,
+        }
+    }
+}
+
+impl fmt::Display for Bypass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bypass fwmark {} table {} wan {}",
+            self.fwmark, self.table, self.wan
+        )
+    }
+}
+
+impl FromStr for Bypass {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = crate::lowercase_keywords(s, 2);
+        let mut words = s.split_whitespace();
+
+        let version_str = words.next().ok_or(RouteParseError::NoVersion)?;
+        if version_str != "bypass" {
+            return Err(RouteParseError::InvalidVersion(version_str.to_string()));
+        }
+
+        let cmd = words.next().ok_or(RouteParseError::NoCmd)?;
+        let delete = match cmd {
+            "add" => false,
+            "del" => true,
+            _ => return Err(RouteParseError::InvalidCmd(cmd.to_string())),
+        };
+
+        let attrs = crate::attr_pairs(words, |attr| attr)?;
+
+        let mut fwmark = None;
+        let mut table = None;
+        let mut wan = DEFAULT_WAN.to_string();
+
+        for (attr, value) in attrs {
+            match attr {
+                "fwmark" => fwmark = Some(crate::parse_u32(value)?),
+                "table" => table = Some(crate::parse_table(value)?),
+                "wan" => wan = value.to_string(),
+                _ => return Err(RouteParseError::InvalidAttr(attr.to_string())),
+            }
+        }
+
+        Ok(Self {
+            delete,
+            fwmark: fwmark.ok_or(RouteParseError::NoFwmark)?,
+            table: table.ok_or(RouteParseError::NoTable)?,
+            wan,
+            line: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::round_trip;
+
+    #[test]
+    fn marks() {
+        assert_eq!(
+            round_trip::<Bypass>("bypass add fwmark 16 table 100"),
+            "bypass fwmark 16 table 100 wan ppp0"
+        );
+        assert_eq!(
+            round_trip::<Bypass>("bypass add fwmark 16 table 100 wan eth1"),
+            "bypass fwmark 16 table 100 wan eth1"
+        );
+        assert!(matches!(
+            "bypass add table 100".parse::<Bypass>(),
+            Err(RouteParseError::NoFwmark)
+        ));
+    }
+}
@@ -0,0 +1,184 @@
+//! Classless static routes (DHCP option 121, RFC 3442) received
+//! by rsdsl's DHCPv4 client (`classless` lines of the route configuration).
+//!
+//! The client keeps its lease in a JSON file. Its `routes` field lists
+//! the routes as objects with a `dst` prefix and a `rtr` address,
+//! an unspecified router meaning that the destination is on-link.
+
+use crate::{Route, RouteBuilder, RouteParseError};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Where rsdsl's DHCPv4 client keeps its lease.
+const LEASE_PATH: &str = "/data/dhcp4.ip_config";
+
+/// A `classless` line of the route configuration.
+#[derive(Clone, Debug)]
+pub struct Classless {
+    pub delete: bool,
+    /// The interface the lease was obtained on.
+    pub link: String,
+    pub table: Option<u32>,
+    pub metric: Option<u32>,
+    pub path: PathBuf,
+    pub line: usize,
+}
+
+impl Classless {
+    /// Reads the routes from the lease file.
+    /// There are none as long as the client hasn't obtained a lease.
+    pub fn load(&self) -> io::Result<Vec<Route>> {
+        let s = match fs::read_to_string(&self.path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+
+        let lease: serde_json::Value =
+            serde_json::from_str(&s).map_err(|e| invalid(e.to_string()))?;
+        let Some(entries) = lease.get("routes") else {
+            return Ok(Vec::new());
+        };
+        let entries = entries
+            .as_array()
+            .ok_or_else(|| invalid("routes is not an array".to_string()))?;
+
+        entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                self.route(entry)
+                    .ok_or_else(|| invalid(format!("invalid route {}: {}", i, entry)))
+            })
+            .collect()
+    }
+
+    fn route(&self, entry: &serde_json::Value) -> Option<Route> {
+        let (dst, prefix_len) = entry.get("dst")?.as_str()?.split_once('/')?;
+        let dst: Ipv4Addr = dst.parse().ok()?;
+        let rtr: Ipv4Addr = entry.get("rtr")?.as_str()?.parse().ok()?;
+
+        let mut builder = RouteBuilder::v4()
+            .dst(dst, prefix_len.parse().ok()?)
+            .dev(self.link.as_str());
+        if !rtr.is_unspecified() {
+            builder = builder.via(IpAddr::V4(rtr));
+        }
+        if let Some(table) = self.table {
+            builder = builder.table(table);
+        }
+        if let Some(metric) = self.metric {
+            builder = builder.metric(metric);
+        }
+
+        builder.build().ok()
+    }
+}
+
+impl fmt::Display for Classless {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "classless dev {}", self.link)?;
+        if let Some(table) = self.table {
+            write!(f, " table {}", table)?;
+        }
+        if let Some(metric) = self.metric {
+            write!(f, " metric {}", metric)?;
+        }
+        if self.path.as_os_str() != LEASE_PATH {
+            write!(f, " file {}", self.path.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Classless {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = crate::lowercase_keywords(s, 2);
+        let mut words = s.split_whitespace();
+
+        let version_str = words.next().ok_or(RouteParseError::NoVersion)?;
+        if version_str != "classless" {
+            return Err(RouteParseError::InvalidVersion(version_str.to_string()));
+        }
+
+        let cmd = words.next().ok_or(RouteParseError::NoCmd)?;
+        let delete = match cmd {
+            "add" => false,
+            "del" => true,
+            _ => return Err(RouteParseError::InvalidCmd(cmd.to_string())),
+        };
+
+        let attrs = crate::attr_pairs(words, |attr| attr)?;
+
+        let mut link = None;
+        let mut table = None;
+        let mut metric = None;
+        let mut path = PathBuf::from(LEASE_PATH);
+
+        for (attr, value) in attrs {
+            match attr {
+                "dev" => link = Some(value.to_string()),
+                "table" => table = Some(crate::parse_table(value)?),
+                "metric" => metric = Some(value.parse()?),
+                "file" => path = PathBuf::from(value),
+                _ => return Err(RouteParseError::InvalidAttr(attr.to_string())),
+            }
+        }
+
+        Ok(Self {
+            delete,
+            link: link.ok_or(RouteParseError::NoLink)?,
+            table,
+            metric,
+            path,
+            line: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::round_trip;
+
+    #[test]
+    fn leases() {
+        assert_eq!(
+            round_trip::<Classless>("classless add dev eth1"),
+            "classless dev eth1"
+        );
+        assert_eq!(
+            round_trip::<Classless>(
+                "classless add dev eth1 table 100 metric 20 file /run/dhcp4.eth1"
+            ),
+            "classless dev eth1 table 100 metric 20 file /run/dhcp4.eth1"
+        );
+
+        let classless: Classless = "classless add dev eth1 metric 20".parse().unwrap();
+        let entry = |dst: &str, rtr: &str| serde_json::json!({ "dst": dst, "rtr": rtr });
+        assert_eq!(
+            classless
+                .route(&entry("10.1.0.0/16", "192.0.2.1"))
+                .map(|route| route.to_string()),
+            Some("route4 10.1.0.0/16 via 192.0.2.1 metric 20 dev eth1".to_string())
+        );
+        // Destinations on the link itself come without a gateway.
+        assert_eq!(
+            classless
+                .route(&entry("10.2.0.0/16", "0.0.0.0"))
+                .map(|route| route.to_string()),
+            Some("route4 10.2.0.0/16 metric 20 dev eth1".to_string())
+        );
+        assert!(classless.route(&entry("10.3.0.0", "192.0.2.1")).is_none());
+    }
+}
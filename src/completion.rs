@@ -0,0 +1,133 @@
+//! `completion bash|zsh`: prints a shell completion script.
+//!
+//! The script completes the options, the subcommands and their keywords.
+//! Interface names are looked up when completing, so they are always current.
+
+use crate::{BIN, CTL_BIN, CTL_COMMANDS};
+
+use std::fmt;
+use std::fmt::Write;
+
+/// The options, those listed in `VALUE_OPTIONS` take a value.
+/// Only the first one concerns `rtdctl`.
+const OPTIONS: &[&str] = &[
+    "--log-level",
+    "--force",
+    "--monitor",
+    "--confirm",
+    "--backend",
+];
+const VALUE_OPTIONS: &[&str] = &["--log-level", "--confirm", "--backend"];
+
+/// The subcommands and the keywords they accept.
+const SUBCOMMANDS: &[(&str, &[&str])] = &[
+    ("route-get", &["src", "iif", "fwmark"]),
+    ("self-test", &[]),
+    ("snapshot", &["save", "restore", "tables"]),
+    ("rollback", &[]),
+    ("confirm", &[]),
+    ("edit", &["routes", "rules", "neighbors", "reload"]),
+    ("write", &["routes", "rules", "neighbors"]),
+    ("bench", &["parse", "routes", "table", "dev", "connections"]),
+    ("panic", &["wan"]),
+    ("completion", &["bash", "zsh"]),
+    (
+        "explain",
+        &["route4", "route6", "dslite", "rule", "rule4", "rule6"],
+    ),
+    ("diff", &["routes", "rules", "neighbors"]),
+    ("plan", &[]),
+];
+
+/// The keywords followed by an interface name.
+const LINK_KEYWORDS: &[&str] = &["dev", "iif", "wan"];
+
+#[derive(Debug)]
+pub enum CompletionError {
+    InvalidShell(String),
+    NoShell,
+}
+
+impl fmt::Display for CompletionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidShell(s) => write!(f, "invalid shell {} (want \"bash\" or \"zsh\")", s)?,
+            Self::NoShell => write!(f, "missing shell (want \"bash\" or \"zsh\")")?,
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for CompletionError {}
+
+pub fn completion(args: &[String]) -> Result<(), CompletionError> {
+    let shell = args.first().ok_or(CompletionError::NoShell)?;
+    match shell.as_str() {
+        "bash" => print!("{}", bash()),
+        // zsh can use bash completions through its compatibility layer.
+        "zsh" => print!("autoload -U +X bashcompinit && bashcompinit\n{}", bash()),
+        _ => return Err(CompletionError::InvalidShell(shell.clone())),
+    }
+
+    Ok(())
+}
+
+fn bash() -> String {
+    let func = format!("_{}", BIN);
+    // rtdctl only takes some of them, the other options only concern the daemon.
+    let options = if BIN == CTL_BIN {
+        &OPTIONS[..1]
+    } else {
+        OPTIONS
+    };
+    let commands: Vec<_> = SUBCOMMANDS
+        .iter()
+        .filter(|(cmd, _)| BIN != CTL_BIN || CTL_COMMANDS.contains(cmd))
+        .collect();
+    let subcommands: Vec<&str> = commands.iter().map(|(cmd, _)| *cmd).collect();
+
+    let mut s = String::new();
+    let _ = writeln!(s, "{}() {{", func);
+    let _ = writeln!(s, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"");
+    let _ = writeln!(s, "    local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"");
+    let _ = writeln!(s, "    case \"$prev\" in");
+    let _ = writeln!(
+        s,
+        "        {}) COMPREPLY=($(compgen -W \"$(ls /sys/class/net 2>/dev/null)\" -- \"$cur\")); return ;;",
+        LINK_KEYWORDS.join("|")
+    );
+    let _ = writeln!(
+        s,
+        "        --backend) COMPREPLY=($(compgen -W \"netlink ip\" -- \"$cur\")); return ;;"
+    );
+    let _ = writeln!(s, "        {}) return ;;", VALUE_OPTIONS.join("|"));
+    let _ = writeln!(s, "    esac");
+    let _ = writeln!(s);
+    let _ = writeln!(s, "    local i cmd=");
+    let _ = writeln!(s, "    for ((i = 1; i < COMP_CWORD; i++)); do");
+    let _ = writeln!(s, "        case \"${{COMP_WORDS[i]}}\" in");
+    let _ = writeln!(s, "            {}) ((i++)) ;;", VALUE_OPTIONS.join("|"));
+    let _ = writeln!(s, "            --*) ;;");
+    let _ = writeln!(s, "            *) cmd=\"${{COMP_WORDS[i]}}\"; break ;;");
+    let _ = writeln!(s, "        esac");
+    let _ = writeln!(s, "    done");
+    let _ = writeln!(s);
+    let _ = writeln!(s, "    local words");
+    let _ = writeln!(s, "    case \"$cmd\" in");
+    let _ = writeln!(
+        s,
+        "        \"\") words=\"{} {}\" ;;",
+        options.join(" "),
+        subcommands.join(" ")
+    );
+    for (cmd, keywords) in commands {
+        let _ = writeln!(s, "        {}) words=\"{}\" ;;", cmd, keywords.join(" "));
+    }
+    let _ = writeln!(s, "    esac");
+    let _ = writeln!(s, "    COMPREPLY=($(compgen -W \"$words\" -- \"$cur\"))");
+    let _ = writeln!(s, "}}");
+    let _ = writeln!(s, "complete -F {} {}", func, BIN);
+
+    s
+}
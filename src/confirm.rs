@@ -0,0 +1,232 @@
+//! Commit-confirm apply (`--confirm <seconds>`).
+//!
+//! A configuration that cuts off the operator can't be fixed remotely.
+//! With `--confirm`, the configuration has to be confirmed (`confirm`)
+//! within the given time, otherwise rtd removes its routes and rules,
+//! reverts to the most recent configuration that was applied completely
+//! and restarts with it.
+
+use crate::audit::Source;
+use crate::removal;
+use crate::{history, log, reload, shutdown, status, wildcard};
+
+use rsdsl_rtd::{netns, rtnl, Backend, Balance, Route, Routes, Rule, Rules, SetupError};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The configuration awaiting confirmation, if any.
+static PENDING: Mutex<Option<history::Config>> = Mutex::new(None);
+static CONFIRMED: Condvar = Condvar::new();
+
+/// Starts the window for confirming the configuration that is about to be applied.
+pub fn arm(backend: &dyn Backend, config: history::Config, timeout: Duration) {
+    *pending() = Some(config);
+
+    // Without a connection the revert still restores the configuration,
+    // the restart then only adds its entries.
+    let backend = backend
+        .connect()
+        .inspect_err(|e| log::error!(Netlink, "connect for reverting: {}", e))
+        .ok();
+
+    log::warn!(
+        General,
+        "configuration reverts in {} s unless confirmed (\"confirm\")",
+        timeout.as_secs()
+    );
+
+    thread::spawn(move || {
+        let deadline = Instant::now() + timeout;
+
+        let mut pending = pending();
+        while let Some(config) = pending.as_ref() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                let config = config.clone();
+                drop(pending);
+                revert(backend.as_deref(), &config);
+                return;
+            }
+
+            pending = CONFIRMED
+                .wait_timeout(pending, left)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    });
+}
+
+/// Confirms the pending configuration, reporting whether there was one.
+pub fn confirm() -> bool {
+    let Some(config) = pending().take() else {
+        return false;
+    };
+    CONFIRMED.notify_all();
+
+    log::info!(General, "configuration confirmed");
+    history::record(&config, true);
+    true
+}
+
+fn revert(backend: Option<&(dyn Backend + Send)>, config: &history::Config) {
+    log::warn!(General, "configuration not confirmed in time, revert");
+
+    match history::restore_last_good(Source::Revert) {
+        Ok(true) => {
+            if let Some(backend) = backend {
+                withdraw(backend, config);
+            }
+            shutdown::revert();
+        }
+        Ok(false) => log::error!(
+            General,
+            "no known-good configuration to revert to, keep the current one"
+        ),
+        Err(e) => log::error!(General, "revert: {}", e),
+    }
+}
+
+/// Removes the routes and rules a configuration added. Those of the
+/// reverted configuration are added again right after the restart.
+/// Unmanaged entries belong to someone else by now, they stay.
+fn withdraw(backend: &dyn Backend, config: &history::Config) {
+    let routes = config
+        .routes
+        .parse::<Routes>()
+        .map(|routes| routes.routes)
+        .unwrap_or_default();
+    let rules = config
+        .rules
+        .parse::<Rules>()
+        .map(|rules| rules.rules)
+        .unwrap_or_default();
+
+    // The entries by network namespace, `None` for rtd's own.
+    let mut namespaces: BTreeMap<Option<String>, (Vec<Route>, Vec<Rule>)> = BTreeMap::new();
+    for route in routes {
+        namespaces
+            .entry(route.netns.clone())
+            .or_default()
+            .0
+            .push(route);
+    }
+    for rule in rules {
+        namespaces
+            .entry(rule.netns.clone())
+            .or_default()
+            .1
+            .push(rule);
+    }
+
+    let mut removed = 0;
+    for (name, (routes, rules)) in namespaces {
+        let Some(name) = name else {
+            removed += remove(backend, routes, rules);
+            continue;
+        };
+
+        // Requests have to be made from inside the namespace.
+        let res = backend.connect().and_then(|backend| {
+            netns::run(&name, move || {
+                backend
+                    .connect()
+                    .map(|backend| remove(&*backend, routes, rules))
+            })?
+        });
+        match res {
+            Ok(n) => removed += n,
+            Err(e) => log::error!(Netlink, "revert in netns {}: {}", name, e),
+        }
+    }
+
+    log::info!(General, "removed {} unconfirmed routes and rules", removed);
+}
+
+/// Removes the given routes and rules, returning how many of them existed.
+/// Entries are expanded the way they were applied: placeholders are resolved,
+/// link patterns and hostnames stand for their copies and routes through
+/// several links are balance groups of their own.
+fn remove(backend: &dyn Backend, routes: Vec<Route>, rules: Vec<Rule>) -> usize {
+    let links = rtnl::link_names()
+        .inspect_err(|e| log::error!(Netlink, "list links: {}", e))
+        .unwrap_or_default();
+
+    let mut removed = 0;
+    let mut members = Vec::new();
+    for route in routes {
+        if route.delete || !route.managed {
+            continue;
+        }
+
+        // Placeholders that can't be resolved would stand for the wrong route.
+        let route = match &route.template {
+            Some(_) => match reload::current_route(&route) {
+                Some(route) => route,
+                None => continue,
+            },
+            None => route,
+        };
+
+        if route.has_link_pattern() {
+            match wildcard::remove(backend, &route, &links) {
+                Ok(false) => {}
+                res => removed += usize::from(del(&route, res.map(drop))),
+            }
+            continue;
+        }
+
+        // The multipath route goes as a whole, whatever its members.
+        if route.balance.is_some() || route.has_several_links() {
+            members.extend(route.split_links());
+            continue;
+        }
+
+        let copies = match route.resolve_host() {
+            Ok(copies) => copies,
+            Err(e) => {
+                log::error!(General, "resolve {}: {}", route.host.unwrap_or_default(), e);
+                continue;
+            }
+        };
+        for copy in copies {
+            removed += usize::from(del(&copy, backend.del_route(&copy.def)));
+            if let Some(mirror) = copy.mirror_def() {
+                let _ = backend.del_route(&mirror);
+            }
+        }
+    }
+    for balance in Balance::group(members) {
+        removed += usize::from(del(&balance, backend.del_balance(&balance)));
+    }
+
+    for rule in rules {
+        if rule.delete || !rule.managed {
+            continue;
+        }
+
+        let rule = match &rule.template {
+            Some(_) => match reload::current_rule(&rule) {
+                Some(rule) => rule,
+                None => continue,
+            },
+            None => rule,
+        };
+
+        removed += usize::from(del(&rule, backend.del_rule(&rule)));
+    }
+
+    removed
+}
+
+/// Reports the removal of an entry, telling whether it was removed.
+fn del(entry: &dyn fmt::Display, res: Result<(), SetupError>) -> bool {
+    removal(Source::Revert, entry, res) == status::State::Removed
+}
+
+fn pending() -> std::sync::MutexGuard<'static, Option<history::Config>> {
+    PENDING.lock().unwrap_or_else(|e| e.into_inner())
+}
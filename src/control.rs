@@ -0,0 +1,160 @@
+//! Control socket for local clients such as the web UI.
+//!
+//! Clients connect to `/run/rtd.sock` and send a single request line:
+//!
+//! * `subscribe` streams events as JSON lines: state changes of configured
+//!   entries and, with `--monitor`, routing changes of other processes.
+//! * `restart` makes rtd restart to apply the configuration files again.
+//! * `confirm` confirms a configuration applied with `--confirm`.
+//! * `panic [wan <link>]` flushes the tables and installs plain default
+//!   routes, see `rescue`.
+
+use crate::audit::{self, Source};
+use crate::{confirm, log, rescue, shutdown};
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+const SOCKET_PATH: &str = "/run/rtd.sock";
+/// How long a request or an event may take to transfer before
+/// the client is dropped, slow clients must not hold up the others.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+static EVENTS: OnceLock<Sender<String>> = OnceLock::new();
+static SUBSCRIBERS: Mutex<Vec<UnixStream>> = Mutex::new(Vec::new());
+
+/// Starts serving the control socket in the background.
+pub fn spawn() {
+    // A previous instance may have left its socket behind.
+    match fs::remove_file(SOCKET_PATH) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => log::warn!(General, "remove stale control socket: {}", e),
+    }
+
+    let listener = match UnixListener::bind(SOCKET_PATH) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!(General, "bind control socket ({}): {}", SOCKET_PATH, e);
+            return;
+        }
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let _ = EVENTS.set(tx);
+    thread::spawn(move || dispatch(rx));
+
+    log::debug!(General, "serve control socket at {}", SOCKET_PATH);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let res = stream.and_then(handle);
+            if let Err(e) = res {
+                log::debug!(General, "control socket: {}", e);
+            }
+        }
+    });
+}
+
+/// Sends an event to all subscribers. Does nothing if the socket isn't served.
+pub fn publish(event: serde_json::Value) {
+    if let Some(events) = EVENTS.get() {
+        let _ = events.send(event.to_string() + "\n");
+    }
+}
+
+fn handle(mut stream: UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+
+    match request.trim() {
+        "subscribe" => {
+            SUBSCRIBERS
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(stream);
+        }
+        "restart" => {
+            writeln!(stream, "{}", serde_json::json!({ "ok": true }))?;
+            audit::record(
+                Source::Client { request: "restart" },
+                "restart",
+                &"rtd",
+                Ok(()),
+            );
+            shutdown::restart();
+        }
+        "confirm" => {
+            let reply = if confirm::confirm() {
+                serde_json::json!({ "ok": true })
+            } else {
+                serde_json::json!({ "error": "nothing to confirm" })
+            };
+            writeln!(stream, "{}", reply)?;
+        }
+        op if op.split_whitespace().next() == Some("panic") => {
+            let args: Vec<String> = op.split_whitespace().skip(1).map(String::from).collect();
+            let reply = match rescue::panic(Source::Client { request: "panic" }, &args) {
+                Ok(()) => serde_json::json!({ "ok": true }),
+                Err(e) => serde_json::json!({ "error": e.to_string() }),
+            };
+            writeln!(stream, "{}", reply)?;
+        }
+        op => {
+            let reply = serde_json::json!({
+                "error": format!("invalid request {} (want \"subscribe\", \"restart\", \"confirm\" or \"panic\")", op),
+            });
+            writeln!(stream, "{}", reply)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends a request to the running daemon and waits for its reply.
+pub fn request(op: &str) -> io::Result<()> {
+    let mut stream = UnixStream::connect(SOCKET_PATH)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    writeln!(stream, "{}", op)?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+
+    let reply: serde_json::Value = serde_json::from_str(&reply)?;
+    match reply["error"].as_str() {
+        Some(e) => Err(io::Error::other(e.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Asks a running daemon to restart with the configuration files as they are now.
+pub fn reapply() {
+    match request("restart") {
+        Ok(()) => log::info!(General, "rtd restarts to apply it"),
+        Err(e) => log::warn!(
+            General,
+            "can't reach rtd ({}), it applies the configuration when it starts",
+            e
+        ),
+    }
+}
+
+fn dispatch(events: Receiver<String>) {
+    for event in events {
+        // Subscribers that went away or can't keep up are dropped.
+        SUBSCRIBERS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain_mut(|stream| stream.write_all(event.as_bytes()).is_ok());
+    }
+}
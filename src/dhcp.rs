@@ -0,0 +1,152 @@
+//! Classless static routes of DHCPv4 leases (`classless` lines),
+//! which are kept in sync with the lease files.
+//!
+//! The lease files are polled for modifications. Routes that are no longer
+//! part of the lease are deleted, new ones are added.
+
+use crate::audit::Source;
+use crate::{guard, log, status};
+use crate::{outcome, report};
+
+use rsdsl_rtd::{Backend, Classless, Route, SetupError};
+
+use std::collections::HashSet;
+use std::fs;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Installs (or removes) the routes of a lease,
+/// returning the routes that are now installed.
+pub fn apply(backend: &dyn Backend, source: Source, classless: &Classless) -> Vec<Route> {
+    let routes = match classless.load() {
+        Ok(routes) => routes,
+        Err(e) => {
+            log::error!(Parser, "read lease {}: {}", classless.path.display(), e);
+            status::set(source, status::State::Failed(e.to_string()));
+            return Vec::new();
+        }
+    };
+
+    if classless.delete {
+        for route in &routes {
+            let _ = report(source, "del", route, backend.del_route(&route.def));
+        }
+
+        status::set(source, status::State::Removed);
+        return Vec::new();
+    }
+
+    if routes.is_empty() {
+        log::info!(
+            General,
+            "no classless static routes in {} yet",
+            classless.path.display()
+        );
+    }
+
+    let (res, installed) = install(backend, source, &[], routes);
+    status::set(source, outcome(res, status::State::Applied));
+    installed
+}
+
+/// Re-applies the given leases whenever their files change.
+pub fn watch(backend: &dyn Backend, leases: Vec<(Source, Classless, Vec<Route>)>) {
+    let mut leases: Vec<_> = leases
+        .into_iter()
+        .filter(|(_, classless, _)| !classless.delete)
+        .map(|(source, classless, installed)| {
+            let modified = modified(&classless);
+            (source, classless, installed, modified)
+        })
+        .collect();
+
+    if leases.is_empty() {
+        return;
+    }
+
+    let backend = match backend.connect() {
+        Ok(backend) => backend,
+        Err(e) => {
+            log::error!(Netlink, "connect for leases: {}", e);
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            let mut changed = false;
+
+            for (source, classless, installed, last_modified) in &mut leases {
+                let current = modified(classless);
+                if current == *last_modified {
+                    continue;
+                }
+                *last_modified = current;
+
+                log::info!(
+                    General,
+                    "lease {} changed, reload",
+                    classless.path.display()
+                );
+
+                let routes = match classless.load() {
+                    Ok(routes) => routes,
+                    Err(e) => {
+                        // Keep the previous routes rather than dropping them all.
+                        log::error!(Parser, "read lease {}: {}", classless.path.display(), e);
+                        status::set(*source, status::State::Failed(e.to_string()));
+                        continue;
+                    }
+                };
+
+                let (res, routes) = install(&*backend, *source, installed, routes);
+                status::set(*source, outcome(res, status::State::Applied));
+
+                *installed = routes;
+                changed = true;
+            }
+
+            if changed {
+                status::applied();
+            }
+        }
+    });
+}
+
+/// Replaces the routes of a previous lease with those of the current one,
+/// returning the routes that are now installed.
+fn install(
+    backend: &dyn Backend,
+    source: Source,
+    old: &[Route],
+    new: Vec<Route>,
+) -> (Result<(), SetupError>, Vec<Route>) {
+    // Routes conflicting with protected prefixes are left out like configured ones.
+    let new: Vec<Route> = new
+        .into_iter()
+        .filter(|route| guard::allow_route(source, route))
+        .collect();
+
+    let keep: HashSet<&Route> = old.iter().filter(|route| new.contains(route)).collect();
+
+    for route in old.iter().filter(|route| !keep.contains(route)) {
+        let _ = report(source, "del", route, backend.del_route(&route.def));
+    }
+
+    let mut res = Ok(());
+    for route in new.iter().filter(|route| !keep.contains(route)) {
+        res = res.and(report(source, "add", route, backend.add_route(&route.def)));
+    }
+
+    (res, new)
+}
+
+fn modified(classless: &Classless) -> Option<SystemTime> {
+    fs::metadata(&classless.path)
+        .and_then(|m| m.modified())
+        .ok()
+}
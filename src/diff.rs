@@ -0,0 +1,327 @@
+//! `diff routes|rules|neighbors [<old>] <new>`: compares two versions
+//! of a configuration file entry by entry, ignoring order and formatting.
+//! Without `<old>` the configuration rtd applied most recently is used.
+//!
+//! Entries are matched by what identifies them to the kernel,
+//! e.g. the destination, table and metric of a route. A matched entry
+//! that differs otherwise, e.g. in its gateway, counts as changed.
+
+use crate::term::{self, Color, Paint};
+use crate::{history, tables};
+
+use rsdsl_rtd::{Neighbors, Route, Routes, Rule, Rules};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+
+#[derive(Debug)]
+pub enum DiffError {
+    InvalidArg(String),
+    InvalidFile(String),
+    NoFile,
+    NoHistory,
+    NoPath,
+    Parse(String, String),
+    Read(String, io::Error),
+    ReadHistory(io::Error),
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidArg(a) => write!(f, "invalid argument {} (want [<old>] <new>)", a)?,
+            Self::InvalidFile(file) => write!(
+                f,
+                "invalid file {} (want \"routes\", \"rules\" or \"neighbors\")",
+                file
+            )?,
+            Self::NoFile => write!(
+                f,
+                "missing file (want \"routes\", \"rules\" or \"neighbors\")"
+            )?,
+            Self::NoHistory => write!(f, "no applied configuration to compare with")?,
+            Self::NoPath => write!(f, "missing path (want [<old>] <new>)")?,
+            Self::Parse(path, e) => write!(f, "parse {}: {}", path, e)?,
+            Self::Read(path, e) => write!(f, "read {}: {}", path, e)?,
+            Self::ReadHistory(e) => write!(f, "read history: {}", e)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for DiffError {}
+
+/// The kinds of configuration files.
+#[derive(Clone, Copy, Debug)]
+enum File {
+    Routes,
+    Rules,
+    Neighbors,
+}
+
+/// An entry of a configuration file.
+#[derive(Debug)]
+struct Item {
+    /// What identifies the entry to the kernel.
+    key: String,
+    label: String,
+    line: usize,
+}
+
+impl Item {
+    fn new(key: impl Into<String>, label: impl Into<String>, line: usize) -> Self {
+        Self {
+            key: key.into(),
+            label: label.into(),
+            line,
+        }
+    }
+
+    /// Creates an item that is identified by all of it,
+    /// for entries that can't change without becoming another one.
+    fn whole(entry: &impl fmt::Display, line: usize) -> Self {
+        Self::new(entry.to_string(), entry.to_string(), line)
+    }
+}
+
+pub fn diff(args: &[String]) -> Result<(), DiffError> {
+    let file = match args.first().map(String::as_str) {
+        Some("routes") => File::Routes,
+        Some("rules") => File::Rules,
+        Some("neighbors") => File::Neighbors,
+        Some(file) => return Err(DiffError::InvalidFile(file.to_string())),
+        None => return Err(DiffError::NoFile),
+    };
+
+    // Table names have to be known before parsing.
+    tables::load();
+
+    let (old_name, old, new_name, new) = match &args[1..] {
+        [] => return Err(DiffError::NoPath),
+        [new_path] => {
+            let new = read(new_path)?;
+            let config = history::last_applied()
+                .map_err(DiffError::ReadHistory)?
+                .ok_or(DiffError::NoHistory)?;
+            let old = match file {
+                File::Routes => config.routes,
+                File::Rules => config.rules,
+                File::Neighbors => config.neighbors.unwrap_or_default(),
+            };
+
+            ("applied".to_string(), old, new_path.clone(), new)
+        }
+        [old, new] => (old.clone(), read(old)?, new.clone(), read(new)?),
+        [_, _, extra, ..] => return Err(DiffError::InvalidArg(extra.clone())),
+    };
+
+    let old = items(file, &old).map_err(|e| DiffError::Parse(old_name.clone(), e))?;
+    let new = items(file, &new).map_err(|e| DiffError::Parse(new_name.clone(), e))?;
+
+    print_diff(&old_name, old, &new_name, new);
+    Ok(())
+}
+
+fn read(path: &str) -> Result<String, DiffError> {
+    fs::read_to_string(path).map_err(|e| DiffError::Read(path.to_string(), e))
+}
+
+/// Parses a configuration file into its entries.
+fn items(file: File, s: &str) -> Result<Vec<Item>, String> {
+    let mut items = Vec::new();
+    match file {
+        File::Routes => {
+            let routes = s.parse::<Routes>().map_err(|e| e.to_string())?;
+
+            items.extend(routes.routes.iter().map(|route| {
+                Item::new(
+                    route_key(route),
+                    label(route.delete, &route.label()),
+                    route.line,
+                )
+            }));
+            items.extend(routes.vrfs.iter().map(|vrf| {
+                Item::new(
+                    format!("vrf {}", vrf.name),
+                    label(vrf.delete, vrf),
+                    vrf.line,
+                )
+            }));
+            items.extend(routes.sysctls.iter().map(|sysctl| {
+                Item::new(
+                    format!("sysctl {}", sysctl.link),
+                    sysctl.to_string(),
+                    sysctl.line,
+                )
+            }));
+            items.extend(
+                routes
+                    .prefix_lists
+                    .iter()
+                    .map(|list| Item::whole(list, list.line)),
+            );
+            items.extend(
+                routes
+                    .bogons
+                    .iter()
+                    .map(|bogons| Item::whole(bogons, bogons.line)),
+            );
+            items.extend(
+                routes
+                    .mroutes
+                    .iter()
+                    .map(|mroute| Item::whole(mroute, mroute.line)),
+            );
+            items.extend(
+                routes
+                    .isolates
+                    .iter()
+                    .map(|isolate| Item::whole(isolate, isolate.line)),
+            );
+            items.extend(
+                routes
+                    .bypasses
+                    .iter()
+                    .map(|bypass| Item::whole(bypass, bypass.line)),
+            );
+            items.extend(
+                routes
+                    .link_metrics
+                    .iter()
+                    .map(|metrics| Item::whole(metrics, metrics.line)),
+            );
+            items.extend(routes.classless.iter().map(|classless| {
+                Item::new(
+                    format!("classless {}", classless.path.display()),
+                    label(classless.delete, classless),
+                    classless.line,
+                )
+            }));
+        }
+        File::Rules => {
+            let rules = s.parse::<Rules>().map_err(|e| e.to_string())?;
+
+            items.extend(rules.rules.iter().map(|rule| {
+                Item::new(rule_key(rule), label(rule.delete, &rule.label()), rule.line)
+            }));
+            items.extend(rules.kernel_rules.iter().map(|kernel_rule| {
+                let key = format!("kernel {:?} {}", kernel_rule.version, kernel_rule.table);
+                Item::new(key, kernel_rule.to_string(), kernel_rule.line)
+            }));
+        }
+        File::Neighbors => {
+            let neighbors = s.parse::<Neighbors>().map_err(|e| e.to_string())?;
+
+            items.extend(neighbors.neighbors.iter().map(|neighbor| {
+                let key = format!(
+                    "{} {} {} {}",
+                    neighbor.delete, neighbor.proxy, neighbor.link, neighbor.addr
+                );
+                Item::new(
+                    key,
+                    label(neighbor.delete, &neighbor.label()),
+                    neighbor.line,
+                )
+            }));
+        }
+    }
+
+    Ok(items)
+}
+
+fn label(delete: bool, entry: &dyn fmt::Display) -> String {
+    match delete {
+        true => format!("del {}", entry),
+        false => entry.to_string(),
+    }
+}
+
+/// Identifies a route by its destination, table and metric. Members of
+/// balance groups share those, they are told apart by their nexthop.
+fn route_key(route: &Route) -> String {
+    let def = &route.def;
+    let dst = match &route.host {
+        Some(host) => host.clone(),
+        None => def.dst().to_string(),
+    };
+
+    let mut key = format!(
+        "{} {} {}/{} table {} metric {:?} netns {:?}",
+        route.delete,
+        if def.dst().is_ipv4() { 4 } else { 6 },
+        dst,
+        def.prefix_len(),
+        def.table(),
+        def.metric(),
+        route.netns
+    );
+    if route.balance.is_some() {
+        key.push_str(&format!(" via {:?} dev {}", def.rtr(), def.link()));
+    }
+
+    key
+}
+
+/// Identifies a rule by the packets it matches, i.e. everything but its action.
+fn rule_key(rule: &Rule) -> String {
+    format!(
+        "{} {:?} {} {:?} {:?} {:?} netns {:?}",
+        rule.delete, rule.version, rule.invert, rule.fwmark, rule.dst, rule.src, rule.netns
+    )
+}
+
+fn print_diff(old_name: &str, old: Vec<Item>, new_name: &str, new: Vec<Item>) {
+    // Entries are compared by their description, which doesn't depend on formatting.
+    let mut keys: BTreeMap<String, (Vec<Item>, Vec<Item>)> = BTreeMap::new();
+    for item in old {
+        keys.entry(item.key.clone()).or_default().0.push(item);
+    }
+    for item in new {
+        keys.entry(item.key.clone()).or_default().1.push(item);
+    }
+
+    let (mut added, mut removed, mut changed) = (Vec::new(), Vec::new(), Vec::new());
+    for (_, (mut old, mut new)) in keys {
+        old.retain(|item| {
+            let same = new.iter().position(|other| other.label == item.label);
+            if let Some(i) = same {
+                new.remove(i);
+            }
+
+            same.is_none()
+        });
+
+        match (old.len(), new.len()) {
+            (1, 1) => changed.push((old.remove(0), new.remove(0))),
+            _ => {
+                removed.extend(old);
+                added.extend(new);
+            }
+        }
+    }
+
+    let color = |color| term::stdout().then_some(color);
+    for item in &removed {
+        let line = format!("- {} ({}:{})", item.label, old_name, item.line);
+        println!("{}", Paint(color(Color::Red), line));
+    }
+    for item in &added {
+        let line = format!("+ {} ({}:{})", item.label, new_name, item.line);
+        println!("{}", Paint(color(Color::Green), line));
+    }
+    for (old, new) in &changed {
+        let line = format!("~ {} ({}:{})", old.label, old_name, old.line);
+        println!("{}", Paint(color(Color::Yellow), line));
+        println!("  {} ({}:{})", new.label, new_name, new.line);
+    }
+
+    println!(
+        "{} added, {} removed, {} changed",
+        added.len(),
+        removed.len(),
+        changed.len()
+    );
+}
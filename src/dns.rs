@@ -0,0 +1,144 @@
+//! Routes to DNS names.
+//!
+//! A hostname resolves to any number of addresses that change over time,
+//! e.g. those of a SaaS endpoint. It is resolved when the configuration is applied
+//! and again periodically, adding and removing host routes as the answers change.
+
+use crate::audit::Source;
+use crate::{log, status};
+use crate::{outcome, replace_mirror, report};
+
+use rsdsl_rtd::{Backend, Route};
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(300);
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A route to a hostname and the host routes currently installed for it.
+#[derive(Debug)]
+pub struct Entry {
+    source: Source,
+    route: Route,
+    installed: Vec<Route>,
+    next: Instant,
+}
+
+impl Entry {
+    /// Resolves the hostname and installs a route to each of its addresses.
+    pub fn apply(backend: &dyn Backend, source: Source, route: Route) -> Self {
+        let mut entry = Self {
+            source,
+            route,
+            installed: Vec::new(),
+            next: Instant::now(),
+        };
+        entry.update(backend);
+
+        entry
+    }
+
+    /// Re-resolves the hostname, replacing the routes to addresses that are gone.
+    /// The routes are left alone if resolution fails, the outage may be temporary.
+    fn update(&mut self, backend: &dyn Backend) {
+        let host = self.route.host.as_deref().unwrap_or_default();
+
+        let routes = match self.route.resolve_host() {
+            Ok(routes) => routes,
+            Err(e) => {
+                log::error!(General, "resolve {}: {}", host, e);
+                status::set(self.source, status::State::Failed(e.to_string()));
+                self.next = Instant::now() + RETRY_INTERVAL;
+                return;
+            }
+        };
+        self.next = Instant::now() + RESOLVE_INTERVAL;
+
+        let is_installed = |route: &Route| {
+            self.installed
+                .iter()
+                .any(|installed| installed.def.dst() == route.def.dst())
+        };
+
+        let mut res = Ok(());
+        for route in routes.iter().filter(|route| !is_installed(route)) {
+            let _ = backend.del_route(&route.def);
+            res = res.and(report(self.source, "add", route, route.add(backend)));
+            res = res.and(replace_mirror(backend, self.source, route, route));
+        }
+        for route in &self.installed {
+            if !routes.iter().any(|r| r.def.dst() == route.def.dst()) {
+                let _ = report(self.source, "del", route, backend.del_route(&route.def));
+                if let Some(mirror) = route.mirror_def() {
+                    let _ = backend.del_route(&mirror);
+                }
+            }
+        }
+
+        if routes.is_empty() {
+            log::warn!(General, "{} doesn't resolve to any addresses", host);
+            status::set(
+                self.source,
+                status::State::Failed(format!("{} doesn't resolve to any addresses", host)),
+            );
+        } else {
+            status::set(self.source, outcome(res, status::State::Applied));
+        }
+
+        self.installed = routes;
+    }
+}
+
+/// Removes the routes to the addresses a hostname currently resolves to.
+pub fn remove(backend: &dyn Backend, source: Source, route: &Route) {
+    let host = route.host.as_deref().unwrap_or_default();
+
+    match route.resolve_host() {
+        Ok(routes) => {
+            for route in routes {
+                let _ = report(source, "del", &route, backend.del_route(&route.def));
+                if let Some(mirror) = route.mirror_def() {
+                    let _ = report(source, "del", &mirror, backend.del_route(&mirror));
+                }
+            }
+            status::set(source, status::State::Removed);
+        }
+        Err(e) => {
+            log::error!(General, "resolve {}: {}", host, e);
+            status::set(source, status::State::Failed(e.to_string()));
+        }
+    }
+}
+
+/// Periodically re-resolves the hostnames of the given routes, retrying
+/// failed lookups more frequently.
+pub fn watch(backend: &dyn Backend, mut entries: Vec<Entry>) {
+    if entries.is_empty() {
+        return;
+    }
+
+    let backend = match backend.connect() {
+        Ok(backend) => backend,
+        Err(e) => {
+            log::error!(Netlink, "connect for hostname routes: {}", e);
+            return;
+        }
+    };
+
+    thread::spawn(move || loop {
+        let next = entries
+            .iter()
+            .map(|entry| entry.next)
+            .min()
+            .unwrap_or_else(Instant::now);
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+
+        let now = Instant::now();
+        for entry in entries.iter_mut().filter(|entry| entry.next <= now) {
+            entry.update(&*backend);
+        }
+
+        status::applied();
+    });
+}
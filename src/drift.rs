@@ -0,0 +1,46 @@
+//! What rtd does about drift, i.e. configured entries that went missing
+//! from the kernel or deleted ones that reappeared (`drift` attribute).
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// The reaction to drift noticed while reconciling.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Drift {
+    /// Leave the entry alone without telling anyone.
+    Ignore,
+    /// Log the drift once but leave the entry alone.
+    Log,
+    /// Restore the entry as configured.
+    #[default]
+    Repair,
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ignore => write!(f, "ignore"),
+            Self::Log => write!(f, "log"),
+            Self::Repair => write!(f, "repair"),
+        }
+    }
+}
+
+impl FromStr for Drift {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(Self::Ignore),
+            "log" => Ok(Self::Log),
+            "repair" => Ok(Self::Repair),
+            _ => Err(format!(
+                "invalid drift policy {} (want \"ignore\", \"log\" or \"repair\")",
+                s
+            )),
+        }
+    }
+}
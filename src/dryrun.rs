@@ -0,0 +1,85 @@
+//! `plan [<routes> <rules>]`: lists the operations applying
+//! the configuration would take right now, without taking them.
+//! The configuration files rtd applies are used if no paths are given.
+
+use crate::term::{self, Color, Paint};
+use crate::{installed, tables};
+use crate::{ROUTES_PATH, RULES_PATH};
+
+use rsdsl_rtd::{Op, Routes, Rules, SetupError};
+
+use std::fmt;
+use std::fs;
+use std::io;
+
+use rsdsl_netlinklib::blocking::Connection;
+
+#[derive(Debug)]
+pub enum PlanError {
+    InvalidArg(String),
+    Netlink(SetupError),
+    NoRules,
+    Parse(String, String),
+    Read(String, io::Error),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidArg(a) => write!(f, "invalid argument {} (want [<routes> <rules>])", a)?,
+            Self::Netlink(e) => write!(f, "dump kernel: {}", e)?,
+            Self::NoRules => write!(f, "missing rules path (want [<routes> <rules>])")?,
+            Self::Parse(path, e) => write!(f, "parse {}: {}", path, e)?,
+            Self::Read(path, e) => write!(f, "read {}: {}", path, e)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+pub fn plan(args: &[String]) -> Result<(), PlanError> {
+    let (routes_path, rules_path) = match args {
+        [] => (ROUTES_PATH, RULES_PATH),
+        [_] => return Err(PlanError::NoRules),
+        [routes, rules] => (routes.as_str(), rules.as_str()),
+        [_, _, extra, ..] => return Err(PlanError::InvalidArg(extra.clone())),
+    };
+
+    // Table names have to be known before parsing.
+    tables::load();
+
+    let routes: Routes = parse(routes_path)?;
+    let rules: Rules = parse(rules_path)?;
+
+    let conn = Connection::new()
+        .map_err(SetupError::from)
+        .map_err(PlanError::Netlink)?;
+    let installed = installed::dump(&conn);
+
+    let steps = rsdsl_rtd::plan(&routes, &rules, &installed);
+
+    let color = |color| term::stdout().then_some(color);
+    for step in &steps {
+        let (path, color) = match step.op {
+            Op::AddRoute(_) => (routes_path, color(Color::Green)),
+            Op::DelRoute(_) => (routes_path, color(Color::Red)),
+            Op::AddRule(_) => (rules_path, color(Color::Green)),
+            _ => (rules_path, color(Color::Red)),
+        };
+
+        let line = format!("{} ({}:{})", step, path, step.line);
+        println!("{}", Paint(color, line));
+    }
+
+    println!("{} operations", steps.len());
+    Ok(())
+}
+
+/// Reads and parses a configuration file.
+fn parse<T: std::str::FromStr<Err = E>, E: fmt::Display>(path: &str) -> Result<T, PlanError> {
+    let s = fs::read_to_string(path).map_err(|e| PlanError::Read(path.to_string(), e))?;
+    s.parse()
+        .map_err(|e: E| PlanError::Parse(path.to_string(), e.to_string()))
+}
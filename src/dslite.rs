@@ -0,0 +1,77 @@
+//! IPv4 default route via the DS-Lite tunnel maintained by rsdsl's netlinkd.
+//!
+//! The tunnel device is torn down and recreated whenever the AFTR
+//! or the WAN connection changes, taking its routes with it.
+//! `watch` notices this and re-adds the affected routes.
+
+use crate::audit::Source;
+use crate::{log, status};
+use crate::{outcome, replace_mirror, report};
+
+use rsdsl_rtd::{rtnl, Backend, Route, DSLITE_LINK as LINK};
+
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Re-applies the given DS-Lite routes whenever the tunnel is recreated.
+pub fn watch(backend: &dyn Backend, routes: Vec<(Source, Route)>) {
+    if routes.is_empty() {
+        return;
+    }
+
+    let backend = match backend.connect() {
+        Ok(backend) => backend,
+        Err(e) => {
+            log::error!(Netlink, "connect for DS-Lite: {}", e);
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        let mut index = rtnl::link_index(LINK).ok();
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            let current = rtnl::link_index(LINK).ok();
+            if current == index {
+                continue;
+            }
+            index = current;
+
+            for (source, _) in &routes {
+                status::set(*source, status::State::WaitingForLink(LINK.to_string()));
+            }
+
+            if index.is_none() {
+                log::info!(Netlink, "DS-Lite tunnel {} removed", LINK);
+                continue;
+            }
+
+            log::info!(Netlink, "DS-Lite tunnel {} recreated, wait for it", LINK);
+            if let Err(e) = backend
+                .link_wait_exists(LINK)
+                .and_then(|_| backend.link_wait_up(LINK))
+            {
+                log::error!(Netlink, "wait for DS-Lite tunnel {}: {}", LINK, e);
+                continue;
+            }
+
+            for (source, route) in &routes {
+                // Routes whose conditions don't hold are installed later.
+                if !route.is_active() {
+                    status::set(*source, status::State::Inactive(route.condition()));
+                    continue;
+                }
+
+                let res = report(*source, "add", route, route.add(&*backend))
+                    .and(replace_mirror(&*backend, *source, route, route));
+                status::set(*source, outcome(res, status::State::Applied));
+            }
+
+            status::applied();
+        }
+    });
+}
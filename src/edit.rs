@@ -0,0 +1,110 @@
+//! `edit routes|rules|neighbors [reload]`: edits a configuration file
+//! in `$EDITOR` and installs it only if it is valid.
+//!
+//! The file is edited as a copy, the live one is replaced atomically
+//! once the parser accepts the result. With `reload`, rtd restarts
+//! to apply it right away.
+
+use crate::lock::{self, WriteError};
+use crate::{control, log};
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process::Command;
+
+const DEFAULT_EDITOR: &str = "vi";
+
+pub fn edit(args: &[String]) -> Result<(), WriteError> {
+    let (file, reload) = match args {
+        [] => return Err(WriteError::NoFile),
+        [file] => (file, false),
+        [file, reload] if reload == "reload" => (file, true),
+        [_, reload, extra, ..] if reload == "reload" => {
+            return Err(WriteError::InvalidArg(extra.clone(), "<file> [reload]"))
+        }
+        [_, extra, ..] => return Err(WriteError::InvalidArg(extra.clone(), "<file> [reload]")),
+    };
+
+    let path = lock::config_path(file)?;
+    let original = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(WriteError::ReadConfig(e)),
+    };
+
+    // The name is predictable, never follow whatever is in its place already.
+    let copy = std::env::temp_dir().join(format!("rtd-edit-{}-{}", std::process::id(), file));
+    let mut f = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&copy)
+        .map_err(WriteError::Write)?;
+
+    let res = f
+        .write_all(original.as_bytes())
+        .map_err(WriteError::Write)
+        .and_then(|_| edit_copy(&copy, path, &original));
+    let _ = fs::remove_file(&copy);
+    let Some(content) = res? else {
+        log::info!(General, "no changes");
+        return Ok(());
+    };
+
+    lock::install(path, &content)?;
+
+    if reload {
+        control::reapply();
+    }
+
+    Ok(())
+}
+
+/// Lets the user edit the copy until it is valid or they give up.
+/// Returns `None` if nothing changed.
+fn edit_copy(copy: &Path, path: &str, original: &str) -> Result<Option<String>, WriteError> {
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| DEFAULT_EDITOR.to_string());
+
+    loop {
+        // Through the shell so that the variable may contain arguments.
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", editor))
+            .arg("sh")
+            .arg(copy)
+            .status()
+            .map_err(WriteError::Editor)?;
+        if !status.success() {
+            return Err(WriteError::EditorFailed(status));
+        }
+
+        let content = fs::read_to_string(copy).map_err(WriteError::ReadConfig)?;
+        if content == original {
+            return Ok(None);
+        }
+
+        match lock::validate(path, &content) {
+            Ok(()) => return Ok(Some(content)),
+            Err(e) => {
+                log::error!(Parser, "{}", e);
+                if !ask("edit again? [Y/n] ") {
+                    return Err(WriteError::Aborted);
+                }
+            }
+        }
+    }
+}
+
+/// Asks a yes/no question, defaulting to yes.
+fn ask(question: &str) -> bool {
+    eprint!("{}", question);
+    let _ = io::stderr().flush();
+
+    let mut answer = String::new();
+    match io::stdin().lock().read_line(&mut answer) {
+        Ok(0) | Err(_) => false,
+        Ok(_) => !answer.trim().eq_ignore_ascii_case("n"),
+    }
+}
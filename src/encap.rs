@@ -0,0 +1,139 @@
+//! Lightweight tunnel encapsulation of routes (`encap` attributes).
+//!
+//! The kernel adds the outer header itself, no tunnel device per peer is needed.
+//! The route has to go through a tunnel device in external (metadata) mode,
+//! e.g. `ip link add gre0 type gre external`, which takes the remaining
+//! parameters from the route. The `encap-id` is the key of such a device,
+//! e.g. the GRE key, and FOU ports come from the device's own encapsulation.
+
+use crate::rtnl;
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// The outer header of an encapsulated route.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncapType {
+    #[default]
+    Ip,
+    Ip6,
+}
+
+impl fmt::Display for EncapType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ip => write!(f, "ip"),
+            Self::Ip6 => write!(f, "ip6"),
+        }
+    }
+}
+
+impl FromStr for EncapType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ip" => Ok(Self::Ip),
+            "ip6" => Ok(Self::Ip6),
+            _ => Err(format!(
+                "invalid encapsulation {} (want \"ip\" or \"ip6\")",
+                s
+            )),
+        }
+    }
+}
+
+/// The encapsulation of a route. Unset fields are up to the tunnel device.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Encap {
+    pub ty: EncapType,
+    /// The tunnel key, e.g. the GRE key or VXLAN VNI.
+    pub id: Option<u64>,
+    pub src: Option<IpAddr>,
+    pub dst: Option<IpAddr>,
+    pub ttl: Option<u8>,
+}
+
+impl Encap {
+    /// Returns the address that doesn't match the type of the outer header, if any.
+    pub fn mismatched_addr(&self) -> Option<IpAddr> {
+        let ip6 = self.ty == EncapType::Ip6;
+        [self.src, self.dst]
+            .into_iter()
+            .flatten()
+            .find(|addr| addr.is_ipv6() != ip6)
+    }
+
+    /// Appends the encapsulation to an `RTM_NEWROUTE` request.
+    pub(crate) fn put(&self, req: &mut Vec<u8>) {
+        let ty = match self.ty {
+            EncapType::Ip => rtnl::LWTUNNEL_ENCAP_IP,
+            EncapType::Ip6 => rtnl::LWTUNNEL_ENCAP_IP6,
+        };
+        rtnl::put_attr(req, rtnl::RTA_ENCAP_TYPE, &ty.to_ne_bytes());
+
+        // The attributes of both types share their numbers.
+        let mut encap = Vec::new();
+        if let Some(id) = self.id {
+            rtnl::put_attr(&mut encap, rtnl::LWTUNNEL_IP_ID, &id.to_be_bytes());
+        }
+        if let Some(dst) = self.dst {
+            rtnl::put_addr(&mut encap, rtnl::LWTUNNEL_IP_DST, dst);
+        }
+        if let Some(src) = self.src {
+            rtnl::put_addr(&mut encap, rtnl::LWTUNNEL_IP_SRC, src);
+        }
+        if let Some(ttl) = self.ttl {
+            rtnl::put_attr(&mut encap, rtnl::LWTUNNEL_IP_TTL, &[ttl]);
+        }
+        rtnl::put_attr(req, rtnl::RTA_ENCAP | rtnl::NLA_F_NESTED, &encap);
+    }
+
+    /// Returns the arguments of ip(8) for the encapsulation.
+    pub(crate) fn ip_args(&self) -> Vec<String> {
+        let mut args = vec!["encap".to_string(), self.ty.to_string()];
+        if let Some(id) = self.id {
+            args.extend(["id".to_string(), id.to_string()]);
+        }
+        if let Some(dst) = self.dst {
+            args.extend(["dst".to_string(), dst.to_string()]);
+        }
+        if let Some(src) = self.src {
+            args.extend(["src".to_string(), src.to_string()]);
+        }
+        if let Some(ttl) = self.ttl {
+            let name = match self.ty {
+                EncapType::Ip => "ttl",
+                EncapType::Ip6 => "hoplimit",
+            };
+            args.extend([name.to_string(), ttl.to_string()]);
+        }
+
+        args
+    }
+}
+
+/// Formats the encapsulation as the attributes of a configuration line.
+impl fmt::Display for Encap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "encap {}", self.ty)?;
+        if let Some(id) = self.id {
+            write!(f, " encap-id {}", id)?;
+        }
+        if let Some(src) = self.src {
+            write!(f, " encap-src {}", src)?;
+        }
+        if let Some(dst) = self.dst {
+            write!(f, " encap-dst {}", dst)?;
+        }
+        if let Some(ttl) = self.ttl {
+            write!(f, " encap-ttl {}", ttl)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,410 @@
+//! `explain <line>`: describes in plain words what a line of the route
+//! or rule configuration does, followed by warnings about problems with it.
+//!
+//! The configuration files are consulted for the other half of policy
+//! routing, i.e. the rules looking up the table of a route
+//! and the routes of the table a rule looks up.
+
+use crate::term::{self, Color, Paint};
+use crate::{guard, tables};
+use crate::{ROUTES_PATH, RULES_PATH};
+
+use rsdsl_rtd::rtnl;
+use rsdsl_rtd::vars::{self, VarError, Vars};
+use rsdsl_rtd::{
+    Route, RouteParseError, Routes, Rule, RuleAction, RuleParseError, RuleVersion, Rules,
+};
+
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+
+#[derive(Debug)]
+pub enum ExplainError {
+    InvalidVersion(String),
+    NoLine,
+    ParseRoute(RouteParseError),
+    ParseRule(RuleParseError),
+    Var(VarError),
+}
+
+impl fmt::Display for ExplainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidVersion(v) => write!(
+                f,
+                "invalid version {} (want \"route4\", \"route6\", \"dslite\", \"rule\", \"rule4\" or \"rule6\")",
+                v
+            )?,
+            Self::NoLine => write!(f, "missing configuration line")?,
+            Self::ParseRoute(e) => write!(f, "parse route: {}", e)?,
+            Self::ParseRule(e) => write!(f, "parse rule: {}", e)?,
+            Self::Var(e) => write!(f, "variable: {}", e)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ExplainError {}
+
+/// The explanation of a line.
+#[derive(Debug, Default)]
+struct Explanation {
+    notes: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl Explanation {
+    fn note(&mut self, note: impl Into<String>) {
+        self.notes.push(note.into());
+    }
+
+    fn warn(&mut self, warning: impl Into<String>) {
+        self.warnings.push(warning.into());
+    }
+}
+
+pub fn explain(args: &[String]) -> Result<(), ExplainError> {
+    if args.is_empty() {
+        return Err(ExplainError::NoLine);
+    }
+    let line = args.join(" ");
+
+    // Table names and protected prefixes are needed for parsing and warnings.
+    let registry = tables::load();
+    let _ = guard::init(false);
+
+    let mut explanation = Explanation::default();
+
+    // Placeholders are explained with their current values if known.
+    let expanded = match vars::has_vars(&line) {
+        true => match vars::expand(&line, &Vars::load()) {
+            Ok(expanded) => {
+                explanation.note(format!("placeholders currently expand to: {}", expanded));
+                expanded
+            }
+            Err(e) => {
+                explanation.note(format!("placeholders are resolved at apply time ({})", e));
+                vars::expand(&line, &Vars::placeholders()).map_err(ExplainError::Var)?
+            }
+        },
+        false => line.clone(),
+    };
+
+    let version = expanded
+        .split_whitespace()
+        .next()
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+    match version.as_str() {
+        "route4" | "route6" | "dslite" => {
+            let route: Route = expanded.parse().map_err(ExplainError::ParseRoute)?;
+            explain_route(&mut explanation, &registry, &route);
+        }
+        "rule" | "rule4" | "rule6" => {
+            let rule: Rule = expanded.parse().map_err(ExplainError::ParseRule)?;
+            explain_rule(&mut explanation, &registry, &rule);
+        }
+        _ => return Err(ExplainError::InvalidVersion(version)),
+    }
+
+    println!("{}", line);
+    for note in explanation.notes {
+        println!("  {}", note);
+    }
+    let color = term::stdout().then_some(Color::Yellow);
+    for warning in explanation.warnings {
+        println!("  {} {}", Paint(color, "warning:"), warning);
+    }
+
+    Ok(())
+}
+
+fn explain_route(explanation: &mut Explanation, registry: &tables::Registry, route: &Route) {
+    let def = &route.def;
+    let dst = match &route.host {
+        Some(host) => format!("each address {} resolves to (re-resolved regularly)", host),
+        None => describe_prefix(def.dst(), def.prefix_len()),
+    };
+    match route.delete {
+        true => explanation.note(format!("removes the route to {}", dst)),
+        false => explanation.note(format!("adds a route to {}", dst)),
+    }
+
+    let nexthop = if route.dslite {
+        format!("through the DS-Lite tunnel {} once it is up", def.link())
+    } else if route.has_several_links() {
+        format!(
+            "directly out of links {} without gateways, spreading the traffic evenly across them",
+            def.link().replace(',', ", ")
+        )
+    } else if route.via_peer {
+        format!(
+            "to the remote end of point-to-point link {}, whatever its address",
+            def.link()
+        )
+    } else {
+        match def.rtr() {
+            Some(rtr) if def.on_link() => format!(
+                "to gateway {} on link {}, assumed to be reachable even outside its networks",
+                rtr,
+                def.link()
+            ),
+            Some(rtr) => format!("to gateway {} on link {}", rtr, def.link()),
+            None => format!(
+                "directly out of link {} without a gateway (connected network or point-to-point link)",
+                def.link()
+            ),
+        }
+    };
+    explanation.note(format!("sends the packets {}", nexthop));
+    if route.has_link_pattern() {
+        explanation.note(format!(
+            "{} is a pattern: installed on each matching link, including those appearing later, with the metric increased by one for each further link",
+            def.link()
+        ));
+    }
+
+    if route.src_auto {
+        explanation.note(format!(
+            "packets of the router itself come from the primary address of {}, following its changes",
+            def.link()
+        ));
+    } else if let Some(src) = route.src {
+        explanation.note(format!("packets of the router itself come from {}", src));
+    }
+
+    if let Some(metric) = def.metric() {
+        explanation.note(format!(
+            "metric {}: of several routes to the same destination the lowest metric wins",
+            metric
+        ));
+    }
+
+    let table = def.table();
+    explanation.note(format!("table: {}", describe_table(registry, table)));
+    if let Some(mirror) = route.mirror {
+        explanation.note(format!(
+            "copied to table {} to keep traffic flowing while it is replaced",
+            describe_table(registry, mirror)
+        ));
+    }
+
+    if let Some(netns) = &route.netns {
+        explanation.note(format!("installed in network namespace {}", netns));
+    }
+    if let Some(probe) = &route.probe {
+        explanation.note(format!(
+            "withdrawn while probe {} fails so that a backup route can take over",
+            probe
+        ));
+    }
+    if let Some(balance) = &route.balance {
+        explanation.note(format!(
+            "shares the traffic of balance group {} with the other members, weight {}",
+            balance, route.weight
+        ));
+    }
+    if route.is_conditional() {
+        explanation.note(format!("only installed while: {}", route.condition()));
+    }
+
+    // Other network namespaces have tables and rules of their own.
+    if route.netns.is_none() {
+        if table == rtnl::RT_TABLE_MAIN {
+            explanation.note(
+                "matches packets to the destination unless a more specific route or a rule sends them elsewhere",
+            );
+        } else {
+            let rules = configured_rules()
+                .into_iter()
+                .filter(|rule| !rule.delete && rule.action == RuleAction::ToTable)
+                .filter(|rule| rule.table == table && rule.netns.is_none())
+                .collect::<Vec<_>>();
+            if rules.is_empty() && !route.delete {
+                explanation.warn(format!(
+                    "no rule in {} looks up table {}, no traffic reaches the route",
+                    RULES_PATH, table
+                ));
+            } else if !rules.is_empty() {
+                explanation
+                    .note("matches packets to the destination these rules send to the table:");
+                for rule in rules {
+                    explanation.note(format!("  {}:{}: {}", RULES_PATH, rule.line, rule.label()));
+                }
+            }
+        }
+    }
+
+    if route.delete || route.netns.is_some() {
+        return;
+    }
+
+    warn_owner(explanation, registry, table);
+    if rtnl::link_index(def.link()).is_err() {
+        explanation.warn(format!(
+            "link {} doesn't exist, rtd waits for it",
+            def.link()
+        ));
+    } else if let Err(e) = def.check_gateway() {
+        explanation.warn(e.to_string());
+    }
+    if let Err(e) = guard::check_route(def) {
+        explanation.warn(format!(
+            "{}, refused unless rtd is started with \"--force\"",
+            e
+        ));
+    }
+}
+
+fn explain_rule(explanation: &mut Explanation, registry: &tables::Registry, rule: &Rule) {
+    let family = match rule.version {
+        RuleVersion::Both => "IPv4 and IPv6",
+        RuleVersion::Ipv4 => "IPv4",
+        RuleVersion::Ipv6 => "IPv6",
+    };
+    match rule.delete {
+        true => explanation.note(format!("removes the {} policy rule", family)),
+        false => explanation.note(format!("adds a policy rule for {} packets", family)),
+    }
+
+    let mut selectors = Vec::new();
+    if let Some(fwmark) = rule.fwmark {
+        selectors.push(format!("carrying firewall mark {:#x}", fwmark));
+    }
+    if let Some((addr, len)) = rule.dst {
+        selectors.push(format!("to {}", describe_prefix(addr, len)));
+    }
+    if let Some((addr, len)) = rule.src {
+        selectors.push(format!("from {}", describe_prefix(addr, len)));
+    }
+    let matches = match (selectors.is_empty(), rule.invert) {
+        (true, false) => "all packets".to_string(),
+        (true, true) => "no packets at all (inverted rule without selectors)".to_string(),
+        (false, false) => format!("packets {}", selectors.join(" and ")),
+        (false, true) => format!("all packets except those {}", selectors.join(" and ")),
+    };
+    explanation.note(format!("matches {}", matches));
+
+    let action = match rule.action {
+        RuleAction::ToTable => format!(
+            "looks them up in table {}, packets without a matching route there go on to the next rule",
+            describe_table(registry, rule.table)
+        ),
+        RuleAction::Blackhole => "drops them silently".to_string(),
+        RuleAction::Unreachable => "rejects them as unreachable (ICMP)".to_string(),
+        RuleAction::Prohibit => "rejects them as administratively prohibited (ICMP)".to_string(),
+        _ => "does nothing with them".to_string(),
+    };
+    explanation.note(format!("and {}", action));
+
+    if let Some(netns) = &rule.netns {
+        explanation.note(format!("installed in network namespace {}", netns));
+    }
+
+    let lookup = rule.action == RuleAction::ToTable
+        && !matches!(
+            rule.table,
+            rtnl::RT_TABLE_MAIN | rtnl::RT_TABLE_LOCAL | rtnl::RT_TABLE_DEFAULT
+        );
+    if lookup {
+        let routes = configured_routes()
+            .into_iter()
+            .filter(|route| !route.delete && route.netns == rule.netns)
+            .filter(|route| route.def.table() == rule.table || route.mirror == Some(rule.table))
+            .collect::<Vec<_>>();
+        if routes.is_empty() && !rule.delete {
+            explanation.warn(format!(
+                "no route in {} uses table {}, the lookup finds only what others put there",
+                ROUTES_PATH, rule.table
+            ));
+        } else if !routes.is_empty() {
+            explanation.note("the table holds these routes:");
+            for route in routes {
+                explanation.note(format!(
+                    "  {}:{}: {}",
+                    ROUTES_PATH,
+                    route.line,
+                    route.label()
+                ));
+            }
+        }
+    }
+
+    if rule.delete || rule.netns.is_some() {
+        return;
+    }
+
+    if rule.action == RuleAction::ToTable {
+        warn_owner(explanation, registry, rule.table);
+    }
+    if let Err(e) = guard::check_rule(rule) {
+        explanation.warn(format!(
+            "{}, refused unless rtd is started with \"--force\"",
+            e
+        ));
+    }
+}
+
+/// Warns if the registry assigns a table to another daemon or the user.
+fn warn_owner(explanation: &mut Explanation, registry: &tables::Registry, table: u32) {
+    if let Some(owner) = registry
+        .owner(table)
+        .filter(|owner| *owner != tables::Owner::Rtd)
+    {
+        explanation.warn(format!(
+            "table {} belongs to {} according to the table registry",
+            table, owner
+        ));
+    }
+}
+
+fn describe_prefix(addr: IpAddr, len: u8) -> String {
+    let (family, max_len) = match addr {
+        IpAddr::V4(_) => ("IPv4", 32),
+        IpAddr::V6(_) => ("IPv6", 128),
+    };
+
+    if len == 0 {
+        format!("all {} destinations (default route)", family)
+    } else if len == max_len {
+        format!("the single address {}", addr)
+    } else if addr.is_ipv4() {
+        format!("{}/{} ({} addresses)", addr, len, 1u64 << (max_len - len))
+    } else {
+        format!("{}/{}", addr, len)
+    }
+}
+
+fn describe_table(registry: &tables::Registry, table: u32) -> String {
+    let name = match table {
+        rtnl::RT_TABLE_MAIN => Some("main"),
+        rtnl::RT_TABLE_LOCAL => Some("local"),
+        rtnl::RT_TABLE_DEFAULT => Some("default"),
+        _ => registry.name(table),
+    };
+
+    match name {
+        Some(name) => format!("{} ({})", table, name),
+        None => table.to_string(),
+    }
+}
+
+/// Returns the routes of the configuration, none if it can't be read.
+fn configured_routes() -> Vec<Route> {
+    fs::read_to_string(ROUTES_PATH)
+        .ok()
+        .and_then(|s| s.parse::<Routes>().ok())
+        .map(|routes| routes.routes)
+        .unwrap_or_default()
+}
+
+/// Returns the rules of the configuration, none if it can't be read.
+fn configured_rules() -> Vec<Rule> {
+    fs::read_to_string(RULES_PATH)
+        .ok()
+        .and_then(|s| s.parse::<Rules>().ok())
+        .map(|rules| rules.rules)
+        .unwrap_or_default()
+}
@@ -0,0 +1,225 @@
+//! Gateway health checks for routes with a `probe`.
+//!
+//! A link that stays up doesn't guarantee a working path behind it,
+//! so metrics alone can't fail over to a backup uplink. Each probed route
+//! is checked periodically, either by pinging a target through it or by
+//! watching the ARP/NDP state of its gateway, and withdrawn after several
+//! consecutive failures, letting routes with a higher metric take over.
+//! It is restored once the probe succeeds again.
+//!
+//! Members of a balance group are taken out of the multipath route instead,
+//! the remaining members share the traffic according to their weights.
+
+use crate::audit::Source;
+use crate::balance::Group;
+use crate::{log, probe, status};
+use crate::{outcome, report};
+
+use rsdsl_rtd::{Backend, Probe, Route, RouteDef};
+
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+const SCRIPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The number of consecutive probes that have to fail or succeed
+/// before a route is withdrawn or restored.
+const THRESHOLD: u32 = 3;
+
+/// Starts checking the gateways of the given routes and balance group members.
+pub fn watch(backend: &dyn Backend, routes: Vec<(Source, Route)>, groups: Vec<Arc<Mutex<Group>>>) {
+    for (source, route) in routes {
+        let Some(probe) = route.probe.clone() else {
+            continue;
+        };
+
+        spawn(
+            backend,
+            source,
+            route,
+            probe.clone(),
+            move |backend, route, up| {
+                if up {
+                    log::info!(
+                        Netlink,
+                        "probe {} via {} succeeds again, restore route",
+                        probe,
+                        route.def.link()
+                    );
+
+                    let res = report(source, "restore", route, route.add(backend));
+                    status::set(source, outcome(res, status::State::Applied));
+                } else {
+                    log::warn!(
+                        Netlink,
+                        "probe {} via {} fails, withdraw route",
+                        probe,
+                        route.def.link()
+                    );
+
+                    let res = report(source, "withdraw", route, backend.del_route(&route.def));
+                    status::set(
+                        source,
+                        outcome(res, status::State::Withdrawn(probe.to_string())),
+                    );
+                }
+            },
+        );
+    }
+
+    for group in groups {
+        let (name, members, sources) = {
+            let group = group.lock().unwrap_or_else(|e| e.into_inner());
+            (
+                group.balance().name.clone(),
+                group.balance().members.clone(),
+                group.sources().to_vec(),
+            )
+        };
+
+        for (i, (member, source)) in members.into_iter().zip(sources).enumerate() {
+            let Some(probe) = member.probe.clone() else {
+                continue;
+            };
+
+            let name = name.clone();
+            let group = group.clone();
+
+            spawn(
+                backend,
+                source,
+                member,
+                probe.clone(),
+                move |backend, member, up| {
+                    if up {
+                        log::info!(
+                            Netlink,
+                            "probe {} via {} succeeds again, rejoin balance group {}",
+                            probe,
+                            member.def.link(),
+                            name
+                        );
+                    } else {
+                        log::warn!(
+                            Netlink,
+                            "probe {} via {} fails, leave balance group {}",
+                            probe,
+                            member.def.link(),
+                            name
+                        );
+                    }
+
+                    group
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .set(backend, i, up);
+                },
+            );
+        }
+    }
+}
+
+/// Probes the path of `route` in the background,
+/// calling `on_change` whenever its liveness changes.
+fn spawn<F>(backend: &dyn Backend, source: Source, route: Route, probe: Probe, on_change: F)
+where
+    F: FnMut(&dyn Backend, &Route, bool) + Send + 'static,
+{
+    let backend = match backend.connect() {
+        Ok(backend) => backend,
+        Err(e) => {
+            log::error!(Netlink, "connect for probing {}: {}", probe, e);
+            return;
+        }
+    };
+
+    thread::spawn(move || run(&*backend, source, &route, probe, on_change));
+}
+
+fn run<F>(backend: &dyn Backend, source: Source, route: &Route, probe: Probe, mut on_change: F)
+where
+    F: FnMut(&dyn Backend, &Route, bool),
+{
+    let link = route.def.link();
+
+    // Pin the pings to the path being checked,
+    // even while the route itself is withdrawn.
+    let host = match probe {
+        Probe::Icmp(target) => {
+            let host = host_route(&route.def, target);
+            let _ = report(source, "add", &host, backend.add_route(&host));
+
+            Some(host)
+        }
+        _ => None,
+    };
+
+    let mut up = true;
+    let mut streak = 0;
+    loop {
+        thread::sleep(PROBE_INTERVAL);
+
+        let res = match (&probe, route.def.rtr()) {
+            (Probe::Icmp(target), _) => probe::icmp(*target, link, PROBE_TIMEOUT).map(Some),
+            (Probe::Script(path), rtr) => probe::script(path, link, rtr, SCRIPT_TIMEOUT).map(Some),
+            (_, Some(rtr)) => probe::neighbor(rtr, link),
+            (_, None) => Ok(None),
+        };
+        let ok = match res {
+            Ok(Some(ok)) => ok,
+            // Nothing to go by yet, e.g. address resolution in progress.
+            Ok(None) => continue,
+            Err(e) => {
+                log::debug!(Netlink, "probe {} via {}: {}", probe, link, e);
+                false
+            }
+        };
+
+        if let Some(host) = host.as_ref().filter(|_| !ok) {
+            // The host route goes away with the link, e.g. on reconnects.
+            let _ = backend.add_route(host);
+        }
+
+        if ok == up {
+            streak = 0;
+            continue;
+        }
+
+        streak += 1;
+        if streak < THRESHOLD {
+            continue;
+        }
+
+        streak = 0;
+        up = ok;
+
+        on_change(backend, route, up);
+        status::applied();
+    }
+}
+
+/// Builds a host route to the probe target that uses the same path as `def`.
+fn host_route(def: &RouteDef, target: IpAddr) -> RouteDef {
+    match (def, target) {
+        (RouteDef::V4(r), IpAddr::V4(target)) => RouteDef::V4(rsdsl_netlinklib::route::Route4 {
+            dst: target,
+            prefix_len: 32,
+            table: None,
+            metric: None,
+            ..r.clone()
+        }),
+        (RouteDef::V6(r), IpAddr::V6(target)) => RouteDef::V6(rsdsl_netlinklib::route::Route6 {
+            dst: target,
+            prefix_len: 128,
+            table: None,
+            metric: None,
+            ..r.clone()
+        }),
+        // The parser rejects probe targets of the wrong family.
+        _ => def.clone(),
+    }
+}
@@ -0,0 +1,223 @@
+//! C interface for validating and applying single entries,
+//! see `include/rsdsl_rtd.h`.
+//!
+//! Entries are parsed from lines in the format of the configuration files
+//! with their placeholders expanded using the current values.
+//! Error messages are returned as strings the caller has to free.
+
+use crate::{vars, Backend, Balance, Neighbor, Route, Rule, SetupError};
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr;
+
+use rsdsl_netlinklib::blocking::Connection;
+
+/// A parsed route, rule or neighbor.
+#[derive(Debug)]
+pub enum RtdEntry {
+    Route(Box<Route>),
+    Rule(Rule),
+    Neighbor(Neighbor),
+}
+
+impl RtdEntry {
+    fn parse(line: &str) -> Result<Self, String> {
+        let (line, comment) = crate::split_comment(line);
+        let comment = comment.map(String::from);
+        let line = vars::expand(line, &vars::Vars::load()).map_err(|e| e.to_string())?;
+        let line = crate::lowercase_keywords(&line, 2);
+
+        match line.split_whitespace().next() {
+            Some("route4" | "route6" | "dslite") => line
+                .parse()
+                .map(|route| Self::Route(Box::new(Route { comment, ..route })))
+                .map_err(|e: crate::RouteParseError| e.to_string()),
+            Some("rule" | "rule4" | "rule6") => line
+                .parse()
+                .map(|rule| Self::Rule(Rule { comment, ..rule }))
+                .map_err(|e: crate::RuleParseError| e.to_string()),
+            Some("neigh4" | "neigh6" | "proxy4" | "proxy6") => line
+                .parse()
+                .map(|neighbor| {
+                    Self::Neighbor(Neighbor {
+                        comment,
+                        ..neighbor
+                    })
+                })
+                .map_err(|e: crate::NeighborParseError| e.to_string()),
+            Some(kind) => Err(format!(
+                "unsupported entry {} (want a route, rule or neighbor)",
+                kind
+            )),
+            None => Err("empty line".to_string()),
+        }
+    }
+
+    /// Installs the entry, replacing an existing one, or removes it for `del` lines.
+    fn apply(&self) -> Result<(), SetupError> {
+        match self {
+            Self::Route(route) => {
+                if route.host.is_some() {
+                    return Err(SetupError::Netlink(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "routes to hostnames need the daemon",
+                    )));
+                }
+                if route.via_peer && !route.delete {
+                    return Err(SetupError::Netlink(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "routes via peer need the daemon",
+                    )));
+                }
+                if route.src_auto && !route.delete {
+                    return Err(SetupError::Netlink(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "routes with src auto need the daemon",
+                    )));
+                }
+
+                // Routes through several links are multipath routes of their own.
+                if route.has_several_links() {
+                    let balance = Balance {
+                        name: String::new(),
+                        members: (**route).clone().split_links(),
+                    };
+                    if route.delete {
+                        balance.blocking_del().or_else(|e| {
+                            if e.is_not_found() {
+                                Ok(())
+                            } else {
+                                Err(e)
+                            }
+                        })?;
+                    } else {
+                        balance.blocking_replace()?;
+                    }
+
+                    return Ok(());
+                }
+
+                let conn = Connection::new()?;
+                let mut defs = vec![(route.def.clone(), route.attrs())];
+                defs.extend(route.mirror_def().map(|def| (def, None)));
+                for (def, attrs) in defs {
+                    let res = def.clone().blocking_del(&conn);
+                    if route.delete {
+                        res.or_else(|e| if e.is_not_found() { Ok(()) } else { Err(e) })?;
+                    } else if let Some(attrs) = attrs {
+                        conn.add_route_attrs(&def, &attrs)?;
+                    } else {
+                        def.blocking_add(&conn)?;
+                    }
+                }
+            }
+            Self::Rule(rule) => {
+                let conn = Connection::new()?;
+                let res = rule.clone().blocking_del(&conn);
+                if rule.delete {
+                    res.or_else(|e| if e.is_not_found() { Ok(()) } else { Err(e) })?;
+                } else {
+                    rule.clone().blocking_add(&conn)?;
+                }
+            }
+            Self::Neighbor(neighbor) => {
+                let res = neighbor.blocking_del();
+                if neighbor.delete {
+                    res.or_else(|e| if e.is_not_found() { Ok(()) } else { Err(e) })?;
+                } else {
+                    neighbor.blocking_add()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Stores an error message for the caller if it asked for one.
+unsafe fn set_err(err: *mut *mut c_char, msg: &str) {
+    if err.is_null() {
+        return;
+    }
+
+    // Messages never contain NUL bytes unless the input did.
+    let msg = CString::new(msg.replace('\0', "")).unwrap_or_default();
+    *err = msg.into_raw();
+}
+
+/// Parses a line, returning `NULL` and setting `*err` on failure.
+///
+/// # Safety
+///
+/// `line` must be a valid NUL-terminated string,
+/// `err` must be `NULL` or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rtd_parse(line: *const c_char, err: *mut *mut c_char) -> *mut RtdEntry {
+    if line.is_null() {
+        set_err(err, "line is NULL");
+        return ptr::null_mut();
+    }
+
+    let line = match CStr::from_ptr(line).to_str() {
+        Ok(line) => line,
+        Err(e) => {
+            set_err(err, &e.to_string());
+            return ptr::null_mut();
+        }
+    };
+
+    match RtdEntry::parse(line) {
+        Ok(entry) => Box::into_raw(Box::new(entry)),
+        Err(e) => {
+            set_err(err, &e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Applies a parsed entry, returning 0 on success
+/// and -1 on failure, setting `*err` in that case.
+///
+/// # Safety
+///
+/// `entry` must have been returned by `rtd_parse` and not been freed yet,
+/// `err` must be `NULL` or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rtd_apply(entry: *const RtdEntry, err: *mut *mut c_char) -> c_int {
+    let Some(entry) = entry.as_ref() else {
+        set_err(err, "entry is NULL");
+        return -1;
+    };
+
+    match entry.apply() {
+        Ok(()) => 0,
+        Err(e) => {
+            set_err(err, &e.to_string());
+            -1
+        }
+    }
+}
+
+/// Frees a parsed entry. Does nothing if `entry` is `NULL`.
+///
+/// # Safety
+///
+/// `entry` must have been returned by `rtd_parse` and not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn rtd_free(entry: *mut RtdEntry) {
+    if !entry.is_null() {
+        drop(Box::from_raw(entry));
+    }
+}
+
+/// Frees an error message. Does nothing if `msg` is `NULL`.
+///
+/// # Safety
+///
+/// `msg` must have been returned through an `err` argument and not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn rtd_free_string(msg: *mut c_char) {
+    if !msg.is_null() {
+        drop(CString::from_raw(msg));
+    }
+}
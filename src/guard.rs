@@ -0,0 +1,244 @@
+//! Protection of the management path.
+//!
+//! The prefixes listed in /data/protected.pfx (usually those the administrator
+//! connects from) must stay reachable the way they are. Routes and rules that
+//! would send their traffic elsewhere or drop it are refused,
+//! unless rtd was started with `--force`.
+
+use crate::audit::Source;
+use crate::{log, status};
+
+use rsdsl_rtd::rtnl::{self, RouteMsg};
+use rsdsl_rtd::{Route, RouteDef, Rule, RuleAction, RuleVersion};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+const PROTECTED_PATH: &str = "/data/protected.pfx";
+
+static GUARD: OnceLock<Guard> = OnceLock::new();
+
+#[derive(Debug, Default)]
+struct Guard {
+    prefixes: Vec<(IpAddr, u8)>,
+    force: bool,
+}
+
+/// A protected prefix an entry would cut off.
+#[derive(Debug)]
+pub struct Shadowed(IpAddr, u8);
+
+impl fmt::Display for Shadowed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "would cut off protected prefix {}/{}", self.0, self.1)
+    }
+}
+
+impl std::error::Error for Shadowed {}
+
+/// Reads the protected prefixes. The file is optional, nothing is protected without it.
+pub fn init(force: bool) -> io::Result<()> {
+    let prefixes = match fs::read_to_string(PROTECTED_PATH) {
+        Ok(s) => parse(&s)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    log::debug!(
+        Parser,
+        "parsed {} protected prefixes from {}",
+        prefixes.len(),
+        PROTECTED_PATH
+    );
+
+    let _ = GUARD.set(Guard { prefixes, force });
+    Ok(())
+}
+
+fn parse(s: &str) -> io::Result<Vec<(IpAddr, u8)>> {
+    let mut prefixes = Vec::new();
+    for (i, line) in s.lines().enumerate() {
+        let prefix = line.split('#').next().unwrap_or_default().trim();
+        if prefix.is_empty() {
+            continue;
+        }
+
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: invalid prefix {}", PROTECTED_PATH, i + 1, prefix),
+            )
+        };
+
+        let (addr, len) = match prefix.split_once('/') {
+            Some((addr, len)) => (
+                addr.parse::<IpAddr>().map_err(|_| invalid())?,
+                len.parse().map_err(|_| invalid())?,
+            ),
+            None => {
+                let addr = prefix.parse::<IpAddr>().map_err(|_| invalid())?;
+                (addr, if addr.is_ipv4() { 32 } else { 128 })
+            }
+        };
+        if len > if addr.is_ipv4() { 32 } else { 128 } {
+            return Err(invalid());
+        }
+
+        prefixes.push((addr, len));
+    }
+
+    Ok(prefixes)
+}
+
+/// Checks whether a route may be installed, logging and recording the refusal if not.
+/// Call this before removing any previous version of the route.
+pub fn allow_route(source: Source, route: &Route) -> bool {
+    allow(source, route, check_route(&route.def))
+}
+
+/// Checks whether a rule may be installed, logging and recording the refusal if not.
+pub fn allow_rule(source: Source, rule: &Rule) -> bool {
+    allow(source, rule, check_rule(rule))
+}
+
+fn allow(source: Source, entry: &dyn fmt::Display, res: Result<(), Shadowed>) -> bool {
+    let Err(e) = res else {
+        return true;
+    };
+
+    if guard().force {
+        log::warn!(General, "add {}: {}, forced", entry, e);
+        return true;
+    }
+
+    let e = format!("{} (start with \"--force\" to apply anyway)", e);
+    log::error!(General, "refuse {}: {}", entry, e);
+    status::set(source, status::State::Failed(e));
+    false
+}
+
+/// Checks that a route doesn't take over the traffic to a protected prefix,
+/// i.e. that it doesn't become the most specific match for (part of) it
+/// with a different path than the one currently in use.
+pub fn check_route(def: &RouteDef) -> Result<(), Shadowed> {
+    for &(prefix, len) in &guard().prefixes {
+        if prefix.is_ipv4() != def.dst().is_ipv4() {
+            continue;
+        }
+
+        // The most specific address both of them cover.
+        let addr = if def.prefix_len() >= len {
+            def.dst()
+        } else {
+            prefix
+        };
+        if !rtnl::prefix_contains(prefix, len, addr)
+            || !rtnl::prefix_contains(def.dst(), def.prefix_len(), addr)
+        {
+            continue;
+        }
+
+        let Some(current) = lookup(def.table(), addr) else {
+            continue;
+        };
+        let wins = def.prefix_len() > current.dst_len
+            || (def.prefix_len() == current.dst_len
+                && def.metric().unwrap_or(0) <= current.metric.unwrap_or(0));
+        let same_path = current.ty == rtnl::RTN_UNICAST
+            && current.oif.is_some()
+            && current.oif == rtnl::link_index(def.link()).ok()
+            && def.rtr().is_none_or(|rtr| current.gateway == Some(rtr));
+
+        if wins && !same_path {
+            return Err(Shadowed(prefix, len));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that a rule doesn't drop the traffic to a protected prefix
+/// or divert it to a table that routes it differently than the main table.
+/// Only rules that can match the administrator's connections are considered.
+pub fn check_rule(rule: &Rule) -> Result<(), Shadowed> {
+    if rule.invert || rule.fwmark.is_some() || rule.src.is_some() {
+        return Ok(());
+    }
+
+    for &(prefix, len) in &guard().prefixes {
+        let family = match rule.version {
+            RuleVersion::Both => true,
+            RuleVersion::Ipv4 => prefix.is_ipv4(),
+            RuleVersion::Ipv6 => prefix.is_ipv6(),
+        };
+        let addr = match rule.dst {
+            Some((dst, dst_len)) if dst_len >= len => dst,
+            _ => prefix,
+        };
+        let overlaps = rule.dst.is_none_or(|(dst, dst_len)| {
+            rtnl::prefix_contains(prefix, len, addr) && rtnl::prefix_contains(dst, dst_len, addr)
+        });
+        if !family || !overlaps {
+            continue;
+        }
+
+        let shadows = match rule.action {
+            RuleAction::Blackhole | RuleAction::Unreachable | RuleAction::Prohibit => true,
+            RuleAction::ToTable if rule.table != rtnl::RT_TABLE_MAIN => {
+                // Lookups fall through to the main table if there's no matching route.
+                match lookup(rule.table, addr) {
+                    Some(diverted) => lookup(rtnl::RT_TABLE_MAIN, addr)
+                        .is_none_or(|current| !same_path(&current, &diverted)),
+                    None => false,
+                }
+            }
+            _ => false,
+        };
+        if shadows {
+            return Err(Shadowed(prefix, len));
+        }
+    }
+
+    Ok(())
+}
+
+fn guard() -> &'static Guard {
+    GUARD.get_or_init(Guard::default)
+}
+
+/// Returns the route of a table currently used for an address.
+/// Failing dumps are treated as if there were no route,
+/// leaving the decision to the kernel.
+fn lookup(table: u32, addr: IpAddr) -> Option<RouteMsg> {
+    let family = if addr.is_ipv4() {
+        libc::AF_INET
+    } else {
+        libc::AF_INET6
+    } as u8;
+    let routes = rtnl::Socket::new()
+        .and_then(|mut sock| sock.dump_routes(family))
+        .ok()?;
+
+    routes
+        .into_iter()
+        .filter(|route| {
+            route.table == table
+                && matches!(
+                    route.ty,
+                    rtnl::RTN_UNICAST
+                        | rtnl::RTN_BLACKHOLE
+                        | rtnl::RTN_UNREACHABLE
+                        | rtnl::RTN_PROHIBIT
+                )
+                && route
+                    .dst
+                    .is_none_or(|dst| rtnl::prefix_contains(dst, route.dst_len, addr))
+        })
+        .min_by_key(|route| (u8::MAX - route.dst_len, route.metric.unwrap_or(0)))
+}
+
+fn same_path(a: &RouteMsg, b: &RouteMsg) -> bool {
+    a.ty == b.ty && a.oif == b.oif && a.gateway == b.gateway
+}
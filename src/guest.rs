@@ -0,0 +1,137 @@
+//! Guest network isolation presets.
+//!
+//! The delegated prefix is part of the LAN that guests must not reach.
+//! It changes with reconnects, so the state file is polled and the
+//! unreachable route covering it is kept in sync.
+
+use crate::audit::Source;
+use crate::{log, status};
+use crate::{outcome, report};
+
+use rsdsl_rtd::{vars, Backend, Blackhole, Isolate};
+
+use std::collections::HashSet;
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Installs (or removes) the rules and routes of an isolation preset,
+/// returning the unreachable routes that are now installed.
+pub fn apply(backend: &dyn Backend, source: Source, isolate: &Isolate) -> Vec<Blackhole> {
+    let pd_prefix = vars::Vars::load().pd_prefix();
+
+    let _ = report(source, "del", isolate, backend.del_isolate_rules(isolate));
+    if isolate.delete {
+        for route in isolate.default_routes() {
+            let _ = report(source, "del", &route, backend.del_route(&route));
+        }
+        for unreachable in isolate.unreachables(pd_prefix) {
+            let _ = report(
+                source,
+                "del",
+                &unreachable,
+                backend.del_reject(&unreachable),
+            );
+        }
+
+        status::set(source, status::State::Removed);
+        return Vec::new();
+    }
+
+    for link in [&isolate.link, &isolate.wan] {
+        status::set(source, status::State::WaitingForLink(link.clone()));
+        log::info!(Netlink, "wait for link {}", link);
+        if let Err(e) = backend.link_wait_exists(link) {
+            log::error!(Netlink, "wait for link {}: {}", link, e);
+            status::set(source, status::State::Failed(e.to_string()));
+            return Vec::new();
+        }
+    }
+
+    // Nothing may leak to the LAN before the table is complete.
+    let unreachables = isolate.unreachables(pd_prefix);
+    let mut res = Ok(());
+    for unreachable in &unreachables {
+        res = res.and(report(
+            source,
+            "add",
+            unreachable,
+            backend.add_reject(unreachable),
+        ));
+    }
+    for route in isolate.default_routes() {
+        let _ = backend.del_route(&route);
+        res = res.and(report(source, "add", &route, backend.add_route(&route)));
+    }
+    res = res.and(report(
+        source,
+        "add",
+        isolate,
+        backend.add_isolate_rules(isolate),
+    ));
+
+    status::set(source, outcome(res, status::State::Applied));
+    unreachables
+}
+
+/// Keeps the unreachable routes of the given presets in sync with the delegated prefix.
+pub fn watch(backend: &dyn Backend, isolates: Vec<(Source, Isolate, Vec<Blackhole>)>) {
+    let mut isolates: Vec<_> = isolates
+        .into_iter()
+        .filter(|(_, isolate, _)| !isolate.delete)
+        .collect();
+
+    if isolates.is_empty() {
+        return;
+    }
+
+    let backend = match backend.connect() {
+        Ok(backend) => backend,
+        Err(e) => {
+            log::error!(Netlink, "connect for isolation: {}", e);
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        let mut pd_prefix = vars::Vars::load().pd_prefix();
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            let current = vars::Vars::load().pd_prefix();
+            if current == pd_prefix {
+                continue;
+            }
+            pd_prefix = current;
+
+            log::info!(Events, "delegated prefix changed, reload isolation");
+
+            for (source, isolate, installed) in &mut isolates {
+                let unreachables = isolate.unreachables(pd_prefix);
+
+                let old: HashSet<&Blackhole> = installed.iter().collect();
+                let new: HashSet<&Blackhole> = unreachables.iter().collect();
+
+                let mut res = Ok(());
+                for unreachable in unreachables.iter().filter(|u| !old.contains(u)) {
+                    res = res.and(report(
+                        *source,
+                        "add",
+                        unreachable,
+                        backend.add_reject(unreachable),
+                    ));
+                }
+                for unreachable in installed.iter().filter(|u| !new.contains(u)) {
+                    let _ = report(*source, "del", unreachable, backend.del_reject(unreachable));
+                }
+                status::set(*source, outcome(res, status::State::Applied));
+
+                *installed = unreachables;
+            }
+
+            status::applied();
+        }
+    });
+}
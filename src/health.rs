@@ -0,0 +1,80 @@
+//! Tiny localhost HTTP endpoint for container-style health checks.
+//!
+//! Enabled by setting `RTD_HEALTH_PORT`. Responds with 200 if all configured
+//! entries are applied and 503 otherwise, the body is the current status.
+
+use crate::log;
+use crate::status;
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::time::Duration;
+
+const PORT_VAR: &str = "RTD_HEALTH_PORT";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Starts serving the health endpoint in the background if it is enabled.
+pub fn spawn() {
+    let port = match std::env::var(PORT_VAR).map(|v| v.parse::<u16>()) {
+        Ok(Ok(port)) => port,
+        Ok(Err(e)) => {
+            log::error!(General, "parse {}: {}", PORT_VAR, e);
+            return;
+        }
+        Err(_) => return,
+    };
+
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!(General, "bind health endpoint to port {}: {}", port, e);
+            return;
+        }
+    };
+
+    log::debug!(General, "serve health endpoint on port {}", port);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let res = stream.and_then(handle);
+            if let Err(e) = res {
+                log::debug!(General, "health endpoint: {}", e);
+            }
+        }
+    });
+}
+
+fn handle(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut buf = [0; 1024];
+    let n = stream.read(&mut buf)?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+
+    let (code, reason, body) = match (method, path) {
+        ("GET", "/" | "/health") => {
+            let (healthy, status) = status::health();
+            if healthy {
+                (200, "OK", status.to_string())
+            } else {
+                (503, "Service Unavailable", status.to_string())
+            }
+        }
+        ("GET", _) => (404, "Not Found", String::new()),
+        _ => (405, "Method Not Allowed", String::new()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.0 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason,
+        body.len(),
+        body
+    )
+}
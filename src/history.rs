@@ -0,0 +1,310 @@
+//! History of the applied configurations and `rollback [n]`.
+//!
+//! Every configuration rtd applies is stored under `/data/rtd.history`,
+//! the files by their hash so that unchanged ones take no extra space.
+//! The index lists the most recent ones, oldest first, one per line:
+//! `<timestamp> <routes> <rules> <neighbors> <good|failed>`.
+
+use crate::audit::{self, Source};
+use crate::{control, lock, log};
+use crate::{NEIGHBORS_PATH, ROUTES_PATH, RULES_PATH};
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const HISTORY_DIR: &str = "/data/rtd.history";
+const INDEX_FILE: &str = "index";
+/// The number of configurations to keep.
+const MAX_ENTRIES: usize = 10;
+/// Stands in for the hash of the optional neighbor file if it doesn't exist.
+const ABSENT: &str = "-";
+
+#[derive(Debug)]
+pub enum HistoryError {
+    InvalidArg(String),
+    Lock(io::Error),
+    NoEntry(usize, usize),
+    ParseInt(std::num::ParseIntError),
+    ReadHistory(io::Error),
+    WriteConfig(&'static str, io::Error),
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidArg(a) => write!(f, "invalid argument {} (want [n])", a)?,
+            Self::Lock(e) => write!(f, "lock configuration: {}", e)?,
+            Self::NoEntry(n, len) => write!(
+                f,
+                "no configuration {} back (history has {} older ones)",
+                n, len
+            )?,
+            Self::ParseInt(e) => write!(f, "parse integer: {}", e)?,
+            Self::ReadHistory(e) => write!(f, "read history ({}): {}", HISTORY_DIR, e)?,
+            Self::WriteConfig(path, e) => write!(f, "write {}: {}", path, e)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl From<std::num::ParseIntError> for HistoryError {
+    fn from(e: std::num::ParseIntError) -> HistoryError {
+        HistoryError::ParseInt(e)
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+/// The configuration files as they were read for an apply pass.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub routes: String,
+    pub rules: String,
+    pub neighbors: Option<String>,
+}
+
+/// A line of the index.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Entry {
+    timestamp: String,
+    routes: String,
+    rules: String,
+    neighbors: String,
+    good: bool,
+}
+
+impl Entry {
+    fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let entry = Self {
+            timestamp: words.next()?.to_string(),
+            routes: words.next()?.to_string(),
+            rules: words.next()?.to_string(),
+            neighbors: words.next()?.to_string(),
+            good: words.next()? == "good",
+        };
+
+        Some(entry)
+    }
+
+    fn same_config(&self, other: &Self) -> bool {
+        self.routes == other.routes
+            && self.rules == other.rules
+            && self.neighbors == other.neighbors
+    }
+
+    fn objects(&self) -> impl Iterator<Item = &str> {
+        [&self.routes, &self.rules, &self.neighbors]
+            .into_iter()
+            .map(String::as_str)
+            .filter(|hash| *hash != ABSENT)
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.timestamp,
+            self.routes,
+            self.rules,
+            self.neighbors,
+            if self.good { "good" } else { "failed" }
+        )
+    }
+}
+
+/// Adds an applied configuration to the history unless it matches the most recent one.
+/// `good` tells whether all of its entries could be applied.
+pub fn record(config: &Config, good: bool) {
+    if let Err(e) = try_record(config, good) {
+        log::error!(General, "record configuration history: {}", e);
+    }
+}
+
+fn try_record(config: &Config, good: bool) -> io::Result<()> {
+    fs::create_dir_all(HISTORY_DIR)?;
+
+    let entry = Entry {
+        timestamp: log::timestamp(),
+        routes: store(&config.routes)?,
+        rules: store(&config.rules)?,
+        neighbors: match &config.neighbors {
+            Some(neighbors) => store(neighbors)?,
+            None => ABSENT.to_string(),
+        },
+        good,
+    };
+
+    let mut entries = read_index()?;
+    match entries.last_mut() {
+        // Restarts don't make a new configuration, but it may have turned good.
+        Some(last) if last.same_config(&entry) => last.good |= good,
+        _ => entries.push(entry),
+    }
+
+    let excess = entries.len().saturating_sub(MAX_ENTRIES);
+    entries.drain(..excess);
+
+    let index: String = entries.iter().map(|entry| format!("{}\n", entry)).collect();
+    lock::write_atomic(&path(INDEX_FILE), index.as_bytes())?;
+
+    // Drop the files no configuration refers to anymore.
+    let used: HashSet<&str> = entries.iter().flat_map(Entry::objects).collect();
+    for file in fs::read_dir(HISTORY_DIR)? {
+        let file = file?;
+        let name = file.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+
+        if name != INDEX_FILE && !used.contains(name) {
+            fs::remove_file(file.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// `rollback [n]`: restores the configuration applied `n` (default 1) times
+/// before the current one and has rtd apply it.
+pub fn rollback(args: &[String]) -> Result<(), HistoryError> {
+    let n = match args {
+        [] => 1,
+        [n] => n.parse()?,
+        [_, extra, ..] => return Err(HistoryError::InvalidArg(extra.clone())),
+    };
+
+    let entries = read_index().map_err(HistoryError::ReadHistory)?;
+    let older = entries.len().saturating_sub(1);
+    if n == 0 || n > older {
+        return Err(HistoryError::NoEntry(n, older));
+    }
+
+    let entry = &entries[entries.len() - 1 - n];
+    let config = load(entry).map_err(HistoryError::ReadHistory)?;
+    restore(
+        Source::Cli {
+            command: "rollback",
+        },
+        entry,
+        &config,
+    )?;
+
+    log::info!(
+        General,
+        "restored configuration of {} ({})",
+        entry.timestamp,
+        if entry.good { "good" } else { "failed" }
+    );
+
+    control::reapply();
+    Ok(())
+}
+
+/// Restores the most recent configuration that was applied completely.
+/// Returns `false` if there is none or it is the current one.
+pub fn restore_last_good(source: Source) -> Result<bool, HistoryError> {
+    let entries = read_index().map_err(HistoryError::ReadHistory)?;
+
+    let Some(entry) = entries.iter().rev().find(|entry| entry.good) else {
+        return Ok(false);
+    };
+    if Some(entry) == entries.last() {
+        return Ok(false);
+    }
+
+    let config = load(entry).map_err(HistoryError::ReadHistory)?;
+    restore(source, entry, &config)?;
+
+    log::info!(General, "restored configuration of {}", entry.timestamp);
+    Ok(true)
+}
+
+/// Returns the configuration rtd applied most recently, if any.
+pub fn last_applied() -> io::Result<Option<Config>> {
+    match read_index()?.last() {
+        Some(entry) => Ok(Some(load(entry)?)),
+        None => Ok(None),
+    }
+}
+
+/// Writes the configuration of an entry back to the configuration files
+/// and records this in the audit log.
+fn restore(source: Source, entry: &Entry, config: &Config) -> Result<(), HistoryError> {
+    let res = write_config(config);
+
+    let what = format!("configuration of {}", entry.timestamp);
+    match &res {
+        Ok(()) => audit::record(source, "restore", &what, Ok(())),
+        Err(e) => audit::record(source, "restore", &what, Err(e)),
+    }
+
+    res
+}
+
+fn write_config(config: &Config) -> Result<(), HistoryError> {
+    let _lock = lock::exclusive().map_err(HistoryError::Lock)?;
+
+    lock::write_atomic(Path::new(ROUTES_PATH), config.routes.as_bytes())
+        .map_err(|e| HistoryError::WriteConfig(ROUTES_PATH, e))?;
+    lock::write_atomic(Path::new(RULES_PATH), config.rules.as_bytes())
+        .map_err(|e| HistoryError::WriteConfig(RULES_PATH, e))?;
+    match &config.neighbors {
+        Some(neighbors) => lock::write_atomic(Path::new(NEIGHBORS_PATH), neighbors.as_bytes()),
+        None => match fs::remove_file(NEIGHBORS_PATH) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        },
+    }
+    .map_err(|e| HistoryError::WriteConfig(NEIGHBORS_PATH, e))
+}
+
+fn read_index() -> io::Result<Vec<Entry>> {
+    match fs::read_to_string(path(INDEX_FILE)) {
+        Ok(index) => Ok(index.lines().filter_map(Entry::parse).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn load(entry: &Entry) -> io::Result<Config> {
+    let neighbors = match entry.neighbors.as_str() {
+        ABSENT => None,
+        hash => Some(fs::read_to_string(path(hash))?),
+    };
+
+    Ok(Config {
+        routes: fs::read_to_string(path(&entry.routes))?,
+        rules: fs::read_to_string(path(&entry.rules))?,
+        neighbors,
+    })
+}
+
+/// Stores a file by its hash, returning the hash.
+fn store(content: &str) -> io::Result<String> {
+    let hash = format!("{:016x}", fnv1a(content.as_bytes()));
+
+    let file = path(&hash);
+    if !file.exists() {
+        lock::write_atomic(&file, content.as_bytes())?;
+    }
+
+    Ok(hash)
+}
+
+fn path(name: &str) -> PathBuf {
+    PathBuf::from(HISTORY_DIR).join(name)
+}
+
+/// 64-bit FNV-1a, good enough to tell configurations apart.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,26 @@
+//! The routes and rules the kernel has when rtd starts.
+//!
+//! Configured entries are removed before adding them to replace stale versions,
+//! but most of them don't exist at that point. A single dump tells which
+//! deletions are worth sending.
+
+use crate::log;
+
+pub use rsdsl_rtd::Installed;
+
+use rsdsl_rtd::Backend;
+
+/// Dumps the routes and rules of both address families.
+/// Tables that can't be dumped are assumed to have everything.
+pub fn dump(backend: &dyn Backend) -> Installed {
+    let routes = backend
+        .dump_routes(libc::AF_UNSPEC as u8)
+        .inspect_err(|e| log::warn!(Netlink, "dump installed routes: {}", e))
+        .ok();
+    let rules = backend
+        .dump_rules(libc::AF_UNSPEC as u8)
+        .inspect_err(|e| log::warn!(Netlink, "dump installed rules: {}", e))
+        .ok();
+
+    Installed::new(routes, rules)
+}
@@ -0,0 +1,167 @@
+//! Guest network isolation (`isolate` lines of the route configuration).
+//!
+//! Traffic received on the guest interface looks up a table of its own
+//! that only knows the way to the internet. Private address space and
+//! the delegated prefix are unreachable from there, keeping guests away
+//! from the LAN while still reaching the router itself via the local table.
+
+use crate::{rtnl, Blackhole, RejectKind, RouteDef, RouteParseError, SetupError, DEFAULT_WAN};
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// An `isolate` line of the route configuration.
+#[derive(Clone, Debug)]
+pub struct Isolate {
+    pub delete: bool,
+    /// The guest interface.
+    pub link: String,
+    pub table: u32,
+    /// The interface of the default routes.
+    pub wan: String,
+    pub line: usize,
+}
+
+impl Isolate {
+    /// Installs the rules directing the traffic of the guest interface
+    /// to the isolated table.
+    pub fn blocking_add_rules(&self) -> Result<(), SetupError> {
+        let mut sock = rtnl::Socket::new()?;
+        for family in [libc::AF_INET, libc::AF_INET6] {
+            sock.add_iif_rule(family as u8, &self.link, self.table)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the rules, ignoring ones that don't exist.
+    pub fn blocking_del_rules(&self) -> Result<(), SetupError> {
+        let mut sock = rtnl::Socket::new()?;
+        for family in [libc::AF_INET, libc::AF_INET6] {
+            match sock.del_iif_rule(family as u8, &self.link, self.table) {
+                Ok(()) => {}
+                Err(e) if e.raw_os_error() == Some(libc::ENOENT) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the default routes of the isolated table.
+    pub fn default_routes(&self) -> [RouteDef; 2] {
+        RouteDef::defaults(&self.wan, self.table)
+    }
+
+    /// Returns the unreachable routes of the isolated table,
+    /// covering the delegated prefix if it is known.
+    pub fn unreachables(&self, pd_prefix: Option<(Ipv6Addr, u8)>) -> Vec<Blackhole> {
+        let v4 = PRIVATE_V4
+            .iter()
+            .map(|(addr, len)| (IpAddr::V4(Ipv4Addr::from(*addr)), *len));
+        let v6 = PRIVATE_V6
+            .iter()
+            .map(|(addr, len)| (IpAddr::V6(Ipv6Addr::from(*addr)), *len));
+        let pd = pd_prefix.map(|(prefix, len)| (IpAddr::V6(prefix), len));
+
+        v4.chain(v6)
+            .chain(pd)
+            .map(|(dst, prefix_len)| Blackhole {
+                dst,
+                prefix_len,
+                kind: RejectKind::Unreachable,
+                table: Some(self.table),
+                metric: None,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Isolate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "isolate dev {} table {} wan {}",
+            self.link, self.table, self.wan
+        )
+    }
+}
+
+impl FromStr for Isolate {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = crate::lowercase_keywords(s, 2);
+        let mut words = s.split_whitespace();
+
+        let version_str = words.next().ok_or(RouteParseError::NoVersion)?;
+        if version_str != "isolate" {
+            return Err(RouteParseError::InvalidVersion(version_str.to_string()));
+        }
+
+        let cmd = words.next().ok_or(RouteParseError::NoCmd)?;
+        let delete = match cmd {
+            "add" => false,
+            "del" => true,
+            _ => return Err(RouteParseError::InvalidCmd(cmd.to_string())),
+        };
+
+        let attrs = crate::attr_pairs(words, |attr| attr)?;
+
+        let mut link = None;
+        let mut table = None;
+        let mut wan = DEFAULT_WAN.to_string();
+
+        for (attr, value) in attrs {
+            match attr {
+                "dev" => link = Some(value.to_string()),
+                "table" => table = Some(crate::parse_table(value)?),
+                "wan" => wan = value.to_string(),
+                _ => return Err(RouteParseError::InvalidAttr(attr.to_string())),
+            }
+        }
+
+        Ok(Self {
+            delete,
+            link: link.ok_or(RouteParseError::NoLink)?,
+            table: table.ok_or(RouteParseError::NoTable)?,
+            wan,
+            line: 0,
+        })
+    }
+}
+
+/// Private IPv4 space (RFC 1918) plus link-local addresses. Shared address space
+/// is left alone, it may be used by the ISP (e.g. for its DNS servers).
+const PRIVATE_V4: &[([u8; 4], u8)] = &[
+    ([10, 0, 0, 0], 8),
+    ([169, 254, 0, 0], 16),
+    ([172, 16, 0, 0], 12),
+    ([192, 168, 0, 0], 16),
+];
+
+/// Unique local IPv6 addresses (RFC 4193).
+const PRIVATE_V6: &[([u8; 16], u8)] = &[([0xfc, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 7)];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::round_trip;
+
+    #[test]
+    fn guest_networks() {
+        assert_eq!(
+            round_trip::<Isolate>("isolate add dev guest0 table 100"),
+            "isolate dev guest0 table 100 wan ppp0"
+        );
+        assert_eq!(
+            round_trip::<Isolate>("isolate add dev guest0 table 100 wan eth1"),
+            "isolate dev guest0 table 100 wan eth1"
+        );
+        assert!(matches!(
+            "isolate add dev guest0".parse::<Isolate>(),
+            Err(RouteParseError::NoTable)
+        ));
+    }
+}
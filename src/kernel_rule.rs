@@ -0,0 +1,234 @@
+//! The kernel's built-in rules (`kernel` lines of the rule configuration).
+//!
+//! Every routing policy starts out with rules looking up the local, main
+//! and default tables. Designs that need rules in front of the local table
+//! or instead of the main table have to move or remove them. The daemon
+//! restores them when it shuts down.
+
+use crate::{rtnl, RuleParseError, RuleVersion, SetupError};
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// The priority of the kernel's rule for the local table.
+const LOCAL_PRIORITY: u32 = 0;
+/// The priority of the kernel's rule for the main table.
+const MAIN_PRIORITY: u32 = 32766;
+/// The priority of the kernel's rule for the default table (IPv4 only).
+const DEFAULT_PRIORITY: u32 = 32767;
+
+/// A `kernel` line of the rule configuration,
+/// e.g. `kernel set table local pref 1000` or `kernel4 del table default`.
+#[derive(Clone, Debug)]
+pub struct KernelRule {
+    pub version: RuleVersion,
+    /// The local, main or default table.
+    pub table: u32,
+    /// The priority to move the rule to, `None` to remove it.
+    pub priority: Option<u32>,
+    pub line: usize,
+}
+
+impl KernelRule {
+    /// Moves or removes the rule. Doing so again has no effect.
+    pub fn blocking_apply(&self) -> Result<(), SetupError> {
+        let mut sock = rtnl::Socket::new()?;
+
+        for (family, builtin) in self.builtin() {
+            if self.priority == Some(builtin) {
+                continue;
+            }
+
+            // Add the new rule first so that the table is never unreachable.
+            if let Some(priority) = self.priority {
+                exists_ok(sock.add_table_rule(family, self.table, priority))?;
+            }
+            absent_ok(sock.del_table_rule(family, self.table, builtin))?;
+        }
+
+        Ok(())
+    }
+
+    /// Puts the rule back where the kernel had it.
+    pub fn blocking_restore(&self) -> Result<(), SetupError> {
+        let mut sock = rtnl::Socket::new()?;
+
+        for (family, builtin) in self.builtin() {
+            if self.priority == Some(builtin) {
+                continue;
+            }
+
+            exists_ok(sock.add_table_rule(family, self.table, builtin))?;
+            if let Some(priority) = self.priority {
+                absent_ok(sock.del_table_rule(family, self.table, priority))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the address families the rule is managed for
+    /// along with its original priority.
+    fn builtin(&self) -> Vec<(u8, u32)> {
+        let families = match self.version {
+            RuleVersion::Both => vec![libc::AF_INET as u8, libc::AF_INET6 as u8],
+            RuleVersion::Ipv4 => vec![libc::AF_INET as u8],
+            RuleVersion::Ipv6 => vec![libc::AF_INET6 as u8],
+        };
+
+        families
+            .into_iter()
+            .filter_map(|family| Some((family, builtin_priority(family, self.table)?)))
+            .collect()
+    }
+}
+
+impl fmt::Display for KernelRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let version = match self.version {
+            RuleVersion::Both => "kernel",
+            RuleVersion::Ipv4 => "kernel4",
+            RuleVersion::Ipv6 => "kernel6",
+        };
+        let table = match self.table {
+            rtnl::RT_TABLE_LOCAL => "local",
+            rtnl::RT_TABLE_MAIN => "main",
+            _ => "default",
+        };
+
+        match self.priority {
+            Some(priority) => write!(f, "{} set table {} pref {}", version, table, priority),
+            None => write!(f, "{} del table {}", version, table),
+        }
+    }
+}
+
+impl FromStr for KernelRule {
+    type Err = RuleParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = crate::lowercase_keywords(s, 2);
+        let mut words = s.split_whitespace();
+
+        let version_str = words.next().ok_or(RuleParseError::NoVersion)?;
+        let version = match version_str {
+            "kernel" => RuleVersion::Both,
+            "kernel4" => RuleVersion::Ipv4,
+            "kernel6" => RuleVersion::Ipv6,
+            _ => return Err(RuleParseError::InvalidVersion(version_str.to_string())),
+        };
+
+        let cmd = words.next().ok_or(RuleParseError::NoCmd)?;
+        let delete = match cmd {
+            "set" => false,
+            "del" => true,
+            _ => return Err(RuleParseError::InvalidCmd(cmd.to_string())),
+        };
+
+        let attrs = crate::attr_pairs(words, |attr| match attr {
+            "priority" | "preference" => "pref",
+            _ => attr,
+        })?;
+
+        let mut table = None;
+        let mut priority = None;
+        for (attr, value) in attrs {
+            match attr {
+                "table" => table = Some(crate::parse_table(value)?),
+                "pref" if !delete => priority = Some(crate::parse_u32(value)?),
+                _ => return Err(RuleParseError::InvalidAttr(attr.to_string())),
+            }
+        }
+
+        let rule = Self {
+            version,
+            table: table.ok_or(RuleParseError::NoKernelTable)?,
+            priority,
+            line: 0,
+        };
+
+        if rule.builtin().is_empty() {
+            return Err(RuleParseError::InvalidKernelTable(rule.table));
+        }
+        if !delete && rule.priority.is_none() {
+            return Err(RuleParseError::NoPriority);
+        }
+
+        Ok(rule)
+    }
+}
+
+/// Returns the priority of the rule the kernel creates for a table, if any.
+fn builtin_priority(family: u8, table: u32) -> Option<u32> {
+    match table {
+        rtnl::RT_TABLE_LOCAL => Some(LOCAL_PRIORITY),
+        rtnl::RT_TABLE_MAIN => Some(MAIN_PRIORITY),
+        rtnl::RT_TABLE_DEFAULT if family == libc::AF_INET as u8 => Some(DEFAULT_PRIORITY),
+        _ => None,
+    }
+}
+
+fn exists_ok(res: io::Result<()>) -> io::Result<()> {
+    match res {
+        Err(e) if e.raw_os_error() == Some(libc::EEXIST) => Ok(()),
+        res => res,
+    }
+}
+
+fn absent_ok(res: io::Result<()>) -> io::Result<()> {
+    match res {
+        Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(()),
+        res => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a line, checking that it parses the same as it is shown, and returns that.
+    fn round_trip(line: &str) -> String {
+        let rule: KernelRule = line.parse().unwrap_or_else(|e| panic!("{}: {}", line, e));
+        let shown = rule.to_string();
+
+        let again: KernelRule = shown.parse().unwrap();
+        assert_eq!(again.to_string(), shown);
+
+        shown
+    }
+
+    #[test]
+    fn moves_and_removals() {
+        assert_eq!(
+            round_trip("kernel set table main pref 1000"),
+            "kernel set table main pref 1000"
+        );
+        assert_eq!(
+            round_trip("kernel4 set table 253 priority 40000"),
+            "kernel4 set table default pref 40000"
+        );
+        assert_eq!(
+            round_trip("kernel6 del table local"),
+            "kernel6 del table local"
+        );
+
+        assert!(matches!(
+            "kernel set table main".parse::<KernelRule>(),
+            Err(RuleParseError::NoPriority)
+        ));
+        assert!(matches!(
+            "kernel del table main pref 1000".parse::<KernelRule>(),
+            Err(RuleParseError::InvalidAttr(attr)) if attr == "pref"
+        ));
+        // The kernel only creates a default rule for IPv4.
+        assert!(matches!(
+            "kernel6 del table default".parse::<KernelRule>(),
+            Err(RuleParseError::InvalidKernelTable(253))
+        ));
+        assert!(matches!(
+            "kernel del table 100".parse::<KernelRule>(),
+            Err(RuleParseError::InvalidKernelTable(100))
+        ));
+    }
+}
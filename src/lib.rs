@@ -0,0 +1,289 @@
+//! The configuration model of rtd: static routes and routing policy rules,
+//! their parsers and the functions that apply them via netlink.
+//!
+//! The `rsdsl_rtd` binary is a thin daemon around this library.
+
+pub mod netns;
+pub mod rtnl;
+pub mod vars;
+
+mod attrs;
+mod backend;
+mod blackhole;
+mod bypass;
+mod classless;
+mod drift;
+mod encap;
+#[cfg(feature = "ffi")]
+mod ffi;
+mod isolate;
+mod kernel_rule;
+mod metric;
+mod mroute;
+mod multipath;
+mod neigh;
+mod plan;
+mod route;
+mod rule;
+mod schedule;
+mod sysctl;
+mod text;
+mod vrf;
+
+pub use attrs::RouteAttrs;
+pub use backend::{Backend, Iproute2, Mock, Op};
+pub use blackhole::{Blackhole, Bogons, PrefixList, RejectKind};
+pub use bypass::Bypass;
+pub use classless::Classless;
+pub use drift::Drift;
+pub use encap::{Encap, EncapType};
+pub use isolate::Isolate;
+pub use kernel_rule::KernelRule;
+pub use metric::LinkMetrics;
+pub use mroute::{Mroute, Mrouter};
+pub use multipath::Balance;
+pub use neigh::{Neighbor, NeighborParseError, Neighbors};
+pub use plan::{plan, Installed, Reason, Step};
+pub use route::{
+    Probe, Route, RouteBuilder, RouteDef, RouteParseError, Routes, DEFAULT_WAN, DSLITE_LINK,
+};
+pub use rule::{Rule, RuleBuilder, RuleParseError, RuleVersion, Rules};
+pub use schedule::Schedule;
+pub use sysctl::{Sysctl, SysctlKey};
+pub use text::{attr_pairs, AttrError};
+pub use vrf::Vrf;
+
+pub use rsdsl_netlinklib::rule::RuleAction;
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
+
+/// How often an operation is retried after a transient error by default.
+const RETRIES: u32 = 3;
+/// The default delay before the first retry, doubling with every further one.
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// The retry policy, see `set_retry_policy`.
+static RETRY_COUNT: AtomicU32 = AtomicU32::new(RETRIES);
+static RETRY_DELAY_MS: AtomicU64 = AtomicU64::new(RETRY_DELAY.as_millis() as u64);
+
+/// The names of tables beyond the built-in ones, see `register_table_names`.
+static TABLE_NAMES: RwLock<BTreeMap<String, u32>> = RwLock::new(BTreeMap::new());
+
+/// An error applying a route or rule via netlink.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SetupError {
+    GatewayUnreachable(std::net::IpAddr, String),
+    HalfApplied(Box<SetupError>),
+    /// The error message of ip(8), see `Iproute2`.
+    Ip(String),
+    Netlink(std::io::Error),
+    Netlinklib(rsdsl_netlinklib::Error),
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GatewayUnreachable(rtr, link) => write!(
+                f,
+                "gateway {} is outside the networks of {} (add an address covering it or set \"onlink true\")",
+                rtr, link
+            )?,
+            Self::HalfApplied(e) => write!(
+                f,
+                "IPv6 half: {} (IPv4 half couldn't be rolled back and stays installed)",
+                e
+            )?,
+            Self::Ip(e) => write!(f, "ip: {}", e)?,
+            Self::Netlink(e) => write!(f, "netlink: {}", e)?,
+            Self::Netlinklib(e) => write!(f, "rsdsl_netlinklib: {}", e)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a number in decimal or, prefixed with `0x`, hexadecimal notation.
+/// Firewall configurations usually express marks in hex.
+pub fn parse_u32(s: &str) -> Result<u32, std::num::ParseIntError> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+/// Parses a routing table, accepting the names of the tables
+/// the kernel creates by itself and those registered
+/// with `register_table_names` in addition to numbers.
+pub fn parse_table(s: &str) -> Result<u32, std::num::ParseIntError> {
+    match s {
+        "main" => Ok(rtnl::RT_TABLE_MAIN),
+        "local" => Ok(rtnl::RT_TABLE_LOCAL),
+        "default" => Ok(rtnl::RT_TABLE_DEFAULT),
+        _ => match TABLE_NAMES.read().unwrap_or_else(|e| e.into_inner()).get(s) {
+            Some(table) => Ok(*table),
+            None => parse_u32(s),
+        },
+    }
+}
+
+/// Makes `parse_table` accept the given names for tables,
+/// replacing the names registered before.
+pub fn register_table_names(names: impl IntoIterator<Item = (String, u32)>) {
+    *TABLE_NAMES.write().unwrap_or_else(|e| e.into_inner()) = names.into_iter().collect();
+}
+
+/// Entries are managed unless configured otherwise (`managed false`).
+pub(crate) fn managed_by_default() -> bool {
+    true
+}
+
+/// Splits a trailing `# comment` off a configuration line.
+/// The `#` has to start a word so that values may still contain it.
+pub(crate) fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let start = line
+        .char_indices()
+        .find(|&(i, c)| {
+            c == '#'
+                && line[..i]
+                    .chars()
+                    .next_back()
+                    .is_none_or(char::is_whitespace)
+        })
+        .map(|(i, _)| i);
+
+    match start {
+        Some(i) => (line[..i].trim_end(), Some(line[i + 1..].trim())),
+        None => (line, None),
+    }
+}
+
+/// Lowercases the keywords of a configuration line, i.e. the version,
+/// the command and the names of the attributes starting at word `first_attr`.
+/// Values are left alone, they may be case-sensitive (e.g. interface names).
+/// The line is borrowed if its keywords are lowercase already, as they usually are.
+pub(crate) fn lowercase_keywords(line: &str, first_attr: usize) -> Cow<'_, str> {
+    let is_keyword = |i: usize| i < 2 || (i >= first_attr && (i - first_attr).is_multiple_of(2));
+    let lowercase = line
+        .split_whitespace()
+        .enumerate()
+        .all(|(i, word)| !is_keyword(i) || !word.chars().any(char::is_uppercase));
+    if lowercase {
+        return Cow::Borrowed(line);
+    }
+
+    let mut s = String::with_capacity(line.len());
+    for (i, word) in line.split_whitespace().enumerate() {
+        if i > 0 {
+            s.push(' ');
+        }
+        if is_keyword(i) {
+            s.push_str(&word.to_lowercase());
+        } else {
+            s.push_str(word);
+        }
+    }
+
+    Cow::Owned(s)
+}
+
+impl SetupError {
+    /// Reports whether the kernel doesn't have the entry an operation refers to,
+    /// e.g. when removing a route that doesn't exist.
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            Self::Netlink(e) => matches!(e.raw_os_error(), Some(libc::ESRCH | libc::ENOENT)),
+            // The error code of the kernel is only available as text.
+            // Only its strerror(3) form counts, a missing link is no missing entry.
+            Self::Netlinklib(_) | Self::Ip(_) => {
+                let e = self.to_string();
+                ["No such process", "No such file or directory"]
+                    .iter()
+                    .any(|msg| e.contains(msg))
+            }
+            _ => false,
+        }
+    }
+
+    /// Reports whether the kernel was temporarily unable to process a request,
+    /// e.g. because its socket buffers were full.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Netlink(e) => matches!(
+                e.raw_os_error(),
+                Some(libc::ENOBUFS | libc::EBUSY | libc::EAGAIN)
+            ),
+            Self::Netlinklib(_) | Self::Ip(_) => {
+                let e = self.to_string();
+                [
+                    "No buffer space available",
+                    "Device or resource busy",
+                    "Resource temporarily unavailable",
+                ]
+                .iter()
+                .any(|msg| e.contains(msg))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Runs a netlink operation, retrying it a few times with increasing delays
+/// if it fails transiently. Large configurations on slow CPUs can exhaust
+/// the kernel's buffers for a moment.
+pub(crate) fn retry<T, F>(mut op: F) -> Result<T, SetupError>
+where
+    F: FnMut() -> Result<T, SetupError>,
+{
+    let mut delay = Duration::from_millis(RETRY_DELAY_MS.load(Ordering::Relaxed));
+    for _ in 0..RETRY_COUNT.load(Ordering::Relaxed) {
+        match op() {
+            Err(e) if e.is_transient() => {
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            res => return res,
+        }
+    }
+
+    op()
+}
+
+/// Sets how often transient errors are retried and the delay before
+/// the first retry, keeping the defaults for the parts that are `None`.
+pub fn set_retry_policy(retries: Option<u32>, delay: Option<Duration>) {
+    RETRY_COUNT.store(retries.unwrap_or(RETRIES), Ordering::Relaxed);
+    RETRY_DELAY_MS.store(
+        delay.unwrap_or(RETRY_DELAY).as_millis() as u64,
+        Ordering::Relaxed,
+    );
+}
+
+impl From<std::io::Error> for SetupError {
+    fn from(e: std::io::Error) -> SetupError {
+        SetupError::Netlink(e)
+    }
+}
+
+impl From<rsdsl_netlinklib::Error> for SetupError {
+    fn from(e: rsdsl_netlinklib::Error) -> SetupError {
+        SetupError::Netlinklib(e)
+    }
+}
+
+impl std::error::Error for SetupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::GatewayUnreachable(..) | Self::Ip(_) => None,
+            Self::HalfApplied(e) => Some(e.as_ref()),
+            Self::Netlink(e) => Some(e),
+            Self::Netlinklib(e) => Some(e),
+        }
+    }
+}
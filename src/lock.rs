@@ -0,0 +1,197 @@
+//! Advisory locking of the configuration files.
+//!
+//! Tools writing the configuration take the exclusive lock (or use `write`
+//! or `edit`),
+//! rtd takes the shared one while reading so that it never sees a file
+//! that is half written. The lock is a separate file since writers
+//! usually replace the configuration files rather than modifying them.
+
+use crate::{log, tables};
+use crate::{NEIGHBORS_PATH, ROUTES_PATH, RULES_PATH};
+
+use rsdsl_rtd::{Neighbors, Routes, Rules};
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const LOCK_PATH: &str = "/data/rtd.lock";
+/// How long to wait for the lock before giving up.
+const TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug)]
+pub enum WriteError {
+    Aborted,
+    Editor(io::Error),
+    EditorFailed(std::process::ExitStatus),
+    /// An argument and the usage of the command.
+    InvalidArg(String, &'static str),
+    InvalidFile(String),
+    Lock(io::Error),
+    NoFile,
+    Parse(String),
+    ReadConfig(io::Error),
+    ReadInput(io::Error),
+    Write(io::Error),
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Aborted => write!(f, "aborted, configuration unchanged")?,
+            Self::Editor(e) => write!(f, "run editor: {}", e)?,
+            Self::EditorFailed(status) => write!(f, "editor failed: {}", status)?,
+            Self::InvalidArg(a, usage) => write!(f, "invalid argument {} (want {})", a, usage)?,
+            Self::InvalidFile(file) => write!(
+                f,
+                "invalid file {} (want \"routes\", \"rules\" or \"neighbors\")",
+                file
+            )?,
+            Self::Lock(e) => write!(f, "lock configuration ({}): {}", LOCK_PATH, e)?,
+            Self::NoFile => write!(
+                f,
+                "missing file (want \"routes\", \"rules\" or \"neighbors\")"
+            )?,
+            Self::Parse(e) => write!(f, "refuse invalid configuration: {}", e)?,
+            Self::ReadConfig(e) => write!(f, "read configuration: {}", e)?,
+            Self::ReadInput(e) => write!(f, "read standard input: {}", e)?,
+            Self::Write(e) => write!(f, "write configuration: {}", e)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for WriteError {}
+
+/// A held lock, released when dropped.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+/// Takes the lock for reading the configuration.
+pub fn shared() -> io::Result<Lock> {
+    lock(libc::LOCK_SH)
+}
+
+/// Takes the lock for writing the configuration.
+pub fn exclusive() -> io::Result<Lock> {
+    lock(libc::LOCK_EX)
+}
+
+fn lock(op: libc::c_int) -> io::Result<Lock> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(LOCK_PATH)?;
+
+    let start = Instant::now();
+    loop {
+        // SAFETY: Plain flock(2) call on a file descriptor we own.
+        if unsafe { libc::flock(file.as_raw_fd(), op | libc::LOCK_NB) } == 0 {
+            return Ok(Lock { _file: file });
+        }
+
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::WouldBlock {
+            return Err(e);
+        }
+        if start.elapsed() >= TIMEOUT {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "held by another process",
+            ));
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// `write routes|rules|neighbors`: replaces a configuration file
+/// with standard input under the lock if it is valid.
+pub fn write(args: &[String]) -> Result<(), WriteError> {
+    let file = match args {
+        [] => return Err(WriteError::NoFile),
+        [file] => file,
+        [_, extra, ..] => return Err(WriteError::InvalidArg(extra.clone(), "<file>")),
+    };
+
+    let mut content = String::new();
+    io::stdin()
+        .read_to_string(&mut content)
+        .map_err(WriteError::ReadInput)?;
+
+    let path = config_path(file)?;
+    validate(path, &content)?;
+    install(path, &content)
+}
+
+/// Returns the path of a configuration file by its short name.
+pub fn config_path(file: &str) -> Result<&'static str, WriteError> {
+    match file {
+        "routes" => Ok(ROUTES_PATH),
+        "rules" => Ok(RULES_PATH),
+        "neighbors" => Ok(NEIGHBORS_PATH),
+        _ => Err(WriteError::InvalidFile(file.to_string())),
+    }
+}
+
+/// Checks a configuration file with the parser.
+pub fn validate(path: &str, content: &str) -> Result<(), WriteError> {
+    tables::load();
+
+    let res = match path {
+        ROUTES_PATH => content
+            .parse::<Routes>()
+            .map(drop)
+            .map_err(|e| e.to_string()),
+        RULES_PATH => content
+            .parse::<Rules>()
+            .map(drop)
+            .map_err(|e| e.to_string()),
+        _ => content
+            .parse::<Neighbors>()
+            .map(drop)
+            .map_err(|e| e.to_string()),
+    };
+
+    res.map_err(WriteError::Parse)
+}
+
+/// Replaces a configuration file under the lock.
+pub fn install(path: &str, content: &str) -> Result<(), WriteError> {
+    let _lock = exclusive().map_err(WriteError::Lock)?;
+
+    write_atomic(Path::new(path), content.as_bytes()).map_err(WriteError::Write)?;
+
+    log::info!(General, "wrote {}", path);
+    Ok(())
+}
+
+/// Replaces a file by way of a temporary one so that readers see either
+/// version in full. Both the content and the rename are synced to disk,
+/// a power loss mustn't leave an empty file behind.
+pub fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp, path)?;
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
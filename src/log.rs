@@ -0,0 +1,607 @@
+//! Log output in either the traditional `[level] message` form
+//! or as one JSON object per line for log shippers,
+//! optionally copied to the system log and a size-limited log file.
+//! On a terminal the levels are colored and the actions of entries aligned.
+//!
+//! Records below the configured level of their subsystem are discarded.
+//! Warnings and errors repeating within a minute, e.g. an entry failing
+//! on every reconciliation, are only counted. The count is reported
+//! with the next occurrence after that.
+
+use crate::term::{self, Color, Paint};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const FORMAT_VAR: &str = "RTD_LOG_FORMAT";
+const LEVEL_VAR: &str = "RTD_LOG_LEVEL";
+const SYSLOG_VAR: &str = "RTD_SYSLOG";
+const SYSLOG_PATH: &str = "/dev/log";
+const SYSLOG_IDENT: &str = "rtd";
+const FILE_VAR: &str = "RTD_LOG_FILE";
+const FILE_SIZE_VAR: &str = "RTD_LOG_FILE_SIZE";
+const FILE_KEEP_VAR: &str = "RTD_LOG_FILE_KEEP";
+const FILE_SIZE_DEFAULT: u64 = 1024 * 1024;
+const FILE_KEEP_DEFAULT: u32 = 3;
+/// How long a warning or error is suppressed after it has been emitted.
+const REPEAT_WINDOW: Duration = Duration::from_secs(60);
+/// The number of recent warnings and errors beyond which expired ones are forgotten.
+const REPEAT_CAPACITY: usize = 256;
+
+/// The warnings and errors emitted recently, by level, subsystem and text.
+static RECENT: Mutex<Option<HashMap<RecentKey, Recent>>> = Mutex::new(None);
+
+type RecentKey = (Level, Subsystem, String);
+
+#[derive(Debug)]
+struct Recent {
+    emitted: Instant,
+    suppressed: u64,
+}
+
+macro_rules! log_debug {
+    ($subsystem:ident, $($arg:tt)*) => {
+        $crate::log::message(
+            $crate::log::Level::Debug,
+            $crate::log::Subsystem::$subsystem,
+            format_args!($($arg)*),
+        )
+    };
+}
+
+macro_rules! log_info {
+    ($subsystem:ident, $($arg:tt)*) => {
+        $crate::log::message(
+            $crate::log::Level::Info,
+            $crate::log::Subsystem::$subsystem,
+            format_args!($($arg)*),
+        )
+    };
+}
+
+macro_rules! log_warn {
+    ($subsystem:ident, $($arg:tt)*) => {
+        $crate::log::message(
+            $crate::log::Level::Warn,
+            $crate::log::Subsystem::$subsystem,
+            format_args!($($arg)*),
+        )
+    };
+}
+
+macro_rules! log_error {
+    ($subsystem:ident, $($arg:tt)*) => {
+        $crate::log::message(
+            $crate::log::Level::Error,
+            $crate::log::Subsystem::$subsystem,
+            format_args!($($arg)*),
+        )
+    };
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Plain,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "debug" => Some(Self::Debug),
+            "info" => Some(Self::Info),
+            "warn" => Some(Self::Warn),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+
+    /// The matching syslog severity.
+    fn severity(&self) -> u8 {
+        match self {
+            Self::Debug => 7,
+            Self::Info => 6,
+            Self::Warn => 4,
+            Self::Error => 3,
+        }
+    }
+}
+
+impl Level {
+    fn color(self) -> Color {
+        match self {
+            Self::Debug => Color::Gray,
+            Self::Info => Color::Green,
+            Self::Warn => Color::Yellow,
+            Self::Error => Color::Red,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Debug => write!(f, "debug"),
+            Self::Info => write!(f, "info"),
+            Self::Warn => write!(f, "warn"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The part of rtd a record originates from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Subsystem {
+    General,
+    Parser,
+    Netlink,
+    /// Changes of links, addresses and the kernel's routing entries rtd reacts to.
+    Events,
+}
+
+impl Subsystem {
+    const ALL: [Self; 4] = [Self::General, Self::Parser, Self::Netlink, Self::Events];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.to_string() == name)
+    }
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::General => write!(f, "general"),
+            Self::Parser => write!(f, "parser"),
+            Self::Netlink => write!(f, "netlink"),
+            Self::Events => write!(f, "events"),
+        }
+    }
+}
+
+/// Minimum levels, globally and per subsystem.
+#[derive(Debug)]
+struct Filter {
+    default: Level,
+    overrides: [Option<Level>; Subsystem::ALL.len()],
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            default: Level::Info,
+            overrides: [None; Subsystem::ALL.len()],
+        }
+    }
+}
+
+impl Filter {
+    /// Parses a spec like `warn,netlink=debug`: a bare level sets the default,
+    /// `subsystem=level` pairs override it for individual subsystems.
+    fn parse(spec: &str) -> Result<Self, String> {
+        let mut filter = Self::default();
+
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((subsystem, level)) => {
+                    let subsystem = Subsystem::from_name(subsystem)
+                        .ok_or_else(|| {
+                            format!(
+                                "invalid subsystem {} (want \"general\", \"parser\", \"netlink\" or \"events\")",
+                                subsystem
+                            )
+                        })?;
+                    let level = Level::from_name(level)
+                        .ok_or_else(|| format!("invalid level {}", level))?;
+
+                    filter.overrides[subsystem as usize] = Some(level);
+                }
+                None => {
+                    filter.default = Level::from_name(directive)
+                        .ok_or_else(|| format!("invalid level {}", directive))?;
+                }
+            }
+        }
+
+        Ok(filter)
+    }
+
+    fn enabled(&self, level: Level, subsystem: Subsystem) -> bool {
+        level >= self.overrides[subsystem as usize].unwrap_or(self.default)
+    }
+}
+
+#[derive(Debug)]
+struct Syslog {
+    sock: UnixDatagram,
+    facility: u8,
+}
+
+/// A log file that is rotated to `<path>.1` ... `<path>.<keep>`
+/// once it exceeds `max_size` bytes.
+#[derive(Debug)]
+struct LogFile {
+    path: String,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: u32,
+}
+
+impl LogFile {
+    fn open(path: String, max_size: u64, keep: u32) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+            keep,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+
+        if self.size >= self.max_size {
+            self.rotate()?;
+        }
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let from = format!("{}.{}", self.path, n);
+                if fs::metadata(&from).is_ok() {
+                    fs::rename(&from, format!("{}.{}", self.path, n + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, format!("{}.1", self.path))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Logger {
+    format: Format,
+    /// Whether plain output goes to a terminal.
+    terminal: bool,
+    filter: Filter,
+    syslog: Option<Syslog>,
+    file: Option<Mutex<LogFile>>,
+}
+
+/// Configures the logger from the environment.
+///
+/// `RTD_LOG_FORMAT` selects the output format ("plain" or "json"),
+/// `RTD_LOG_LEVEL` the level filter unless `level_spec` is given,
+/// `default_spec` applies if neither is,
+/// and `RTD_SYSLOG` names a syslog facility to additionally log to.
+/// `RTD_LOG_FILE` names a file to additionally log to, which is rotated
+/// at `RTD_LOG_FILE_SIZE` bytes keeping `RTD_LOG_FILE_KEEP` old files.
+pub fn init(level_spec: Option<&str>, default_spec: Option<&str>) {
+    let mut logger = Logger::default();
+    let mut warnings = Vec::new();
+
+    let env_spec = std::env::var(LEVEL_VAR).ok();
+    if let Some(spec) = level_spec.or(env_spec.as_deref()).or(default_spec) {
+        match Filter::parse(spec) {
+            Ok(filter) => logger.filter = filter,
+            Err(e) => warnings.push(format!("log level {}: {}", spec, e)),
+        }
+    }
+
+    match std::env::var(FORMAT_VAR).as_deref() {
+        Ok("json") => logger.format = Format::Json,
+        Ok("plain") | Err(_) => logger.format = Format::Plain,
+        Ok(v) => warnings.push(format!(
+            "invalid {} {} (want \"plain\" or \"json\")",
+            FORMAT_VAR, v
+        )),
+    }
+
+    if let Ok(v) = std::env::var(SYSLOG_VAR) {
+        match facility(&v) {
+            Some(facility) => match UnixDatagram::unbound()
+                .and_then(|sock| sock.connect(SYSLOG_PATH).map(|_| sock))
+            {
+                Ok(sock) => logger.syslog = Some(Syslog { sock, facility }),
+                Err(e) => warnings.push(format!("connect to syslog ({}): {}", SYSLOG_PATH, e)),
+            },
+            None => warnings.push(format!("invalid {} {} (want facility name)", SYSLOG_VAR, v)),
+        }
+    }
+
+    if let Ok(path) = std::env::var(FILE_VAR) {
+        let max_size = match std::env::var(FILE_SIZE_VAR).map(|v| v.parse()) {
+            Ok(Ok(size)) => size,
+            Ok(Err(e)) => {
+                warnings.push(format!("parse {}: {}", FILE_SIZE_VAR, e));
+                FILE_SIZE_DEFAULT
+            }
+            Err(_) => FILE_SIZE_DEFAULT,
+        };
+        let keep = match std::env::var(FILE_KEEP_VAR).map(|v| v.parse()) {
+            Ok(Ok(keep)) => keep,
+            Ok(Err(e)) => {
+                warnings.push(format!("parse {}: {}", FILE_KEEP_VAR, e));
+                FILE_KEEP_DEFAULT
+            }
+            Err(_) => FILE_KEEP_DEFAULT,
+        };
+
+        match LogFile::open(path.clone(), max_size, keep) {
+            Ok(file) => logger.file = Some(Mutex::new(file)),
+            Err(e) => warnings.push(format!("open log file ({}): {}", path, e)),
+        }
+    }
+
+    logger.terminal = logger.format == Format::Plain && term::stderr();
+
+    let _ = LOGGER.set(logger);
+
+    for warning in warnings {
+        log_warn!(General, "{}", warning);
+    }
+}
+
+fn facility(name: &str) -> Option<u8> {
+    Some(match name {
+        "kern" => 0,
+        "user" => 1,
+        "mail" => 2,
+        "daemon" => 3,
+        "auth" => 4,
+        "syslog" => 5,
+        "lpr" => 6,
+        "news" => 7,
+        "uucp" => 8,
+        "cron" => 9,
+        "authpriv" => 10,
+        "ftp" => 11,
+        "local0" => 16,
+        "local1" => 17,
+        "local2" => 18,
+        "local3" => 19,
+        "local4" => 20,
+        "local5" => 21,
+        "local6" => 22,
+        "local7" => 23,
+        _ => return None,
+    })
+}
+
+fn logger() -> &'static Logger {
+    LOGGER.get_or_init(Logger::default)
+}
+
+/// Logs a free-form message.
+pub fn message(level: Level, subsystem: Subsystem, msg: fmt::Arguments) {
+    if !logger().filter.enabled(level, subsystem) {
+        return;
+    }
+
+    let msg = msg.to_string();
+    write(
+        level,
+        subsystem,
+        &msg,
+        || serde_json::json!({ "message": msg }),
+    );
+}
+
+/// Logs the outcome of an action performed on a route or rule.
+/// The entry is shown in its alternate form, which includes the comment
+/// of its configuration line if it has one.
+pub fn entry(
+    level: Level,
+    subsystem: Subsystem,
+    action: &str,
+    entry: &dyn fmt::Display,
+    error: Option<&dyn fmt::Display>,
+) {
+    let logger = logger();
+    if !logger.filter.enabled(level, subsystem) {
+        return;
+    }
+
+    // The entries line up with each other on a terminal.
+    let width = if logger.terminal { 7 } else { 0 };
+    let msg = match error {
+        Some(e) => format!("{:<width$} {:#}: {}", action, entry, e),
+        None => format!("{:<width$} {:#}", action, entry),
+    };
+
+    write(level, subsystem, &msg, || {
+        let plain = entry.to_string();
+        let full = format!("{:#}", entry);
+        let comment = full
+            .strip_prefix(&plain)
+            .and_then(|s| s.strip_prefix(" # "));
+
+        let mut obj = serde_json::json!({
+            "entry": plain,
+            "action": action,
+        });
+        if let Some(comment) = comment {
+            obj["comment"] = comment.into();
+        }
+        if let Some(e) = error {
+            obj["error"] = e.to_string().into();
+        }
+
+        obj
+    });
+}
+
+/// Counts a warning or error if it has been emitted recently.
+/// Returns `None` if it is to be suppressed, otherwise the number
+/// of times it was repeated (and suppressed) since it was last emitted.
+fn repeats(level: Level, subsystem: Subsystem, msg: &str) -> Option<u64> {
+    if level < Level::Warn {
+        return Some(0);
+    }
+
+    let mut recent = RECENT.lock().unwrap_or_else(|e| e.into_inner());
+    let recent = recent.get_or_insert_with(HashMap::new);
+
+    let key = (level, subsystem, msg.to_string());
+    if let Some(entry) = recent.get_mut(&key) {
+        if entry.emitted.elapsed() < REPEAT_WINDOW {
+            entry.suppressed += 1;
+            return None;
+        }
+
+        let suppressed = std::mem::take(&mut entry.suppressed);
+        entry.emitted = Instant::now();
+        return Some(suppressed);
+    }
+
+    if recent.len() >= REPEAT_CAPACITY {
+        recent.retain(|_, entry| entry.emitted.elapsed() < REPEAT_WINDOW);
+    }
+    recent.insert(
+        key,
+        Recent {
+            emitted: Instant::now(),
+            suppressed: 0,
+        },
+    );
+
+    Some(0)
+}
+
+/// Emits a record to all configured outputs. `msg` is the plain text form,
+/// `json` builds the structured form if it is needed.
+fn write(level: Level, subsystem: Subsystem, msg: &str, json: impl FnOnce() -> serde_json::Value) {
+    let logger = logger();
+
+    let Some(suppressed) = repeats(level, subsystem, msg) else {
+        return;
+    };
+    let repeated;
+    let msg = match suppressed {
+        0 => msg,
+        n => {
+            repeated = format!("{} (message repeated {} times)", msg, n);
+            &repeated
+        }
+    };
+
+    let line = match logger.format {
+        Format::Plain => None,
+        Format::Json => {
+            let mut obj = json();
+            obj["level"] = level.to_string().into();
+            obj["subsystem"] = subsystem.to_string().into();
+            obj["timestamp"] = timestamp().into();
+            if suppressed > 0 {
+                obj["repeated"] = suppressed.into();
+            }
+
+            Some(obj.to_string())
+        }
+    };
+
+    match &line {
+        Some(line) => eprintln!("{}", line),
+        None if logger.terminal => {
+            let tag = format!("{:<7}", format!("[{}]", level));
+            eprintln!("{} {}", Paint(Some(level.color()), tag), msg)
+        }
+        None => eprintln!("[{}] {}", level, msg),
+    }
+
+    if let Some(syslog) = &logger.syslog {
+        let pri = syslog.facility * 8 + level.severity();
+        let packet = format!(
+            "<{}>{}[{}]: {}",
+            pri,
+            SYSLOG_IDENT,
+            std::process::id(),
+            line.as_deref().unwrap_or(msg)
+        );
+
+        // Losing syslog messages must not disrupt normal operation.
+        let _ = syslog.sock.send(packet.as_bytes());
+    }
+
+    if let Some(file) = &logger.file {
+        let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+
+        let res = match &line {
+            Some(line) => file.write_line(line),
+            None => file.write_line(&format!("{} [{}] {}", timestamp(), level, msg)),
+        };
+
+        // Don't recurse into the logger if the file is broken.
+        if let Err(e) = res {
+            eprintln!("[warn] write log file ({}): {}", file.path, e);
+        }
+    }
+}
+
+/// Formats the current time as an RFC 3339 UTC timestamp.
+pub fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    let secs = now.as_secs();
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // Civil date from days since the epoch, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        now.subsec_millis()
+    )
+}
+
+pub(crate) use {log_debug as debug, log_error as error, log_info as info, log_warn as warn};
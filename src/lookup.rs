@@ -0,0 +1,319 @@
+//! `route-get`: asks the kernel which route a packet would take
+//! and reconstructs the policy rule that led to it.
+
+use rsdsl_rtd::rtnl::{self, RouteMsg, RuleMsg};
+use rsdsl_rtd::AttrError;
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+#[derive(Debug)]
+pub enum LookupError {
+    DuplicateAttr(String),
+    InvalidAttr(String),
+    Netlink(io::Error),
+    NoAttrValue(String),
+    NoDst,
+    NoRoute(io::Error),
+    ParseAddr(std::net::AddrParseError),
+    ParseInt(std::num::ParseIntError),
+    SrcFamilyMismatch,
+    UnknownLink(String, io::Error),
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateAttr(a) => write!(f, "duplicate attribute {}", a)?,
+            Self::InvalidAttr(a) => write!(
+                f,
+                "invalid attribute {} (want \"src\", \"iif\" or \"fwmark\")",
+                a
+            )?,
+            Self::Netlink(e) => write!(f, "netlink: {}", e)?,
+            Self::NoAttrValue(a) => write!(f, "missing value for attribute {}", a)?,
+            Self::NoDst => write!(f, "missing destination address")?,
+            Self::NoRoute(e) => write!(f, "no route: {}", e)?,
+            Self::ParseAddr(e) => write!(f, "parse network address: {}", e)?,
+            Self::ParseInt(e) => write!(f, "parse integer: {}", e)?,
+            Self::SrcFamilyMismatch => write!(f, "source and destination family mismatch")?,
+            Self::UnknownLink(l, e) => write!(f, "unknown link {}: {}", l, e)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl From<io::Error> for LookupError {
+    fn from(e: io::Error) -> LookupError {
+        LookupError::Netlink(e)
+    }
+}
+
+impl From<std::net::AddrParseError> for LookupError {
+    fn from(e: std::net::AddrParseError) -> LookupError {
+        LookupError::ParseAddr(e)
+    }
+}
+
+impl From<std::num::ParseIntError> for LookupError {
+    fn from(e: std::num::ParseIntError) -> LookupError {
+        LookupError::ParseInt(e)
+    }
+}
+
+impl From<AttrError> for LookupError {
+    fn from(e: AttrError) -> LookupError {
+        match e {
+            AttrError::Duplicate(attr) => LookupError::DuplicateAttr(attr),
+            AttrError::NoValue(attr) => LookupError::NoAttrValue(attr),
+        }
+    }
+}
+
+impl std::error::Error for LookupError {}
+
+/// The packet properties a lookup is performed for.
+#[derive(Debug)]
+struct Flow<'a> {
+    dst: IpAddr,
+    src: Option<IpAddr>,
+    iif: Option<&'a str>,
+    fwmark: Option<u32>,
+}
+
+impl Flow<'_> {
+    /// Reports whether the selectors of a kernel rule match this flow.
+    fn matches(&self, rule: &RuleMsg) -> bool {
+        let unspecified = match self.dst {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+
+        let src_ok = rule.src.is_none_or(|prefix| {
+            rtnl::prefix_contains(prefix, rule.src_len, self.src.unwrap_or(unspecified))
+        });
+        let dst_ok = rule
+            .dst
+            .is_none_or(|prefix| rtnl::prefix_contains(prefix, rule.dst_len, self.dst));
+        let fwmark_ok = match (rule.fwmark, rule.fwmask) {
+            (None, None) => true,
+            (mark, mask) => {
+                self.fwmark.unwrap_or_default() & mask.unwrap_or(u32::MAX)
+                    == mark.unwrap_or_default()
+            }
+        };
+        // Locally generated packets are looked up with the loopback
+        // interface as their input interface.
+        let iif_ok = rule
+            .iifname
+            .as_ref()
+            .is_none_or(|iif| iif == self.iif.unwrap_or("lo"));
+        // Output lookups never carry an output interface.
+        let oif_ok = rule.oifname.is_none();
+
+        let matched = src_ok && dst_ok && fwmark_ok && iif_ok && oif_ok;
+        matched != (rule.flags & rtnl::FIB_RULE_INVERT != 0)
+    }
+}
+
+/// Performs a route lookup for the packet described by `args`
+/// and prints the rule and route that matched.
+pub fn route_get(args: &[String]) -> Result<(), LookupError> {
+    let mut words = args.iter().map(String::as_str);
+
+    let dst: IpAddr = words.next().ok_or(LookupError::NoDst)?.parse()?;
+
+    let attrs = rsdsl_rtd::attr_pairs(words, |attr| attr)?;
+
+    let mut flow = Flow {
+        dst,
+        src: None,
+        iif: None,
+        fwmark: None,
+    };
+
+    for (attr, value) in attrs {
+        match attr {
+            "src" => flow.src = Some(value.parse()?),
+            "iif" => flow.iif = Some(value),
+            "fwmark" => flow.fwmark = Some(rsdsl_rtd::parse_u32(value)?),
+            _ => return Err(LookupError::InvalidAttr(attr.to_string())),
+        }
+    }
+
+    let (family, full_len) = match dst {
+        IpAddr::V4(_) => (libc::AF_INET as u8, 32),
+        IpAddr::V6(_) => (libc::AF_INET6 as u8, 128),
+    };
+
+    if flow.src.is_some_and(|src| src.is_ipv4() != dst.is_ipv4()) {
+        return Err(LookupError::SrcFamilyMismatch);
+    }
+
+    let mut req = rtnl::rtmsg(
+        family,
+        full_len,
+        if flow.src.is_some() { full_len } else { 0 },
+        0,
+        0,
+        rtnl::RTM_F_LOOKUP_TABLE | rtnl::RTM_F_FIB_MATCH,
+    );
+    rtnl::put_addr(&mut req, rtnl::RTA_DST, dst);
+    if let Some(src) = flow.src {
+        rtnl::put_addr(&mut req, rtnl::RTA_SRC, src);
+    }
+    if let Some(iif) = flow.iif {
+        let index =
+            rtnl::link_index(iif).map_err(|e| LookupError::UnknownLink(iif.to_string(), e))?;
+        rtnl::put_attr(&mut req, rtnl::RTA_IIF, &index.to_ne_bytes());
+    }
+    if let Some(fwmark) = flow.fwmark {
+        rtnl::put_attr(&mut req, rtnl::RTA_MARK, &fwmark.to_ne_bytes());
+    }
+
+    let mut sock = rtnl::Socket::new()?;
+
+    let mut rules = sock.dump_rules(family)?;
+    rules.sort_by_key(|rule| rule.priority);
+
+    let route = match sock.request(rtnl::RTM_GETROUTE, 0, &req) {
+        Ok(msgs) => msgs.iter().find_map(|msg| RouteMsg::parse(msg)),
+        Err(e) => {
+            // Terminal rule actions make the lookup fail,
+            // show the rule responsible if there is one.
+            if let Some(rule) = rules
+                .iter()
+                .find(|rule| flow.matches(rule) && is_terminal(rule.action))
+            {
+                println!("rule: {}", DisplayRule(rule));
+            }
+
+            return Err(LookupError::NoRoute(e));
+        }
+    };
+
+    let route = route.ok_or(LookupError::NoRoute(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "empty reply",
+    )))?;
+
+    // The kernel skips to_table rules whose table has no matching route,
+    // so the first matching rule that leads to the result is the one used.
+    match rules.iter().find(|rule| {
+        flow.matches(rule)
+            && (is_terminal(rule.action)
+                || (rule.action == rtnl::FR_ACT_TO_TBL && rule.table == route.table))
+    }) {
+        Some(rule) => println!("rule: {}", DisplayRule(rule)),
+        None => println!("rule: ?"),
+    }
+
+    println!("route: {}", DisplayRoute(&route));
+
+    Ok(())
+}
+
+fn is_terminal(action: u8) -> bool {
+    matches!(
+        action,
+        rtnl::FR_ACT_BLACKHOLE | rtnl::FR_ACT_UNREACHABLE | rtnl::FR_ACT_PROHIBIT
+    )
+}
+
+pub struct DisplayRule<'a>(pub &'a RuleMsg);
+
+impl fmt::Display for DisplayRule<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rule = self.0;
+
+        match rule.family as i32 {
+            libc::AF_INET => write!(f, "rule4")?,
+            libc::AF_INET6 => write!(f, "rule6")?,
+            _ => write!(f, "rule")?,
+        }
+        write!(f, " priority {}", rule.priority)?;
+        if rule.flags & rtnl::FIB_RULE_INVERT != 0 {
+            write!(f, " invert true")?;
+        }
+        if let Some(fwmark) = rule.fwmark {
+            write!(f, " fwmark {}", fwmark)?;
+        }
+        if let Some(fwmask) = rule.fwmask.filter(|mask| *mask != u32::MAX) {
+            write!(f, " fwmask {}", fwmask)?;
+        }
+        if let Some(dst) = rule.dst {
+            write!(f, " dst {}/{}", dst, rule.dst_len)?;
+        }
+        if let Some(src) = rule.src {
+            write!(f, " src {}/{}", src, rule.src_len)?;
+        }
+        if let Some(iif) = &rule.iifname {
+            write!(f, " iif {}", iif)?;
+        }
+        if let Some(oif) = &rule.oifname {
+            write!(f, " oif {}", oif)?;
+        }
+        match rule.action {
+            rtnl::FR_ACT_TO_TBL => write!(f, " action to_table table {}", rule.table)?,
+            rtnl::FR_ACT_GOTO => write!(f, " action goto")?,
+            rtnl::FR_ACT_NOP => write!(f, " action nop")?,
+            rtnl::FR_ACT_BLACKHOLE => write!(f, " action blackhole")?,
+            rtnl::FR_ACT_UNREACHABLE => write!(f, " action unreachable")?,
+            rtnl::FR_ACT_PROHIBIT => write!(f, " action prohibit")?,
+            a => write!(f, " action {}", a)?,
+        }
+
+        Ok(())
+    }
+}
+
+pub struct DisplayRoute<'a>(pub &'a RouteMsg);
+
+impl fmt::Display for DisplayRoute<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let route = self.0;
+
+        let dst = match (route.family as i32, route.dst) {
+            (_, Some(dst)) => dst,
+            (libc::AF_INET6, None) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            (_, None) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        };
+
+        match dst {
+            IpAddr::V4(_) => write!(f, "route4 {}/{}", dst, route.dst_len)?,
+            IpAddr::V6(_) => write!(f, "route6 {}/{}", dst, route.dst_len)?,
+        }
+        match route.ty {
+            rtnl::RTN_UNICAST => {}
+            rtnl::RTN_LOCAL => write!(f, " type local")?,
+            rtnl::RTN_BROADCAST => write!(f, " type broadcast")?,
+            rtnl::RTN_ANYCAST => write!(f, " type anycast")?,
+            rtnl::RTN_MULTICAST => write!(f, " type multicast")?,
+            rtnl::RTN_BLACKHOLE => write!(f, " type blackhole")?,
+            rtnl::RTN_UNREACHABLE => write!(f, " type unreachable")?,
+            rtnl::RTN_PROHIBIT => write!(f, " type prohibit")?,
+            rtnl::RTN_THROW => write!(f, " type throw")?,
+            t => write!(f, " type {}", t)?,
+        }
+        if let Some(rtr) = route.gateway {
+            write!(f, " via {}", rtr)?;
+        }
+        write!(f, " table {}", route.table)?;
+        if let Some(metric) = route.metric {
+            write!(f, " metric {}", metric)?;
+        }
+        if let Some(oif) = route.oif {
+            match rtnl::link_name(oif) {
+                Some(link) => write!(f, " dev {}", link)?,
+                None => write!(f, " dev #{}", oif)?,
+            }
+        }
+        if let Some(prefsrc) = route.prefsrc {
+            write!(f, " (src {})", prefsrc)?;
+        }
+
+        Ok(())
+    }
+}
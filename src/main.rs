@@ -1,187 +1,90 @@
-use std::collections::HashMap;
+mod activation;
+mod audit;
+mod balance;
+mod bench;
+mod completion;
+mod confirm;
+mod control;
+mod dhcp;
+mod diff;
+mod dns;
+mod dryrun;
+mod dslite;
+mod edit;
+mod explain;
+mod failover;
+mod guard;
+mod guest;
+mod health;
+mod history;
+mod installed;
+mod lock;
+mod log;
+mod lookup;
+mod mcast;
+mod monitor;
+mod notify;
+mod pool;
+mod probe;
+mod reload;
+mod rescue;
+mod resync;
+mod rtbh;
+mod selftest;
+mod settings;
+mod shutdown;
+mod signals;
+mod snapshot;
+mod status;
+mod tables;
+mod term;
+mod timing;
+mod vpn;
+mod wildcard;
+
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::BTreeMap;
 use std::fmt;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::hash::Hash;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use rsdsl_netlinklib::blocking::Connection;
-use rsdsl_netlinklib::rule::RuleAction;
+use rsdsl_rtd::{
+    netns, vars, Backend, Balance, Bogons, Bypass, Classless, Iproute2, Isolate, KernelRule,
+    Mroute, Neighbor, NeighborParseError, Neighbors, PrefixList, Route, RouteDef, RouteParseError,
+    Routes, Rule, RuleParseError, Rules, SetupError, Sysctl, Vrf,
+};
 
 const ROUTES_PATH: &str = "/data/static.rt";
 const RULES_PATH: &str = "/data/policies.rl";
+const NEIGHBORS_PATH: &str = "/data/neighbors.nb";
 
-#[derive(Debug)]
-enum RouteParseError {
-    DstNotIpv4,
-    DstNotIpv6,
-    DuplicateAttr(String),
-    InvalidAttr(String),
-    InvalidCidr(String),
-    InvalidCmd(String),
-    InvalidVersion(String),
-    NoAttrValue(String),
-    NoCmd,
-    NoDst,
-    NoLink,
-    NoVersion,
-    ParseAddr(std::net::AddrParseError),
-    ParseBool(std::str::ParseBoolError),
-    ParseInt(std::num::ParseIntError),
-    RtrNotIpv4,
-    RtrNotIpv6,
-}
-
-impl fmt::Display for RouteParseError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::DstNotIpv4 => write!(f, "route4 with missing or non-IPv4 destination")?,
-            Self::DstNotIpv6 => write!(f, "route6 with missing or non-IPv6 destination")?,
-            Self::DuplicateAttr(a) => write!(f, "duplicate attribute {}", a)?,
-            Self::InvalidAttr(a) => write!(f, "invalid attribute {}", a)?,
-            Self::InvalidCidr(c) => write!(f, "invalid CIDR {} (want exactly 1 /)", c)?,
-            Self::InvalidCmd(c) => write!(f, "invalid command {} (want \"add\" or \"del\")", c)?,
-            Self::InvalidVersion(v) => {
-                write!(f, "invalid version: {} (want \"route4\" or \"route6\")", v)?
-            }
-            Self::NoAttrValue(a) => write!(f, "missing value for attribute {}", a)?,
-            Self::NoCmd => write!(f, "missing command (want \"add\" or \"del\")")?,
-            Self::NoDst => write!(f, "missing destination network (\"to\" attribute)")?,
-            Self::NoLink => write!(f, "missing network interface (\"dev\" attribute)")?,
-            Self::NoVersion => write!(f, "missing version (want \"route4\" or \"route6\")")?,
-            Self::ParseAddr(e) => write!(f, "parse network address: {}", e)?,
-            Self::ParseBool(e) => write!(f, "parse bool: {}", e)?,
-            Self::ParseInt(e) => write!(f, "parse integer: {}", e)?,
-            Self::RtrNotIpv4 => write!(f, "route4 with non-IPv4 gateway")?,
-            Self::RtrNotIpv6 => write!(f, "route6 with non-IPv6 gateway")?,
-        }
-
-        Ok(())
-    }
-}
-
-impl From<std::net::AddrParseError> for RouteParseError {
-    fn from(e: std::net::AddrParseError) -> RouteParseError {
-        RouteParseError::ParseAddr(e)
-    }
-}
-
-impl From<std::str::ParseBoolError> for RouteParseError {
-    fn from(e: std::str::ParseBoolError) -> RouteParseError {
-        RouteParseError::ParseBool(e)
-    }
-}
-
-impl From<std::num::ParseIntError> for RouteParseError {
-    fn from(e: std::num::ParseIntError) -> RouteParseError {
-        RouteParseError::ParseInt(e)
-    }
-}
-
-impl std::error::Error for RouteParseError {}
-
-#[derive(Debug)]
-enum RuleParseError {
-    DstIllegal,
-    DstNotIpv4,
-    DstNotIpv6,
-    DuplicateAttr(String),
-    InvalidAction(String),
-    InvalidAttr(String),
-    InvalidCidr(String),
-    InvalidCmd(String),
-    InvalidVersion(String),
-    NoAction,
-    NoAttrValue(String),
-    NoCmd,
-    NoVersion,
-    ParseAddr(std::net::AddrParseError),
-    ParseBool(std::str::ParseBoolError),
-    ParseInt(std::num::ParseIntError),
-    SrcIllegal,
-    SrcNotIpv4,
-    SrcNotIpv6,
-}
-
-impl fmt::Display for RuleParseError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::DstIllegal => write!(f, "protocol-agnostic rule with destination prefix")?,
-            Self::DstNotIpv4 => write!(f, "rule4 with non-IPv4 destination")?,
-            Self::DstNotIpv6 => write!(f, "rule6 with non-IPv6 destination")?,
-            Self::DuplicateAttr(a) => write!(f, "duplicate attribute {}", a)?,
-            Self::InvalidAction(a) => write!(f, "invalid action {}", a)?,
-            Self::InvalidAttr(a) => write!(f, "invalid attribute {}", a)?,
-            Self::InvalidCidr(c) => write!(f, "invalid CIDR {} (want exactly 1 /)", c)?,
-            Self::InvalidCmd(c) => write!(f, "invalid command {} (want \"add\" or \"del\")", c)?,
-            Self::InvalidVersion(v) => write!(
-                f,
-                "invalid version: {} (want \"rule\", \"rule4\" or \"rule6\")",
-                v
-            )?,
-            Self::NoAction => write!(f, "missing action (\"action\" attribute)")?,
-            Self::NoAttrValue(a) => write!(f, "missing value for attribute {}", a)?,
-            Self::NoCmd => write!(f, "missing command (want \"add\" or \"del\")")?,
-            Self::NoVersion => {
-                write!(f, "missing version (want \"rule\", \"rule4\" or \"rule6\")")?
-            }
-            Self::ParseAddr(e) => write!(f, "parse network address: {}", e)?,
-            Self::ParseBool(e) => write!(f, "parse bool: {}", e)?,
-            Self::ParseInt(e) => write!(f, "parse integer: {}", e)?,
-            Self::SrcIllegal => write!(f, "protocol-agnostic rule with source prefix")?,
-            Self::SrcNotIpv4 => write!(f, "rule4 with non-IPv4 source")?,
-            Self::SrcNotIpv6 => write!(f, "rule6 with non-IPv6 source")?,
-        }
-
-        Ok(())
-    }
-}
-
-impl From<std::net::AddrParseError> for RuleParseError {
-    fn from(e: std::net::AddrParseError) -> RuleParseError {
-        RuleParseError::ParseAddr(e)
-    }
-}
-
-impl From<std::str::ParseBoolError> for RuleParseError {
-    fn from(e: std::str::ParseBoolError) -> RuleParseError {
-        RuleParseError::ParseBool(e)
-    }
-}
-
-impl From<std::num::ParseIntError> for RuleParseError {
-    fn from(e: std::num::ParseIntError) -> RuleParseError {
-        RuleParseError::ParseInt(e)
-    }
-}
-
-impl std::error::Error for RuleParseError {}
-
-#[derive(Debug)]
-enum SetupError {
-    Netlinklib(rsdsl_netlinklib::Error),
-}
-
-impl fmt::Display for SetupError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Netlinklib(e) => write!(f, "rsdsl_netlinklib: {}", e)?,
-        }
+/// The name of the binary, rtd's own or `rtdctl`, see rtdctl.rs.
+const BIN: &str = env!("CARGO_BIN_NAME");
+const CTL_BIN: &str = "rtdctl";
+/// The commands `rtdctl` takes, rtd takes them as well.
+const CTL_COMMANDS: &[&str] = &["write", "edit", "completion"];
 
-        Ok(())
-    }
-}
+const VAR_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
-impl From<rsdsl_netlinklib::Error> for SetupError {
-    fn from(e: rsdsl_netlinklib::Error) -> SetupError {
-        SetupError::Netlinklib(e)
-    }
-}
+/// The delays between attempts to connect to netlink, doubling up to the maximum.
+const CONNECT_DELAY: Duration = Duration::from_millis(250);
+const CONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
 
-impl std::error::Error for SetupError {}
+/// Whether the initial apply pass counts as done, see `pass_done`.
+static PASS_DONE: AtomicBool = AtomicBool::new(false);
 
 #[derive(Debug)]
 enum Error {
+    ParseNeighbors(NeighborParseError),
     ParseRoutes(RouteParseError),
     ParseRules(RuleParseError),
+    ReadNeighbors(std::io::Error),
+    ReadProtected(std::io::Error),
     ReadRoutes(std::io::Error),
     ReadRules(std::io::Error),
     Setup(SetupError),
@@ -190,17 +93,41 @@ enum Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Self::ParseNeighbors(e) => write!(f, "parse neighbors: {}", e)?,
             Self::ParseRoutes(e) => write!(f, "parse routes: {}", e)?,
             Self::ParseRules(e) => write!(f, "parse rules: {}", e)?,
+            Self::ReadNeighbors(e) => write!(f, "read neighbors ({}): {}", NEIGHBORS_PATH, e)?,
+            Self::ReadProtected(e) => write!(f, "read protected prefixes: {}", e)?,
             Self::ReadRoutes(e) => write!(f, "read routes ({}): {}", ROUTES_PATH, e)?,
             Self::ReadRules(e) => write!(f, "read rules ({}): {}", RULES_PATH, e)?,
-            Self::Setup(e) => write!(f, "set up route/rule: {}", e)?,
+            Self::Setup(e) => write!(f, "set up route/rule/neighbor: {}", e)?,
         }
 
         Ok(())
     }
 }
 
+impl Error {
+    fn subsystem(&self) -> log::Subsystem {
+        match self {
+            Self::ParseNeighbors(_) | Self::ParseRoutes(_) | Self::ParseRules(_) => {
+                log::Subsystem::Parser
+            }
+            Self::ReadNeighbors(_)
+            | Self::ReadProtected(_)
+            | Self::ReadRoutes(_)
+            | Self::ReadRules(_) => log::Subsystem::Parser,
+            Self::Setup(_) => log::Subsystem::Netlink,
+        }
+    }
+}
+
+impl From<NeighborParseError> for Error {
+    fn from(e: NeighborParseError) -> Error {
+        Error::ParseNeighbors(e)
+    }
+}
+
 impl From<RouteParseError> for Error {
     fn from(e: RouteParseError) -> Error {
         Error::ParseRoutes(e)
@@ -221,659 +148,1422 @@ impl From<SetupError> for Error {
 
 impl std::error::Error for Error {}
 
-#[derive(Debug)]
-enum RouteVersion {
-    Ipv4,
-    Ipv6,
-}
+fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+
+    let mut log_level = None;
+    let mut force = false;
+    let mut monitor = false;
+    let mut confirm = None;
+    let mut iproute2 = false;
+    let mut invalid_opt = None;
+    while let Some(opt) = args.next_if(|arg| arg.starts_with("--")) {
+        match opt.as_str() {
+            "--log-level" => match args.next() {
+                Some(spec) => log_level = Some(spec),
+                None => invalid_opt = Some(opt),
+            },
+            "--force" => force = true,
+            "--monitor" => monitor = true,
+            "--confirm" => match args.next().map(|secs| (secs.parse(), secs)) {
+                Some((Ok(secs), _)) => confirm = Some(Duration::from_secs(secs)),
+                Some((Err(_), secs)) => invalid_opt = Some(format!("{} {}", opt, secs)),
+                None => invalid_opt = Some(opt),
+            },
+            "--backend" => match args.next() {
+                Some(backend) if backend == "netlink" => iproute2 = false,
+                Some(backend) if backend == "ip" => iproute2 = true,
+                Some(backend) => invalid_opt = Some(format!("{} {}", opt, backend)),
+                None => invalid_opt = Some(opt),
+            },
+            _ => invalid_opt = Some(opt),
+        }
+    }
 
-#[derive(Clone, Debug)]
-enum RouteDef {
-    V4(rsdsl_netlinklib::route::Route4),
-    V6(rsdsl_netlinklib::route::Route6),
-}
+    let warnings = settings::init();
+    log::init(log_level.as_deref(), settings::get().log_level.as_deref());
+    for warning in warnings {
+        log::warn!(General, "{}", warning);
+    }
 
-impl RouteDef {
-    fn add(self, c: &Connection) -> Result<(), SetupError> {
-        match self {
-            Self::V4(r) => c.route_add4(r)?,
-            Self::V6(r) => c.route_add6(r)?,
+    if let Some(opt) = invalid_opt {
+        log::error!(
+            General,
+            "invalid option {} (want \"--log-level <spec>\", \"--force\", \"--monitor\", \"--confirm <seconds>\" or \"--backend netlink|ip\")",
+            opt
+        );
+        std::process::exit(1);
+    }
+
+    let args: Vec<String> = args.collect();
+    if BIN == CTL_BIN {
+        match args.first() {
+            Some(cmd) if CTL_COMMANDS.contains(&cmd.as_str()) => {}
+            Some(cmd) => {
+                log::error!(
+                    General,
+                    "invalid command {} (want \"write\", \"edit\" or \"completion\")",
+                    cmd
+                );
+                std::process::exit(1);
+            }
+            None => {
+                log::error!(
+                    General,
+                    "missing command (want \"write\", \"edit\" or \"completion\")"
+                );
+                std::process::exit(1);
+            }
         }
+    }
 
-        Ok(())
+    match args.first().map(String::as_str) {
+        Some("route-get") => {
+            if let Err(e) = lookup::route_get(&args[1..]) {
+                log::error!(Netlink, "route-get: {}", e);
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Some("self-test") => {
+            match selftest::self_test() {
+                Ok(true) => log::info!(General, "self-test passed"),
+                Ok(false) => {
+                    log::error!(General, "self-test failed");
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    log::error!(General, "self-test: {}", e);
+                    std::process::exit(1);
+                }
+            }
+
+            return;
+        }
+        Some("panic") => {
+            if let Err(e) = rescue::panic(audit::Source::Cli { command: "panic" }, &args[1..]) {
+                log::error!(General, "panic: {}", e);
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Some("bench") => {
+            if let Err(e) = bench::bench(&args[1..]) {
+                log::error!(General, "bench: {}", e);
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Some("confirm") => {
+            if let Err(e) = control::request("confirm") {
+                log::error!(General, "confirm: {}", e);
+                std::process::exit(1);
+            }
+
+            log::info!(General, "configuration confirmed");
+            return;
+        }
+        Some("rollback") => {
+            if let Err(e) = history::rollback(&args[1..]) {
+                log::error!(General, "rollback: {}", e);
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Some("edit") => {
+            if let Err(e) = edit::edit(&args[1..]) {
+                log::error!(General, "edit: {}", e);
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Some("write") => {
+            if let Err(e) = lock::write(&args[1..]) {
+                log::error!(General, "write: {}", e);
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Some("completion") => {
+            if let Err(e) = completion::completion(&args[1..]) {
+                log::error!(General, "completion: {}", e);
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Some("diff") => {
+            if let Err(e) = diff::diff(&args[1..]) {
+                log::error!(Parser, "diff: {}", e);
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Some("explain") => {
+            if let Err(e) = explain::explain(&args[1..]) {
+                log::error!(Parser, "explain: {}", e);
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Some("plan") => {
+            if let Err(e) = dryrun::plan(&args[1..]) {
+                log::error!(General, "plan: {}", e);
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Some("snapshot") => {
+            if let Err(e) = snapshot::snapshot(&args[1..]) {
+                log::error!(General, "snapshot: {}", e);
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Some(cmd) => {
+            log::error!(
+                General,
+                "invalid subcommand {} (want \"route-get\", \"self-test\", \"snapshot\", \"rollback\", \"confirm\", \"edit\", \"write\", \"bench\", \"panic\", \"completion\", \"explain\", \"diff\" or \"plan\")",
+                cmd
+            );
+            std::process::exit(1);
+        }
+        None => {}
     }
 
-    fn delete(self, c: &Connection) -> Result<(), SetupError> {
-        match self {
-            Self::V4(r) => c.route_del4(r)?,
-            Self::V6(r) => c.route_del6(r)?,
+    log::info!(General, "init");
+
+    match shutdown::init() {
+        // Served right away, the first pass may wait for a link indefinitely.
+        Ok(()) => {
+            thread::spawn(|| stop(shutdown::wait()));
         }
+        Err(e) => log::warn!(General, "install signal handlers: {}", e),
+    }
+    if let Err(e) = signals::spawn() {
+        log::warn!(General, "install signal handlers: {}", e);
+    }
 
-        Ok(())
+    health::spawn();
+    control::spawn();
+    if monitor {
+        monitor::spawn();
     }
 
-    fn link(&self) -> &str {
-        match self {
-            Self::V4(r) => &r.link,
-            Self::V6(r) => &r.link,
+    let res = match settings::get().apply_timeout {
+        Some(timeout) => run_with_timeout(force, confirm, iproute2, timeout),
+        None => run(force, confirm, iproute2),
+    };
+    match res {
+        // Shutdown is up to the thread waiting for it from now on.
+        Ok(()) => loop {
+            thread::park();
+        },
+        Err(e) => log::message(log::Level::Error, e.subsystem(), format_args!("{}", e)),
+    }
+
+    shutdown::run_hooks();
+}
+
+/// Shuts down or restarts as requested.
+fn stop(reason: shutdown::Reason) -> ! {
+    match reason {
+        shutdown::Reason::Signal(sig) => {
+            log::info!(General, "caught signal {}, shut down", sig);
+            shutdown::run_hooks();
+            std::process::exit(0);
+        }
+        shutdown::Reason::Restart => {
+            log::info!(General, "restart");
+            shutdown::run_hooks();
+
+            let e = restart(true);
+            log::error!(General, "restart: {}", e);
+            std::process::exit(1);
         }
+        shutdown::Reason::Revert => restart_reverted(),
     }
 }
 
-impl fmt::Display for RouteDef {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::V4(r) => {
-                write!(f, "route4 {}/{}", r.dst, r.prefix_len)?;
-                if let Some(rtr) = r.rtr {
-                    write!(f, " via {}", rtr)?;
-                }
-                if r.on_link {
-                    write!(f, " onlink")?;
-                }
-                if let Some(table) = r.table {
-                    write!(f, " table {}", table)?;
-                }
-                if let Some(metric) = r.metric {
-                    write!(f, " metric {}", metric)?;
-                }
-                write!(f, " dev {}", r.link)?;
-            }
-            Self::V6(r) => {
-                write!(f, "route6 {}/{}", r.dst, r.prefix_len)?;
-                if let Some(rtr) = r.rtr {
-                    write!(f, " via {}", rtr)?;
-                }
-                if r.on_link {
-                    write!(f, " onlink")?;
-                }
-                if let Some(table) = r.table {
-                    write!(f, " table {}", table)?;
-                }
-                if let Some(metric) = r.metric {
-                    write!(f, " metric {}", metric)?;
-                }
-                write!(f, " dev {}", r.link)?;
-            }
+/// Restarts with the configuration an unconfirmed one was reverted to.
+fn restart_reverted() -> ! {
+    log::info!(General, "restart with the reverted configuration");
+    shutdown::run_hooks();
+
+    // The reverted configuration is known to work.
+    let e = restart(false);
+    log::error!(General, "restart: {}", e);
+    std::process::exit(1);
+}
+
+/// Replaces the process with a fresh instance using the same arguments,
+/// optionally without `--confirm`. Returns only on failure.
+fn restart(confirm: bool) -> std::io::Error {
+    use std::os::unix::process::CommandExt;
+
+    let mut args = Vec::new();
+    let mut skip = false;
+    for arg in std::env::args_os().skip(1) {
+        if std::mem::take(&mut skip) {
+            continue;
+        }
+        if !confirm && arg == "--confirm" {
+            skip = true;
+            continue;
         }
 
-        Ok(())
+        args.push(arg);
+    }
+
+    match std::env::current_exe() {
+        Ok(exe) => std::process::Command::new(exe).args(args).exec(),
+        Err(e) => e,
     }
 }
 
-#[derive(Clone, Debug)]
-struct Route {
-    delete: bool,
-    def: RouteDef,
+/// Applies the configuration like `run`, but counts it as done after `timeout`.
+/// Entries still pending by then are applied in the background.
+fn run_with_timeout(
+    force: bool,
+    confirm: Option<Duration>,
+    iproute2: bool,
+    timeout: Duration,
+) -> Result<(), Error> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let res = run(force, confirm, iproute2);
+
+        // Nobody waits for the outcome once the deadline has passed.
+        if let Err(mpsc::SendError(Err(e))) = tx.send(res) {
+            log::message(log::Level::Error, e.subsystem(), format_args!("{}", e));
+        }
+    });
+
+    match rx.recv_timeout(timeout) {
+        Ok(res) => res,
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            let pending = status::pending();
+            log::warn!(
+                General,
+                "apply timeout of {}s exceeded with {} entries pending, continue in background",
+                timeout.as_secs(),
+                pending.len()
+            );
+            for entry in pending {
+                log::warn!(General, "pending: {}", entry);
+            }
+
+            pass_done();
+            Ok(())
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            log::error!(General, "apply pass panicked");
+            std::process::exit(1);
+        }
+    }
 }
 
-impl fmt::Display for Route {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.def.fmt(f)
+/// Records the initial apply pass as done and tells other daemons about it,
+/// unless the apply timeout did so already.
+fn pass_done() {
+    if !PASS_DONE.swap(true, Ordering::SeqCst) {
+        status::applied();
+        notify::applied();
     }
 }
 
-impl FromStr for Route {
-    type Err = RouteParseError;
+/// Logs the error of a configuration file that can't be used,
+/// returning the status entry standing in for the entries of the file.
+fn broken_file(path: &'static str, e: Error) -> (audit::Source, String) {
+    log::message(log::Level::Error, e.subsystem(), format_args!("{}", e));
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut words = s.split_whitespace();
+    let line = match &e {
+        Error::ParseRoutes(RouteParseError::Line(line, _))
+        | Error::ParseRules(RuleParseError::Line(line, _))
+        | Error::ParseNeighbors(NeighborParseError::Line(line, _)) => *line,
+        _ => 0,
+    };
+    (audit::Source::Config { path, line }, e.to_string())
+}
 
-        let version_str = words.next().ok_or(RouteParseError::NoVersion)?;
-        let version = match version_str {
-            "route4" => RouteVersion::Ipv4,
-            "route6" => RouteVersion::Ipv6,
-            _ => return Err(RouteParseError::InvalidVersion(version_str.to_string())),
-        };
+fn read_routes() -> Result<(String, Routes), Error> {
+    let s = std::fs::read_to_string(ROUTES_PATH).map_err(Error::ReadRoutes)?;
+    let routes = s.parse()?;
+    Ok((s, routes))
+}
 
-        let cmd = words.next().ok_or(RouteParseError::NoCmd)?;
-        let delete = match cmd {
-            "add" => false,
-            "del" => true,
-            _ => return Err(RouteParseError::InvalidCmd(cmd.to_string())),
-        };
+fn read_rules() -> Result<(String, Rules), Error> {
+    let s = std::fs::read_to_string(RULES_PATH).map_err(Error::ReadRules)?;
+    let rules = s.parse()?;
+    Ok((s, rules))
+}
 
-        let mut attrs = HashMap::<&str, &str>::new();
-        let mut current_attr = None;
-        for word in words {
-            if let Some(attr) = current_attr {
-                if attrs.insert(attr, word).is_some() {
-                    return Err(RouteParseError::DuplicateAttr(attr.to_string()));
-                }
-                current_attr = None;
-            } else {
-                current_attr = Some(word);
-            }
+/// The neighbor file is optional, most setups don't need static entries.
+fn read_neighbors() -> Result<(Option<String>, Neighbors), Error> {
+    let s = match std::fs::read_to_string(NEIGHBORS_PATH) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok((None, Neighbors::default()))
         }
+        Err(e) => return Err(Error::ReadNeighbors(e)),
+    };
+    let neighbors = s.parse()?;
+    Ok((Some(s), neighbors))
+}
 
-        if let Some(attr) = current_attr {
-            return Err(RouteParseError::NoAttrValue(attr.to_string()));
+/// Applies the configuration, with ip(8) instead of netlink if `iproute2` is set.
+fn run(force: bool, confirm: Option<Duration>, iproute2: bool) -> Result<(), Error> {
+    let start = Instant::now();
+    guard::init(force).map_err(Error::ReadProtected)?;
+
+    // Named tables have to be known before parsing.
+    let tables = tables::load();
+
+    // Read all files at once so that they belong together.
+    let lock = lock::shared()
+        .inspect_err(|e| log::warn!(Parser, "lock configuration, read anyway: {}", e))
+        .ok();
+    // A broken file only costs its own entries, the others still apply.
+    let mut broken = Vec::new();
+    let (routes_file, mut routes) = match read_routes() {
+        Ok((s, routes)) => (Some(s), routes),
+        Err(e) => {
+            broken.push(broken_file(ROUTES_PATH, e));
+            (None, Routes::default())
+        }
+    };
+    dedup(ROUTES_PATH, &mut routes.routes, |route| route.line);
+    log::debug!(
+        Parser,
+        "parsed {} routes from {}",
+        routes.routes.len(),
+        ROUTES_PATH
+    );
+
+    let (rules_file, mut rules) = match read_rules() {
+        Ok((s, rules)) => (Some(s), rules),
+        Err(e) => {
+            broken.push(broken_file(RULES_PATH, e));
+            (None, Rules::default())
         }
+    };
+    dedup(RULES_PATH, &mut rules.rules, |rule| rule.line);
+    log::debug!(
+        Parser,
+        "parsed {} rules from {}",
+        rules.rules.len(),
+        RULES_PATH
+    );
+
+    let (neighbors_file, neighbors) = match read_neighbors() {
+        Ok((s, neighbors)) => (Some(s), neighbors),
+        Err(e) => {
+            broken.push(broken_file(NEIGHBORS_PATH, e));
+            (None, Neighbors::default())
+        }
+    };
+    log::debug!(
+        Parser,
+        "parsed {} neighbors from {}",
+        neighbors.neighbors.len(),
+        NEIGHBORS_PATH
+    );
+    drop(lock);
+    tables.check(&routes, &rules);
+    // Without the routes nothing tells foreign routes from configured ones.
+    if routes_file.is_some() {
+        tables.claim(&routes);
+    }
 
-        let mut dst = None;
-        let mut prefix_len = None;
-        let mut rtr = None;
-        let mut on_link = false;
-        let mut table = None;
-        let mut metric = None;
-        let mut link = None;
+    let config = match (routes_file, rules_file, neighbors_file) {
+        (Some(routes), Some(rules), Some(neighbors)) => Some(history::Config {
+            routes,
+            rules,
+            neighbors,
+        }),
+        _ => None,
+    };
+    let conn = connect();
+    log::debug!(Netlink, "connected");
+    let backend: &dyn Backend = if iproute2 {
+        log::info!(Netlink, "use ip(8) for routes and rules");
+        &Iproute2
+    } else {
+        &conn
+    };
+    let backend: &dyn Backend = &timing::Timed(backend);
 
-        for (attr, value) in attrs {
-            match attr {
-                "to" => {
-                    let mut prefix = value.split('/');
+    // Applying may be what cuts the operator off, so the window starts first.
+    if let (Some(config), Some(timeout)) = (&config, confirm) {
+        // Recorded right away, the revert looks for the one before it.
+        history::record(config, false);
+        confirm::arm(backend, config.clone(), timeout);
+    }
 
-                    let addr = prefix
-                        .next()
-                        .ok_or(RouteParseError::InvalidCidr(value.to_string()))?;
-                    let cidr = prefix
-                        .next()
-                        .ok_or(RouteParseError::InvalidCidr(value.to_string()))?;
+    let route_source = |route: &Route| audit::Source::Config {
+        path: ROUTES_PATH,
+        line: route.line,
+    };
+    let rule_source = |rule: &Rule| audit::Source::Config {
+        path: RULES_PATH,
+        line: rule.line,
+    };
+    let list_source = |list: &PrefixList| audit::Source::Config {
+        path: ROUTES_PATH,
+        line: list.line,
+    };
+    let sysctl_source = |sysctl: &Sysctl| audit::Source::Config {
+        path: ROUTES_PATH,
+        line: sysctl.line,
+    };
+    let bypass_source = |bypass: &Bypass| audit::Source::Config {
+        path: ROUTES_PATH,
+        line: bypass.line,
+    };
+    let isolate_source = |isolate: &Isolate| audit::Source::Config {
+        path: ROUTES_PATH,
+        line: isolate.line,
+    };
+    let mroute_source = |mroute: &Mroute| audit::Source::Config {
+        path: ROUTES_PATH,
+        line: mroute.line,
+    };
+    let vrf_source = |vrf: &Vrf| audit::Source::Config {
+        path: ROUTES_PATH,
+        line: vrf.line,
+    };
+    let bogons_source = |bogons: &Bogons| audit::Source::Config {
+        path: ROUTES_PATH,
+        line: bogons.line,
+    };
+    let classless_source = |classless: &Classless| audit::Source::Config {
+        path: ROUTES_PATH,
+        line: classless.line,
+    };
+    let kernel_rule_source = |kernel_rule: &KernelRule| audit::Source::Config {
+        path: RULES_PATH,
+        line: kernel_rule.line,
+    };
+    let neighbor_source = |neighbor: &Neighbor| audit::Source::Config {
+        path: NEIGHBORS_PATH,
+        line: neighbor.line,
+    };
+
+    status::begin(
+        routes
+            .routes
+            .iter()
+            .map(|route| (route_source(route), route.label()))
+            .chain(
+                routes
+                    .vrfs
+                    .iter()
+                    .map(|vrf| (vrf_source(vrf), vrf.to_string())),
+            )
+            .chain(
+                routes
+                    .bypasses
+                    .iter()
+                    .map(|bypass| (bypass_source(bypass), bypass.to_string())),
+            )
+            .chain(
+                routes
+                    .isolates
+                    .iter()
+                    .map(|isolate| (isolate_source(isolate), isolate.to_string())),
+            )
+            .chain(
+                routes
+                    .mroutes
+                    .iter()
+                    .map(|mroute| (mroute_source(mroute), mroute.to_string())),
+            )
+            .chain(
+                routes
+                    .prefix_lists
+                    .iter()
+                    .map(|list| (list_source(list), list.to_string())),
+            )
+            .chain(
+                routes
+                    .sysctls
+                    .iter()
+                    .map(|sysctl| (sysctl_source(sysctl), sysctl.to_string())),
+            )
+            .chain(
+                routes
+                    .bogons
+                    .iter()
+                    .map(|bogons| (bogons_source(bogons), bogons.to_string())),
+            )
+            .chain(
+                routes
+                    .classless
+                    .iter()
+                    .map(|classless| (classless_source(classless), classless.to_string())),
+            )
+            .chain(
+                rules
+                    .rules
+                    .iter()
+                    .map(|rule| (rule_source(rule), rule.label())),
+            )
+            .chain(
+                rules
+                    .kernel_rules
+                    .iter()
+                    .map(|kernel_rule| (kernel_rule_source(kernel_rule), kernel_rule.to_string())),
+            )
+            .chain(
+                neighbors
+                    .neighbors
+                    .iter()
+                    .map(|neighbor| (neighbor_source(neighbor), neighbor.label())),
+            )
+            .chain(
+                broken
+                    .iter()
+                    .map(|(source, _)| (*source, source.path().to_string())),
+            )
+            .collect(),
+    );
+    for (source, e) in broken {
+        status::set(source, status::State::Failed(e));
+    }
 
-                    if prefix.next().is_some() {
-                        return Err(RouteParseError::InvalidCidr(value.to_string()));
-                    }
+    // The routes scoped to a VRF need its device to exist.
+    for vrf in &routes.vrfs {
+        let source = vrf_source(vrf);
 
-                    dst = Some(addr.parse()?);
-                    prefix_len = Some(cidr.parse()?);
-                }
-                "via" => rtr = Some(value.parse()?),
-                "onlink" => on_link = value.parse()?,
-                "table" => table = Some(value.parse()?),
-                "metric" => metric = Some(value.parse()?),
-                "dev" => link = Some(value.to_string()),
-                _ => return Err(RouteParseError::InvalidAttr(attr.to_string())),
-            }
+        if vrf.delete {
+            let res = report(source, "del", vrf, backend.del_vrf(vrf));
+            status::set(source, outcome(res, status::State::Removed));
+            continue;
         }
 
-        match version {
-            RouteVersion::Ipv4 => Ok(Route {
-                delete,
-                def: RouteDef::V4(rsdsl_netlinklib::route::Route4 {
-                    dst: if let Some(IpAddr::V4(dst)) = dst {
-                        dst
-                    } else {
-                        return Err(RouteParseError::DstNotIpv4);
-                    },
-                    prefix_len: prefix_len.ok_or(RouteParseError::NoDst)?,
-                    rtr: match rtr {
-                        Some(IpAddr::V4(rtr)) => Some(rtr),
-                        Some(_) => return Err(RouteParseError::RtrNotIpv4),
-                        None => None,
-                    },
-                    on_link,
-                    table,
-                    metric,
-                    link: link.ok_or(RouteParseError::NoLink)?,
-                }),
-            }),
-            RouteVersion::Ipv6 => Ok(Route {
-                delete,
-                def: RouteDef::V6(rsdsl_netlinklib::route::Route6 {
-                    dst: if let Some(IpAddr::V6(dst)) = dst {
-                        dst
-                    } else {
-                        return Err(RouteParseError::DstNotIpv6);
-                    },
-                    prefix_len: prefix_len.ok_or(RouteParseError::NoDst)?,
-                    rtr: match rtr {
-                        Some(IpAddr::V6(rtr)) => Some(rtr),
-                        Some(_) => return Err(RouteParseError::RtrNotIpv6),
-                        None => None,
-                    },
-                    on_link,
-                    table,
-                    metric,
-                    link: link.ok_or(RouteParseError::NoLink)?,
-                }),
-            }),
+        let mut res = report(source, "add", vrf, backend.add_vrf(vrf));
+        for member in &vrf.members {
+            status::set(source, status::State::WaitingForLink(member.clone()));
+            log::info!(Events, "wait for link {}", member);
+            backend.link_wait_exists(member)?;
+
+            let r = report(
+                source,
+                "bind",
+                &format!("{} to vrf {}", member, vrf.name),
+                backend.bind_vrf(vrf, member),
+            );
+            res = res.and(r);
         }
+        status::set(source, outcome(res, status::State::Applied));
     }
-}
 
-#[derive(Debug)]
-struct Routes {
-    routes: Vec<Route>,
-}
-
-impl FromStr for Routes {
-    type Err = RouteParseError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let routes = s
-            .lines()
-            .map(|l| l.parse::<Route>())
-            .collect::<Result<Vec<Route>, Self::Err>>()?;
-
-        Ok(Self { routes })
-    }
-}
-
-#[derive(Clone, Debug, Default)]
-enum RuleVersion {
-    #[default]
-    Both,
-    Ipv4,
-    Ipv6,
-}
-
-#[derive(Clone, Debug)]
-struct Rule {
-    delete: bool,
-    version: RuleVersion,
-    invert: bool,
-    fwmark: Option<u32>,
-    dst: Option<(IpAddr, u8)>,
-    src: Option<(IpAddr, u8)>,
-    action: RuleAction,
-    table: u32,
-}
-
-impl Rule {
-    fn add(self, c: &Connection) -> Result<(), SetupError> {
-        match self.version {
-            RuleVersion::Both => {
-                rsdsl_netlinklib::rule::Rule::<Ipv4Addr> {
-                    invert: self.invert,
-                    fwmark: self.fwmark,
-                    dst: None,
-                    src: None,
-                    action: self.action,
-                    table: self.table,
-                }
-                .blocking_add(c)?;
-                rsdsl_netlinklib::rule::Rule::<Ipv6Addr> {
-                    invert: self.invert,
-                    fwmark: self.fwmark,
-                    dst: None,
-                    src: None,
-                    action: self.action,
-                    table: self.table,
+    // Interface specific settings are applied right before the first route
+    // using the interface, global ones right away.
+    let (mut pending_sysctls, global_sysctls): (Vec<_>, Vec<_>) = routes
+        .sysctls
+        .into_iter()
+        .partition(Sysctl::is_link_specific);
+    let apply_sysctl = |sysctl: &Sysctl| {
+        let source = sysctl_source(sysctl);
+        let res = report(source, "set", sysctl, backend.apply_sysctl(sysctl));
+        status::set(source, outcome(res, status::State::Applied));
+    };
+    global_sysctls.iter().for_each(apply_sysctl);
+
+    let mut dslite_routes = Vec::new();
+    let mut dynamic_routes = Vec::new();
+    let mut probed_routes = Vec::new();
+    let mut balance_members = Vec::new();
+    let mut conditional_routes = Vec::new();
+    let mut hostname_routes = Vec::new();
+    let mut resync_routes = Vec::new();
+    let mut batch = Vec::new();
+    // Replacing stale entries only takes deletions for those that exist.
+    let mut installed = installed::dump(backend);
+    // Entries of other network namespaces are applied separately, see `apply_netns`.
+    let (netns_routes, routes_here): (Vec<_>, Vec<_>) = routes
+        .routes
+        .into_iter()
+        .partition(|route| route.netns.is_some());
+    let (netns_rules, rules_here): (Vec<_>, Vec<_>) = rules
+        .rules
+        .into_iter()
+        .partition(|rule| rule.netns.is_some());
+    // Traffic falls through the tables until their routes are in place.
+    let (rules_applied, rules_here) = match settings::get().apply_order {
+        settings::ApplyOrder::RulesFirst => (
+            Some(apply_rules(backend, &installed, rules_here)),
+            Vec::new(),
+        ),
+        settings::ApplyOrder::RoutesFirst => (None, rules_here),
+    };
+    // Link patterns stand for the links matching them.
+    let (routes_here, patterns) = wildcard::apply(backend, routes_here, route_source);
+    // Routes through several links are balance groups of their own.
+    let routes_here: Vec<Route> = routes_here
+        .into_iter()
+        .flat_map(Route::split_links)
+        .collect();
+    for route in routes_here {
+        let source = route_source(&route);
+
+        let route = match &route.template {
+            Some(template) => match resolve::<Route>(source, template) {
+                Ok(resolved) => route.resolved(resolved),
+                Err(e) => {
+                    log::error!(Parser, "resolve {}: {}", template, e);
+                    status::set(source, status::State::Failed(e.to_string()));
+                    continue;
                 }
-                .blocking_add(c)?;
-            }
-            RuleVersion::Ipv4 => rsdsl_netlinklib::rule::Rule::<Ipv4Addr> {
-                invert: self.invert,
-                fwmark: self.fwmark,
-                dst: self.dst.map(|dst| {
-                    if let (IpAddr::V4(addr), cidr) = dst {
-                        (addr, cidr)
-                    } else {
-                        unreachable!()
-                    }
-                }),
-                src: self.src.map(|src| {
-                    if let (IpAddr::V4(addr), cidr) = src {
-                        (addr, cidr)
-                    } else {
-                        unreachable!()
-                    }
-                }),
-                action: self.action,
-                table: self.table,
-            }
-            .blocking_add(c)?,
-            RuleVersion::Ipv6 => rsdsl_netlinklib::rule::Rule::<Ipv6Addr> {
-                invert: self.invert,
-                fwmark: self.fwmark,
-                dst: self.dst.map(|dst| {
-                    if let (IpAddr::V6(addr), cidr) = dst {
-                        (addr, cidr)
-                    } else {
-                        unreachable!()
-                    }
-                }),
-                src: self.src.map(|src| {
-                    if let (IpAddr::V6(addr), cidr) = src {
-                        (addr, cidr)
-                    } else {
-                        unreachable!()
-                    }
-                }),
-                action: self.action,
-                table: self.table,
-            }
-            .blocking_add(c)?,
+            },
+            None => route,
         };
 
-        Ok(())
-    }
+        if !route.delete && route.host.is_none() && !guard::allow_route(source, &route) {
+            continue;
+        }
 
-    fn delete(self, c: &Connection) -> Result<(), SetupError> {
-        match self.version {
-            RuleVersion::Both => {
-                rsdsl_netlinklib::rule::Rule::<Ipv4Addr> {
-                    invert: self.invert,
-                    fwmark: self.fwmark,
-                    dst: None,
-                    src: None,
-                    action: self.action,
-                    table: self.table,
-                }
-                .blocking_del(c)?;
-                rsdsl_netlinklib::rule::Rule::<Ipv6Addr> {
-                    invert: self.invert,
-                    fwmark: self.fwmark,
-                    dst: None,
-                    src: None,
-                    action: self.action,
-                    table: self.table,
+        // An earlier entry may add the same route.
+        if route.delete {
+            pool::add_routes(backend, std::mem::take(&mut batch));
+        }
+
+        // Hostnames stand for the host routes to their current addresses.
+        if route.host.is_none() {
+            if route.delete {
+                if let Some(mirror) = route.mirror_def() {
+                    let _ = report(source, "del", &mirror, backend.del_route(&mirror));
                 }
-                .blocking_del(c)?;
+
+                let res = backend.del_route(&route.def);
+                status::set(source, removal(source, &route, res));
+                resync_routes.push((source, route));
+                continue;
             }
-            RuleVersion::Ipv4 => rsdsl_netlinklib::rule::Rule::<Ipv4Addr> {
-                invert: self.invert,
-                fwmark: self.fwmark,
-                dst: self.dst.map(|dst| {
-                    if let (IpAddr::V4(addr), cidr) = dst {
-                        (addr, cidr)
-                    } else {
-                        unreachable!()
-                    }
-                }),
-                src: self.src.map(|src| {
-                    if let (IpAddr::V4(addr), cidr) = src {
-                        (addr, cidr)
-                    } else {
-                        unreachable!()
-                    }
-                }),
-                action: self.action,
-                table: self.table,
+
+            if !route.managed {
+                // Someone else took over since rtd installed it.
+                if installed.has_exact_route(&route.def) {
+                    hand_over(source, &route);
+                    continue;
+                }
+            } else if installed.has_route(&route.def) && route.balance.is_none() {
+                // Balance groups replace their route in place.
+                let res = backend.del_route(&route.def);
+                let _ = report(source, "del", &route, res);
             }
-            .blocking_del(c)?,
-            RuleVersion::Ipv6 => rsdsl_netlinklib::rule::Rule::<Ipv6Addr> {
-                invert: self.invert,
-                fwmark: self.fwmark,
-                dst: self.dst.map(|dst| {
-                    if let (IpAddr::V6(addr), cidr) = dst {
-                        (addr, cidr)
-                    } else {
-                        unreachable!()
-                    }
-                }),
-                src: self.src.map(|src| {
-                    if let (IpAddr::V6(addr), cidr) = src {
-                        (addr, cidr)
-                    } else {
-                        unreachable!()
-                    }
-                }),
-                action: self.action,
-                table: self.table,
+            installed.add_route(&route.def);
+        } else if route.delete {
+            dns::remove(backend, source, &route);
+            continue;
+        }
+
+        if route.dslite {
+            status::set(
+                source,
+                status::State::WaitingForLink(route.def.link().to_string()),
+            );
+
+            // The tunnel only comes up once the AFTR is known.
+            log::info!(Events, "wait for DS-Lite tunnel {}", route.def.link());
+            backend
+                .link_wait_exists(route.def.link())
+                .and_then(|_| backend.link_wait_up(route.def.link()))?;
+        } else if !wait_for_link(backend, source, route.def.link())? {
+            continue;
+        }
+
+        pending_sysctls.retain(|sysctl| {
+            let due = sysctl.link == route.def.link();
+            if due {
+                apply_sysctl(sysctl);
             }
-            .blocking_del(c)?,
+
+            !due
+        });
+
+        let route = if route.via_peer {
+            resolve_peer(source, route)
+        } else {
+            route
+        };
+        let route = if route.src_auto {
+            resolve_src(source, route)
+        } else {
+            route
         };
 
-        Ok(())
-    }
-}
+        // The addresses of hostnames change, each may need any number of routes.
+        if route.host.is_some() {
+            pool::add_routes(backend, std::mem::take(&mut batch));
+            hostname_routes.push(dns::Entry::apply(backend, source, route));
+            continue;
+        }
 
-impl fmt::Display for Rule {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.version {
-            RuleVersion::Both => write!(f, "rule")?,
-            RuleVersion::Ipv4 => write!(f, "rule4")?,
-            RuleVersion::Ipv6 => write!(f, "rule6")?,
+        // Balance group members are installed together once all of them are known.
+        if route.balance.is_some() {
+            balance_members.push(route);
+            continue;
         }
-        if self.invert {
-            write!(f, " invert true")?;
+
+        if route.is_conditional() {
+            let active = route.is_active() && !activation::expired(&route);
+            if active {
+                pool::add_routes(backend, std::mem::take(&mut batch));
+                let res = report(source, "add", &route, route.add(backend));
+                status::set(source, outcome(res, status::State::Applied));
+            } else {
+                status::set(source, status::State::Inactive(route.condition()));
+            }
+
+            if route.dslite {
+                dslite_routes.push((source, route.clone()));
+            }
+            resync_routes.push((source, route.clone()));
+            conditional_routes.push((source, route, active));
+            continue;
         }
-        if let Some(fwmark) = self.fwmark {
-            write!(f, " fwmark {}", fwmark)?;
+
+        // Nothing keeps unmanaged routes up to date.
+        if !route.managed {
+            batch.push((source, route));
+            continue;
         }
-        if let Some(dst) = self.dst {
-            write!(f, " dst {}/{}", dst.0, dst.1)?;
+
+        if route.template.is_some() || route.via_peer || route.src_auto {
+            dynamic_routes.push((source, route.clone()));
         }
-        if let Some(src) = self.src {
-            write!(f, " src {}/{}", src.0, src.1)?;
+        if route.probe.is_some() {
+            probed_routes.push((source, route.clone()));
         }
-        match self.action {
-            RuleAction::Unspec => write!(f, " action unspec")?,
-            RuleAction::ToTable => write!(f, " action to_table")?,
-            RuleAction::Goto => write!(f, " action goto")?,
-            RuleAction::Nop => write!(f, " action nop")?,
-            RuleAction::Blackhole => write!(f, " action blackhole")?,
-            RuleAction::Unreachable => write!(f, " action unreachable")?,
-            RuleAction::Prohibit => write!(f, " action prohibit")?,
-            RuleAction::Other(a) => write!(f, " action {}", a)?,
-            _ => write!(f, " action ?")?,
+        if route.dslite {
+            dslite_routes.push((source, route.clone()));
         }
-        if self.action == RuleAction::ToTable {
-            write!(f, " table {}", self.table)?;
+        resync_routes.push((source, route.clone()));
+        batch.push((source, route));
+    }
+    pool::add_routes(backend, batch);
+
+    for sysctl in pending_sysctls {
+        if wait_for_link(backend, sysctl_source(&sysctl), &sysctl.link)? {
+            apply_sysctl(&sysctl);
         }
+    }
 
-        Ok(())
+    let groups: Vec<Arc<Mutex<balance::Group>>> = Balance::group(balance_members)
+        .into_iter()
+        .map(|balance| {
+            let sources = balance.members.iter().map(route_source).collect();
+            let group = balance::Group::new(balance, sources);
+            group.apply(backend);
+
+            Arc::new(Mutex::new(group))
+        })
+        .collect();
+
+    for bogons in &routes.bogons {
+        rtbh::apply_bogons(backend, bogons_source(bogons), bogons);
     }
-}
 
-impl FromStr for Rule {
-    type Err = RuleParseError;
+    let isolates = routes
+        .isolates
+        .into_iter()
+        .map(|isolate| {
+            let source = isolate_source(&isolate);
+            let installed = guest::apply(backend, source, &isolate);
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut words = s.split_whitespace();
+            (source, isolate, installed)
+        })
+        .collect();
 
-        let version_str = words.next().ok_or(RuleParseError::NoVersion)?;
-        let version = match version_str {
-            "rule" => RuleVersion::Both,
-            "rule4" => RuleVersion::Ipv4,
-            "rule6" => RuleVersion::Ipv6,
-            _ => return Err(RuleParseError::InvalidVersion(version_str.to_string())),
-        };
+    for bypass in &routes.bypasses {
+        vpn::apply(backend, bypass_source(bypass), bypass);
+    }
 
-        let cmd = words.next().ok_or(RuleParseError::NoCmd)?;
-        let delete = match cmd {
-            "add" => false,
-            "del" => true,
-            _ => return Err(RuleParseError::InvalidCmd(cmd.to_string())),
-        };
+    let lists = routes
+        .prefix_lists
+        .into_iter()
+        .map(|list| {
+            let source = list_source(&list);
+            let installed = rtbh::apply(backend, source, &list);
+
+            (source, list, installed)
+        })
+        .collect();
+
+    let leases = routes
+        .classless
+        .into_iter()
+        .map(|classless| {
+            let source = classless_source(&classless);
+            let installed = dhcp::apply(backend, source, &classless);
+
+            (source, classless, installed)
+        })
+        .collect();
+
+    let (dynamic_rules, resync_rules) = match rules_applied {
+        Some(applied) => applied,
+        None => apply_rules(backend, &installed, rules_here),
+    };
 
-        let mut attrs = HashMap::<&str, &str>::new();
-        let mut current_attr = None;
-        for word in words {
-            if let Some(attr) = current_attr {
-                if attrs.insert(attr, word).is_some() {
-                    return Err(RuleParseError::DuplicateAttr(attr.to_string()));
+    apply_netns(
+        iproute2,
+        netns_routes
+            .into_iter()
+            .map(|route| (route_source(&route), route))
+            .collect(),
+        netns_rules
+            .into_iter()
+            .map(|rule| (rule_source(&rule), rule))
+            .collect(),
+    );
+
+    // Moving the kernel's rules can make tables unreachable
+    // until the configured rules are in place.
+    for kernel_rule in rules.kernel_rules {
+        let source = kernel_rule_source(&kernel_rule);
+
+        let res = report(
+            source,
+            "apply",
+            &kernel_rule,
+            backend.apply_kernel_rule(&kernel_rule),
+        );
+        status::set(source, outcome(res, status::State::Applied));
+
+        // Partial changes are undone, too.
+        match backend.connect() {
+            Ok(backend) => shutdown::defer(move || {
+                let _ = report(
+                    source,
+                    "restore",
+                    &kernel_rule,
+                    backend.restore_kernel_rule(&kernel_rule),
+                );
+            }),
+            Err(e) => log::error!(Netlink, "connect for restoring {}: {}", kernel_rule, e),
+        }
+    }
+
+    let mut dynamic_neighbors = Vec::new();
+    for neighbor in neighbors.neighbors {
+        let source = neighbor_source(&neighbor);
+
+        let neighbor = match &neighbor.template {
+            Some(template) => match resolve::<Neighbor>(source, template) {
+                Ok(resolved) => Neighbor {
+                    line: neighbor.line,
+                    template: neighbor.template.clone(),
+                    comment: neighbor.comment.clone(),
+                    ..resolved
+                },
+                Err(e) => {
+                    log::error!(Parser, "resolve {}: {}", template, e);
+                    status::set(source, status::State::Failed(e.to_string()));
+                    continue;
                 }
-                current_attr = None;
-            } else {
-                current_attr = Some(word);
-            }
+            },
+            None => neighbor,
+        };
+
+        let res = backend.del_neighbor(&neighbor);
+        if neighbor.delete {
+            status::set(source, removal(source, &neighbor, res));
+            continue;
         }
+        let _ = report(source, "del", &neighbor, res);
 
-        if let Some(attr) = current_attr {
-            return Err(RuleParseError::NoAttrValue(attr.to_string()));
+        if !wait_for_link(backend, source, &neighbor.link)? {
+            continue;
         }
 
-        let mut invert = false;
-        let mut fwmark = None;
-        let mut dst = None;
-        let mut src = None;
-        let mut action = None;
-        let mut table = None;
+        let res = report(source, "add", &neighbor, backend.add_neighbor(&neighbor));
+        status::set(source, outcome(res, status::State::Applied));
 
-        for (attr, value) in attrs {
-            match attr {
-                "invert" => invert = value.parse()?,
-                "fwmark" => fwmark = Some(value.parse()?),
-                "dst" => {
-                    let mut prefix = value.split('/');
+        if neighbor.template.is_some() {
+            dynamic_neighbors.push((source, neighbor));
+        }
+    }
 
-                    let addr = prefix
-                        .next()
-                        .ok_or(RuleParseError::InvalidCidr(value.to_string()))?;
-                    let cidr = prefix
-                        .next()
-                        .ok_or(RuleParseError::InvalidCidr(value.to_string()))?;
+    tables::enforce();
 
-                    if prefix.next().is_some() {
-                        return Err(RuleParseError::InvalidCidr(value.to_string()));
-                    }
+    pass_done();
+    status::summarize(start.elapsed());
 
-                    dst = Some((addr.parse()?, cidr.parse()?));
-                }
-                "src" => {
-                    let mut prefix = value.split('/');
+    match &config {
+        // Unconfirmed configurations don't count as known-good yet.
+        Some(config) => history::record(config, status::health().0 && confirm.is_none()),
+        None => log::warn!(General, "configuration is broken, not recorded"),
+    }
 
-                    let addr = prefix
-                        .next()
-                        .ok_or(RuleParseError::InvalidCidr(value.to_string()))?;
-                    let cidr = prefix
-                        .next()
-                        .ok_or(RuleParseError::InvalidCidr(value.to_string()))?;
+    resync::register(iproute2, resync_routes, resync_rules);
+    if let Some(interval) = settings::get().reconcile_interval {
+        resync::watch(interval);
+    }
+    reload::watch(backend, dynamic_routes, dynamic_rules, dynamic_neighbors);
+    failover::watch(backend, probed_routes, groups);
+    activation::watch(backend, conditional_routes);
+    dns::watch(backend, hostname_routes);
+    wildcard::watch(backend, patterns);
+    rtbh::watch(backend, lists);
+    dhcp::watch(backend, leases);
+    guest::watch(backend, isolates);
+    mcast::watch(
+        backend,
+        routes
+            .mroutes
+            .into_iter()
+            .map(|mroute| (mroute_source(&mroute), mroute))
+            .collect(),
+    );
+    dslite::watch(backend, dslite_routes);
 
-                    if prefix.next().is_some() {
-                        return Err(RuleParseError::InvalidCidr(value.to_string()));
-                    }
+    Ok(())
+}
 
-                    src = Some((addr.parse()?, cidr.parse()?));
-                }
-                "action" => match value {
-                    "to_table" => action = Some(RuleAction::ToTable),
-                    "blackhole" => action = Some(RuleAction::Blackhole),
-                    "unreachable" => action = Some(RuleAction::Unreachable),
-                    "prohibit" => action = Some(RuleAction::Prohibit),
-                    a => return Err(RuleParseError::InvalidAction(a.to_string())),
-                },
-                "table" => table = Some(value.parse()?),
-                _ => return Err(RuleParseError::InvalidAttr(attr.to_string())),
+/// Connects to netlink, retrying with increasing delays until it works.
+/// Very early at boot netlink may not be available for a moment,
+/// giving up would leave the router without static routing.
+fn connect() -> Connection {
+    let mut delay = CONNECT_DELAY;
+    loop {
+        match Connection::new() {
+            Ok(conn) => return conn,
+            Err(e) => {
+                log::warn!(Netlink, "connect: {}, retry in {:?}", e, delay);
+                thread::sleep(delay);
+                delay = (delay * 2).min(CONNECT_MAX_DELAY);
             }
         }
+    }
+}
 
-        match version {
-            RuleVersion::Both => Ok(Rule {
-                delete,
-                version,
-                invert,
-                fwmark,
-                dst: if dst.is_some() {
-                    return Err(RuleParseError::DstIllegal);
-                } else {
-                    None
-                },
-                src: if src.is_some() {
-                    return Err(RuleParseError::SrcIllegal);
-                } else {
-                    None
-                },
-                action: action.ok_or(RuleParseError::NoAction)?,
-                table: table.unwrap_or_default(),
-            }),
-            RuleVersion::Ipv4 => Ok(Rule {
-                delete,
-                version,
-                invert,
-                fwmark,
-                dst: match dst {
-                    Some((IpAddr::V4(dst), cidr)) => Some((IpAddr::V4(dst), cidr)),
-                    Some(_) => return Err(RuleParseError::DstNotIpv4),
-                    None => None,
-                },
-                src: match src {
-                    Some((IpAddr::V4(src), cidr)) => Some((IpAddr::V4(src), cidr)),
-                    Some(_) => return Err(RuleParseError::SrcNotIpv4),
-                    None => None,
-                },
-                action: action.ok_or(RuleParseError::NoAction)?,
-                table: table.unwrap_or_default(),
-            }),
-            RuleVersion::Ipv6 => Ok(Rule {
-                delete,
-                version,
-                invert,
-                fwmark,
-                dst: match dst {
-                    Some((IpAddr::V6(dst), cidr)) => Some((IpAddr::V6(dst), cidr)),
-                    Some(_) => return Err(RuleParseError::DstNotIpv6),
-                    None => None,
-                },
-                src: match src {
-                    Some((IpAddr::V6(src), cidr)) => Some((IpAddr::V6(src), cidr)),
-                    Some(_) => return Err(RuleParseError::SrcNotIpv6),
-                    None => None,
+/// Applies the rules of rtd's own network namespace. Returns those
+/// with placeholders, which need reloading, and those to re-apply on request.
+#[allow(clippy::type_complexity)]
+fn apply_rules(
+    backend: &dyn Backend,
+    installed: &installed::Installed,
+    rules: Vec<Rule>,
+) -> (Vec<(audit::Source, Rule)>, Vec<(audit::Source, Rule)>) {
+    let mut dynamic_rules = Vec::new();
+    let mut resync_rules = Vec::new();
+    for rule in rules {
+        let source = audit::Source::Config {
+            path: RULES_PATH,
+            line: rule.line,
+        };
+
+        let rule = match &rule.template {
+            Some(template) => match resolve::<Rule>(source, template) {
+                Ok(resolved) => Rule {
+                    line: rule.line,
+                    template: rule.template.clone(),
+                    comment: rule.comment.clone(),
+                    ..resolved
                 },
-                action: action.ok_or(RuleParseError::NoAction)?,
-                table: table.unwrap_or_default(),
-            }),
+                Err(e) => {
+                    log::error!(Parser, "resolve {}: {}", template, e);
+                    status::set(source, status::State::Failed(e.to_string()));
+                    continue;
+                }
+            },
+            None => rule,
+        };
+
+        if !rule.delete && !guard::allow_rule(source, &rule) {
+            continue;
+        }
+
+        if rule.delete {
+            let res = backend.del_rule(&rule);
+            status::set(source, removal(source, &rule, res));
+            resync_rules.push((source, rule));
+            continue;
+        }
+
+        if !rule.managed {
+            // Someone else took over since rtd installed it.
+            if installed.has_exact_rule(&rule) {
+                hand_over(source, &rule);
+                continue;
+            }
+        } else if installed.has_rule(&rule) {
+            let _ = report(source, "del", &rule, backend.del_rule(&rule));
+        }
+
+        status::set(source, add_rule(backend, source, &rule));
+        // Nothing keeps unmanaged rules up to date.
+        if !rule.managed {
+            continue;
+        }
+        resync_rules.push((source, rule.clone()));
+
+        if rule.template.is_some() {
+            dynamic_rules.push((source, rule));
         }
     }
-}
 
-#[derive(Debug)]
-struct Rules {
-    rules: Vec<Rule>,
+    (dynamic_rules, resync_rules)
 }
 
-impl FromStr for Rules {
-    type Err = RuleParseError;
+/// Installs a rule, returning its resulting state. The halves of
+/// a protocol-agnostic rule are reported separately so that a failure
+/// of one address family stands out, but still go in together or not at all.
+fn add_rule(backend: &dyn Backend, source: audit::Source, rule: &Rule) -> status::State {
+    let Some((v4, v6)) = rule.halves() else {
+        let res = report(source, "add", rule, backend.add_rule(rule));
+        return outcome(res, status::State::Applied);
+    };
+
+    if let Err(e) = report(source, "add", &v4, backend.add_rule(&v4)) {
+        return status::State::Failed(format!("IPv4 half: {}", e));
+    }
+    if let Err(e) = report(source, "add", &v6, backend.add_rule(&v6)) {
+        return match report(source, "del", &v4, backend.del_rule(&v4)) {
+            Ok(()) => status::State::Failed(format!("IPv6 half: {}", e)),
+            Err(_) => status::State::Failed(SetupError::HalfApplied(Box::new(e)).to_string()),
+        };
+    }
+
+    status::State::Applied
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let rules = s
-            .lines()
-            .map(|l| l.parse::<Rule>())
-            .collect::<Result<Vec<Rule>, Self::Err>>()?;
+/// Leaves an unmanaged entry that is already installed alone.
+fn hand_over(source: audit::Source, entry: &dyn fmt::Display) {
+    log::info!(General, "leave {} to its current owner", entry);
+    status::set(source, status::State::Unmanaged);
+}
 
-        Ok(Self { rules })
+/// Waits for a link to exist or, with the `missing-link skip` setting,
+/// fails the entry right away if it doesn't. Returns whether the link exists.
+fn wait_for_link(
+    backend: &dyn Backend,
+    source: audit::Source,
+    link: &str,
+) -> Result<bool, SetupError> {
+    status::set(source, status::State::WaitingForLink(link.to_string()));
+
+    let skip = settings::get().missing_link == settings::MissingLink::Skip;
+    if skip && !backend.link_exists(link)? {
+        log::warn!(Events, "link {} doesn't exist, skip {}", link, source);
+        status::set(
+            source,
+            status::State::Failed(format!("link {} doesn't exist", link)),
+        );
+        return Ok(false);
     }
+
+    log::info!(Events, "wait for link {}", link);
+    backend.link_wait_exists(link)?;
+    Ok(true)
 }
 
-fn main() {
-    eprintln!("[info] init");
+/// Expands the placeholders of a templated entry and parses the result,
+/// waiting for variables whose value isn't known yet.
+fn resolve<T>(source: audit::Source, template: &str) -> Result<T, T::Err>
+where
+    T: FromStr,
+    T::Err: From<vars::VarError>,
+{
+    let mut waiting = false;
+    loop {
+        match vars::expand(template, &vars::Vars::load()) {
+            Ok(line) => return line.parse(),
+            Err(vars::VarError::Unavailable(var, path)) => {
+                if !waiting {
+                    log::info!(General, "wait for ${} ({})", var, path);
+                    status::set(source, status::State::WaitingForVar(var));
+                    waiting = true;
+                }
 
-    match run() {
-        Ok(()) => loop {
-            std::thread::park()
-        },
-        Err(e) => eprintln!("[warn] {}", e),
+                std::thread::sleep(VAR_POLL_INTERVAL);
+            }
+            Err(e) => return Err(e.into()),
+        }
     }
 }
 
-fn run() -> Result<(), Error> {
-    let routes = match std::fs::read_to_string(ROUTES_PATH) {
-        Ok(s) => s,
-        Err(e) => return Err(Error::ReadRoutes(e)),
-    };
-    let routes: Routes = routes.parse()?;
+/// Fills in the gateway of a `via peer` route,
+/// waiting for the link to be assigned a peer address.
+fn resolve_peer(source: audit::Source, mut route: Route) -> Route {
+    let ipv6 = matches!(route.def, RouteDef::V6(_));
 
-    let rules = match std::fs::read_to_string(RULES_PATH) {
-        Ok(s) => s,
-        Err(e) => return Err(Error::ReadRules(e)),
-    };
-    let rules: Rules = rules.parse()?;
+    let mut waiting = false;
+    loop {
+        if let Some(peer) = vars::peer(route.def.link(), ipv6) {
+            route.def.set_rtr(peer);
+            return route;
+        }
+
+        if !waiting {
+            log::info!(Events, "wait for peer address of {}", route.def.link());
+            status::set(
+                source,
+                status::State::WaitingForPeer(route.def.link().to_string()),
+            );
+            waiting = true;
+        }
+
+        std::thread::sleep(VAR_POLL_INTERVAL);
+    }
+}
 
-    let conn = Connection::new().map_err(SetupError::from)?;
+/// Fills in the source of a `src auto` route,
+/// waiting for the link to be assigned an address.
+fn resolve_src(source: audit::Source, mut route: Route) -> Route {
+    let ipv6 = matches!(route.def, RouteDef::V6(_));
 
-    for route in routes.routes {
-        match route.def.clone().delete(&conn) {
-            Ok(_) => eprintln!("[info] del {}", route),
-            Err(e) => eprintln!("[warn] del {}: {}", route, e),
+    let mut waiting = false;
+    loop {
+        if let Some(addr) = vars::primary_addr(route.def.link(), ipv6) {
+            route.src = Some(addr);
+            return route;
         }
 
-        eprintln!("[info] wait for link {}", route.def.link());
-        conn.link_wait_exists(route.def.link().to_string())
-            .map_err(SetupError::from)?;
+        if !waiting {
+            log::info!(Events, "wait for address of {}", route.def.link());
+            status::set(
+                source,
+                status::State::WaitingForAddr(route.def.link().to_string()),
+            );
+            waiting = true;
+        }
 
-        if !route.delete {
-            match route.def.clone().add(&conn) {
-                Ok(_) => eprintln!("[info] add {}", route),
-                Err(e) => eprintln!("[warn] add {}: {}", route, e),
+        std::thread::sleep(VAR_POLL_INTERVAL);
+    }
+}
+
+/// Applies the routes and rules of other network namespaces. The entries
+/// of a namespace are applied from a thread inside it, in the same way
+/// as those of rtd's own namespace but without dynamic features.
+fn apply_netns(
+    iproute2: bool,
+    routes: Vec<(audit::Source, Route)>,
+    rules: Vec<(audit::Source, Rule)>,
+) {
+    let mut namespaces: BTreeMap<String, (Vec<_>, Vec<_>)> = BTreeMap::new();
+    for (source, route) in routes {
+        let name = route.netns.clone().unwrap_or_default();
+        namespaces.entry(name).or_default().0.push((source, route));
+    }
+    for (source, rule) in rules {
+        let name = rule.netns.clone().unwrap_or_default();
+        namespaces.entry(name).or_default().1.push((source, rule));
+    }
+
+    for (name, (mut routes, rules)) in namespaces {
+        let sources: Vec<audit::Source> = routes
+            .iter()
+            .map(|(source, _)| *source)
+            .chain(rules.iter().map(|(source, _)| *source))
+            .collect();
+        status::begin_netns(&name, sources.clone());
+
+        // Gateways may only be reachable through the routes without one.
+        routes.sort_by_key(|(_, route)| route.def.rtr().is_some());
+
+        let res = netns::run(&name, || {
+            let backend: Box<dyn Backend> = if iproute2 {
+                Box::new(Iproute2)
+            } else {
+                Box::new(Connection::new().map_err(SetupError::from)?)
+            };
+            log::debug!(Netlink, "connected in netns {}", name);
+            let installed = installed::dump(&*backend);
+
+            for (source, route) in routes {
+                if route.delete {
+                    let res = backend.del_route(&route.def);
+                    status::set(source, removal(source, &route, res));
+                    continue;
+                }
+                if !guard::allow_route(source, &route) {
+                    continue;
+                }
+
+                // Someone else took over since rtd installed it.
+                if !route.managed && installed.has_exact_route(&route.def) {
+                    hand_over(source, &route);
+                    continue;
+                }
+
+                // Replace a stale version, unmanaged ones never replace anything.
+                if route.managed {
+                    let _ = backend.del_route(&route.def);
+                }
+
+                status::set(
+                    source,
+                    status::State::WaitingForLink(route.def.link().to_string()),
+                );
+                log::info!(
+                    Netlink,
+                    "wait for link {} in netns {}",
+                    route.def.link(),
+                    name
+                );
+                if let Err(e) = backend.link_wait_exists(route.def.link()) {
+                    let res = report(source, "add", &route, Err(e));
+                    status::set(source, outcome(res, status::State::Applied));
+                    continue;
+                }
+
+                let res = report(source, "add", &route, route.add(&*backend));
+                status::set(source, outcome(res, status::State::Applied));
             }
+
+            for (source, rule) in rules {
+                if rule.delete {
+                    let res = backend.del_rule(&rule);
+                    status::set(source, removal(source, &rule, res));
+                    continue;
+                }
+
+                // Someone else took over since rtd installed it.
+                if !rule.managed && installed.has_exact_rule(&rule) {
+                    hand_over(source, &rule);
+                    continue;
+                }
+
+                // Replace a stale version, unmanaged ones never replace anything.
+                if rule.managed {
+                    let _ = backend.del_rule(&rule);
+                }
+
+                status::set(source, add_rule(&*backend, source, &rule));
+            }
+
+            Ok(())
+        });
+
+        // Nothing has been applied if the namespace can't be entered.
+        if let Err(e) = res.map_err(SetupError::from).and_then(|res| res) {
+            log::error!(Netlink, "enter netns {}: {}", name, e);
+            for source in sources {
+                status::set(source, status::State::Failed(e.to_string()));
+            }
+            continue;
         }
+
+        status::applied_netns(&name);
     }
+}
 
-    for rule in rules.rules {
-        match rule.clone().delete(&conn) {
-            Ok(_) => eprintln!("[info] del {}", rule),
-            Err(e) => eprintln!("[warn] del {}: {}", rule, e),
-        }
+/// Replaces the copy of a route in its mirror table, if it has one.
+/// The old copy stays in place until the route itself has been replaced,
+/// call this right after installing the new route.
+fn replace_mirror(
+    backend: &dyn Backend,
+    source: audit::Source,
+    old: &Route,
+    new: &Route,
+) -> Result<(), SetupError> {
+    if let Some(mirror) = old.mirror_def() {
+        let _ = backend.del_route(&mirror);
+    }
 
-        if !rule.delete {
-            match rule.clone().add(&conn) {
-                Ok(_) => eprintln!("[info] add {}", rule),
-                Err(e) => eprintln!("[warn] add {}: {}", rule, e),
+    match new.mirror_def() {
+        Some(mirror) => report(source, "add", &mirror, backend.add_route(&mirror)),
+        None => Ok(()),
+    }
+}
+
+/// Drops entries identical to an earlier one, e.g. from concatenated
+/// generated files, so that each change is only made once.
+/// `line` returns the line of an entry.
+fn dedup<T: Eq + Hash>(path: &str, entries: &mut Vec<T>, line: impl Fn(&T) -> usize) {
+    let mut seen = HashMap::with_capacity(entries.len());
+    let duplicate: Vec<bool> = entries
+        .iter()
+        .map(|entry| match seen.entry(entry) {
+            Entry::Occupied(first) => {
+                log::info!(
+                    Parser,
+                    "{}:{} duplicates line {}, merge",
+                    path,
+                    line(entry),
+                    first.get()
+                );
+                true
             }
+            Entry::Vacant(vacant) => {
+                vacant.insert(line(entry));
+                false
+            }
+        })
+        .collect();
+    drop(seen);
+
+    let mut duplicate = duplicate.into_iter();
+    entries.retain(|_| !duplicate.next().unwrap_or_default());
+}
+
+/// Logs the outcome of an add or delete operation and records it in the audit log.
+fn report(
+    source: audit::Source,
+    action: &str,
+    entry: &dyn fmt::Display,
+    res: Result<(), SetupError>,
+) -> Result<(), SetupError> {
+    match &res {
+        Ok(()) => {
+            log::entry(
+                log::Level::Info,
+                log::Subsystem::Netlink,
+                action,
+                entry,
+                None,
+            );
+            audit::record(source, action, entry, Ok(()));
+        }
+        Err(e) => {
+            log::entry(
+                log::Level::Warn,
+                log::Subsystem::Netlink,
+                action,
+                entry,
+                Some(e),
+            );
+            audit::record(source, action, entry, Err(e));
         }
     }
 
-    Ok(())
+    res
+}
+
+/// Reports the outcome of a `del` entry. Entries that are already absent
+/// only get a note so that real failures stand out.
+fn removal(
+    source: audit::Source,
+    entry: &dyn fmt::Display,
+    res: Result<(), SetupError>,
+) -> status::State {
+    match res {
+        Err(e) if e.is_not_found() => {
+            log::info!(Netlink, "del {:#}: already absent", entry);
+            status::State::Absent
+        }
+        res => outcome(report(source, "del", entry, res), status::State::Removed),
+    }
+}
+
+/// Maps the result of an operation to the resulting entry state.
+fn outcome(res: Result<(), SetupError>, success: status::State) -> status::State {
+    match res {
+        Ok(()) => success,
+        Err(e) => status::State::Failed(e.to_string()),
+    }
 }
@@ -0,0 +1,89 @@
+//! Static multicast routes.
+//!
+//! The kernel drops the entries as soon as the multicast routing socket
+//! is closed, so it is held by a thread for the lifetime of the daemon.
+//! Interfaces that are recreated (e.g. PPP reconnects) need to be
+//! registered again, `watch` re-adds the affected entries.
+//!
+//! The entries themselves are made through the multicast routing socket
+//! rather than a backend, netlink has no requests for them.
+
+use crate::audit::Source;
+use crate::{log, status};
+use crate::{outcome, report};
+
+use rsdsl_rtd::{Backend, Mroute, Mrouter};
+
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Installs the given multicast routes and keeps them in place.
+pub fn watch(backend: &dyn Backend, mroutes: Vec<(Source, Mroute)>) {
+    if mroutes.is_empty() {
+        return;
+    }
+
+    let backend = match backend.connect() {
+        Ok(backend) => backend,
+        Err(e) => {
+            log::error!(Netlink, "connect for multicast routing: {}", e);
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        let mut mrouter = match Mrouter::new() {
+            Ok(mrouter) => mrouter,
+            Err(e) => {
+                log::error!(Netlink, "take over multicast routing: {}", e);
+                for (source, _) in &mroutes {
+                    status::set(*source, status::State::Failed(e.to_string()));
+                }
+
+                status::applied();
+                return;
+            }
+        };
+
+        for (source, mroute) in &mroutes {
+            add(&*backend, &mut mrouter, *source, mroute);
+        }
+        status::applied();
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            let mut changed = false;
+            for (source, mroute) in &mroutes {
+                if !mrouter.is_stale(mroute) {
+                    continue;
+                }
+
+                log::info!(Netlink, "interface of {} recreated, re-add", mroute);
+                add(&*backend, &mut mrouter, *source, mroute);
+                changed = true;
+            }
+
+            if changed {
+                status::applied();
+            }
+        }
+    });
+}
+
+fn add(backend: &dyn Backend, mrouter: &mut Mrouter, source: Source, mroute: &Mroute) {
+    for link in mroute.links() {
+        status::set(source, status::State::WaitingForLink(link.to_string()));
+        log::info!(Netlink, "wait for link {}", link);
+        if let Err(e) = backend.link_wait_exists(link) {
+            log::error!(Netlink, "wait for link {}: {}", link, e);
+            status::set(source, status::State::Failed(e.to_string()));
+            return;
+        }
+    }
+
+    let res = report(source, "add", mroute, mrouter.add(mroute));
+    status::set(source, outcome(res, status::State::Applied));
+}
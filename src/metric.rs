@@ -0,0 +1,106 @@
+//! Default metrics per interface (`metrics` lines of the route configuration).
+//!
+//! Primary and backup WANs usually differ in nothing but the metric of their
+//! routes. Declaring it once per interface keeps it out of every route line.
+
+use crate::RouteParseError;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A `metrics` line of the route configuration, e.g. `metrics set ppp0=100 lte0=200`.
+///
+/// Routes through the listed interfaces that don't specify a metric
+/// get the one of their interface, wherever the line is in the configuration.
+#[derive(Clone, Debug)]
+pub struct LinkMetrics {
+    pub metrics: Vec<(String, u32)>,
+    pub line: usize,
+}
+
+impl LinkMetrics {
+    /// Returns the default metric of an interface, if there is one.
+    pub fn get(&self, link: &str) -> Option<u32> {
+        self.metrics
+            .iter()
+            .find(|(l, _)| l == link)
+            .map(|(_, metric)| *metric)
+    }
+}
+
+impl fmt::Display for LinkMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "metrics")?;
+        for (link, metric) in &self.metrics {
+            write!(f, " {}={}", link, metric)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for LinkMetrics {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Interface names are case-sensitive.
+        let s = crate::lowercase_keywords(s, usize::MAX);
+        let mut words = s.split_whitespace();
+
+        let version_str = words.next().ok_or(RouteParseError::NoVersion)?;
+        if version_str != "metrics" {
+            return Err(RouteParseError::InvalidVersion(version_str.to_string()));
+        }
+
+        let cmd = words.next().ok_or(RouteParseError::NoCmd)?;
+        if cmd != "set" {
+            return Err(RouteParseError::InvalidCmd(cmd.to_string()));
+        }
+
+        let mut metrics = Vec::<(String, u32)>::new();
+        for word in words {
+            let (link, metric) = word
+                .split_once('=')
+                .filter(|(link, _)| !link.is_empty())
+                .ok_or_else(|| RouteParseError::InvalidLinkMetric(word.to_string()))?;
+
+            if metrics.iter().any(|(l, _)| l == link) {
+                return Err(RouteParseError::DuplicateAttr(link.to_string()));
+            }
+            metrics.push((link.to_string(), metric.parse()?));
+        }
+
+        if metrics.is_empty() {
+            return Err(RouteParseError::NoLinkMetrics);
+        }
+
+        Ok(Self { metrics, line: 0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::round_trip;
+
+    #[test]
+    fn link_metrics() {
+        assert_eq!(
+            round_trip::<LinkMetrics>("metrics set ppp0=100 lte0=200"),
+            "metrics ppp0=100 lte0=200"
+        );
+
+        assert!(matches!(
+            "metrics set ppp0=100 ppp0=200".parse::<LinkMetrics>(),
+            Err(RouteParseError::DuplicateAttr(link)) if link == "ppp0"
+        ));
+        assert!(matches!(
+            "metrics set =100".parse::<LinkMetrics>(),
+            Err(RouteParseError::InvalidLinkMetric(_))
+        ));
+        assert!(matches!(
+            "metrics set".parse::<LinkMetrics>(),
+            Err(RouteParseError::NoLinkMetrics)
+        ));
+    }
+}
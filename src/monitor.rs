@@ -0,0 +1,222 @@
+//! Logs the route and rule changes of other processes (`--monitor`).
+//!
+//! Routes that keep changing under rtd's feet are hard to pin down
+//! otherwise. Each change is logged along with the protocol of the entry
+//! and the process that made it if it can still be identified.
+//! Routes others add to exclusive tables are removed right away.
+
+use crate::lookup::{DisplayRoute, DisplayRule};
+use crate::{control, log, tables};
+
+use rsdsl_rtd::rtnl::{self, RouteMsg, RuleMsg};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::thread;
+
+/// The origin of a change.
+#[derive(Debug)]
+enum Origin {
+    Kernel,
+    Own,
+    Process {
+        pid: u32,
+        name: String,
+    },
+    /// The socket was closed before it could be attributed.
+    Unknown(u32),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Kernel => write!(f, "kernel"),
+            Self::Own => write!(f, "rtd"),
+            Self::Process { pid, name } => write!(f, "{} (pid {})", name, pid),
+            Self::Unknown(port) => write!(f, "unknown process (netlink port {})", port),
+        }
+    }
+}
+
+/// Starts logging foreign changes in the background.
+pub fn spawn() {
+    let mut sock = match rtnl::Socket::new() {
+        Ok(sock) => sock,
+        Err(e) => {
+            log::error!(Netlink, "connect for monitoring: {}", e);
+            return;
+        }
+    };
+
+    let groups = [
+        rtnl::RTNLGRP_IPV4_ROUTE,
+        rtnl::RTNLGRP_IPV6_ROUTE,
+        rtnl::RTNLGRP_IPV4_RULE,
+        rtnl::RTNLGRP_IPV6_RULE,
+    ];
+    if let Err(e) = sock.subscribe(&groups) {
+        log::error!(Netlink, "subscribe to routing changes: {}", e);
+        return;
+    }
+
+    log::info!(General, "monitor routing changes");
+
+    thread::spawn(move || loop {
+        match sock.recv_events() {
+            Ok(events) => events.iter().for_each(handle),
+            Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                log::warn!(Netlink, "monitor: missed routing changes, too many at once");
+            }
+            Err(e) => {
+                log::error!(Netlink, "monitor: {}", e);
+                return;
+            }
+        }
+    });
+}
+
+fn handle(event: &rtnl::Event) {
+    let action = match event.ty {
+        rtnl::RTM_NEWROUTE | rtnl::RTM_NEWRULE => "add",
+        rtnl::RTM_DELROUTE | rtnl::RTM_DELRULE => "del",
+        _ => return,
+    };
+
+    let origin = origin(event.portid);
+    if matches!(origin, Origin::Own) {
+        return;
+    }
+
+    match event.ty {
+        rtnl::RTM_NEWROUTE | rtnl::RTM_DELROUTE => {
+            let Some(route) = RouteMsg::parse(&event.payload) else {
+                return;
+            };
+
+            // These follow the addresses of the interfaces.
+            if route.table == rtnl::RT_TABLE_LOCAL && route.protocol == rtnl::RTPROT_KERNEL {
+                return;
+            }
+
+            log::info!(
+                Events,
+                "monitor: {} {} proto {} by {}",
+                action,
+                DisplayRoute(&route),
+                rtnl::protocol_name(route.protocol),
+                origin
+            );
+            control::publish(serde_json::json!({
+                "event": "kernel",
+                "action": action,
+                "entry": DisplayRoute(&route).to_string(),
+                "proto": rtnl::protocol_name(route.protocol),
+                "origin": origin.to_string(),
+            }));
+
+            if event.ty == rtnl::RTM_NEWROUTE && tables::is_foreign(&route) {
+                match tables::evict(&route) {
+                    Ok(()) => log::warn!(
+                        Netlink,
+                        "monitor: removed foreign {} by {} from exclusive table {}",
+                        DisplayRoute(&route),
+                        origin,
+                        route.table
+                    ),
+                    Err(e) => log::error!(
+                        Netlink,
+                        "monitor: remove foreign {} from exclusive table {}: {}",
+                        DisplayRoute(&route),
+                        route.table,
+                        e
+                    ),
+                }
+            }
+        }
+        _ => {
+            let Some(rule) = RuleMsg::parse(&event.payload) else {
+                return;
+            };
+
+            log::info!(
+                Events,
+                "monitor: {} {} proto {} by {}",
+                action,
+                DisplayRule(&rule),
+                rtnl::protocol_name(rule.protocol),
+                origin
+            );
+            control::publish(serde_json::json!({
+                "event": "kernel",
+                "action": action,
+                "entry": DisplayRule(&rule).to_string(),
+                "proto": rtnl::protocol_name(rule.protocol),
+                "origin": origin.to_string(),
+            }));
+        }
+    }
+}
+
+/// Attributes a change to the process owning the netlink socket it came from.
+fn origin(port: u32) -> Origin {
+    if port == 0 {
+        return Origin::Kernel;
+    }
+    // The first socket of a process is bound to its PID.
+    if port == std::process::id() || rtnl::is_own_port(port) {
+        return Origin::Own;
+    }
+
+    match owner(port) {
+        Ok(Some(pid)) if pid == std::process::id() => Origin::Own,
+        Ok(Some(pid)) => {
+            let name = fs::read_to_string(format!("/proc/{}/comm", pid))
+                .map(|comm| comm.trim_end().to_string())
+                .unwrap_or_else(|_| "?".to_string());
+
+            Origin::Process { pid, name }
+        }
+        Ok(None) => Origin::Unknown(port),
+        Err(e) => {
+            log::debug!(Netlink, "find owner of netlink port {}: {}", port, e);
+            Origin::Unknown(port)
+        }
+    }
+}
+
+/// Looks up the process that has the netlink socket bound to a port open.
+fn owner(port: u32) -> io::Result<Option<u32>> {
+    // sk Eth Pid Groups Rmem Wmem Dump Locks Drops Inode
+    let sockets = fs::read_to_string("/proc/net/netlink")?;
+    let inode = sockets.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let protocol = fields.get(1)?.parse::<i32>().ok()?;
+        let pid = fields.get(2)?.parse::<u32>().ok()?;
+
+        (protocol == libc::NETLINK_ROUTE && pid == port).then(|| fields.get(9).copied())?
+    });
+    let Some(inode) = inode else {
+        return Ok(None);
+    };
+
+    let target = format!("socket:[{}]", inode);
+    for proc in fs::read_dir("/proc")? {
+        let proc = proc?;
+        let Some(pid) = proc.file_name().to_str().and_then(|s| s.parse().ok()) else {
+            continue;
+        };
+
+        // Processes may exit or deny access, skip them.
+        let Ok(fds) = fs::read_dir(proc.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            if fs::read_link(fd.path()).is_ok_and(|link| link.as_os_str() == target.as_str()) {
+                return Ok(Some(pid));
+            }
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,287 @@
+//! Static multicast routes (`mroute` lines of the route configuration),
+//! e.g. to forward IPTV streams from the WAN to the LAN.
+//!
+//! Unlike unicast routes these are not managed via rtnetlink. The kernel only
+//! forwards multicast traffic while a process holds its multicast routing
+//! socket and drops all entries once it is closed.
+
+use crate::{rtnl, RouteParseError, SetupError};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::str::FromStr;
+
+const MRT_INIT: i32 = 200;
+const MRT_ADD_VIF: i32 = 202;
+const MRT_DEL_VIF: i32 = 203;
+const MRT_ADD_MFC: i32 = 204;
+const MRT_DEL_MFC: i32 = 205;
+
+const VIFF_USE_IFINDEX: u8 = 0x8;
+
+/// The maximum number of interfaces (VIFs) the kernel supports.
+const MAXVIFS: usize = 32;
+
+/// An `mroute` line of the route configuration.
+#[derive(Clone, Debug)]
+pub struct Mroute {
+    pub group: Ipv4Addr,
+    /// The sender, any sender if unset.
+    pub source: Option<Ipv4Addr>,
+    /// The interface the traffic is received on.
+    pub iif: String,
+    /// The interfaces the traffic is forwarded to.
+    pub oifs: Vec<String>,
+    pub line: usize,
+}
+
+impl Mroute {
+    /// Returns the interfaces of the entry, the incoming one first.
+    pub fn links(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.iif.as_str()).chain(self.oifs.iter().map(String::as_str))
+    }
+}
+
+impl fmt::Display for Mroute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mroute group {}", self.group)?;
+        if let Some(source) = self.source {
+            write!(f, " source {}", source)?;
+        }
+        write!(f, " iif {} oifs {}", self.iif, self.oifs.join(","))?;
+
+        Ok(())
+    }
+}
+
+impl FromStr for Mroute {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = crate::lowercase_keywords(s, 2);
+        let mut words = s.split_whitespace();
+
+        let version_str = words.next().ok_or(RouteParseError::NoVersion)?;
+        if version_str != "mroute" {
+            return Err(RouteParseError::InvalidVersion(version_str.to_string()));
+        }
+
+        // Entries vanish with the socket of the previous instance,
+        // there is nothing to delete.
+        let cmd = words.next().ok_or(RouteParseError::NoCmd)?;
+        if cmd != "add" {
+            return Err(RouteParseError::InvalidCmd(cmd.to_string()));
+        }
+
+        let attrs = crate::attr_pairs(words, |attr| attr)?;
+
+        let mut group = None;
+        let mut source = None;
+        let mut iif = None;
+        let mut oifs = Vec::new();
+
+        for (attr, value) in attrs {
+            match attr {
+                "group" => group = Some(value.parse()?),
+                "source" => source = Some(value.parse()?),
+                "iif" => iif = Some(value.to_string()),
+                "oifs" => oifs = value.split(',').map(String::from).collect(),
+                _ => return Err(RouteParseError::InvalidAttr(attr.to_string())),
+            }
+        }
+
+        let group: Ipv4Addr = group.ok_or(RouteParseError::NoGroup)?;
+        if !group.is_multicast() {
+            return Err(RouteParseError::NotMulticast(IpAddr::V4(group)));
+        }
+
+        let iif = iif.ok_or(RouteParseError::NoIif)?;
+        if oifs.is_empty() {
+            return Err(RouteParseError::NoOifs);
+        }
+
+        Ok(Self {
+            group,
+            source,
+            iif,
+            oifs,
+            line: 0,
+        })
+    }
+}
+
+/// The multicast routing socket of the kernel. Dropping it removes all entries.
+#[derive(Debug)]
+pub struct Mrouter {
+    fd: OwnedFd,
+    /// The interface index each VIF was created for, by link name.
+    vifs: HashMap<String, (u16, u32)>,
+}
+
+impl Mrouter {
+    /// Takes over multicast routing, which fails if another
+    /// multicast routing daemon is running.
+    pub fn new() -> io::Result<Self> {
+        // SAFETY: Plain socket(2) call, the result is checked below.
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_INET,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::IPPROTO_IGMP,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: fd is a freshly created socket that nothing else owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mrouter = Self {
+            fd,
+            vifs: HashMap::new(),
+        };
+        mrouter.setsockopt(MRT_INIT, &1i32.to_ne_bytes())?;
+
+        Ok(mrouter)
+    }
+
+    /// Installs an entry, registering its interfaces with the kernel first.
+    pub fn add(&mut self, mroute: &Mroute) -> Result<(), SetupError> {
+        let mut ttls = [0; MAXVIFS];
+        let parent = self.vif(&mroute.iif)?;
+        for oif in &mroute.oifs {
+            // Forward packets with any TTL above the threshold of 1.
+            ttls[usize::from(self.vif(oif)?)] = 1;
+        }
+
+        self.setsockopt(MRT_ADD_MFC, &mfcctl(mroute, parent, ttls))?;
+        Ok(())
+    }
+
+    /// Removes an entry.
+    pub fn del(&mut self, mroute: &Mroute) -> Result<(), SetupError> {
+        let parent = self
+            .vifs
+            .get(&mroute.iif)
+            .map(|(vifi, _)| *vifi)
+            .unwrap_or_default();
+
+        self.setsockopt(MRT_DEL_MFC, &mfcctl(mroute, parent, [0; MAXVIFS]))?;
+        Ok(())
+    }
+
+    /// Reports whether any interface of an entry was recreated
+    /// since it was registered, e.g. by a reconnect.
+    pub fn is_stale(&self, mroute: &Mroute) -> bool {
+        mroute.links().any(|link| {
+            self.vifs
+                .get(link)
+                .is_some_and(|(_, index)| rtnl::link_index(link).ok() != Some(*index))
+        })
+    }
+
+    /// Returns the VIF of a link, (re-)creating it if needed.
+    fn vif(&mut self, link: &str) -> io::Result<u16> {
+        let index = rtnl::link_index(link)?;
+
+        let vifi = match self.vifs.get(link) {
+            Some((vifi, current)) if *current == index => return Ok(*vifi),
+            Some((vifi, _)) => {
+                // The kernel usually removed it along with the old interface.
+                let _ = self.setsockopt(MRT_DEL_VIF, &vifctl(*vifi, 0));
+                *vifi
+            }
+            None if self.vifs.len() >= MAXVIFS => {
+                return Err(io::Error::other(format!(
+                    "more than {} multicast interfaces",
+                    MAXVIFS
+                )))
+            }
+            None => self.vifs.len() as u16,
+        };
+
+        self.setsockopt(MRT_ADD_VIF, &vifctl(vifi, index))?;
+        self.vifs.insert(link.to_string(), (vifi, index));
+
+        Ok(vifi)
+    }
+
+    fn setsockopt(&self, name: i32, value: &[u8]) -> io::Result<()> {
+        // SAFETY: value is a valid buffer of the advertised length.
+        let res = unsafe {
+            libc::setsockopt(
+                self.fd.as_raw_fd(),
+                libc::IPPROTO_IP,
+                name,
+                value.as_ptr() as *const libc::c_void,
+                value.len() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+/// Builds a struct vifctl referring to the interface by index.
+fn vifctl(vifi: u16, index: u32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(16);
+    buf.extend_from_slice(&vifi.to_ne_bytes());
+    buf.push(VIFF_USE_IFINDEX);
+    buf.push(1); // vifc_threshold
+    buf.extend_from_slice(&0u32.to_ne_bytes()); // vifc_rate_limit
+    buf.extend_from_slice(&index.to_ne_bytes());
+    buf.extend_from_slice(&[0; 4]); // vifc_rmt_addr
+    buf
+}
+
+/// Builds a struct mfcctl, the counters are output only.
+fn mfcctl(mroute: &Mroute, parent: u16, ttls: [u8; MAXVIFS]) -> Vec<u8> {
+    let source = mroute.source.unwrap_or(Ipv4Addr::UNSPECIFIED);
+
+    let mut buf = Vec::with_capacity(60);
+    buf.extend_from_slice(&source.octets());
+    buf.extend_from_slice(&mroute.group.octets());
+    buf.extend_from_slice(&parent.to_ne_bytes());
+    buf.extend_from_slice(&ttls);
+    buf.resize(60, 0);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::round_trip;
+
+    #[test]
+    fn entries() {
+        assert_eq!(
+            round_trip::<Mroute>("mroute add group 239.1.1.1 iif eth0 oifs eth1,eth2"),
+            "mroute group 239.1.1.1 iif eth0 oifs eth1,eth2"
+        );
+        assert_eq!(
+            round_trip::<Mroute>("mroute add group 239.1.1.1 source 192.0.2.5 iif eth0 oifs eth1"),
+            "mroute group 239.1.1.1 source 192.0.2.5 iif eth0 oifs eth1"
+        );
+
+        assert!(matches!(
+            "mroute add group 192.0.2.1 iif eth0 oifs eth1".parse::<Mroute>(),
+            Err(RouteParseError::NotMulticast(_))
+        ));
+        assert!(matches!(
+            "mroute add group 239.1.1.1 iif eth0".parse::<Mroute>(),
+            Err(RouteParseError::NoOifs)
+        ));
+        // Entries go away with rtd, there is nothing to delete.
+        assert!(matches!(
+            "mroute del group 239.1.1.1 iif eth0 oifs eth1".parse::<Mroute>(),
+            Err(RouteParseError::InvalidCmd(cmd)) if cmd == "del"
+        ));
+    }
+}
@@ -0,0 +1,134 @@
+//! Weighted multipath routes spreading traffic across several uplinks.
+
+use crate::{rtnl, Route, RouteDef, SetupError};
+
+use std::fmt;
+use std::net::IpAddr;
+
+/// The members of a balance group, installed as a single multipath route.
+#[derive(Clone, Debug)]
+pub struct Balance {
+    pub name: String,
+    pub members: Vec<Route>,
+}
+
+impl Balance {
+    /// Groups routes by their balance group in order of appearance,
+    /// skipping routes that aren't part of one.
+    pub fn group(routes: Vec<Route>) -> Vec<Balance> {
+        let mut groups: Vec<Balance> = Vec::new();
+
+        for route in routes {
+            let Some(name) = route.balance.clone() else {
+                continue;
+            };
+
+            match groups.iter_mut().find(|group| group.name == name) {
+                Some(group) => group.members.push(route),
+                None => groups.push(Balance {
+                    name,
+                    members: vec![route],
+                }),
+            }
+        }
+
+        groups
+    }
+
+    /// Returns the group restricted to the members that are `up`.
+    pub fn subset(&self, up: &[bool]) -> Balance {
+        Balance {
+            name: self.name.clone(),
+            members: self
+                .members
+                .iter()
+                .zip(up)
+                .filter(|(_, up)| **up)
+                .map(|(member, _)| member.clone())
+                .collect(),
+        }
+    }
+
+    /// Installs the multipath route with all members as its nexthops,
+    /// removing it if there are none.
+    pub fn blocking_replace(&self) -> Result<(), SetupError> {
+        let Some(first) = self.members.first() else {
+            return Ok(());
+        };
+        let (dst, prefix_len, table, metric) = key(&first.def);
+
+        let nexthops = self
+            .members
+            .iter()
+            .map(|member| {
+                let on_link = match &member.def {
+                    RouteDef::V4(r) => r.on_link,
+                    RouteDef::V6(r) => r.on_link,
+                };
+
+                Ok(rtnl::Nexthop {
+                    index: rtnl::link_index(member.def.link())?,
+                    gateway: member.def.rtr(),
+                    on_link,
+                    weight: member.weight,
+                })
+            })
+            .collect::<Result<Vec<_>, SetupError>>()?;
+
+        crate::retry(|| {
+            Ok(
+                rtnl::Socket::new()?
+                    .replace_multipath(dst, prefix_len, table, metric, &nexthops)?,
+            )
+        })
+    }
+
+    /// Removes the multipath route.
+    pub fn blocking_del(&self) -> Result<(), SetupError> {
+        let Some(first) = self.members.first() else {
+            return Ok(());
+        };
+        let (dst, prefix_len, table, metric) = key(&first.def);
+
+        crate::retry(|| Ok(rtnl::Socket::new()?.del_route(dst, prefix_len, table, metric)?))
+    }
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "balance {}", self.name)?;
+
+        let Some(first) = self.members.first() else {
+            return write!(f, " (no members)");
+        };
+
+        match &first.def {
+            RouteDef::V4(r) => write!(f, " route4 {}/{}", r.dst, r.prefix_len)?,
+            RouteDef::V6(r) => write!(f, " route6 {}/{}", r.dst, r.prefix_len)?,
+        }
+        let (_, _, table, metric) = key(&first.def);
+        if let Some(table) = table {
+            write!(f, " table {}", table)?;
+        }
+        if let Some(metric) = metric {
+            write!(f, " metric {}", metric)?;
+        }
+
+        for member in &self.members {
+            write!(f, " nexthop")?;
+            if let Some(rtr) = member.def.rtr() {
+                write!(f, " via {}", rtr)?;
+            }
+            write!(f, " dev {} weight {}", member.def.link(), member.weight)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn key(def: &RouteDef) -> (IpAddr, u8, Option<u32>, Option<u32>) {
+    match def {
+        RouteDef::V4(r) => (IpAddr::V4(r.dst), r.prefix_len, r.table, r.metric),
+        RouteDef::V6(r) => (IpAddr::V6(r.dst), r.prefix_len, r.table, r.metric),
+    }
+}
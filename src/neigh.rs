@@ -0,0 +1,462 @@
+//! Permanent neighbor (ARP/NDP) and proxy entries (`/data/neighbors.nb`).
+//!
+//! Instead of an address, entries can specify a prefix (typically
+//! `$PDPREFIX[n]`) and the host part of the address via `host`.
+
+use crate::{rtnl, vars, SetupError};
+
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// An error parsing a neighbor configuration line or file.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum NeighborParseError {
+    AddrNotIpv4,
+    AddrNotIpv6,
+    DuplicateAttr(String),
+    InvalidAttr(String),
+    InvalidCidr(String),
+    InvalidCmd(String),
+    InvalidLladdr(String),
+    InvalidVersion(String),
+    Line(usize, Box<NeighborParseError>),
+    NoAddr,
+    NoAttrValue(String),
+    NoCmd,
+    NoHost,
+    NoLink,
+    NoLladdr,
+    NoVersion,
+    ParseAddr(std::net::AddrParseError),
+    ParseInt(std::num::ParseIntError),
+    Var(vars::VarError),
+}
+
+impl fmt::Display for NeighborParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AddrNotIpv4 => write!(f, "neigh4/proxy4 with non-IPv4 address")?,
+            Self::AddrNotIpv6 => write!(f, "neigh6/proxy6 with non-IPv6 address")?,
+            Self::DuplicateAttr(a) => write!(f, "duplicate attribute {}", a)?,
+            Self::InvalidAttr(a) => write!(f, "invalid attribute {}", a)?,
+            Self::InvalidCidr(c) => write!(f, "invalid CIDR {}", c)?,
+            Self::InvalidCmd(c) => write!(f, "invalid command {} (want \"add\" or \"del\")", c)?,
+            Self::InvalidLladdr(l) => write!(
+                f,
+                "invalid link-layer address {} (want xx:xx:xx:xx:xx:xx)",
+                l
+            )?,
+            Self::InvalidVersion(v) => write!(
+                f,
+                "invalid version: {} (want \"neigh4\", \"neigh6\", \"proxy4\" or \"proxy6\")",
+                v
+            )?,
+            Self::Line(line, e) => write!(f, "line {}: {}", line, e)?,
+            Self::NoAddr => write!(f, "missing neighbor address")?,
+            Self::NoAttrValue(a) => write!(f, "missing value for attribute {}", a)?,
+            Self::NoCmd => write!(f, "missing command (want \"add\" or \"del\")")?,
+            Self::NoHost => write!(f, "prefix without host part (\"host\" attribute)")?,
+            Self::NoLink => write!(f, "missing network interface (\"dev\" attribute)")?,
+            Self::NoLladdr => write!(f, "missing link-layer address (\"lladdr\" attribute)")?,
+            Self::NoVersion => write!(
+                f,
+                "missing version (want \"neigh4\", \"neigh6\", \"proxy4\" or \"proxy6\")"
+            )?,
+            Self::ParseAddr(e) => write!(f, "parse network address: {}", e)?,
+            Self::ParseInt(e) => write!(f, "parse integer: {}", e)?,
+            Self::Var(e) => write!(f, "variable: {}", e)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl From<std::net::AddrParseError> for NeighborParseError {
+    fn from(e: std::net::AddrParseError) -> NeighborParseError {
+        NeighborParseError::ParseAddr(e)
+    }
+}
+
+impl From<std::num::ParseIntError> for NeighborParseError {
+    fn from(e: std::num::ParseIntError) -> NeighborParseError {
+        NeighborParseError::ParseInt(e)
+    }
+}
+
+impl From<crate::AttrError> for NeighborParseError {
+    fn from(e: crate::AttrError) -> NeighborParseError {
+        match e {
+            crate::AttrError::Duplicate(attr) => NeighborParseError::DuplicateAttr(attr),
+            crate::AttrError::NoValue(attr) => NeighborParseError::NoAttrValue(attr),
+        }
+    }
+}
+
+impl From<vars::VarError> for NeighborParseError {
+    fn from(e: vars::VarError) -> NeighborParseError {
+        NeighborParseError::Var(e)
+    }
+}
+
+impl NeighborParseError {
+    /// Returns the (1-based) line the error occurred on if parsing a whole file.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Self::Line(line, _) => Some(*line),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the offending attribute if the error is specific to one.
+    pub fn attr(&self) -> Option<&str> {
+        match self {
+            Self::DuplicateAttr(a) | Self::InvalidAttr(a) | Self::NoAttrValue(a) => Some(a),
+            Self::Line(_, e) => e.attr(),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for NeighborParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Line(_, e) => Some(e.as_ref()),
+            Self::ParseAddr(e) => Some(e),
+            Self::ParseInt(e) => Some(e),
+            Self::Var(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A single line of the neighbor configuration.
+#[derive(Clone, Debug)]
+pub struct Neighbor {
+    pub delete: bool,
+    pub proxy: bool,
+    pub addr: IpAddr,
+    /// The link-layer address, `None` for proxy entries.
+    pub lladdr: Option<[u8; 6]>,
+    pub link: String,
+    pub line: usize,
+    pub template: Option<String>,
+    /// The trailing comment of the configuration line, shown in the log.
+    pub comment: Option<String>,
+}
+
+impl Neighbor {
+    /// Describes the entry as configured, i.e. with placeholders intact.
+    pub fn label(&self) -> String {
+        match &self.template {
+            Some(template) => template.clone(),
+            None => self.to_string(),
+        }
+    }
+
+    /// Installs the entry, replacing any existing one for the address.
+    /// IPv6 proxy entries additionally require NDP proxying on the link.
+    pub fn blocking_add(&self) -> Result<(), SetupError> {
+        if self.proxy && self.addr.is_ipv6() {
+            fs::write(
+                format!("/proc/sys/net/ipv6/conf/{}/proxy_ndp", self.link),
+                "1",
+            )?;
+        }
+
+        let index = rtnl::link_index(&self.link)?;
+        let lladdr = self.lladdr.as_ref().map(|l| &l[..]);
+        crate::retry(|| Ok(rtnl::Socket::new()?.add_neigh(index, self.addr, lladdr)?))
+    }
+
+    /// Removes the entry.
+    pub fn blocking_del(&self) -> Result<(), SetupError> {
+        let index = rtnl::link_index(&self.link)?;
+        crate::retry(|| Ok(rtnl::Socket::new()?.del_neigh(index, self.addr, self.proxy)?))
+    }
+}
+
+/// The alternate form (`{:#}`) includes the comment of the configuration line.
+impl fmt::Display for Neighbor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.proxy, self.addr) {
+            (false, IpAddr::V4(_)) => write!(f, "neigh4")?,
+            (false, IpAddr::V6(_)) => write!(f, "neigh6")?,
+            (true, IpAddr::V4(_)) => write!(f, "proxy4")?,
+            (true, IpAddr::V6(_)) => write!(f, "proxy6")?,
+        }
+        write!(f, " {}", self.addr)?;
+        if let Some(lladdr) = self.lladdr {
+            write!(f, " lladdr ")?;
+            for (i, octet) in lladdr.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ":")?;
+                }
+                write!(f, "{:02x}", octet)?;
+            }
+        }
+        write!(f, " dev {}", self.link)?;
+        if let Some(comment) = self.comment.as_ref().filter(|_| f.alternate()) {
+            write!(f, " # {}", comment)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Neighbor {
+    type Err = NeighborParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = crate::lowercase_keywords(s, 3);
+        let mut words = s.split_whitespace();
+
+        let version = words.next().ok_or(NeighborParseError::NoVersion)?;
+        let (proxy, ipv6) = match version {
+            "neigh4" => (false, false),
+            "neigh6" => (false, true),
+            "proxy4" => (true, false),
+            "proxy6" => (true, true),
+            _ => return Err(NeighborParseError::InvalidVersion(version.to_string())),
+        };
+
+        let cmd = words.next().ok_or(NeighborParseError::NoCmd)?;
+        let delete = match cmd {
+            "add" => false,
+            "del" => true,
+            _ => return Err(NeighborParseError::InvalidCmd(cmd.to_string())),
+        };
+
+        let addr = words.next().ok_or(NeighborParseError::NoAddr)?;
+        let (addr, prefix_len) = match addr.split_once('/') {
+            Some((addr, prefix_len)) => (addr.parse::<IpAddr>()?, Some(prefix_len.parse()?)),
+            None => (addr.parse()?, None),
+        };
+
+        let attrs = crate::attr_pairs(words, |attr| attr)?;
+
+        let mut lladdr = None;
+        let mut host = None;
+        let mut link = None;
+
+        for (attr, value) in attrs {
+            match attr {
+                "lladdr" if !proxy => lladdr = Some(parse_lladdr(value)?),
+                "host" if prefix_len.is_some() => host = Some(value.parse()?),
+                "dev" => link = Some(value.to_string()),
+                _ => return Err(NeighborParseError::InvalidAttr(attr.to_string())),
+            }
+        }
+
+        if !proxy && lladdr.is_none() {
+            return Err(NeighborParseError::NoLladdr);
+        }
+
+        let addr = match prefix_len {
+            Some(prefix_len) => with_host(
+                addr,
+                prefix_len,
+                host.ok_or(NeighborParseError::NoHost)?,
+                ipv6,
+            )?,
+            None => addr,
+        };
+        match addr {
+            IpAddr::V4(_) if ipv6 => return Err(NeighborParseError::AddrNotIpv6),
+            IpAddr::V6(_) if !ipv6 => return Err(NeighborParseError::AddrNotIpv4),
+            _ => {}
+        }
+
+        Ok(Self {
+            delete,
+            proxy,
+            addr,
+            lladdr,
+            link: link.ok_or(NeighborParseError::NoLink)?,
+            line: 0,
+            template: None,
+            comment: None,
+        })
+    }
+}
+
+/// Combines the network part of `prefix` with the host part of `host`.
+fn with_host(
+    prefix: IpAddr,
+    prefix_len: u8,
+    host: IpAddr,
+    ipv6: bool,
+) -> Result<IpAddr, NeighborParseError> {
+    let invalid = || NeighborParseError::InvalidCidr(format!("{}/{}", prefix, prefix_len));
+
+    match (prefix, host) {
+        (IpAddr::V4(prefix), IpAddr::V4(host)) => {
+            let host_mask = u32::MAX
+                .checked_shr(prefix_len.into())
+                .ok_or_else(invalid)?;
+            Ok(IpAddr::V4(Ipv4Addr::from(
+                (u32::from(prefix) & !host_mask) | (u32::from(host) & host_mask),
+            )))
+        }
+        (IpAddr::V6(prefix), IpAddr::V6(host)) => {
+            let host_mask = u128::MAX
+                .checked_shr(prefix_len.into())
+                .ok_or_else(invalid)?;
+            Ok(IpAddr::V6(Ipv6Addr::from(
+                (u128::from(prefix) & !host_mask) | (u128::from(host) & host_mask),
+            )))
+        }
+        _ if ipv6 => Err(NeighborParseError::AddrNotIpv6),
+        _ => Err(NeighborParseError::AddrNotIpv4),
+    }
+}
+
+fn parse_lladdr(s: &str) -> Result<[u8; 6], NeighborParseError> {
+    let invalid = || NeighborParseError::InvalidLladdr(s.to_string());
+
+    let mut lladdr = [0; 6];
+    let mut octets = s.split(':');
+    for octet in lladdr.iter_mut() {
+        let hex = octets.next().ok_or_else(invalid)?;
+        if hex.len() != 2 {
+            return Err(invalid());
+        }
+
+        *octet = u8::from_str_radix(hex, 16).map_err(|_| invalid())?;
+    }
+
+    if octets.next().is_some() {
+        return Err(invalid());
+    }
+
+    Ok(lladdr)
+}
+
+/// A parsed neighbor configuration file.
+#[derive(Debug, Default)]
+pub struct Neighbors {
+    pub neighbors: Vec<Neighbor>,
+}
+
+impl FromStr for Neighbors {
+    type Err = NeighborParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let neighbors = s
+            .lines()
+            .enumerate()
+            .map(|(i, l)| (i, crate::split_comment(l)))
+            .filter(|(_, (l, comment))| comment.is_none() || !l.trim().is_empty())
+            .map(|(i, (l, comment))| {
+                // Lines with placeholders are resolved at apply time,
+                // check their syntax using stand-in values for now.
+                let template = vars::has_vars(l).then(|| l.to_string());
+                let parsed = match template {
+                    Some(_) => vars::expand(l, &vars::Vars::placeholders())
+                        .map_err(NeighborParseError::from)
+                        .and_then(|l| l.parse::<Neighbor>()),
+                    None => l.parse::<Neighbor>(),
+                };
+
+                parsed
+                    .map(|neighbor| Neighbor {
+                        line: i + 1,
+                        template,
+                        comment: comment.map(String::from),
+                        ..neighbor
+                    })
+                    .map_err(|e| NeighborParseError::Line(i + 1, Box::new(e)))
+            })
+            .collect::<Result<Vec<Neighbor>, Self::Err>>()?;
+
+        Ok(Self { neighbors })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::round_trip;
+
+    fn parse_err(line: &str) -> NeighborParseError {
+        match line.parse::<Neighbor>() {
+            Ok(neighbor) => panic!("{}: parsed as {}", line, neighbor),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn static_entries() {
+        assert_eq!(
+            round_trip::<Neighbor>("neigh4 add 192.0.2.7 lladdr 02:00:00:00:00:0A dev eth0"),
+            "neigh4 192.0.2.7 lladdr 02:00:00:00:00:0a dev eth0"
+        );
+        assert_eq!(
+            round_trip::<Neighbor>("neigh6 add 2001:db8::7 lladdr 02:00:00:00:00:07 dev eth0"),
+            "neigh6 2001:db8::7 lladdr 02:00:00:00:00:07 dev eth0"
+        );
+
+        assert!(matches!(
+            parse_err("neigh4 add 192.0.2.7 dev eth0"),
+            NeighborParseError::NoLladdr
+        ));
+        assert!(matches!(
+            parse_err("neigh4 add 192.0.2.7 lladdr 02:00:00:00:07 dev eth0"),
+            NeighborParseError::InvalidLladdr(_)
+        ));
+        assert!(matches!(
+            parse_err("neigh6 add 192.0.2.7 lladdr 02:00:00:00:00:07 dev eth0"),
+            NeighborParseError::AddrNotIpv6
+        ));
+    }
+
+    #[test]
+    fn proxy_arp() {
+        assert_eq!(
+            round_trip::<Neighbor>("proxy4 add 192.0.2.7 dev eth0"),
+            "proxy4 192.0.2.7 dev eth0"
+        );
+
+        // The link answers with its own address.
+        assert!(matches!(
+            parse_err("proxy4 add 192.0.2.7 lladdr 02:00:00:00:00:07 dev eth0"),
+            NeighborParseError::InvalidAttr(attr) if attr == "lladdr"
+        ));
+        assert!(matches!(
+            parse_err("proxy4 add 192.0.2.7"),
+            NeighborParseError::NoLink
+        ));
+    }
+
+    #[test]
+    fn proxy_ndp_in_prefixes() {
+        assert_eq!(
+            round_trip::<Neighbor>("proxy6 add 2001:db8:100:1::/64 host ::7 dev lan0"),
+            "proxy6 2001:db8:100:1::7 dev lan0"
+        );
+        assert!(matches!(
+            parse_err("proxy6 add 2001:db8:100:1::/64 dev lan0"),
+            NeighborParseError::NoHost
+        ));
+
+        // The delegated prefix is only known at apply time.
+        let line = "proxy6 add $PDPREFIX[1] host ::7 dev lan0";
+        let neighbors: Neighbors = line.parse().unwrap();
+        assert_eq!(neighbors.neighbors[0].label(), line);
+    }
+
+    #[test]
+    fn comments() {
+        let neighbors: Neighbors =
+            "# printer\nneigh4 add 192.0.2.7 lladdr 02:00:00:00:00:07 dev eth0 # printer\n"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            format!("{:#}", neighbors.neighbors[0]),
+            "neigh4 192.0.2.7 lladdr 02:00:00:00:00:07 dev eth0 # printer"
+        );
+        assert_eq!(neighbors.neighbors[0].line, 2);
+    }
+}
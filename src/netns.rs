@@ -0,0 +1,155 @@
+//! Network namespaces other than rtd's own, such as those of containers
+//! (`netns` attribute).
+//!
+//! A netlink socket belongs to the namespace of the thread that creates it.
+//! Work inside a namespace runs on a short-lived thread that enters it first,
+//! the rest of the process stays where it is.
+//!
+//! Comments (`# ...`) are split off the lines here, too.
+//!
+//! The entries of a namespace can be grouped in the configuration files:
+//!
+//! ```text
+//! netns box {
+//! route4 add to 10.0.0.0/8 dev eth0
+//! }
+//! ```
+
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::thread;
+
+/// Where `ip netns add` keeps the namespaces.
+const NETNS_DIR: &str = "/run/netns";
+
+/// Runs `f` inside the named network namespace.
+pub fn run<T: Send>(name: &str, f: impl FnOnce() -> T + Send) -> io::Result<T> {
+    let ns = File::open(Path::new(NETNS_DIR).join(name))?;
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            // SAFETY: Plain setns(2) call on a file descriptor we own,
+            // it only affects the calling thread.
+            if unsafe { libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNET) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(f())
+        })
+        .join()
+        .unwrap_or_else(|_| Err(io::Error::other("thread panicked")))
+    })
+}
+
+/// An error in the `netns` blocks of a configuration file.
+#[derive(Debug)]
+pub enum BlockError {
+    InvalidName(String),
+    Nested,
+    NotInBlock,
+    Unclosed(String),
+    Unsupported(String),
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidName(n) => write!(
+                f,
+                "invalid network namespace {} (want name as in \"ip netns\")",
+                n
+            )?,
+            Self::Nested => write!(f, "netns block inside netns block")?,
+            Self::NotInBlock => write!(f, "}} outside of netns block")?,
+            Self::Unclosed(n) => write!(f, "netns block {} not closed (want }})", n)?,
+            Self::Unsupported(v) => write!(f, "{} not supported inside netns block", v)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for BlockError {}
+
+/// A line of a configuration file along with the namespace of its block.
+pub(crate) struct Line<'a> {
+    pub number: usize,
+    pub text: &'a str,
+    pub netns: Option<&'a str>,
+    /// The trailing comment, without the `#`.
+    pub comment: Option<&'a str>,
+}
+
+impl<'a> Line<'a> {
+    /// Returns the first word of the line, e.g. `route4`.
+    pub fn version(&self) -> Option<Cow<'a, str>> {
+        let version = self.text.split_whitespace().next()?;
+        Some(match version.chars().any(char::is_uppercase) {
+            true => Cow::Owned(version.to_lowercase()),
+            false => Cow::Borrowed(version),
+        })
+    }
+
+    /// Returns the line with the namespace of its block as an attribute.
+    pub fn scoped(&self) -> Cow<'a, str> {
+        match self.netns {
+            Some(netns) => Cow::Owned(format!("{} netns {}", self.text, netns)),
+            None => Cow::Borrowed(self.text),
+        }
+    }
+}
+
+/// Returns the lines of a configuration file except for the delimiters
+/// of `netns <name> {` ... `}` blocks and lines holding nothing but a comment.
+/// Errors come with their line number.
+pub(crate) fn lines(s: &str) -> Result<Vec<Line<'_>>, (usize, BlockError)> {
+    let mut lines = Vec::new();
+    let mut block: Option<(usize, &str)> = None;
+    for (i, text) in s.lines().enumerate() {
+        let (text, comment) = crate::split_comment(text);
+        if comment.is_some() && text.trim().is_empty() {
+            continue;
+        }
+
+        let mut words = text.split_whitespace();
+        match [words.next(), words.next(), words.next(), words.next()] {
+            [Some(keyword), Some(name), Some("{"), None]
+                if keyword.eq_ignore_ascii_case("netns") =>
+            {
+                if block.is_some() {
+                    return Err((i + 1, BlockError::Nested));
+                }
+                if !is_valid_name(name) {
+                    return Err((i + 1, BlockError::InvalidName(name.to_string())));
+                }
+
+                block = Some((i + 1, name));
+            }
+            [Some("}"), None, ..] => {
+                if block.take().is_none() {
+                    return Err((i + 1, BlockError::NotInBlock));
+                }
+            }
+            _ => lines.push(Line {
+                number: i + 1,
+                text,
+                netns: block.map(|(_, name)| name),
+                comment,
+            }),
+        }
+    }
+
+    match block {
+        Some((line, name)) => Err((line, BlockError::Unclosed(name.to_string()))),
+        None => Ok(lines),
+    }
+}
+
+/// Reports whether a namespace name refers to an entry of the namespace directory.
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+}
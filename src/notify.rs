@@ -0,0 +1,84 @@
+//! Tells other daemons that the routing configuration has been (re-)applied.
+//!
+//! The time of the last apply is written to a file other daemons can watch.
+//! Additionally, `RTD_NOTIFY` can list processes to signal in the form
+//! `name:SIGNAL[,name:SIGNAL...]`, e.g. `rsdsl_nftables:USR1`.
+
+use crate::log;
+
+use std::fs;
+
+const NOTIFY_PATH: &str = "/run/rtd.applied";
+const NOTIFY_VAR: &str = "RTD_NOTIFY";
+
+/// Signals that routes and rules have just been applied.
+pub fn applied() {
+    if let Err(e) = fs::write(NOTIFY_PATH, log::timestamp() + "\n") {
+        log::error!(General, "write notify file ({}): {}", NOTIFY_PATH, e);
+    }
+
+    let Ok(targets) = std::env::var(NOTIFY_VAR) else {
+        return;
+    };
+
+    for target in targets.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let Some((name, sig_name)) = target.split_once(':') else {
+            log::error!(
+                General,
+                "invalid {} entry {} (want name:SIGNAL)",
+                NOTIFY_VAR,
+                target
+            );
+            continue;
+        };
+
+        let Some(sig) = signal(sig_name) else {
+            log::error!(
+                General,
+                "invalid signal {} (want HUP, USR1 or USR2)",
+                sig_name
+            );
+            continue;
+        };
+
+        for pid in pids(name) {
+            // SAFETY: kill(2) has no memory safety requirements.
+            if unsafe { libc::kill(pid, sig) } < 0 {
+                log::error!(
+                    General,
+                    "notify {} ({}): {}",
+                    name,
+                    pid,
+                    std::io::Error::last_os_error()
+                );
+            } else {
+                log::debug!(General, "notify {} ({}) with SIG{}", name, pid, sig_name);
+            }
+        }
+    }
+}
+
+fn signal(name: &str) -> Option<libc::c_int> {
+    match name.strip_prefix("SIG").unwrap_or(name) {
+        "HUP" => Some(libc::SIGHUP),
+        "USR1" => Some(libc::SIGUSR1),
+        "USR2" => Some(libc::SIGUSR2),
+        _ => None,
+    }
+}
+
+/// Returns the PIDs of all processes with the given name.
+fn pids(name: &str) -> Vec<libc::pid_t> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<libc::pid_t>().ok())
+        .filter(|pid| {
+            fs::read_to_string(format!("/proc/{}/comm", pid))
+                .is_ok_and(|comm| comm.trim_end() == name)
+        })
+        .collect()
+}
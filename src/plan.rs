@@ -0,0 +1,494 @@
+//! Planning: the operations applying a configuration would take,
+//! worked out from the kernel's routes and rules without changing them.
+//!
+//! The plan follows what the daemon does for the same configuration.
+//! Configured entries replace installed versions, which may be stale,
+//! and `del` entries remove what is installed. Entries whose routes
+//! are only known while applying are left out: hostnames, placeholders,
+//! `via peer`, `src auto`, link patterns, balance groups (including
+//! routes through several links) and other network namespaces.
+
+use crate::rtnl::{self, RouteMsg, RuleMsg};
+use crate::SetupError;
+use crate::{Backend, Op, Route, RouteDef, Routes, Rule, RuleAction, RuleVersion, Rules};
+
+use std::fmt;
+use std::net::IpAddr;
+
+/// The routes and rules of the kernel, `None` for tables that couldn't be dumped.
+/// Everything is assumed to exist then.
+///
+/// The comparison is deliberately loose,
+/// an unnecessary deletion is cheaper than a stale entry.
+#[derive(Clone, Debug, Default)]
+pub struct Installed {
+    routes: Option<Vec<RouteMsg>>,
+    rules: Option<Vec<RuleMsg>>,
+    added: Vec<RouteDef>,
+}
+
+impl Installed {
+    pub fn new(routes: Option<Vec<RouteMsg>>, rules: Option<Vec<RuleMsg>>) -> Self {
+        Self {
+            routes,
+            rules,
+            added: Vec::new(),
+        }
+    }
+
+    /// Dumps the routes and rules of both address families.
+    pub fn dump(backend: &dyn Backend) -> Result<Self, SetupError> {
+        Ok(Self::new(
+            Some(backend.dump_routes(libc::AF_UNSPEC as u8)?),
+            Some(backend.dump_rules(libc::AF_UNSPEC as u8)?),
+        ))
+    }
+
+    /// Reports whether a route may exist, either in the kernel
+    /// or because an earlier entry added it.
+    pub fn has_route(&self, def: &RouteDef) -> bool {
+        let Some(routes) = &self.routes else {
+            return true;
+        };
+
+        let family = if def.dst().is_ipv4() {
+            libc::AF_INET
+        } else {
+            libc::AF_INET6
+        } as u8;
+
+        routes.iter().any(|route| {
+            route.family == family
+                && same_prefix(
+                    route.dst.unwrap_or(unspecified(def.dst())),
+                    route.dst_len,
+                    Some((def.dst(), def.prefix_len())),
+                )
+                && route.table == def.table()
+                && def
+                    .metric()
+                    .is_none_or(|metric| route.metric.unwrap_or(0) == metric)
+        }) || self.added.iter().any(|added| {
+            added.dst() == def.dst()
+                && added.prefix_len() == def.prefix_len()
+                && added.table() == def.table()
+        })
+    }
+
+    /// Reports whether the kernel has exactly the route rtd would install,
+    /// down to its metric, gateway, link and protocol. Unlike [`Self::has_route`]
+    /// this assumes nothing is installed if the routes couldn't be dumped.
+    pub fn has_exact_route(&self, def: &RouteDef) -> bool {
+        let Some(routes) = &self.routes else {
+            return false;
+        };
+        let Ok(oif) = rtnl::link_index(def.link()) else {
+            return false;
+        };
+
+        let (family, default_metric) = if def.dst().is_ipv4() {
+            (libc::AF_INET, 0)
+        } else {
+            (libc::AF_INET6, rtnl::IP6_RT_PRIO_USER)
+        };
+        let metric = def.metric().unwrap_or(default_metric);
+
+        routes.iter().any(|route| {
+            route.family == family as u8
+                && route.dst == Some(def.dst())
+                && route.dst_len == def.prefix_len()
+                && route.table == def.table()
+                && route.metric.unwrap_or(0) == metric
+                && route.gateway == def.rtr()
+                && route.oif == Some(oif)
+                // netlinklib and rtd's own requests use static, ip(8) boot.
+                && matches!(
+                    route.protocol,
+                    rtnl::RTPROT_STATIC | rtnl::RTPROT_BOOT | rtnl::RTPROT_RTD
+                )
+        })
+    }
+
+    /// Records that a route is being added.
+    pub fn add_route(&mut self, def: &RouteDef) {
+        self.added.push(def.clone());
+    }
+
+    /// Reports whether a rule may exist in the kernel.
+    pub fn has_rule(&self, rule: &Rule) -> bool {
+        let Some(rules) = &self.rules else {
+            return true;
+        };
+
+        let Some(action) = action_type(rule.action) else {
+            return true;
+        };
+
+        rules.iter().any(|msg| same_rule(msg, rule, action))
+    }
+
+    /// Reports whether the kernel has exactly the rule rtd would install,
+    /// including its protocol. Unlike [`Self::has_rule`] this assumes
+    /// nothing is installed if the rules couldn't be dumped.
+    pub fn has_exact_rule(&self, rule: &Rule) -> bool {
+        let Some(rules) = &self.rules else {
+            return false;
+        };
+        let Some(action) = action_type(rule.action) else {
+            return false;
+        };
+
+        rules
+            .iter()
+            .any(|msg| msg.protocol == rule.protocol && same_rule(msg, rule, action))
+    }
+}
+
+/// Returns the `FR_ACT_*` action of a rule,
+/// `None` for actions the comparison doesn't cover.
+fn action_type(action: RuleAction) -> Option<u8> {
+    match action {
+        RuleAction::ToTable => Some(rtnl::FR_ACT_TO_TBL),
+        RuleAction::Blackhole => Some(rtnl::FR_ACT_BLACKHOLE),
+        RuleAction::Unreachable => Some(rtnl::FR_ACT_UNREACHABLE),
+        RuleAction::Prohibit => Some(rtnl::FR_ACT_PROHIBIT),
+        _ => None,
+    }
+}
+
+/// Reports whether a kernel rule matches the same packets as a configured one
+/// and does the same with them.
+fn same_rule(msg: &RuleMsg, rule: &Rule, action: u8) -> bool {
+    let family = match rule.version {
+        RuleVersion::Both => true,
+        RuleVersion::Ipv4 => msg.family == libc::AF_INET as u8,
+        RuleVersion::Ipv6 => msg.family == libc::AF_INET6 as u8,
+    };
+
+    family
+        && msg.action == action
+        && (action != rtnl::FR_ACT_TO_TBL || msg.table == rule.table)
+        && (msg.flags & rtnl::FIB_RULE_INVERT != 0) == rule.invert
+        && msg.fwmark.filter(|fwmark| *fwmark != 0) == rule.fwmark.filter(|fwmark| *fwmark != 0)
+        && msg.dst.map_or(rule.dst.is_none(), |dst| {
+            same_prefix(dst, msg.dst_len, rule.dst)
+        })
+        && msg.src.map_or(rule.src.is_none(), |src| {
+            same_prefix(src, msg.src_len, rule.src)
+        })
+}
+
+/// Why an operation is part of a plan.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    /// The entry isn't installed yet.
+    Missing,
+    /// An installed version is replaced as it may be stale.
+    Stale,
+    /// The entry is configured to be removed.
+    Deleted,
+    /// The route is copied to its mirror table.
+    Mirror,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "missing")?,
+            Self::Stale => write!(f, "replace installed version")?,
+            Self::Deleted => write!(f, "configured for deletion")?,
+            Self::Mirror => write!(f, "copy to mirror table")?,
+        }
+
+        Ok(())
+    }
+}
+
+/// An operation applying the configuration would take.
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub op: Op,
+    pub reason: Reason,
+    /// The line of the entry the operation is for.
+    pub line: usize,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.op, self.reason)
+    }
+}
+
+/// Returns the operations applying the routes and rules would take,
+/// routes first. Nothing is changed.
+pub fn plan(routes: &Routes, rules: &Rules, installed: &Installed) -> Vec<Step> {
+    let mut installed = installed.clone();
+    let mut steps = Vec::new();
+
+    for route in routes.routes.iter().filter(|route| is_plannable(route)) {
+        let mut step = |op, reason| {
+            steps.push(Step {
+                op,
+                reason,
+                line: route.line,
+            })
+        };
+
+        if route.delete {
+            if let Some(mirror) = route.mirror_def().filter(|def| installed.has_route(def)) {
+                step(Op::DelRoute(mirror), Reason::Deleted);
+            }
+            if installed.has_route(&route.def) {
+                step(Op::DelRoute(route.def.clone()), Reason::Deleted);
+            }
+            continue;
+        }
+
+        // Someone else took over since rtd installed it.
+        if !route.managed && installed.has_exact_route(&route.def) {
+            continue;
+        }
+
+        let reason = if route.managed && installed.has_route(&route.def) {
+            step(Op::DelRoute(route.def.clone()), Reason::Stale);
+            Reason::Stale
+        } else {
+            Reason::Missing
+        };
+        installed.add_route(&route.def);
+
+        if route.is_conditional() && !route.is_active() {
+            continue;
+        }
+
+        step(Op::AddRoute(route.def.clone()), reason);
+        if let Some(mirror) = route.mirror_def() {
+            if installed.has_route(&mirror) {
+                step(Op::DelRoute(mirror.clone()), Reason::Stale);
+            }
+            step(Op::AddRoute(mirror), Reason::Mirror);
+        }
+    }
+
+    for rule in rules
+        .rules
+        .iter()
+        .filter(|rule| rule.netns.is_none() && rule.template.is_none())
+    {
+        let mut step = |op, reason| {
+            steps.push(Step {
+                op,
+                reason,
+                line: rule.line,
+            })
+        };
+
+        let exists = installed.has_rule(rule);
+        if rule.delete {
+            if exists {
+                step(Op::DelRule(rule.clone()), Reason::Deleted);
+            }
+            continue;
+        }
+
+        if !rule.managed && installed.has_exact_rule(rule) {
+            continue;
+        }
+
+        let reason = if exists && rule.managed {
+            step(Op::DelRule(rule.clone()), Reason::Stale);
+            Reason::Stale
+        } else {
+            Reason::Missing
+        };
+        step(Op::AddRule(rule.clone()), reason);
+    }
+
+    steps
+}
+
+/// Reports whether the route is known before applying it, see the module docs.
+fn is_plannable(route: &Route) -> bool {
+    route.host.is_none()
+        && route.template.is_none()
+        && !route.via_peer
+        && !route.src_auto
+        && !route.has_link_pattern()
+        && !route.has_several_links()
+        && route.balance.is_none()
+        && route.netns.is_none()
+}
+
+fn unspecified(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V4(_) => IpAddr::from([0; 4]),
+        IpAddr::V6(_) => IpAddr::from([0; 16]),
+    }
+}
+
+/// Compares prefixes ignoring any host bits.
+fn same_prefix(addr: IpAddr, len: u8, other: Option<(IpAddr, u8)>) -> bool {
+    other.is_some_and(|(other, other_len)| {
+        len == other_len && rtnl::prefix_contains(addr, len, other)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Mock;
+
+    fn parse(routes: &str, rules: &str) -> (Routes, Rules) {
+        (routes.parse().unwrap(), rules.parse().unwrap())
+    }
+
+    /// Carries the steps of a plan out like the daemon would.
+    fn execute(backend: &dyn Backend, steps: &[Step]) {
+        for step in steps {
+            let res = match &step.op {
+                Op::AddRoute(def) => backend.add_route(def),
+                Op::DelRoute(def) => backend.del_route(def),
+                Op::AddRule(rule) => backend.add_rule(rule),
+                Op::DelRule(rule) => backend.del_rule(rule),
+                op => panic!("unexpected step {}", op),
+            };
+            res.unwrap_or_else(|e| panic!("{}: {}", step, e));
+        }
+    }
+
+    fn reasons(steps: &[Step]) -> Vec<(String, Reason)> {
+        steps
+            .iter()
+            .map(|step| (step.op.to_string(), step.reason))
+            .collect()
+    }
+
+    #[test]
+    fn adds_missing_entries() {
+        let mock = Mock::new();
+        let (routes, rules) = parse(
+            "route4 add to 10.1.0.0/16 via 192.0.2.1 dev eth0\nroute6 add to 2001:db8::/32 dev eth1\n",
+            "rule4 add fwmark 0x5 lookup 100\n",
+        );
+
+        let steps = plan(&routes, &rules, &Installed::dump(&mock).unwrap());
+        assert!(steps.iter().all(|step| step.reason == Reason::Missing));
+        assert_eq!(
+            steps.iter().map(|step| step.line).collect::<Vec<_>>(),
+            [1, 2, 1]
+        );
+
+        execute(&mock, &steps);
+        assert_eq!(mock.routes().len(), 2);
+        assert_eq!(mock.rules().len(), 1);
+    }
+
+    #[test]
+    fn replaces_installed_entries() {
+        let mock = Mock::new();
+        let (routes, rules) = parse(
+            "route4 add to 10.1.0.0/16 via 192.0.2.1 dev eth0\n",
+            "rule4 add fwmark 0x5 lookup 100\n",
+        );
+
+        execute(
+            &mock,
+            &plan(&routes, &rules, &Installed::dump(&mock).unwrap()),
+        );
+        let steps = plan(&routes, &rules, &Installed::dump(&mock).unwrap());
+        assert_eq!(
+            reasons(&steps),
+            [
+                (
+                    "del route4 10.1.0.0/16 via 192.0.2.1 dev eth0".to_string(),
+                    Reason::Stale
+                ),
+                (
+                    "add route4 10.1.0.0/16 via 192.0.2.1 dev eth0".to_string(),
+                    Reason::Stale
+                ),
+                (
+                    "del rule4 fwmark 5 action to_table table 100".to_string(),
+                    Reason::Stale
+                ),
+                (
+                    "add rule4 fwmark 5 action to_table table 100".to_string(),
+                    Reason::Stale
+                ),
+            ]
+        );
+
+        // Applying twice leaves the same entries behind.
+        execute(&mock, &steps);
+        assert_eq!(mock.routes().len(), 1);
+        assert_eq!(mock.rules().len(), 1);
+    }
+
+    #[test]
+    fn removes_deleted_entries_only_if_installed() {
+        let mock = Mock::new();
+        let (routes, rules) = parse(
+            "route4 add to 10.1.0.0/16 dev eth0\n",
+            "rule4 add fwmark 0x5 lookup 100\n",
+        );
+        execute(
+            &mock,
+            &plan(&routes, &rules, &Installed::dump(&mock).unwrap()),
+        );
+
+        let (routes, rules) = parse(
+            "route4 del to 10.1.0.0/16 dev eth0\nroute4 del to 10.2.0.0/16 dev eth0\n",
+            "rule4 del fwmark 0x5 lookup 100\nrule4 del fwmark 0x6 lookup 100\n",
+        );
+        let steps = plan(&routes, &rules, &Installed::dump(&mock).unwrap());
+        assert!(steps.iter().all(|step| step.reason == Reason::Deleted));
+        assert_eq!(
+            steps.iter().map(|step| step.line).collect::<Vec<_>>(),
+            [1, 1]
+        );
+
+        execute(&mock, &steps);
+        assert!(mock.routes().is_empty());
+        assert!(mock.rules().is_empty());
+    }
+
+    #[test]
+    fn leaves_out_routes_only_known_while_applying() {
+        let (routes, rules) = parse(
+            "route4 add to 10.1.0.0/16 dev ppp+\nroute4 add to 10.2.0.0/16 via peer dev ppp0\nroute4 add to 10.3.0.0/16 dev eth0,eth1\n",
+            "",
+        );
+
+        assert!(plan(&routes, &rules, &Installed::default()).is_empty());
+    }
+
+    #[test]
+    fn dump_failures_assume_everything_exists() {
+        let mock = Mock::new();
+        mock.fail(|op| matches!(op, Op::DumpRoutes(_)).then_some(libc::ENOBUFS));
+        assert!(Installed::dump(&mock).is_err());
+
+        let (routes, rules) = parse("route4 add to 10.1.0.0/16 dev eth0\n", "");
+        let installed = Installed::new(None, None);
+        assert_eq!(
+            reasons(&plan(&routes, &rules, &installed))
+                .into_iter()
+                .map(|(_, reason)| reason)
+                .collect::<Vec<_>>(),
+            [Reason::Stale, Reason::Stale]
+        );
+        // Unless rtd handed the route over, which takes knowing it is there.
+        assert!(!installed.has_exact_route(&routes.routes[0].def));
+    }
+
+    #[test]
+    fn failed_requests_change_nothing() {
+        let mock = Mock::new();
+        mock.fail(|op| matches!(op, Op::AddRule(_)).then_some(libc::EPERM));
+
+        let rule: Rule = "rule4 add fwmark 0x5 lookup 100".parse().unwrap();
+        assert!(mock.add_rule(&rule).is_err());
+        assert!(mock.rules().is_empty());
+        assert!(matches!(mock.ops()[..], [Op::AddRule(_)]));
+    }
+}
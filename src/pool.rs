@@ -0,0 +1,61 @@
+//! Bulk installation of routes over several netlink connections.
+//!
+//! Each request waits for the kernel's answer, on slow CPUs these round trips
+//! dominate the time it takes to apply large configurations. Routes that don't
+//! depend on each other are installed concurrently instead.
+
+use crate::audit::Source;
+use crate::status;
+use crate::{log, outcome, replace_mirror, report};
+
+use rsdsl_rtd::{Backend, Route};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// The maximum number of connections, including the caller's.
+const POOL_SIZE: usize = 4;
+/// The number of routes that warrants another connection.
+const ROUTES_PER_CONNECTION: usize = 16;
+
+/// Installs the given routes, concurrently if there are enough of them.
+/// Routes without a gateway are installed first, those with one
+/// may need them to reach it.
+pub fn add_routes(backend: &dyn Backend, routes: Vec<(Source, Route)>) {
+    let (direct, via): (Vec<_>, Vec<_>) = routes
+        .into_iter()
+        .partition(|(_, route)| route.def.rtr().is_none());
+
+    run(backend, &direct);
+    run(backend, &via);
+}
+
+fn run(backend: &dyn Backend, routes: &[(Source, Route)]) {
+    let next = AtomicUsize::new(0);
+    let work = |backend: &dyn Backend| {
+        while let Some((source, route)) = routes.get(next.fetch_add(1, Ordering::Relaxed)) {
+            let res = report(*source, "add", route, route.add(backend))
+                .and(replace_mirror(backend, *source, route, route));
+            status::set(*source, outcome(res, status::State::Applied));
+        }
+    };
+
+    let helpers = routes
+        .len()
+        .div_ceil(ROUTES_PER_CONNECTION)
+        .min(POOL_SIZE)
+        .saturating_sub(1);
+    thread::scope(|s| {
+        for _ in 0..helpers {
+            match backend.connect() {
+                Ok(helper) => {
+                    s.spawn(move || work(helper.as_ref()));
+                }
+                // The remaining connections pick up the work.
+                Err(e) => log::warn!(Netlink, "connect for bulk apply: {}", e),
+            }
+        }
+
+        work(backend);
+    });
+}
@@ -0,0 +1,322 @@
+//! Reachability probes for gateway health checks.
+
+use rsdsl_rtd::rtnl;
+
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+const PAYLOAD: &[u8] = b"rsdsl_rtd";
+
+/// The discard service, datagrams sent to it don't cause any replies.
+const DISCARD_PORT: u16 = 9;
+
+const SCRIPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static SEQ: AtomicU16 = AtomicU16::new(0);
+
+/// Sends an ICMP echo request to `target` through `link`,
+/// reporting whether a reply arrived within `timeout`.
+pub fn icmp(target: IpAddr, link: &str, timeout: Duration) -> io::Result<bool> {
+    let (family, protocol, request, reply) = match target {
+        IpAddr::V4(_) => (
+            libc::AF_INET,
+            libc::IPPROTO_ICMP,
+            ICMP_ECHO_REQUEST,
+            ICMP_ECHO_REPLY,
+        ),
+        IpAddr::V6(_) => (
+            libc::AF_INET6,
+            libc::IPPROTO_ICMPV6,
+            ICMPV6_ECHO_REQUEST,
+            ICMPV6_ECHO_REPLY,
+        ),
+    };
+
+    let fd = bound_socket(family, libc::SOCK_RAW, protocol, link)?;
+
+    let id = std::process::id() as u16;
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+
+    let mut msg = vec![request, 0, 0, 0];
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&seq.to_be_bytes());
+    msg.extend_from_slice(PAYLOAD);
+    // The kernel computes the checksum of ICMPv6 messages itself.
+    if target.is_ipv4() {
+        let checksum = checksum(&msg);
+        msg[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    send_to(&fd, &msg, target, 0, link)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0; 1500];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+
+        let n = match recv_from(&fd, &mut buf, target, remaining) {
+            Ok(Some(n)) => n,
+            Ok(None) => continue,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(false)
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        // Raw IPv4 sockets deliver the IP header as well.
+        let icmp = match target {
+            IpAddr::V4(_) => {
+                let ihl = usize::from(buf[0] & 0x0f) * 4;
+                buf[..n].get(ihl..).unwrap_or_default()
+            }
+            IpAddr::V6(_) => &buf[..n],
+        };
+
+        if icmp.len() >= 8
+            && icmp[0] == reply
+            && icmp[4..6] == id.to_be_bytes()
+            && icmp[6..8] == seq.to_be_bytes()
+        {
+            return Ok(true);
+        }
+    }
+}
+
+/// Runs an external health check, reporting whether it exited successfully
+/// within `timeout`. The link and gateway of the route are passed
+/// in `RTD_LINK` and `RTD_GATEWAY` so that scripts can be shared by routes.
+pub fn script(
+    path: &Path,
+    link: &str,
+    gateway: Option<IpAddr>,
+    timeout: Duration,
+) -> io::Result<bool> {
+    let mut cmd = Command::new(path);
+    cmd.env("RTD_LINK", link)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null());
+    if let Some(gateway) = gateway {
+        cmd.env("RTD_GATEWAY", gateway.to_string());
+    }
+
+    let mut child = cmd.spawn()?;
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status.success());
+        }
+
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Ok(false);
+        }
+
+        thread::sleep(SCRIPT_POLL_INTERVAL);
+    }
+}
+
+/// Checks the neighbor (ARP/NDP) entry of `gateway` on `link`, reporting
+/// whether address resolution works. `None` if the state isn't known yet.
+///
+/// The kernel only resolves addresses it has traffic for, so a datagram is
+/// sent to the gateway afterwards to have the entry refreshed for the next check.
+pub fn neighbor(gateway: IpAddr, link: &str) -> io::Result<Option<bool>> {
+    let index = rtnl::link_index(link)?;
+    let state = rtnl::Socket::new()?.neigh_state(index, gateway)?;
+
+    let family = match gateway {
+        IpAddr::V4(_) => libc::AF_INET,
+        IpAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = bound_socket(family, libc::SOCK_DGRAM, 0, link)?;
+    // Sending fails right away while resolution is known to be failing.
+    let _ = send_to(&fd, &[], gateway, DISCARD_PORT, link);
+
+    Ok(match state {
+        Some(rtnl::NUD_FAILED) => Some(false),
+        Some(rtnl::NUD_INCOMPLETE) | None => None,
+        Some(_) => Some(true),
+    })
+}
+
+/// Opens a socket that only sends through `link`.
+fn bound_socket(family: i32, ty: i32, protocol: i32, link: &str) -> io::Result<OwnedFd> {
+    // SAFETY: Plain socket(2) call, the result is checked below.
+    let fd = unsafe { libc::socket(family, ty | libc::SOCK_CLOEXEC, protocol) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: fd is a freshly created socket that nothing else owns.
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // Probes have to take the path of the route they are checking
+    // rather than whatever route is preferred at the moment.
+    // SAFETY: link is a valid buffer of the advertised length.
+    let res = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            link.as_ptr() as *const libc::c_void,
+            link.len() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(fd)
+}
+
+fn send_to(fd: &OwnedFd, msg: &[u8], target: IpAddr, port: u16, link: &str) -> io::Result<()> {
+    // SAFETY: sockaddr_storage is plain old data, all zeroes is a valid value.
+    let mut sa: libc::sockaddr_storage = unsafe { mem::zeroed() };
+
+    let len = match target {
+        IpAddr::V4(addr) => {
+            // SAFETY: sockaddr_storage is large enough and suitably aligned
+            // for any socket address type.
+            let sin = unsafe { &mut *(&mut sa as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = port.to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(addr.octets());
+
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        IpAddr::V6(addr) => {
+            // SAFETY: sockaddr_storage is large enough and suitably aligned
+            // for any socket address type.
+            let sin6 = unsafe { &mut *(&mut sa as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = port.to_be();
+            sin6.sin6_addr.s6_addr = addr.octets();
+            if is_link_local(addr) {
+                sin6.sin6_scope_id = rtnl::link_index(link)?;
+            }
+
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    // SAFETY: msg is a valid buffer and sa a valid socket address of the given lengths.
+    let n = unsafe {
+        libc::sendto(
+            fd.as_raw_fd(),
+            msg.as_ptr() as *const libc::c_void,
+            msg.len(),
+            0,
+            &sa as *const _ as *const libc::sockaddr,
+            len as libc::socklen_t,
+        )
+    };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Receives a single message, `None` if it didn't come from `target`.
+fn recv_from(
+    fd: &OwnedFd,
+    buf: &mut [u8],
+    target: IpAddr,
+    timeout: Duration,
+) -> io::Result<Option<usize>> {
+    let tv = libc::timeval {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        // Never zero as that would disable the timeout.
+        tv_usec: timeout.subsec_micros().max(1) as libc::suseconds_t,
+    };
+
+    // SAFETY: tv is a valid timeval of the advertised size.
+    let res = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            &tv as *const _ as *const libc::c_void,
+            mem::size_of::<libc::timeval>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: sockaddr_storage is plain old data, all zeroes is a valid value.
+    let mut sa: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+
+    // SAFETY: buf and sa are valid, writable buffers of the given lengths.
+    let n = unsafe {
+        libc::recvfrom(
+            fd.as_raw_fd(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+            0,
+            &mut sa as *mut _ as *mut libc::sockaddr,
+            &mut len,
+        )
+    };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let from = match i32::from(sa.ss_family) {
+        libc::AF_INET => {
+            // SAFETY: The kernel filled in a sockaddr_in for this family.
+            let sin = unsafe { &*(&sa as *const _ as *const libc::sockaddr_in) };
+            IpAddr::from(sin.sin_addr.s_addr.to_ne_bytes())
+        }
+        libc::AF_INET6 => {
+            // SAFETY: The kernel filled in a sockaddr_in6 for this family.
+            let sin6 = unsafe { &*(&sa as *const _ as *const libc::sockaddr_in6) };
+            IpAddr::from(sin6.sin6_addr.s6_addr)
+        }
+        _ => return Ok(None),
+    };
+
+    Ok((from == target).then_some(n as usize))
+}
+
+/// Computes the internet checksum (RFC 1071) of an ICMP message.
+fn checksum(msg: &[u8]) -> u16 {
+    let mut sum = msg
+        .chunks(2)
+        .map(|chunk| u32::from(u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)])))
+        .sum::<u32>();
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+fn is_link_local(addr: Ipv6Addr) -> bool {
+    addr.segments()[0] & 0xffc0 == 0xfe80
+}
@@ -0,0 +1,175 @@
+//! Keeps entries with dynamic values (placeholders, `via peer`, `src auto`)
+//! up to date.
+//!
+//! The state files of the other rsdsl daemons and the peer and primary
+//! addresses of the links are polled. Entries whose resolved form has changed
+//! are removed and re-added with the new values.
+
+use crate::audit::Source;
+use crate::{guard, log, status};
+use crate::{outcome, replace_mirror, report};
+
+use rsdsl_rtd::{vars, Backend, Neighbor, Route, RouteDef, Rule};
+
+use std::collections::HashSet;
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Re-applies the given routes, rules and neighbors whenever their values change.
+pub fn watch(
+    backend: &dyn Backend,
+    mut routes: Vec<(Source, Route)>,
+    mut rules: Vec<(Source, Rule)>,
+    mut neighbors: Vec<(Source, Neighbor)>,
+) {
+    if routes.is_empty() && rules.is_empty() && neighbors.is_empty() {
+        return;
+    }
+
+    let backend = match backend.connect() {
+        Ok(backend) => backend,
+        Err(e) => {
+            log::error!(Netlink, "connect for reloading: {}", e);
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        // Values the guard refused, the previous ones stay in place until they change again.
+        let mut refused_routes = HashSet::new();
+        let mut refused_rules = HashSet::new();
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            let mut changed = false;
+
+            for (source, route) in &mut routes {
+                let Some(current) = current_route(route) else {
+                    continue;
+                };
+                if current == *route || refused_routes.contains(&current) {
+                    continue;
+                }
+
+                log::info!(Events, "values of {} changed, reload", source);
+                if !guard::allow_route(*source, &current) {
+                    refused_routes.insert(current);
+                    continue;
+                }
+
+                let _ = report(*source, "del", route, backend.del_route(&route.def));
+                let res = report(*source, "add", &current, current.add(&*backend))
+                    .and(replace_mirror(&*backend, *source, route, &current));
+                status::set(*source, outcome(res, status::State::Applied));
+
+                *route = current;
+                changed = true;
+            }
+
+            for (source, rule) in &mut rules {
+                let Some(current) = current_rule(rule) else {
+                    continue;
+                };
+                if current == *rule || refused_rules.contains(&current) {
+                    continue;
+                }
+
+                log::info!(Events, "values of {} changed, reload", source);
+                if !guard::allow_rule(*source, &current) {
+                    refused_rules.insert(current);
+                    continue;
+                }
+
+                let _ = report(*source, "del", rule, backend.del_rule(rule));
+                let res = report(*source, "add", &current, backend.add_rule(&current));
+                status::set(*source, outcome(res, status::State::Applied));
+
+                *rule = current;
+                changed = true;
+            }
+
+            for (source, neighbor) in &mut neighbors {
+                let Some(current) = current_neighbor(neighbor) else {
+                    continue;
+                };
+                if current.to_string() == neighbor.to_string() {
+                    continue;
+                }
+
+                log::info!(Events, "values of {} changed, reload", source);
+
+                let _ = report(*source, "del", neighbor, backend.del_neighbor(neighbor));
+                let res = report(*source, "add", &current, backend.add_neighbor(&current));
+                status::set(*source, outcome(res, status::State::Applied));
+
+                *neighbor = current;
+                changed = true;
+            }
+
+            if changed {
+                status::applied();
+            }
+        }
+    });
+}
+
+/// Resolves a route using the current values,
+/// `None` if they aren't (all) available at the moment.
+pub fn current_route(route: &Route) -> Option<Route> {
+    let mut current = match &route.template {
+        Some(template) => {
+            let line = vars::expand(template, &vars::Vars::load()).ok()?;
+            let mut current = route.resolved(line.parse().ok()?);
+            // Copies for the links matching a pattern keep their link and metric.
+            if current.has_link_pattern() {
+                current.def.set_link(route.def.link());
+                current.def.set_metric(route.def.metric());
+            }
+
+            current
+        }
+        None => route.clone(),
+    };
+
+    if current.via_peer {
+        let ipv6 = matches!(current.def, RouteDef::V6(_));
+        current.def.set_rtr(vars::peer(current.def.link(), ipv6)?);
+    }
+    if current.src_auto {
+        let ipv6 = matches!(current.def, RouteDef::V6(_));
+        current.src = Some(vars::primary_addr(current.def.link(), ipv6)?);
+    }
+
+    Some(current)
+}
+
+/// Resolves a rule using the current values,
+/// `None` if they aren't (all) available at the moment.
+pub fn current_rule(rule: &Rule) -> Option<Rule> {
+    let template = rule.template.as_ref()?;
+    let line = vars::expand(template, &vars::Vars::load()).ok()?;
+
+    Some(Rule {
+        line: rule.line,
+        template: rule.template.clone(),
+        comment: rule.comment.clone(),
+        ..line.parse().ok()?
+    })
+}
+
+/// Resolves a neighbor using the current values,
+/// `None` if they aren't (all) available at the moment.
+fn current_neighbor(neighbor: &Neighbor) -> Option<Neighbor> {
+    let template = neighbor.template.as_ref()?;
+    let line = vars::expand(template, &vars::Vars::load()).ok()?;
+
+    Some(Neighbor {
+        line: neighbor.line,
+        template: neighbor.template.clone(),
+        comment: neighbor.comment.clone(),
+        ..line.parse().ok()?
+    })
+}
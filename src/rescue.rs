@@ -0,0 +1,148 @@
+//! `panic`: an escape hatch for when a bad policy locks the operator out.
+//!
+//! Removes all policy rules except the kernel's defaults and all static
+//! routes, then installs plain default routes through the WAN.
+//! Routes the kernel maintains by itself (connected networks, router
+//! advertisements) are kept, so the LAN stays reachable.
+//!
+//! A running daemon re-applies entries whose values change,
+//! stop it first to keep the tables in this state. It also takes the
+//! request over the control socket, e.g. for the web UI.
+//!
+//! Every removed and added entry is recorded in the audit log.
+
+use crate::audit::{self, Source};
+use crate::log;
+
+use rsdsl_rtd::rtnl::{self, RouteMsg, RuleMsg};
+use rsdsl_rtd::{RouteDef, SetupError, DEFAULT_WAN};
+
+use std::fmt;
+use std::io;
+
+use rsdsl_netlinklib::blocking::Connection;
+
+#[derive(Debug)]
+pub enum PanicError {
+    InvalidAttr(String),
+    Netlink(io::Error),
+    NoAttrValue(String),
+    Setup(SetupError),
+}
+
+impl fmt::Display for PanicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidAttr(a) => write!(f, "invalid attribute {} (want \"wan\")", a)?,
+            Self::Netlink(e) => write!(f, "netlink: {}", e)?,
+            Self::NoAttrValue(a) => write!(f, "missing value for attribute {}", a)?,
+            Self::Setup(e) => write!(f, "install default routes: {}", e)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl From<io::Error> for PanicError {
+    fn from(e: io::Error) -> PanicError {
+        PanicError::Netlink(e)
+    }
+}
+
+impl From<SetupError> for PanicError {
+    fn from(e: SetupError) -> PanicError {
+        PanicError::Setup(e)
+    }
+}
+
+impl std::error::Error for PanicError {}
+
+pub fn panic(source: Source, args: &[String]) -> Result<(), PanicError> {
+    let mut words = args.iter().map(String::as_str);
+
+    let mut wan = DEFAULT_WAN;
+    while let Some(attr) = words.next() {
+        let value = words
+            .next()
+            .ok_or_else(|| PanicError::NoAttrValue(attr.to_string()))?;
+        match attr {
+            "wan" => wan = value,
+            _ => return Err(PanicError::InvalidAttr(attr.to_string())),
+        }
+    }
+
+    let mut sock = rtnl::Socket::new()?;
+
+    for rule in sock.flush_rules(|rule| !is_default_rule(rule))? {
+        let rule = format!("rule priority {} table {}", rule.priority, rule.table);
+        report(source, "del", &rule);
+    }
+
+    for route in sock.flush_routes(is_static_route)? {
+        report(source, "del", &format!("route {}", Dst(&route)));
+    }
+
+    let conn = Connection::new().map_err(SetupError::from)?;
+    for route in RouteDef::defaults(wan, rtnl::RT_TABLE_MAIN) {
+        // Router advertisements may have installed one already.
+        let _ = route.clone().blocking_del(&conn);
+        if let Err(e) = route.clone().blocking_add(&conn) {
+            audit::record(source, "add", &route, Err(&e));
+            return Err(e.into());
+        }
+        report(source, "add", &route);
+    }
+
+    log::warn!(
+        General,
+        "flushed rules and static routes, default route via {}",
+        wan
+    );
+    Ok(())
+}
+
+/// Logs a change that was made and records it in the audit log.
+fn report(source: Source, action: &str, entry: &dyn fmt::Display) {
+    log::info!(Netlink, "{} {}", action, entry);
+    audit::record(source, action, entry, Ok(()));
+}
+
+/// Reports whether a rule is one of those the kernel starts out with,
+/// looking up the local, main and default tables in that order.
+fn is_default_rule(rule: &RuleMsg) -> bool {
+    let selectors = rule.dst.is_some()
+        || rule.src.is_some()
+        || rule.iifname.is_some()
+        || rule.oifname.is_some()
+        || rule.fwmark.is_some_and(|fwmark| fwmark != 0);
+
+    !selectors
+        && rule.flags & rtnl::FIB_RULE_INVERT == 0
+        && rule.action == rtnl::FR_ACT_TO_TBL
+        && matches!(
+            (rule.priority, rule.table),
+            (0, rtnl::RT_TABLE_LOCAL)
+                | (32766, rtnl::RT_TABLE_MAIN)
+                | (32767, rtnl::RT_TABLE_DEFAULT)
+        )
+}
+
+/// Reports whether a route was added by a program or the administrator
+/// rather than by the kernel itself.
+fn is_static_route(route: &RouteMsg) -> bool {
+    route.table != rtnl::RT_TABLE_LOCAL
+        && matches!(route.protocol, rtnl::RTPROT_STATIC | rtnl::RTPROT_BOOT)
+}
+
+/// Formats the destination and table of a kernel route.
+struct Dst<'a>(&'a RouteMsg);
+
+impl fmt::Display for Dst<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.dst {
+            Some(dst) => write!(f, "{}/{}", dst, self.0.dst_len)?,
+            None => write!(f, "default")?,
+        }
+        write!(f, " table {}", self.0.table)
+    }
+}
@@ -0,0 +1,212 @@
+//! Re-applies the parsed configuration on SIGUSR2 without reading the files
+//! again, e.g. after something else flushed the routing tables.
+//! With a `reconcile-interval` setting, entries that went missing
+//! are added back periodically. What happens to them instead
+//! is up to their drift policy, which SIGUSR2 overrides.
+//!
+//! Only entries that are in place as configured are re-applied. Those whose
+//! state is up to a watcher (withdrawn, inactive or waiting) are left alone,
+//! as are balance groups, hostname routes, link pattern copies,
+//! other network namespaces and unmanaged entries.
+
+use crate::audit::Source;
+use crate::{add_rule, removal, report};
+use crate::{installed, log, pool, reload, settings, status};
+
+use rsdsl_rtd::{Backend, Drift, Iproute2, Route, Rule, SetupError};
+
+use std::fmt;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use rsdsl_netlinklib::blocking::Connection;
+
+static PLAN: Mutex<Option<Plan>> = Mutex::new(None);
+
+/// The entries of the last apply pass.
+#[derive(Clone, Debug)]
+struct Plan {
+    iproute2: bool,
+    routes: Vec<(Source, Route)>,
+    rules: Vec<(Source, Rule)>,
+}
+
+fn plan() -> std::sync::MutexGuard<'static, Option<Plan>> {
+    PLAN.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Records the entries of a completed apply pass for later re-application.
+pub fn register(iproute2: bool, routes: Vec<(Source, Route)>, rules: Vec<(Source, Rule)>) {
+    *plan() = Some(Plan {
+        iproute2,
+        routes,
+        rules,
+    });
+}
+
+/// Deletes and re-adds the entries of the last apply pass.
+pub fn run() {
+    let Some(plan) = plan().clone() else {
+        log::info!(General, "nothing applied yet, skip re-apply");
+        return;
+    };
+
+    log::info!(General, "re-apply configuration");
+    if let Err(e) = apply(plan, true) {
+        log::error!(Netlink, "re-apply: {}", e);
+    }
+}
+
+/// Adds back the entries of the last apply pass that are missing from the kernel
+/// and removes deleted ones that reappeared, every `interval`.
+pub fn watch(interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+
+        let Some(plan) = plan().clone() else {
+            continue;
+        };
+        match apply(plan, false) {
+            Ok(0) => {}
+            Ok(n) => log::info!(General, "reconcile: repaired {} entries", n),
+            Err(e) => log::error!(Netlink, "reconcile: {}", e),
+        }
+    });
+}
+
+/// Applies the entries of the plan, only those that differ from the kernel
+/// unless `full` is set. Returns the number of entries applied.
+fn apply(plan: Plan, full: bool) -> Result<usize, SetupError> {
+    let backend: Box<dyn Backend + Send> = match plan.iproute2 {
+        true => Box::new(Iproute2),
+        false => Box::new(Connection::new()?),
+    };
+    let backend = &*backend;
+    let mut installed = installed::dump(backend);
+
+    let mut applied = 0;
+    let mut batch = Vec::new();
+    for (source, route) in plan.routes {
+        if !is_settled(source) {
+            continue;
+        }
+        // Dynamic values may have changed since.
+        let Some(route) = reload::current_route(&route) else {
+            continue;
+        };
+
+        if !full && installed.has_route(&route.def) != route.delete {
+            in_place(source, &route, route.delete);
+            continue;
+        }
+        if !full && !repair(source, &route, route.delete, route.drift) {
+            continue;
+        }
+        applied += 1;
+
+        if route.delete {
+            if let Some(mirror) = route.mirror_def() {
+                let _ = report(source, "del", &mirror, backend.del_route(&mirror));
+            }
+
+            let res = backend.del_route(&route.def);
+            status::set(source, removal(source, &route, res));
+            continue;
+        }
+
+        if installed.has_route(&route.def) {
+            let _ = report(source, "del", &route, backend.del_route(&route.def));
+        }
+        installed.add_route(&route.def);
+
+        batch.push((source, route));
+    }
+    pool::add_routes(backend, batch);
+
+    for (source, rule) in plan.rules {
+        if !is_settled(source) {
+            continue;
+        }
+        let rule = match rule.template {
+            Some(_) => match reload::current_rule(&rule) {
+                Some(rule) => rule,
+                None => continue,
+            },
+            None => rule,
+        };
+
+        if !full && installed.has_rule(&rule) != rule.delete {
+            in_place(source, &rule, rule.delete);
+            continue;
+        }
+        if !full && !repair(source, &rule, rule.delete, rule.drift) {
+            continue;
+        }
+        applied += 1;
+
+        if rule.delete {
+            let res = backend.del_rule(&rule);
+            status::set(source, removal(source, &rule, res));
+            continue;
+        }
+
+        if installed.has_rule(&rule) {
+            let _ = report(source, "del", &rule, backend.del_rule(&rule));
+        }
+
+        status::set(source, add_rule(backend, source, &rule));
+    }
+
+    if applied > 0 {
+        status::applied();
+    }
+    Ok(applied)
+}
+
+/// Reacts to an entry that differs from the kernel according to its drift policy,
+/// or the `drift` setting if it has none. Returns whether to repair it.
+fn repair(source: Source, entry: &dyn fmt::Display, delete: bool, drift: Option<Drift>) -> bool {
+    match drift.unwrap_or(settings::get().drift) {
+        Drift::Ignore => false,
+        Drift::Log => {
+            let drift = if delete { "reappeared" } else { "missing" };
+            // Once is enough, the entry stays like this until it comes back.
+            if !matches!(status::state(source), Some(status::State::Drifted(_))) {
+                log::warn!(General, "drift: {} {}, leave it", entry, drift);
+                status::set(source, status::State::Drifted(drift.to_string()));
+            }
+
+            false
+        }
+        Drift::Repair => true,
+    }
+}
+
+/// Notes that a drifted entry is back as configured, e.g. repaired by someone else.
+fn in_place(source: Source, entry: &dyn fmt::Display, delete: bool) {
+    if matches!(status::state(source), Some(status::State::Drifted(_))) {
+        log::info!(General, "drift: {} back as configured", entry);
+
+        let state = if delete {
+            status::State::Removed
+        } else {
+            status::State::Applied
+        };
+        status::set(source, state);
+    }
+}
+
+/// Reports whether an entry is in place as configured or has drifted,
+/// i.e. not withdrawn, inactive, waiting or failed.
+fn is_settled(source: Source) -> bool {
+    matches!(
+        status::state(source),
+        Some(
+            status::State::Applied
+                | status::State::Removed
+                | status::State::Absent
+                | status::State::Drifted(_)
+        )
+    )
+}
@@ -0,0 +1,2775 @@
+//! Static routes (`/data/static.rt`).
+
+use crate::{
+    rtnl, vars, Backend, Bogons, Bypass, Classless, Drift, Encap, EncapType, Isolate, LinkMetrics,
+    Mroute, PrefixList, RouteAttrs, Schedule, SetupError, Sysctl, Vrf,
+};
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use rsdsl_netlinklib::blocking::Connection;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The name of the DS-Lite tunnel device maintained by rsdsl's netlinkd.
+pub const DSLITE_LINK: &str = "dslite";
+
+/// The uplink of rsdsl's PPPoE client, the default WAN of presets.
+pub const DEFAULT_WAN: &str = "ppp0";
+
+/// An error parsing a route configuration line or file.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RouteParseError {
+    BalanceMismatch(String),
+    Conflict(&'static str, &'static str),
+    DstNotIpv4,
+    DstNotIpv6,
+    DuplicateAttr(String),
+    EncapMismatch(IpAddr),
+    InvalidAttr(String),
+    InvalidCidr(String),
+    InvalidCmd(String),
+    InvalidDrift(String),
+    InvalidEncap(String),
+    InvalidHost(String),
+    InvalidLinkMetric(String),
+    InvalidLinkPattern(String),
+    InvalidLinks(String),
+    InvalidNetns(String),
+    InvalidSchedule(String),
+    InvalidType(String),
+    InvalidVersion(String),
+    InvalidWeight(u16),
+    InvalidZone(String),
+    GatewayCycle(Vec<usize>),
+    LinkLocalNoLink(Ipv6Addr),
+    Line(usize, Box<RouteParseError>),
+    NoAttrValue(String),
+    NoCmd,
+    NoDst,
+    NoFile,
+    NoFwmark,
+    NoGroup,
+    NoIif,
+    NoLink,
+    NoLinkMetrics,
+    NoName,
+    NoOifs,
+    NoSysctl,
+    NoTable,
+    NoVersion,
+    NotMulticast(IpAddr),
+    Netns(crate::netns::BlockError),
+    ParseAddr(std::net::AddrParseError),
+    ParseBool(std::str::ParseBoolError),
+    ParseInt(std::num::ParseIntError),
+    ProbeNoRtr,
+    ProbeNotIpv4,
+    ProbeNotIpv6,
+    RtrNotIpv4,
+    RtrNotIpv6,
+    SrcNotIpv4,
+    SrcNotIpv6,
+    Var(vars::VarError),
+    ZoneMismatch(String, String),
+}
+
+impl fmt::Display for RouteParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BalanceMismatch(b) => write!(
+                f,
+                "members of balance group {} differ in destination, table or metric",
+                b
+            )?,
+            Self::Conflict(a, b) => write!(f, "{} can't be combined with {}", a, b)?,
+            Self::DstNotIpv4 => write!(f, "route4 with non-IPv4 destination")?,
+            Self::DstNotIpv6 => write!(f, "route6 with non-IPv6 destination")?,
+            Self::DuplicateAttr(a) => write!(f, "duplicate attribute {}", a)?,
+            Self::EncapMismatch(addr) => write!(
+                f,
+                "tunnel endpoint {} doesn't match encapsulation (want \"ip\" for IPv4, \"ip6\" for IPv6)",
+                addr
+            )?,
+            Self::InvalidAttr(a) => write!(f, "invalid attribute {}", a)?,
+            Self::InvalidCidr(c) => write!(f, "invalid CIDR {} (want exactly 1 /)", c)?,
+            Self::InvalidCmd(c) => write!(
+                f,
+                "invalid command {} (want \"add\" or \"del\", \"set\" for sysctl and metrics)",
+                c
+            )?,
+            Self::InvalidDrift(d) => write!(
+                f,
+                "invalid drift policy {} (want \"ignore\", \"log\" or \"repair\")",
+                d
+            )?,
+            Self::InvalidEncap(e) => {
+                write!(f, "invalid encapsulation {} (want \"ip\" or \"ip6\")", e)?
+            }
+            Self::InvalidHost(h) => write!(f, "invalid hostname {} (want prefix or DNS name)", h)?,
+            Self::InvalidLinkMetric(m) => {
+                write!(f, "invalid interface metric {} (want <dev>=<metric>)", m)?
+            }
+            Self::InvalidLinkPattern(p) => write!(
+                f,
+                "invalid interface pattern {} (want a trailing + as in ppp+ or wildcards as in wg*, not both)",
+                p
+            )?,
+            Self::InvalidLinks(l) => write!(
+                f,
+                "invalid interfaces {} (want names separated by commas, as in ppp0,ppp1)",
+                l
+            )?,
+            Self::InvalidNetns(n) => write!(
+                f,
+                "invalid network namespace {} (want name as in \"ip netns\")",
+                n
+            )?,
+            Self::InvalidSchedule(s) => write!(
+                f,
+                "invalid schedule {} (want HH:MM-HH:MM, multiple separated by commas)",
+                s
+            )?,
+            Self::InvalidType(t) => write!(
+                f,
+                "invalid type {} (want \"blackhole\", \"unreachable\" or \"prohibit\")",
+                t
+            )?,
+            Self::InvalidVersion(v) => write!(
+                f,
+                "invalid version: {} (want \"route4\", \"route6\", \"dslite\", \"rtbh\", \"bogons\", \"sysctl\", \"vrf\", \"mroute\", \"isolate\", \"bypass\", \"metrics\" or \"classless\")",
+                v
+            )?,
+            Self::InvalidWeight(w) => write!(f, "invalid weight {} (want 1-256)", w)?,
+            Self::InvalidZone(rtr) => write!(
+                f,
+                "invalid gateway {} (want link-local address and interface, as in fe80::1%ppp0)",
+                rtr
+            )?,
+            Self::GatewayCycle(lines) => {
+                let lines: Vec<String> = lines.iter().map(usize::to_string).collect();
+                write!(
+                    f,
+                    "routes on lines {} need each other to reach their gateways",
+                    lines.join(", ")
+                )?
+            }
+            Self::LinkLocalNoLink(rtr) => write!(
+                f,
+                "link-local gateway {} without network interface (want \"dev\" attribute or {}%<dev>)",
+                rtr, rtr
+            )?,
+            Self::Line(line, e) => write!(f, "line {}: {}", line, e)?,
+            Self::NoAttrValue(a) => write!(f, "missing value for attribute {}", a)?,
+            Self::NoCmd => write!(f, "missing command (want \"add\" or \"del\")")?,
+            Self::NoDst => write!(f, "missing destination network (\"to\" attribute)")?,
+            Self::NoFile => write!(f, "missing prefix list (\"file\" attribute)")?,
+            Self::NoLink => write!(f, "missing network interface (\"dev\" attribute)")?,
+            Self::NoLinkMetrics => write!(f, "metrics without interfaces")?,
+            Self::NoFwmark => write!(f, "missing firewall mark (\"fwmark\" attribute)")?,
+            Self::NoGroup => write!(f, "missing multicast group (\"group\" attribute)")?,
+            Self::NoIif => write!(f, "missing incoming interface (\"iif\" attribute)")?,
+            Self::NoOifs => write!(f, "missing outgoing interfaces (\"oifs\" attribute)")?,
+            Self::NoName => write!(f, "missing VRF name (\"name\" attribute)")?,
+            Self::NoSysctl => write!(f, "sysctl without settings")?,
+            Self::NoTable => write!(f, "missing routing table (\"table\" attribute)")?,
+            Self::NoVersion => write!(
+                f,
+                "missing version (want \"route4\", \"route6\", \"dslite\", \"rtbh\", \"bogons\", \"sysctl\", \"vrf\", \"mroute\", \"isolate\", \"bypass\", \"metrics\" or \"classless\")"
+            )?,
+            Self::NotMulticast(addr) => write!(f, "{} is not a multicast group", addr)?,
+            Self::Netns(e) => write!(f, "{}", e)?,
+            Self::ParseAddr(e) => write!(f, "parse network address: {}", e)?,
+            Self::ParseBool(e) => write!(f, "parse bool: {}", e)?,
+            Self::ParseInt(e) => write!(f, "parse integer: {}", e)?,
+            Self::ProbeNoRtr => write!(f, "neighbor probe without gateway")?,
+            Self::ProbeNotIpv4 => {
+                write!(f, "route4 with non-IPv4 probe (want address or \"arp\")")?
+            }
+            Self::ProbeNotIpv6 => {
+                write!(f, "route6 with non-IPv6 probe (want address or \"ndp\")")?
+            }
+            Self::RtrNotIpv4 => write!(f, "route4 with non-IPv4 gateway")?,
+            Self::RtrNotIpv6 => write!(f, "route6 with non-IPv6 gateway")?,
+            Self::SrcNotIpv4 => {
+                write!(f, "route4 with non-IPv4 source (want address or \"auto\")")?
+            }
+            Self::SrcNotIpv6 => {
+                write!(f, "route6 with non-IPv6 source (want address or \"auto\")")?
+            }
+            Self::Var(e) => write!(f, "variable: {}", e)?,
+            Self::ZoneMismatch(zone, link) => write!(
+                f,
+                "gateway zone {} doesn't match network interface {}",
+                zone, link
+            )?,
+        }
+
+        Ok(())
+    }
+}
+
+impl From<std::net::AddrParseError> for RouteParseError {
+    fn from(e: std::net::AddrParseError) -> RouteParseError {
+        RouteParseError::ParseAddr(e)
+    }
+}
+
+impl From<std::str::ParseBoolError> for RouteParseError {
+    fn from(e: std::str::ParseBoolError) -> RouteParseError {
+        RouteParseError::ParseBool(e)
+    }
+}
+
+impl From<std::num::ParseIntError> for RouteParseError {
+    fn from(e: std::num::ParseIntError) -> RouteParseError {
+        RouteParseError::ParseInt(e)
+    }
+}
+
+impl From<crate::netns::BlockError> for RouteParseError {
+    fn from(e: crate::netns::BlockError) -> RouteParseError {
+        RouteParseError::Netns(e)
+    }
+}
+
+impl From<crate::AttrError> for RouteParseError {
+    fn from(e: crate::AttrError) -> RouteParseError {
+        match e {
+            crate::AttrError::Duplicate(attr) => RouteParseError::DuplicateAttr(attr),
+            crate::AttrError::NoValue(attr) => RouteParseError::NoAttrValue(attr),
+        }
+    }
+}
+
+impl From<vars::VarError> for RouteParseError {
+    fn from(e: vars::VarError) -> RouteParseError {
+        RouteParseError::Var(e)
+    }
+}
+
+impl RouteParseError {
+    /// Returns the (1-based) line the error occurred on if parsing a whole file.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Self::Line(line, _) => Some(*line),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the offending attribute if the error is specific to one.
+    pub fn attr(&self) -> Option<&str> {
+        match self {
+            Self::DuplicateAttr(a) | Self::InvalidAttr(a) | Self::NoAttrValue(a) => Some(a),
+            Self::Line(_, e) => e.attr(),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for RouteParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Line(_, e) => Some(e.as_ref()),
+            Self::ParseAddr(e) => Some(e),
+            Self::ParseBool(e) => Some(e),
+            Self::ParseInt(e) => Some(e),
+            Self::Var(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum RouteVersion {
+    Ipv4,
+    Ipv6,
+    DsLite,
+}
+
+/// The netlink representation of a route. It is serialized as a single object,
+/// the address family follows from the destination.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(into = "RouteDefFields", try_from = "RouteDefFields")]
+pub enum RouteDef {
+    V4(rsdsl_netlinklib::route::Route4),
+    V6(rsdsl_netlinklib::route::Route6),
+}
+
+impl RouteDef {
+    /// Installs the route.
+    pub fn blocking_add(self, c: &Connection) -> Result<(), SetupError> {
+        self.check_gateway()?;
+
+        crate::retry(|| {
+            match self.clone() {
+                Self::V4(r) => c.route_add4(r)?,
+                Self::V6(r) => c.route_add6(r)?,
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Removes the route.
+    pub fn blocking_del(self, c: &Connection) -> Result<(), SetupError> {
+        crate::retry(|| {
+            match self.clone() {
+                Self::V4(r) => c.route_del4(r)?,
+                Self::V6(r) => c.route_del6(r)?,
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Checks that the gateway is on the link, i.e. covered by a route
+    /// through it without a gateway (such as that of a connected network).
+    /// The kernel only reports "network unreachable" otherwise.
+    pub fn check_gateway(&self) -> Result<(), SetupError> {
+        let (Some(rtr), false) = (self.rtr(), self.on_link()) else {
+            return Ok(());
+        };
+
+        // Link-local addresses are always on the link they're used with.
+        if let IpAddr::V6(rtr) = rtr {
+            if rtr.is_unicast_link_local() {
+                return Ok(());
+            }
+        }
+
+        // Leave errors about the link itself to the kernel.
+        let Ok(index) = rtnl::link_index(self.link()) else {
+            return Ok(());
+        };
+        let family = if rtr.is_ipv4() {
+            libc::AF_INET
+        } else {
+            libc::AF_INET6
+        } as u8;
+        let Ok(routes) = rtnl::Socket::new().and_then(|mut sock| sock.dump_routes(family)) else {
+            return Ok(());
+        };
+
+        let on_link = routes.iter().any(|route| {
+            route.ty == rtnl::RTN_UNICAST
+                && route.oif == Some(index)
+                && route.gateway.is_none()
+                && route
+                    .dst
+                    .is_none_or(|dst| rtnl::prefix_contains(dst, route.dst_len, rtr))
+        });
+        if !on_link {
+            return Err(SetupError::GatewayUnreachable(rtr, self.link().to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the IPv4 and IPv6 default routes through a point-to-point link.
+    pub fn defaults(link: &str, table: u32) -> [RouteDef; 2] {
+        [
+            RouteDef::V4(rsdsl_netlinklib::route::Route4 {
+                dst: Ipv4Addr::UNSPECIFIED,
+                prefix_len: 0,
+                rtr: None,
+                on_link: false,
+                table: Some(table),
+                metric: None,
+                link: link.to_string(),
+            }),
+            RouteDef::V6(rsdsl_netlinklib::route::Route6 {
+                dst: Ipv6Addr::UNSPECIFIED,
+                prefix_len: 0,
+                rtr: None,
+                on_link: false,
+                table: Some(table),
+                metric: None,
+                link: link.to_string(),
+            }),
+        ]
+    }
+
+    pub fn link(&self) -> &str {
+        match self {
+            Self::V4(r) => &r.link,
+            Self::V6(r) => &r.link,
+        }
+    }
+
+    pub fn dst(&self) -> IpAddr {
+        match self {
+            Self::V4(r) => r.dst.into(),
+            Self::V6(r) => r.dst.into(),
+        }
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        match self {
+            Self::V4(r) => r.prefix_len,
+            Self::V6(r) => r.prefix_len,
+        }
+    }
+
+    /// Returns the table of the route, the main table if unset.
+    pub fn table(&self) -> u32 {
+        match self {
+            Self::V4(r) => r.table,
+            Self::V6(r) => r.table,
+        }
+        .unwrap_or(rtnl::RT_TABLE_MAIN)
+    }
+
+    pub fn metric(&self) -> Option<u32> {
+        match self {
+            Self::V4(r) => r.metric,
+            Self::V6(r) => r.metric,
+        }
+    }
+
+    pub fn on_link(&self) -> bool {
+        match self {
+            Self::V4(r) => r.on_link,
+            Self::V6(r) => r.on_link,
+        }
+    }
+
+    pub fn rtr(&self) -> Option<IpAddr> {
+        match self {
+            Self::V4(r) => r.rtr.map(IpAddr::V4),
+            Self::V6(r) => r.rtr.map(IpAddr::V6),
+        }
+    }
+
+    /// Makes the route a host route to the given address,
+    /// ignoring addresses of the wrong family.
+    pub fn set_host(&mut self, addr: IpAddr) {
+        match (self, addr) {
+            (Self::V4(r), IpAddr::V4(addr)) => {
+                r.dst = addr;
+                r.prefix_len = 32;
+            }
+            (Self::V6(r), IpAddr::V6(addr)) => {
+                r.dst = addr;
+                r.prefix_len = 128;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_link(&mut self, link: &str) {
+        match self {
+            Self::V4(r) => r.link = link.to_string(),
+            Self::V6(r) => r.link = link.to_string(),
+        }
+    }
+
+    pub fn set_table(&mut self, table: Option<u32>) {
+        match self {
+            Self::V4(r) => r.table = table,
+            Self::V6(r) => r.table = table,
+        }
+    }
+
+    pub fn set_metric(&mut self, metric: Option<u32>) {
+        match self {
+            Self::V4(r) => r.metric = metric,
+            Self::V6(r) => r.metric = metric,
+        }
+    }
+
+    /// Sets the table and metric unless the route has its own,
+    /// e.g. to those of the VRF and link the file assigns it to.
+    pub fn inherit(&mut self, table: Option<u32>, metric: Option<u32>) {
+        let (own_table, own_metric) = match self {
+            Self::V4(r) => (&mut r.table, &mut r.metric),
+            Self::V6(r) => (&mut r.table, &mut r.metric),
+        };
+        *own_table = own_table.or(table);
+        *own_metric = own_metric.or(metric);
+    }
+
+    /// Sets the gateway, ignoring addresses of the wrong family.
+    pub fn set_rtr(&mut self, rtr: IpAddr) {
+        match (self, rtr) {
+            (Self::V4(r), IpAddr::V4(rtr)) => r.rtr = Some(rtr),
+            (Self::V6(r), IpAddr::V6(rtr)) => r.rtr = Some(rtr),
+            _ => {}
+        }
+    }
+
+    /// Returns what [`RouteDef::same_dst`] compares.
+    fn dst_key(&self) -> (IpAddr, u8, Option<u32>, Option<u32>) {
+        match self {
+            Self::V4(r) => (r.dst.into(), r.prefix_len, r.table, r.metric),
+            Self::V6(r) => (r.dst.into(), r.prefix_len, r.table, r.metric),
+        }
+    }
+
+    /// Reports whether both routes have the same destination, table and metric,
+    /// i.e. would be the same kernel route if they had the same nexthop.
+    pub fn same_dst(&self, other: &RouteDef) -> bool {
+        match (self, other) {
+            (Self::V4(a), Self::V4(b)) => {
+                a.dst == b.dst
+                    && a.prefix_len == b.prefix_len
+                    && a.table == b.table
+                    && a.metric == b.metric
+            }
+            (Self::V6(a), Self::V6(b)) => {
+                a.dst == b.dst
+                    && a.prefix_len == b.prefix_len
+                    && a.table == b.table
+                    && a.metric == b.metric
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns what identifies the route, an unset table being the main table.
+    fn key(&self) -> (IpAddr, u8, Option<IpAddr>, bool, u32, Option<u32>, &str) {
+        (
+            self.dst(),
+            self.prefix_len(),
+            self.rtr(),
+            self.on_link(),
+            self.table(),
+            self.metric(),
+            self.link(),
+        )
+    }
+
+    /// Formats the route with custom destination and gateway descriptions.
+    fn fmt_as(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        dst: Option<&dyn fmt::Display>,
+        via: Option<&dyn fmt::Display>,
+    ) -> fmt::Result {
+        match self {
+            Self::V4(r) => {
+                match dst {
+                    Some(dst) => write!(f, "route4 {}", dst)?,
+                    None => write!(f, "route4 {}/{}", r.dst, r.prefix_len)?,
+                }
+                if let Some(rtr) = via {
+                    write!(f, " via {}", rtr)?;
+                }
+                if r.on_link {
+                    write!(f, " onlink")?;
+                }
+                if let Some(table) = r.table {
+                    write!(f, " table {}", table)?;
+                }
+                if let Some(metric) = r.metric {
+                    write!(f, " metric {}", metric)?;
+                }
+                write!(f, " dev {}", r.link)?;
+            }
+            Self::V6(r) => {
+                match dst {
+                    Some(dst) => write!(f, "route6 {}", dst)?,
+                    None => write!(f, "route6 {}/{}", r.dst, r.prefix_len)?,
+                }
+                if let Some(rtr) = via {
+                    write!(f, " via {}", rtr)?;
+                }
+                if r.on_link {
+                    write!(f, " onlink")?;
+                }
+                if let Some(table) = r.table {
+                    write!(f, " table {}", table)?;
+                }
+                if let Some(metric) = r.metric {
+                    write!(f, " metric {}", metric)?;
+                }
+                write!(f, " dev {}", r.link)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for RouteDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.rtr() {
+            Some(rtr) => self.fmt_as(f, None, Some(&rtr)),
+            None => self.fmt_as(f, None, None),
+        }
+    }
+}
+
+/// The serialized form of a [`RouteDef`].
+#[derive(Serialize, Deserialize)]
+struct RouteDefFields {
+    dst: IpAddr,
+    prefix_len: u8,
+    rtr: Option<IpAddr>,
+    #[serde(default)]
+    on_link: bool,
+    table: Option<u32>,
+    metric: Option<u32>,
+    link: String,
+}
+
+impl From<RouteDef> for RouteDefFields {
+    fn from(def: RouteDef) -> Self {
+        Self {
+            dst: def.dst(),
+            prefix_len: def.prefix_len(),
+            rtr: def.rtr(),
+            on_link: def.on_link(),
+            table: match &def {
+                RouteDef::V4(r) => r.table,
+                RouteDef::V6(r) => r.table,
+            },
+            metric: def.metric(),
+            link: def.link().to_string(),
+        }
+    }
+}
+
+impl TryFrom<RouteDefFields> for RouteDef {
+    type Error = RouteParseError;
+
+    fn try_from(fields: RouteDefFields) -> Result<Self, Self::Error> {
+        match fields.dst {
+            IpAddr::V4(dst) => Ok(Self::V4(rsdsl_netlinklib::route::Route4 {
+                dst,
+                prefix_len: fields.prefix_len,
+                rtr: match fields.rtr {
+                    Some(IpAddr::V4(rtr)) => Some(rtr),
+                    Some(_) => return Err(RouteParseError::RtrNotIpv4),
+                    None => None,
+                },
+                on_link: fields.on_link,
+                table: fields.table,
+                metric: fields.metric,
+                link: fields.link,
+            })),
+            IpAddr::V6(dst) => Ok(Self::V6(rsdsl_netlinklib::route::Route6 {
+                dst,
+                prefix_len: fields.prefix_len,
+                rtr: match fields.rtr {
+                    Some(IpAddr::V6(rtr)) => Some(rtr),
+                    Some(_) => return Err(RouteParseError::RtrNotIpv6),
+                    None => None,
+                },
+                on_link: fields.on_link,
+                table: fields.table,
+                metric: fields.metric,
+                link: fields.link,
+            })),
+        }
+    }
+}
+
+impl PartialEq for RouteDef {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for RouteDef {}
+
+impl Hash for RouteDef {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// How the path of a route is checked for liveness.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Probe {
+    /// Ping the given host through the route.
+    Icmp(IpAddr),
+    /// Treat a persistently failed ARP entry of the gateway as a dead path.
+    Arp,
+    /// Treat a persistently failed NDP entry of the gateway as a dead path.
+    Ndp,
+    /// Run an external command, the path is alive if it exits successfully.
+    Script(PathBuf),
+}
+
+impl From<IpAddr> for Probe {
+    fn from(target: IpAddr) -> Probe {
+        Probe::Icmp(target)
+    }
+}
+
+impl From<Ipv4Addr> for Probe {
+    fn from(target: Ipv4Addr) -> Probe {
+        Probe::Icmp(target.into())
+    }
+}
+
+impl From<Ipv6Addr> for Probe {
+    fn from(target: Ipv6Addr) -> Probe {
+        Probe::Icmp(target.into())
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Icmp(target) => write!(f, "{}", target),
+            Self::Arp => write!(f, "arp"),
+            Self::Ndp => write!(f, "ndp"),
+            Self::Script(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl Serialize for Probe {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::text::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Probe {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::text::deserialize(deserializer)
+    }
+}
+
+impl FromStr for Probe {
+    type Err = std::net::AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "arp" => Ok(Self::Arp),
+            "ndp" => Ok(Self::Ndp),
+            _ if s.starts_with('/') => Ok(Self::Script(PathBuf::from(s))),
+            _ => Ok(Self::Icmp(s.parse()?)),
+        }
+    }
+}
+
+/// A single line of the route configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Route {
+    pub delete: bool,
+    pub def: RouteDef,
+    pub dslite: bool,
+    pub via_peer: bool,
+    /// The preferred source address of packets the host itself sends along the route.
+    #[serde(default)]
+    pub src: Option<IpAddr>,
+    /// Whether the source is the primary address of the link, which is only
+    /// known once it is assigned. `src` holds it after that.
+    #[serde(default)]
+    pub src_auto: bool,
+    /// Liveness check of the path. The route is withdrawn
+    /// while it fails so that a backup route can take over.
+    pub probe: Option<Probe>,
+    /// The balance group the route is a nexthop of, if any.
+    /// All members of a group are installed as a single multipath route.
+    pub balance: Option<String>,
+    /// The share of the group's traffic the route receives.
+    pub weight: u16,
+    /// The times of day the route is installed at, always if unset.
+    pub schedule: Option<Schedule>,
+    /// A file whose existence the route is installed during.
+    pub when_exists: Option<PathBuf>,
+    /// The number of seconds after which the route is removed for good,
+    /// counting from when it is first installed.
+    pub ttl: Option<u64>,
+    /// The DNS name the destination is resolved from. Each address it resolves to
+    /// gets a host route of its own, see [`Route::resolve_host`].
+    pub host: Option<String>,
+    /// A secondary table the route is copied to. A `lookup` rule of lower priority
+    /// pointing to it keeps traffic flowing while the route is being replaced.
+    pub mirror: Option<u32>,
+    /// The tunnel the kernel encapsulates the packets in, see [`Encap`].
+    #[serde(default)]
+    pub encap: Option<Encap>,
+    /// The network namespace the route is installed in, rtd's own if unset.
+    pub netns: Option<String>,
+    /// What to do when the route drifts from the configuration,
+    /// the `drift` setting if unset.
+    #[serde(default)]
+    pub drift: Option<Drift>,
+    /// Whether rtd keeps the route as configured. An unmanaged route
+    /// is installed once, it is never deleted, restored or reloaded after that.
+    #[serde(default = "crate::managed_by_default")]
+    pub managed: bool,
+    #[serde(default)]
+    pub line: usize,
+    pub template: Option<String>,
+    /// The trailing comment of the configuration line, shown in the log.
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+impl Route {
+    /// Reports whether the route is only installed under some condition.
+    pub fn is_conditional(&self) -> bool {
+        self.schedule.is_some() || self.when_exists.is_some() || self.ttl.is_some()
+    }
+
+    /// Reports whether the conditions of the route currently hold.
+    /// The expiry of the `ttl` is up to the caller.
+    pub fn is_active(&self) -> bool {
+        self.schedule.as_ref().is_none_or(Schedule::is_active)
+            && self.when_exists.as_ref().is_none_or(|path| path.exists())
+    }
+
+    /// Describes the conditions of the route, e.g. for status reports.
+    pub fn condition(&self) -> String {
+        let mut conditions = Vec::new();
+        if let Some(schedule) = &self.schedule {
+            conditions.push(format!("schedule {}", schedule));
+        }
+        if let Some(path) = &self.when_exists {
+            conditions.push(format!("when-exists {}", path.display()));
+        }
+        if let Some(ttl) = self.ttl {
+            conditions.push(format!("ttl {}", ttl));
+        }
+
+        conditions.join(" ")
+    }
+
+    /// Returns the copy of the route in the mirror table, if any.
+    pub fn mirror_def(&self) -> Option<RouteDef> {
+        let mut def = self.def.clone();
+        def.set_table(Some(self.mirror?));
+
+        Some(def)
+    }
+
+    /// Returns the attributes beyond the definition, if the route has any.
+    pub fn attrs(&self) -> Option<RouteAttrs> {
+        if self.src.is_none() && self.encap.is_none() {
+            return None;
+        }
+
+        Some(RouteAttrs {
+            src: self.src,
+            encap: self.encap.clone(),
+        })
+    }
+
+    /// Installs the route including its attributes.
+    pub fn add(&self, backend: &dyn Backend) -> Result<(), SetupError> {
+        match self.attrs() {
+            Some(attrs) => backend.add_route_attrs(&self.def, &attrs),
+            None => backend.add_route(&self.def),
+        }
+    }
+
+    /// Returns a host route to each address of the route's family the hostname
+    /// currently resolves to, or the route itself if it doesn't have a hostname.
+    pub fn resolve_host(&self) -> io::Result<Vec<Route>> {
+        let Some(host) = &self.host else {
+            return Ok(vec![self.clone()]);
+        };
+
+        let ipv6 = matches!(self.def, RouteDef::V6(_));
+        let mut addrs: Vec<IpAddr> = (host.as_str(), 0)
+            .to_socket_addrs()?
+            .map(|addr| addr.ip())
+            .filter(|addr| addr.is_ipv6() == ipv6 && !addr.is_unspecified())
+            .collect();
+        addrs.sort();
+        addrs.dedup();
+
+        Ok(addrs
+            .into_iter()
+            .map(|addr| {
+                let mut route = self.clone();
+                route.def.set_host(addr);
+                route
+            })
+            .collect())
+    }
+
+    /// Reports whether the route goes through every link matching
+    /// a pattern such as `ppp+` or `wg*` rather than a single link.
+    pub fn has_link_pattern(&self) -> bool {
+        is_link_pattern(self.def.link())
+    }
+
+    /// Reports whether the route goes through several links (`dev ppp0,ppp1`)
+    /// as a multipath route without gateways.
+    pub fn has_several_links(&self) -> bool {
+        self.def.link().contains(',')
+    }
+
+    /// Returns the members of the balance group a route through several links
+    /// stands for, one per link, or the route itself if it only has one.
+    /// The group is named after the line, which user-defined names can't clash with.
+    pub fn split_links(self) -> Vec<Route> {
+        if !self.has_several_links() {
+            return vec![self];
+        }
+
+        let name = format!("line {}", self.line);
+        self.def
+            .link()
+            .split(',')
+            .map(|link| {
+                let mut member = self.clone();
+                member.def.set_link(link);
+                member.balance = Some(name.clone());
+                member
+            })
+            .collect()
+    }
+
+    /// Reports whether a link matches the link pattern of the route.
+    pub fn matches_link(&self, link: &str) -> bool {
+        link_matches(self.def.link(), link)
+    }
+
+    /// Returns the copy of a route with a link pattern for a matching link.
+    /// The kernel only keeps one route per destination, table and metric,
+    /// so the copy in slot `n` gets the metric of the route increased by `n`.
+    pub fn link_copy(&self, link: &str, slot: u32) -> Route {
+        let mut route = self.clone();
+        route.def.set_link(link);
+        if slot > 0 {
+            let default = match self.def {
+                RouteDef::V4(_) => 0,
+                RouteDef::V6(_) => rtnl::IP6_RT_PRIO_USER,
+            };
+            let metric = self.def.metric().unwrap_or(default).saturating_add(slot);
+            route.def.set_metric(Some(metric));
+        }
+
+        route
+    }
+
+    /// Returns the route its template resolved to, keeping the line,
+    /// the comment and the table and metric it got from its file.
+    pub fn resolved(&self, mut resolved: Route) -> Route {
+        let table = match &self.def {
+            RouteDef::V4(r) => r.table,
+            RouteDef::V6(r) => r.table,
+        };
+        resolved.def.inherit(table, self.def.metric());
+
+        Route {
+            line: self.line,
+            template: self.template.clone(),
+            comment: self.comment.clone(),
+            ..resolved
+        }
+    }
+
+    /// Describes the entry as configured, i.e. with placeholders intact.
+    pub fn label(&self) -> String {
+        match &self.template {
+            Some(template) => template.clone(),
+            None => self.to_string(),
+        }
+    }
+
+    /// Returns the route with its line number cleared and its table spelled out,
+    /// so that equal routes are identical field by field.
+    pub fn canonical(&self) -> Route {
+        let mut route = self.clone();
+        route.def.set_table(Some(self.def.table()));
+        route.line = 0;
+
+        route
+    }
+
+    /// Returns everything but the line number, which doesn't change what a route does.
+    /// The template stays, different placeholders expand to the same values
+    /// until they are resolved.
+    #[allow(clippy::type_complexity)]
+    fn key(
+        &self,
+    ) -> (
+        bool,
+        &RouteDef,
+        bool,
+        (bool, Option<IpAddr>, bool),
+        &Option<Probe>,
+        (&Option<String>, u16),
+        (&Option<Schedule>, &Option<PathBuf>, Option<u64>),
+        &Option<String>,
+        (Option<u32>, &Option<Encap>),
+        &Option<String>,
+        (Option<Drift>, bool),
+        &Option<String>,
+    ) {
+        (
+            self.delete,
+            &self.def,
+            self.dslite,
+            (self.via_peer, self.src, self.src_auto),
+            &self.probe,
+            (&self.balance, self.weight),
+            (&self.schedule, &self.when_exists, self.ttl),
+            &self.host,
+            (self.mirror, &self.encap),
+            &self.netns,
+            (self.drift, self.managed),
+            &self.template,
+        )
+    }
+}
+
+impl PartialEq for Route {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Route {}
+
+impl Hash for Route {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// The alternate form (`{:#}`) includes the comment of the configuration line.
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The addresses of a hostname are only known once it is resolved.
+        let dst = match &self.host {
+            Some(host) if self.def.dst().is_unspecified() => Some(host as &dyn fmt::Display),
+            _ => None,
+        };
+
+        // The peer address is only known once the link is up.
+        match self.def.rtr() {
+            None if self.via_peer => self.def.fmt_as(f, dst, Some(&"peer"))?,
+            Some(rtr) => self.def.fmt_as(f, dst, Some(&rtr))?,
+            None => self.def.fmt_as(f, dst, None)?,
+        }
+
+        // The primary address is only known once the link has one.
+        match self.src {
+            Some(src) => write!(f, " src {}", src)?,
+            None if self.src_auto => write!(f, " src auto")?,
+            None => {}
+        }
+        if let Some(probe) = &self.probe {
+            write!(f, " probe {}", probe)?;
+        }
+        if let Some(balance) = &self.balance {
+            write!(f, " balance {} weight {}", balance, self.weight)?;
+        }
+        if let Some(schedule) = &self.schedule {
+            write!(f, " schedule {}", schedule)?;
+        }
+        if let Some(path) = &self.when_exists {
+            write!(f, " when-exists {}", path.display())?;
+        }
+        if let Some(ttl) = self.ttl {
+            write!(f, " ttl {}", ttl)?;
+        }
+        if let Some(mirror) = self.mirror {
+            write!(f, " mirror {}", mirror)?;
+        }
+        if let Some(encap) = &self.encap {
+            write!(f, " {}", encap)?;
+        }
+        if let Some(netns) = &self.netns {
+            write!(f, " netns {}", netns)?;
+        }
+        if let Some(drift) = self.drift {
+            write!(f, " drift {}", drift)?;
+        }
+        if !self.managed {
+            write!(f, " managed false")?;
+        }
+        if let Some(comment) = self.comment.as_ref().filter(|_| f.alternate()) {
+            write!(f, " # {}", comment)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Constructs a route programmatically,
+/// applying the same checks as the configuration parser.
+#[derive(Clone, Debug)]
+pub struct RouteBuilder {
+    version: RouteVersion,
+    delete: bool,
+    dst: Option<(IpAddr, u8)>,
+    rtr: Option<IpAddr>,
+    zone: Option<String>,
+    via_peer: bool,
+    src: Option<IpAddr>,
+    src_auto: bool,
+    on_link: bool,
+    table: Option<u32>,
+    metric: Option<u32>,
+    link: Option<String>,
+    probe: Option<Probe>,
+    balance: Option<String>,
+    weight: Option<u16>,
+    schedule: Option<Schedule>,
+    when_exists: Option<PathBuf>,
+    ttl: Option<u64>,
+    host: Option<String>,
+    mirror: Option<u32>,
+    encap: Option<Encap>,
+    netns: Option<String>,
+    drift: Option<Drift>,
+    managed: bool,
+}
+
+impl RouteBuilder {
+    fn new(version: RouteVersion) -> Self {
+        Self {
+            version,
+            delete: false,
+            dst: None,
+            rtr: None,
+            zone: None,
+            via_peer: false,
+            src: None,
+            src_auto: false,
+            on_link: false,
+            table: None,
+            metric: None,
+            link: None,
+            probe: None,
+            balance: None,
+            weight: None,
+            schedule: None,
+            when_exists: None,
+            ttl: None,
+            host: None,
+            mirror: None,
+            encap: None,
+            netns: None,
+            drift: None,
+            managed: true,
+        }
+    }
+
+    /// Starts an IPv4 route.
+    pub fn v4() -> Self {
+        Self::new(RouteVersion::Ipv4)
+    }
+
+    /// Starts an IPv6 route.
+    pub fn v6() -> Self {
+        Self::new(RouteVersion::Ipv6)
+    }
+
+    /// Starts an IPv4 default route via the DS-Lite tunnel.
+    pub fn dslite() -> Self {
+        Self::new(RouteVersion::DsLite)
+    }
+
+    /// Makes the entry remove the route instead of installing it.
+    pub fn delete(mut self) -> Self {
+        self.delete = true;
+        self
+    }
+
+    pub fn dst(mut self, addr: impl Into<IpAddr>, prefix_len: u8) -> Self {
+        self.dst = Some((addr.into(), prefix_len));
+        self.host = None;
+        self
+    }
+
+    /// Routes the addresses a DNS name resolves to, see [`Route::resolve_host`].
+    pub fn dst_host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self.dst = None;
+        self
+    }
+
+    pub fn via(mut self, rtr: impl Into<IpAddr>) -> Self {
+        self.rtr = Some(rtr.into());
+        self.zone = None;
+        self
+    }
+
+    /// Uses a link-local gateway scoped to an interface (`fe80::1%ppp0`).
+    /// The interface is used if `dev` isn't set and has to match it otherwise.
+    pub fn via_scoped(mut self, rtr: Ipv6Addr, zone: impl Into<String>) -> Self {
+        self.rtr = Some(rtr.into());
+        self.zone = Some(zone.into());
+        self
+    }
+
+    /// Uses the peer address of the link as the gateway.
+    pub fn via_peer(mut self) -> Self {
+        self.via_peer = true;
+        self
+    }
+
+    /// Sets the preferred source address of packets the host itself sends.
+    pub fn src(mut self, src: impl Into<IpAddr>) -> Self {
+        self.src = Some(src.into());
+        self.src_auto = false;
+        self
+    }
+
+    /// Uses the primary address of the link as the source, see [`Route::src_auto`].
+    pub fn src_auto(mut self) -> Self {
+        self.src = None;
+        self.src_auto = true;
+        self
+    }
+
+    pub fn on_link(mut self, on_link: bool) -> Self {
+        self.on_link = on_link;
+        self
+    }
+
+    pub fn table(mut self, table: u32) -> Self {
+        self.table = Some(table);
+        self
+    }
+
+    pub fn metric(mut self, metric: u32) -> Self {
+        self.metric = Some(metric);
+        self
+    }
+
+    pub fn dev(mut self, link: impl Into<String>) -> Self {
+        self.link = Some(link.into());
+        self
+    }
+
+    /// Withdraws the route while the probe fails, e.g. while a host
+    /// doesn't answer pings sent through it.
+    pub fn probe(mut self, probe: impl Into<Probe>) -> Self {
+        self.probe = Some(probe.into());
+        self
+    }
+
+    /// Makes the route a nexthop of a weighted multipath route.
+    pub fn balance(mut self, group: impl Into<String>) -> Self {
+        self.balance = Some(group.into());
+        self
+    }
+
+    /// Sets the share of the balance group's traffic, 1 by default.
+    pub fn weight(mut self, weight: u16) -> Self {
+        self.weight = Some(weight);
+        self
+    }
+
+    /// Only installs the route during the given times of day.
+    pub fn schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+
+    /// Only installs the route while the given file exists.
+    pub fn when_exists(mut self, path: impl Into<PathBuf>) -> Self {
+        self.when_exists = Some(path.into());
+        self
+    }
+
+    /// Removes the route the given number of seconds after installing it.
+    pub fn ttl(mut self, ttl: u64) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Keeps a copy of the route in the given table.
+    pub fn mirror(mut self, table: u32) -> Self {
+        self.mirror = Some(table);
+        self
+    }
+
+    /// Encapsulates the packets in a tunnel, see [`Encap`].
+    pub fn encap(mut self, encap: Encap) -> Self {
+        self.encap = Some(encap);
+        self
+    }
+
+    /// Installs the route in another network namespace, see [`crate::netns`].
+    pub fn netns(mut self, name: impl Into<String>) -> Self {
+        self.netns = Some(name.into());
+        self
+    }
+
+    /// Overrides the `drift` setting for the route.
+    pub fn drift(mut self, drift: Drift) -> Self {
+        self.drift = Some(drift);
+        self
+    }
+
+    /// Hands the route over to someone else once it is installed.
+    pub fn managed(mut self, managed: bool) -> Self {
+        self.managed = managed;
+        self
+    }
+
+    pub fn build(mut self) -> Result<Route, RouteParseError> {
+        // Only the DS-Lite default route has an implicit destination.
+        let dslite = matches!(self.version, RouteVersion::DsLite);
+        if !dslite && self.dst.is_none() && self.host.is_none() {
+            return Err(RouteParseError::NoDst);
+        }
+
+        if let Some(zone) = &self.zone {
+            match &self.link {
+                Some(link) if link != zone => {
+                    return Err(RouteParseError::ZoneMismatch(zone.clone(), link.clone()))
+                }
+                Some(_) => {}
+                None => self.link = Some(zone.clone()),
+            }
+        }
+
+        // The kernel can't tell which link a link-local gateway is on by itself.
+        if let (Some(IpAddr::V6(rtr)), None) = (self.rtr, &self.link) {
+            if rtr.is_unicast_link_local() {
+                return Err(RouteParseError::LinkLocalNoLink(rtr));
+            }
+        }
+
+        // Probes and balance groups withdraw and restore the route themselves.
+        let condition = match (&self.schedule, &self.when_exists, self.ttl) {
+            (Some(_), _, _) => Some("schedule"),
+            (None, Some(_), _) => Some("when-exists"),
+            (None, None, Some(_)) => Some("ttl"),
+            (None, None, None) => None,
+        };
+        // The routes of a hostname come and go with its addresses.
+        if self.host.is_some() {
+            if let Some(condition) = condition {
+                return Err(RouteParseError::Conflict("hostname", condition));
+            }
+            if self.probe.is_some() {
+                return Err(RouteParseError::Conflict("hostname", "probe"));
+            }
+            if self.balance.is_some() {
+                return Err(RouteParseError::Conflict("hostname", "balance"));
+            }
+        }
+
+        // A copy left behind would keep the route alive when it shouldn't be.
+        if self.mirror.is_some() {
+            if let Some(condition) = condition {
+                return Err(RouteParseError::Conflict("mirror", condition));
+            }
+            if self.probe.is_some() {
+                return Err(RouteParseError::Conflict("mirror", "probe"));
+            }
+            if self.balance.is_some() {
+                return Err(RouteParseError::Conflict("mirror", "balance"));
+            }
+            if self.mirror == Some(self.table.unwrap_or(rtnl::RT_TABLE_MAIN)) {
+                return Err(RouteParseError::InvalidAttr("mirror".to_string()));
+            }
+        }
+
+        // Copies and multipath routes are installed without the source.
+        if self.src.is_some() || self.src_auto {
+            let features = [
+                (self.balance.is_some(), "balance"),
+                (self.mirror.is_some(), "mirror"),
+            ];
+            if let Some((_, feature)) = features.iter().find(|(set, _)| *set) {
+                return Err(RouteParseError::Conflict("src", feature));
+            }
+        }
+
+        // Copies and multipath routes are installed without the encapsulation.
+        if let Some(encap) = &self.encap {
+            let features = [
+                (dslite, "dslite"),
+                (self.balance.is_some(), "balance"),
+                (self.mirror.is_some(), "mirror"),
+            ];
+            if let Some((_, feature)) = features.iter().find(|(set, _)| *set) {
+                return Err(RouteParseError::Conflict("encap", feature));
+            }
+            if let Some(addr) = encap.mismatched_addr() {
+                return Err(RouteParseError::EncapMismatch(addr));
+            }
+        }
+
+        // The watchers keeping these up to date only work in rtd's own namespace.
+        if self.netns.is_some() {
+            let features = [
+                (dslite, "dslite"),
+                (self.host.is_some(), "hostname"),
+                (self.via_peer, "via peer"),
+                (self.src_auto, "src auto"),
+                (self.probe.is_some(), "probe"),
+                (self.balance.is_some(), "balance"),
+                (self.mirror.is_some(), "mirror"),
+                (condition.is_some(), condition.unwrap_or_default()),
+            ];
+            if let Some((_, feature)) = features.iter().find(|(set, _)| *set) {
+                return Err(RouteParseError::Conflict("netns", feature));
+            }
+        }
+
+        // Several links make a multipath route without gateways.
+        if let Some(link) = self.link.as_ref().filter(|link| link.contains(',')) {
+            if link
+                .split(',')
+                .any(|link| link.is_empty() || is_link_pattern(link))
+            {
+                return Err(RouteParseError::InvalidLinks(link.clone()));
+            }
+
+            // The kernel only takes IPv6 nexthops with gateways.
+            let features = [
+                (matches!(self.version, RouteVersion::Ipv6), "route6"),
+                (self.rtr.is_some() || self.via_peer, "via"),
+                (self.host.is_some(), "hostname"),
+                (self.balance.is_some(), "balance"),
+                (self.probe.is_some(), "probe"),
+                (self.mirror.is_some(), "mirror"),
+                (self.netns.is_some(), "netns"),
+                (self.src.is_some() || self.src_auto, "src"),
+                (self.encap.is_some(), "encap"),
+                (!self.managed, "managed false"),
+                (condition.is_some(), condition.unwrap_or_default()),
+            ];
+            if let Some((_, feature)) = features.iter().find(|(set, _)| *set) {
+                return Err(RouteParseError::Conflict("several links", feature));
+            }
+        }
+
+        // Links matching later only get the route if it is as simple as it gets.
+        let pattern = self
+            .link
+            .as_ref()
+            .filter(|link| !link.contains(',') && is_link_pattern(link));
+        if let Some(link) = pattern {
+            let prefix = link.strip_suffix('+');
+            if prefix.is_some_and(|prefix| prefix.contains(['+', '*', '?'])) {
+                return Err(RouteParseError::InvalidLinkPattern(link.clone()));
+            }
+
+            let features = [
+                (self.host.is_some(), "hostname"),
+                (self.probe.is_some(), "probe"),
+                (self.balance.is_some(), "balance"),
+                (self.mirror.is_some(), "mirror"),
+                (self.netns.is_some(), "netns"),
+                (!self.managed, "managed false"),
+                (condition.is_some(), condition.unwrap_or_default()),
+            ];
+            if let Some((_, feature)) = features.iter().find(|(set, _)| *set) {
+                return Err(RouteParseError::Conflict("link pattern", feature));
+            }
+        }
+
+        // Nothing may change an unmanaged route once it is installed.
+        if !self.managed {
+            let features = [
+                (self.delete, "del"),
+                (self.host.is_some(), "hostname"),
+                (self.probe.is_some(), "probe"),
+                (self.balance.is_some(), "balance"),
+                (self.mirror.is_some(), "mirror"),
+                (condition.is_some(), condition.unwrap_or_default()),
+            ];
+            if let Some((_, feature)) = features.iter().find(|(set, _)| *set) {
+                return Err(RouteParseError::Conflict("managed false", feature));
+            }
+        }
+
+        if let Some(condition) = condition {
+            if self.probe.is_some() {
+                return Err(RouteParseError::Conflict(condition, "probe"));
+            }
+            if self.balance.is_some() {
+                return Err(RouteParseError::Conflict(condition, "balance"));
+            }
+        }
+
+        if let Some(weight) = self.weight {
+            if self.balance.is_none() {
+                return Err(RouteParseError::InvalidAttr("weight".to_string()));
+            }
+            if !(1..=256).contains(&weight) {
+                return Err(RouteParseError::InvalidWeight(weight));
+            }
+        }
+        let weight = self.weight.unwrap_or(1);
+
+        if matches!(self.probe, Some(Probe::Arp | Probe::Ndp))
+            && self.rtr.is_none()
+            && !self.via_peer
+        {
+            return Err(RouteParseError::ProbeNoRtr);
+        }
+
+        // Hostnames are resolved at apply time, the unspecified address stands in until then.
+        let (dst, prefix_len) = match (self.dst, &self.host, &self.version) {
+            (Some((dst, prefix_len)), _, _) => (Some(dst), Some(prefix_len)),
+            (None, Some(_), RouteVersion::Ipv4) => (Some(Ipv4Addr::UNSPECIFIED.into()), Some(32)),
+            (None, Some(_), RouteVersion::Ipv6) => (Some(Ipv6Addr::UNSPECIFIED.into()), Some(128)),
+            (None, _, _) => (None, None),
+        };
+
+        match self.version {
+            RouteVersion::Ipv4 => Ok(Route {
+                delete: self.delete,
+                dslite: false,
+                via_peer: self.via_peer,
+                src: match self.src {
+                    Some(IpAddr::V4(_)) | None => self.src,
+                    Some(_) => return Err(RouteParseError::SrcNotIpv4),
+                },
+                src_auto: self.src_auto,
+                probe: match self.probe {
+                    Some(Probe::Icmp(IpAddr::V4(_)) | Probe::Arp | Probe::Script(_)) | None => {
+                        self.probe
+                    }
+                    Some(_) => return Err(RouteParseError::ProbeNotIpv4),
+                },
+                balance: self.balance,
+                weight,
+                schedule: self.schedule,
+                when_exists: self.when_exists,
+                ttl: self.ttl,
+                host: self.host,
+                mirror: self.mirror,
+                encap: self.encap,
+                netns: self.netns,
+                drift: self.drift,
+                managed: self.managed,
+                line: 0,
+                template: None,
+                comment: None,
+                def: RouteDef::V4(rsdsl_netlinklib::route::Route4 {
+                    dst: if let Some(IpAddr::V4(dst)) = dst {
+                        dst
+                    } else {
+                        return Err(RouteParseError::DstNotIpv4);
+                    },
+                    prefix_len: prefix_len.ok_or(RouteParseError::NoDst)?,
+                    rtr: match self.rtr {
+                        Some(IpAddr::V4(rtr)) => Some(rtr),
+                        Some(_) => return Err(RouteParseError::RtrNotIpv4),
+                        None => None,
+                    },
+                    on_link: self.on_link,
+                    table: self.table,
+                    metric: self.metric,
+                    link: self.link.ok_or(RouteParseError::NoLink)?,
+                }),
+            }),
+            RouteVersion::Ipv6 => Ok(Route {
+                delete: self.delete,
+                dslite: false,
+                via_peer: self.via_peer,
+                src: match self.src {
+                    Some(IpAddr::V6(_)) | None => self.src,
+                    Some(_) => return Err(RouteParseError::SrcNotIpv6),
+                },
+                src_auto: self.src_auto,
+                probe: match self.probe {
+                    Some(Probe::Icmp(IpAddr::V6(_)) | Probe::Ndp | Probe::Script(_)) | None => {
+                        self.probe
+                    }
+                    Some(_) => return Err(RouteParseError::ProbeNotIpv6),
+                },
+                balance: self.balance,
+                weight,
+                schedule: self.schedule,
+                when_exists: self.when_exists,
+                ttl: self.ttl,
+                host: self.host,
+                mirror: self.mirror,
+                encap: self.encap,
+                netns: self.netns,
+                drift: self.drift,
+                managed: self.managed,
+                line: 0,
+                template: None,
+                comment: None,
+                def: RouteDef::V6(rsdsl_netlinklib::route::Route6 {
+                    dst: if let Some(IpAddr::V6(dst)) = dst {
+                        dst
+                    } else {
+                        return Err(RouteParseError::DstNotIpv6);
+                    },
+                    prefix_len: prefix_len.ok_or(RouteParseError::NoDst)?,
+                    rtr: match self.rtr {
+                        Some(IpAddr::V6(rtr)) => Some(rtr),
+                        Some(_) => return Err(RouteParseError::RtrNotIpv6),
+                        None => None,
+                    },
+                    on_link: self.on_link,
+                    table: self.table,
+                    metric: self.metric,
+                    link: self.link.ok_or(RouteParseError::NoLink)?,
+                }),
+            }),
+            RouteVersion::DsLite => {
+                // The route is re-added whenever the tunnel is recreated.
+                if self.ttl.is_some() {
+                    return Err(RouteParseError::Conflict("ttl", "dslite"));
+                }
+
+                // The DS-Lite default route is fully determined by the tunnel.
+                if dst.is_some() || self.host.is_some() {
+                    return Err(RouteParseError::InvalidAttr("to".to_string()));
+                }
+                if self.rtr.is_some() || self.via_peer {
+                    return Err(RouteParseError::InvalidAttr("via".to_string()));
+                }
+                if self.src.is_some() || self.src_auto {
+                    return Err(RouteParseError::InvalidAttr("src".to_string()));
+                }
+                if self.on_link {
+                    return Err(RouteParseError::InvalidAttr("onlink".to_string()));
+                }
+                if self.link.is_some() {
+                    return Err(RouteParseError::InvalidAttr("dev".to_string()));
+                }
+
+                Ok(Route {
+                    delete: self.delete,
+                    dslite: true,
+                    via_peer: false,
+                    src: None,
+                    src_auto: false,
+                    probe: match self.probe {
+                        Some(Probe::Icmp(IpAddr::V4(_)) | Probe::Script(_)) | None => self.probe,
+                        // The tunnel doesn't have a link-layer gateway.
+                        Some(Probe::Arp) => return Err(RouteParseError::ProbeNoRtr),
+                        Some(_) => return Err(RouteParseError::ProbeNotIpv4),
+                    },
+                    balance: self.balance,
+                    weight,
+                    schedule: self.schedule,
+                    when_exists: self.when_exists,
+                    ttl: self.ttl,
+                    host: None,
+                    mirror: self.mirror,
+                    encap: None,
+                    netns: None,
+                    drift: self.drift,
+                    managed: self.managed,
+                    line: 0,
+                    template: None,
+                    comment: None,
+                    def: RouteDef::V4(rsdsl_netlinklib::route::Route4 {
+                        dst: Ipv4Addr::UNSPECIFIED,
+                        prefix_len: 0,
+                        rtr: None,
+                        on_link: false,
+                        table: self.table,
+                        metric: self.metric,
+                        link: DSLITE_LINK.to_string(),
+                    }),
+                })
+            }
+        }
+    }
+}
+
+impl FromStr for Route {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = crate::lowercase_keywords(s, 2);
+        let mut words = s.split_whitespace();
+
+        let version_str = words.next().ok_or(RouteParseError::NoVersion)?;
+        let mut builder = match version_str {
+            "route4" => RouteBuilder::v4(),
+            "route6" => RouteBuilder::v6(),
+            "dslite" => RouteBuilder::dslite(),
+            _ => return Err(RouteParseError::InvalidVersion(version_str.to_string())),
+        };
+
+        let cmd = words.next().ok_or(RouteParseError::NoCmd)?;
+        match cmd {
+            "add" => {}
+            "del" => builder = builder.delete(),
+            _ => return Err(RouteParseError::InvalidCmd(cmd.to_string())),
+        }
+
+        // iproute2 knows the metric as the preference, too.
+        // Its "pref" is the IPv6 router preference though.
+        let attrs = crate::attr_pairs(words, |attr| match attr {
+            "preference" | "priority" => "metric",
+            attr => attr,
+        })?;
+
+        // The tunnel parameters may come in any order, they only make sense together.
+        let mut encap_type = None;
+        let mut encap = Encap::default();
+        let mut encap_attr = None;
+
+        for (attr, value) in attrs {
+            if attr.starts_with("encap-") {
+                encap_attr = Some(attr);
+            }
+
+            builder = match attr {
+                "to" if !value.contains('/') && value.parse::<IpAddr>().is_err() => {
+                    if !is_hostname(value) {
+                        return Err(RouteParseError::InvalidHost(value.to_string()));
+                    }
+
+                    builder.dst_host(value)
+                }
+                "to" => {
+                    let mut prefix = value.split('/');
+
+                    let addr = prefix
+                        .next()
+                        .ok_or(RouteParseError::InvalidCidr(value.to_string()))?;
+                    let cidr = prefix
+                        .next()
+                        .ok_or(RouteParseError::InvalidCidr(value.to_string()))?;
+
+                    if prefix.next().is_some() {
+                        return Err(RouteParseError::InvalidCidr(value.to_string()));
+                    }
+
+                    builder.dst(addr.parse::<IpAddr>()?, cidr.parse()?)
+                }
+                "via" if value == "peer" => builder.via_peer(),
+                "via" if value.contains('%') => {
+                    let (rtr, zone) = value.split_once('%').unwrap_or_default();
+                    let rtr = rtr.parse::<Ipv6Addr>()?;
+                    if !rtr.is_unicast_link_local() || zone.is_empty() {
+                        return Err(RouteParseError::InvalidZone(value.to_string()));
+                    }
+
+                    builder.via_scoped(rtr, zone)
+                }
+                "via" => builder.via(value.parse::<IpAddr>()?),
+                "src" if value == "auto" => builder.src_auto(),
+                "src" => builder.src(value.parse::<IpAddr>()?),
+                "onlink" => builder.on_link(value.parse()?),
+                "table" => builder.table(crate::parse_table(value)?),
+                "metric" => builder.metric(value.parse()?),
+                "dev" => builder.dev(value),
+                "probe" => builder.probe(value.parse::<Probe>()?),
+                "balance" => builder.balance(value),
+                "schedule" => builder.schedule(value.parse()?),
+                "when-exists" => builder.when_exists(value),
+                "ttl" => builder.ttl(value.parse()?),
+                "mirror" => builder.mirror(crate::parse_table(value)?),
+                "weight" => builder.weight(value.parse()?),
+                "netns" if !crate::netns::is_valid_name(value) => {
+                    return Err(RouteParseError::InvalidNetns(value.to_string()))
+                }
+                "netns" => builder.netns(value),
+                "drift" => builder.drift(
+                    value
+                        .parse()
+                        .map_err(|_| RouteParseError::InvalidDrift(value.to_string()))?,
+                ),
+                "managed" => builder.managed(value.parse()?),
+                "encap" => {
+                    let ty = value
+                        .parse::<EncapType>()
+                        .map_err(|_| RouteParseError::InvalidEncap(value.to_string()))?;
+                    encap_type = Some(ty);
+                    builder
+                }
+                "encap-id" => {
+                    encap.id = Some(value.parse()?);
+                    builder
+                }
+                "encap-src" => {
+                    encap.src = Some(value.parse()?);
+                    builder
+                }
+                "encap-dst" => {
+                    encap.dst = Some(value.parse()?);
+                    builder
+                }
+                "encap-ttl" => {
+                    encap.ttl = Some(value.parse()?);
+                    builder
+                }
+                _ => return Err(RouteParseError::InvalidAttr(attr.to_string())),
+            };
+        }
+
+        match (encap_type, encap_attr) {
+            (Some(ty), _) => builder = builder.encap(Encap { ty, ..encap }),
+            (None, Some(attr)) => return Err(RouteParseError::InvalidAttr(attr.to_string())),
+            (None, None) => {}
+        }
+
+        builder.build()
+    }
+}
+
+/// A parsed route configuration file.
+#[derive(Debug, Default)]
+pub struct Routes {
+    pub routes: Vec<Route>,
+    pub prefix_lists: Vec<PrefixList>,
+    pub bogons: Vec<Bogons>,
+    pub sysctls: Vec<Sysctl>,
+    pub vrfs: Vec<Vrf>,
+    pub mroutes: Vec<Mroute>,
+    pub isolates: Vec<Isolate>,
+    pub bypasses: Vec<Bypass>,
+    pub link_metrics: Vec<LinkMetrics>,
+    pub classless: Vec<Classless>,
+}
+
+impl FromStr for Routes {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lines = crate::netns::lines(s)
+            .map_err(|(line, e)| RouteParseError::Line(line, Box::new(e.into())))?;
+
+        // Nearly all lines of large files are routes.
+        let mut routes = Vec::with_capacity(lines.len());
+        let mut prefix_lists = Vec::new();
+        let mut bogons = Vec::new();
+        let mut sysctls = Vec::new();
+        let mut vrfs: Vec<Vrf> = Vec::new();
+        let mut mroutes = Vec::new();
+        let mut isolates = Vec::new();
+        let mut bypasses = Vec::new();
+        let mut classless = Vec::new();
+
+        // Default metrics apply to the routes above them, too.
+        let mut link_metrics: Vec<LinkMetrics> = Vec::new();
+        for line in lines.iter().filter(|line| line.netns.is_none()) {
+            let (i, l) = (line.number - 1, line.text);
+            if line.version().as_deref() != Some("metrics") {
+                continue;
+            }
+
+            let at_line = |e| RouteParseError::Line(i + 1, Box::new(e));
+            let metrics: LinkMetrics = l.parse().map_err(at_line)?;
+            let duplicate = metrics
+                .metrics
+                .iter()
+                .find(|(link, _)| link_metrics.iter().any(|m| m.get(link).is_some()));
+            if let Some((link, _)) = duplicate {
+                return Err(at_line(RouteParseError::DuplicateAttr(link.clone())));
+            }
+
+            link_metrics.push(LinkMetrics {
+                line: i + 1,
+                ..metrics
+            });
+        }
+
+        for block_line in &lines {
+            let line = block_line.number;
+            let at_line = |e| RouteParseError::Line(line, Box::new(e));
+
+            // The other entries only exist in rtd's own namespace.
+            let version = block_line.version();
+            if block_line.netns.is_some()
+                && !matches!(version.as_deref(), Some("route4" | "route6"))
+            {
+                let version = version.unwrap_or_default().into_owned();
+                return Err(at_line(
+                    crate::netns::BlockError::Unsupported(version).into(),
+                ));
+            }
+
+            let scoped = block_line.scoped();
+            let l = &*scoped;
+            match version.as_deref() {
+                Some("rtbh") => prefix_lists.push(PrefixList {
+                    line,
+                    ..l.parse().map_err(at_line)?
+                }),
+                Some("bogons") => bogons.push(Bogons {
+                    line,
+                    ..l.parse().map_err(at_line)?
+                }),
+                Some("sysctl") => sysctls.push(Sysctl {
+                    line,
+                    ..l.parse().map_err(at_line)?
+                }),
+                Some("bypass") => bypasses.push(Bypass {
+                    line,
+                    ..l.parse().map_err(at_line)?
+                }),
+                Some("classless") => classless.push(Classless {
+                    line,
+                    ..l.parse().map_err(at_line)?
+                }),
+                Some("isolate") => isolates.push(Isolate {
+                    line,
+                    ..l.parse().map_err(at_line)?
+                }),
+                Some("mroute") => mroutes.push(Mroute {
+                    line,
+                    ..l.parse().map_err(at_line)?
+                }),
+                Some("vrf") => vrfs.push(Vrf {
+                    line,
+                    ..l.parse().map_err(at_line)?
+                }),
+                Some("metrics") => {}
+                _ => {
+                    // Lines with placeholders are resolved at apply time,
+                    // check their syntax using stand-in values for now.
+                    let template = vars::has_vars(l).then(|| l.to_string());
+                    let parsed = match template {
+                        Some(_) => vars::expand(l, &vars::Vars::placeholders())
+                            .map_err(RouteParseError::from)
+                            .and_then(|l| l.parse::<Route>()),
+                        None => l.parse::<Route>(),
+                    };
+
+                    let mut route = parsed.map_err(at_line)?;
+                    // Resolved values are only kept up to date in rtd's own namespace.
+                    if template.is_some() && route.netns.is_some() {
+                        return Err(at_line(RouteParseError::Conflict("netns", "placeholders")));
+                    }
+
+                    // Routes without a table go to the table of the VRF above them,
+                    // those without a metric get the default one of their link.
+                    if block_line.netns.is_none() {
+                        let vrf = vrfs.last().filter(|vrf| !vrf.delete);
+                        let metric =
+                            link(l).and_then(|link| link_metrics.iter().find_map(|m| m.get(link)));
+                        route.def.inherit(vrf.map(|vrf| vrf.table), metric);
+                    }
+
+                    routes.push(Route {
+                        line,
+                        template,
+                        comment: block_line.comment.map(String::from),
+                        ..route
+                    });
+                }
+            }
+        }
+
+        // The members of a balance group make up a single kernel route.
+        for (i, route) in routes.iter().enumerate() {
+            let Some(balance) = &route.balance else {
+                continue;
+            };
+
+            let first = routes[..i]
+                .iter()
+                .find(|first| first.balance.as_ref() == Some(balance));
+            if first.is_some_and(|first| !first.def.same_dst(&route.def)) {
+                return Err(RouteParseError::Line(
+                    route.line,
+                    Box::new(RouteParseError::BalanceMismatch(balance.clone())),
+                ));
+            }
+        }
+
+        Ok(Self {
+            routes: order_by_gateway(routes)?,
+            prefix_lists,
+            bogons,
+            sysctls,
+            vrfs,
+            mroutes,
+            isolates,
+            bypasses,
+            link_metrics,
+            classless,
+        })
+    }
+}
+
+/// Reports whether a link name is a pattern, i.e. contains wildcards.
+fn is_link_pattern(link: &str) -> bool {
+    link.ends_with('+') || link.contains(['*', '?'])
+}
+
+/// Matches a link name against a pattern. A trailing `+` matches
+/// any suffix like in iptables, `*` any number of characters
+/// and `?` any single character. Other names only match themselves.
+fn link_matches(pattern: &str, link: &str) -> bool {
+    if let Some(prefix) = pattern.strip_suffix('+') {
+        return link.starts_with(prefix);
+    }
+
+    let (pattern, link) = (pattern.as_bytes(), link.as_bytes());
+    // Where to resume after the last `*`, which may have to match more.
+    let mut star = None;
+    let (mut p, mut l) = (0, 0);
+    while l < link.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, l));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == link[l] => {
+                p += 1;
+                l += 1;
+            }
+            _ => match star {
+                Some((star_p, star_l)) => {
+                    star = Some((star_p, star_l + 1));
+                    p = star_p + 1;
+                    l = star_l + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// Reports whether a string is a syntactically valid DNS name.
+fn is_hostname(s: &str) -> bool {
+    let s = s.strip_suffix('.').unwrap_or(s);
+    !s.is_empty()
+        && s.len() <= 253
+        && s.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+/// Orders routes so that the route covering the gateway of another one
+/// is installed first, keeping the configured order where possible.
+/// Like the kernel, only routes without a gateway through the same link
+/// make a gateway reachable. Entries for the same destination never overtake
+/// each other.
+fn order_by_gateway(routes: Vec<Route>) -> Result<Vec<Route>, RouteParseError> {
+    // Placeholders and hostnames only have stand-in values at this point.
+    let known = |route: &Route| route.template.is_none() && route.host.is_none();
+
+    // Generated files have tens of thousands of routes, look up
+    // the candidates instead of comparing every pair of routes.
+    let mut last_same_dst = HashMap::new();
+    let mut on_link: HashMap<(&str, u32), Vec<usize>> = HashMap::new();
+    for (i, other) in routes.iter().enumerate() {
+        if !other.delete && known(other) && other.def.rtr().is_none() && !other.via_peer {
+            on_link
+                .entry((other.def.link(), other.def.table()))
+                .or_default()
+                .push(i);
+        }
+    }
+
+    let deps: Vec<Vec<usize>> = routes
+        .iter()
+        .enumerate()
+        .map(|(i, route)| {
+            // Each entry waits for the previous one, which waits for its own.
+            let mut deps: Vec<usize> = last_same_dst
+                .insert(route.def.dst_key(), i)
+                .into_iter()
+                .collect();
+
+            let rtr = route
+                .def
+                .rtr()
+                .filter(|_| known(route) && !route.def.on_link());
+            let candidates = on_link
+                .get(&(route.def.link(), route.def.table()))
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let gateway = rtr.and_then(|rtr| {
+                candidates
+                    .iter()
+                    .map(|&j| (j, &routes[j]))
+                    .filter(|(j, other)| {
+                        *j != i
+                            && rtnl::prefix_contains(other.def.dst(), other.def.prefix_len(), rtr)
+                    })
+                    // The most specific route is the one the kernel would use.
+                    .max_by_key(|(j, other)| (other.def.prefix_len(), usize::MAX - j))
+                    .map(|(j, _)| j)
+            });
+            deps.extend(gateway);
+
+            deps
+        })
+        .collect();
+
+    // The earliest route whose dependencies are in place goes next.
+    let mut pending: Vec<usize> = deps.iter().map(Vec::len).collect();
+    let mut dependents = vec![Vec::new(); routes.len()];
+    for (i, deps) in deps.iter().enumerate() {
+        for &dep in deps {
+            dependents[dep].push(i);
+        }
+    }
+
+    let mut ready: BinaryHeap<Reverse<usize>> = (0..routes.len())
+        .filter(|&i| pending[i] == 0)
+        .map(Reverse)
+        .collect();
+    let mut order = Vec::with_capacity(routes.len());
+    while let Some(Reverse(next)) = ready.pop() {
+        order.push(next);
+        for &dependent in &dependents[next] {
+            pending[dependent] -= 1;
+            if pending[dependent] == 0 {
+                ready.push(Reverse(dependent));
+            }
+        }
+    }
+
+    if order.len() < routes.len() {
+        let lines = (0..routes.len())
+            .filter(|&i| pending[i] > 0)
+            .map(|i| routes[i].line)
+            .collect();
+        return Err(RouteParseError::GatewayCycle(lines));
+    }
+
+    // Move the routes in place rather than into a second list.
+    let mut position = vec![0; routes.len()];
+    for (pos, &i) in order.iter().enumerate() {
+        position[i] = pos;
+    }
+    let mut routes = routes;
+    for i in 0..routes.len() {
+        while position[i] != i {
+            let j = position[i];
+            routes.swap(i, j);
+            position.swap(i, j);
+        }
+    }
+
+    Ok(routes)
+}
+
+/// Returns the interface of a route configuration line if it is known
+/// without parsing it (i.e. not a placeholder).
+fn link(line: &str) -> Option<&str> {
+    if line
+        .split_whitespace()
+        .next()
+        .is_some_and(|version| version.eq_ignore_ascii_case("dslite"))
+    {
+        return Some(DSLITE_LINK);
+    }
+
+    let attr = |name: &str| {
+        let mut words = line.split_whitespace().skip(2);
+        while let (Some(attr), Some(value)) = (words.next(), words.next()) {
+            if attr.eq_ignore_ascii_case(name) {
+                return Some(value);
+            }
+        }
+
+        None
+    };
+
+    // The zone of a link-local gateway names the interface, too.
+    attr("dev").or_else(|| Some(attr("via")?.split_once('%')?.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a single configuration line, checking that it parses
+    /// the same when written the way it is shown, and returns that.
+    fn round_trip(line: &str) -> String {
+        let routes: Routes = line.parse().unwrap_or_else(|e| panic!("{}: {}", line, e));
+        let shown = format!("{:#}", routes.routes[0]);
+
+        let (version, attrs) = shown.split_once(' ').unwrap();
+        let again: Routes = format!("{} add to {}", version, attrs)
+            .parse()
+            .unwrap_or_else(|e| panic!("{}: {}", shown, e));
+        assert_eq!(format!("{:#}", again.routes[0]), shown);
+
+        shown
+    }
+
+    fn parse_err(line: &str) -> RouteParseError {
+        match line.parse::<Route>() {
+            Ok(route) => panic!("{}: parsed as {}", line, route),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn dslite() {
+        let route: Route = "dslite add metric 10".parse().unwrap();
+        assert!(route.dslite);
+        assert_eq!(
+            round_trip("dslite add metric 10"),
+            "route4 0.0.0.0/0 metric 10 dev dslite"
+        );
+
+        // The tunnel determines everything but the table and metric.
+        assert!(matches!(
+            parse_err("dslite add to 10.0.0.0/8"),
+            RouteParseError::InvalidAttr(attr) if attr == "to"
+        ));
+        assert!(matches!(
+            parse_err("dslite add dev eth0"),
+            RouteParseError::InvalidAttr(attr) if attr == "dev"
+        ));
+    }
+
+    #[test]
+    fn via_peer() {
+        let route: Route = "route4 add to 0.0.0.0/0 via peer dev ppp0".parse().unwrap();
+        assert!(route.via_peer);
+        assert_eq!(
+            round_trip("route4 add to 0.0.0.0/0 via peer dev ppp0"),
+            "route4 0.0.0.0/0 via peer dev ppp0"
+        );
+        assert_eq!(
+            round_trip("route6 add to ::/0 via peer dev ppp0"),
+            "route6 ::/0 via peer dev ppp0"
+        );
+    }
+
+    #[test]
+    fn icmp_probes() {
+        assert_eq!(
+            round_trip("route4 add to 0.0.0.0/0 via 192.0.2.1 dev eth0 metric 10 probe 8.8.8.8"),
+            "route4 0.0.0.0/0 via 192.0.2.1 metric 10 dev eth0 probe 8.8.8.8"
+        );
+        assert!(matches!(
+            parse_err("route4 add to 0.0.0.0/0 via 192.0.2.1 dev eth0 probe 2001:db8::1"),
+            RouteParseError::ProbeNotIpv4
+        ));
+    }
+
+    #[test]
+    fn balance_groups() {
+        assert_eq!(
+            round_trip("route4 add to 0.0.0.0/0 via 192.0.2.1 dev eth0 balance wan weight 3"),
+            "route4 0.0.0.0/0 via 192.0.2.1 dev eth0 balance wan weight 3"
+        );
+
+        assert!(matches!(
+            parse_err("route4 add to 0.0.0.0/0 via 192.0.2.1 dev eth0 weight 3"),
+            RouteParseError::InvalidAttr(attr) if attr == "weight"
+        ));
+        assert!(matches!(
+            parse_err("route4 add to 0.0.0.0/0 via 192.0.2.1 dev eth0 balance wan weight 0"),
+            RouteParseError::InvalidWeight(0)
+        ));
+        // The members make up a single route.
+        assert!(matches!(
+            "route4 add to 0.0.0.0/0 via 192.0.2.1 dev eth0 balance wan\n\
+             route4 add to 10.0.0.0/8 via 198.51.100.1 dev eth1 balance wan\n"
+                .parse::<Routes>(),
+            Err(RouteParseError::Line(2, e)) if matches!(*e, RouteParseError::BalanceMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn neighbor_probes() {
+        assert_eq!(
+            round_trip("route4 add to 0.0.0.0/0 via 192.0.2.1 dev eth0 probe arp"),
+            "route4 0.0.0.0/0 via 192.0.2.1 dev eth0 probe arp"
+        );
+        assert_eq!(
+            round_trip("route6 add to ::/0 via 2001:db8::1 dev eth0 probe ndp"),
+            "route6 ::/0 via 2001:db8::1 dev eth0 probe ndp"
+        );
+
+        // There is no gateway to watch.
+        assert!(matches!(
+            parse_err("route4 add to 0.0.0.0/0 dev eth0 probe arp"),
+            RouteParseError::ProbeNoRtr
+        ));
+        assert!(matches!(
+            parse_err("route4 add to 0.0.0.0/0 via 192.0.2.1 dev eth0 probe ndp"),
+            RouteParseError::ProbeNotIpv4
+        ));
+    }
+
+    #[test]
+    fn script_probes() {
+        let line = "route4 add to 0.0.0.0/0 via 192.0.2.1 dev eth0 probe /usr/libexec/check-wan";
+        let route: Route = line.parse().unwrap();
+        assert_eq!(
+            route.probe,
+            Some(Probe::Script(PathBuf::from("/usr/libexec/check-wan")))
+        );
+        assert_eq!(
+            round_trip(line),
+            "route4 0.0.0.0/0 via 192.0.2.1 dev eth0 probe /usr/libexec/check-wan"
+        );
+    }
+
+    #[test]
+    fn vrf_tables() {
+        let routes: Routes = "route4 add to 10.0.0.0/16 dev eth0\n\
+            vrf add name mgmt table 10\n\
+            route4 add to 10.1.0.0/16 dev eth0\n\
+            route4 add to 10.2.0.0/16 dev eth0 table 20\n\
+            vrf del name mgmt table 10\n\
+            route4 add to 10.3.0.0/16 dev eth0\n"
+            .parse()
+            .unwrap();
+
+        // Routes without a table go to the table of the VRF above them.
+        let shown: Vec<String> = routes.routes.iter().map(Route::to_string).collect();
+        assert_eq!(
+            shown,
+            [
+                "route4 10.0.0.0/16 dev eth0",
+                "route4 10.1.0.0/16 table 10 dev eth0",
+                "route4 10.2.0.0/16 table 20 dev eth0",
+                "route4 10.3.0.0/16 dev eth0",
+            ]
+        );
+    }
+
+    #[test]
+    fn schedules() {
+        assert_eq!(
+            round_trip("route4 add to 10.1.0.0/16 dev wg0 schedule 22:00-06:00,12:00-13:00"),
+            "route4 10.1.0.0/16 dev wg0 schedule 22:00-06:00,12:00-13:00"
+        );
+        assert!(matches!(
+            parse_err("route4 add to 10.1.0.0/16 dev wg0 schedule 22:00-6"),
+            RouteParseError::InvalidSchedule(_)
+        ));
+    }
+
+    #[test]
+    fn trigger_files() {
+        let route: Route = "route4 add to 10.1.0.0/16 dev wg0 when-exists /run/vpn.up"
+            .parse()
+            .unwrap();
+        assert!(route.is_conditional());
+        assert_eq!(
+            round_trip("route4 add to 10.1.0.0/16 dev wg0 when-exists /run/vpn.up"),
+            "route4 10.1.0.0/16 dev wg0 when-exists /run/vpn.up"
+        );
+    }
+
+    #[test]
+    fn ttl() {
+        assert_eq!(
+            round_trip("route4 add to 10.1.0.0/16 via 192.0.2.1 dev eth0 ttl 3600"),
+            "route4 10.1.0.0/16 via 192.0.2.1 dev eth0 ttl 3600"
+        );
+        // The route is re-added whenever the tunnel is recreated.
+        assert!(matches!(
+            parse_err("dslite add ttl 3600"),
+            RouteParseError::Conflict("ttl", "dslite")
+        ));
+    }
+
+    #[test]
+    fn hostnames() {
+        let route: Route = "route4 add to vpn.example.com dev wg0".parse().unwrap();
+        assert_eq!(route.host.as_deref(), Some("vpn.example.com"));
+        assert_eq!(
+            round_trip("route4 add to vpn.example.com dev wg0"),
+            "route4 vpn.example.com dev wg0"
+        );
+
+        assert!(matches!(
+            parse_err("route4 add to bad_host! dev wg0"),
+            RouteParseError::InvalidHost(host) if host == "bad_host!"
+        ));
+        // The routes of a hostname come and go with its addresses.
+        assert!(matches!(
+            parse_err("route4 add to vpn.example.com dev wg0 ttl 60"),
+            RouteParseError::Conflict("hostname", "ttl")
+        ));
+    }
+
+    #[test]
+    fn mirrors() {
+        let route: Route = "route4 add to 10.1.0.0/16 via 192.0.2.1 dev eth0 mirror 200"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            route.mirror_def().map(|def| def.to_string()),
+            Some("route4 10.1.0.0/16 via 192.0.2.1 table 200 dev eth0".to_string())
+        );
+        assert_eq!(
+            round_trip("route4 add to 10.1.0.0/16 via 192.0.2.1 dev eth0 mirror 200"),
+            "route4 10.1.0.0/16 via 192.0.2.1 dev eth0 mirror 200"
+        );
+
+        // A copy in the same table would be the route itself.
+        assert!(matches!(
+            parse_err("route4 add to 10.1.0.0/16 dev eth0 table 200 mirror 200"),
+            RouteParseError::InvalidAttr(attr) if attr == "mirror"
+        ));
+    }
+
+    #[test]
+    fn missing_destination() {
+        let e = parse_err("route4 add via 192.0.2.1 dev eth0");
+        assert!(matches!(e, RouteParseError::NoDst));
+        assert_eq!(
+            e.to_string(),
+            "missing destination network (\"to\" attribute)"
+        );
+
+        // Only a destination of the other family is reported as such.
+        assert!(matches!(
+            parse_err("route4 add to 2001:db8::/32 dev eth0"),
+            RouteParseError::DstNotIpv4
+        ));
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive() {
+        assert_eq!(
+            round_trip("ROUTE4 Add TO 10.1.0.0/16 Via 192.0.2.1 DEV Eth0 Metric 5"),
+            "route4 10.1.0.0/16 via 192.0.2.1 metric 5 dev Eth0"
+        );
+        assert_eq!(
+            round_trip("Route6 DEL to 2001:db8::/32 dev eth0"),
+            round_trip("route6 del to 2001:db8::/32 dev eth0")
+        );
+    }
+
+    #[test]
+    fn metric_aliases() {
+        for attr in ["metric", "preference", "priority"] {
+            assert_eq!(
+                round_trip(&format!("route4 add to 10.1.0.0/16 dev eth0 {} 5", attr)),
+                "route4 10.1.0.0/16 metric 5 dev eth0"
+            );
+        }
+
+        // The aliases name the same attribute.
+        assert!(matches!(
+            parse_err("route4 add to 10.1.0.0/16 dev eth0 metric 5 priority 6"),
+            RouteParseError::DuplicateAttr(attr) if attr == "metric"
+        ));
+        // Unlike in iproute2, where it is the router preference.
+        assert!(matches!(
+            parse_err("route6 add to 2001:db8::/32 dev eth0 pref medium"),
+            RouteParseError::InvalidAttr(attr) if attr == "pref"
+        ));
+    }
+
+    #[test]
+    fn hex_tables() {
+        for table in ["0x10", "0X10", "16"] {
+            assert_eq!(
+                round_trip(&format!(
+                    "route4 add to 10.1.0.0/16 dev eth0 table {}",
+                    table
+                )),
+                "route4 10.1.0.0/16 table 16 dev eth0"
+            );
+        }
+    }
+
+    #[test]
+    fn table_names() {
+        for (name, table) in [("main", 254), ("local", 255), ("default", 253)] {
+            assert_eq!(
+                round_trip(&format!(
+                    "route4 add to 10.1.0.0/16 dev eth0 table {}",
+                    name
+                )),
+                format!("route4 10.1.0.0/16 table {} dev eth0", table)
+            );
+        }
+    }
+
+    #[test]
+    fn link_metrics() {
+        let routes: Routes = "route4 add to 10.1.0.0/16 dev eth0\n\
+            route4 add to 10.2.0.0/16 dev eth0 metric 3\n\
+            route4 add to 10.3.0.0/16 dev eth1\n\
+            metrics set eth0=50\n"
+            .parse()
+            .unwrap();
+
+        // The metric of the link applies wherever the line is.
+        let shown: Vec<String> = routes.routes.iter().map(Route::to_string).collect();
+        assert_eq!(
+            shown,
+            [
+                "route4 10.1.0.0/16 metric 50 dev eth0",
+                "route4 10.2.0.0/16 metric 3 dev eth0",
+                "route4 10.3.0.0/16 dev eth1",
+            ]
+        );
+    }
+
+    #[test]
+    fn link_local_zones() {
+        assert_eq!(
+            round_trip("route6 add to ::/0 via fe80::1%eth0"),
+            "route6 ::/0 via fe80::1 dev eth0"
+        );
+        assert_eq!(
+            round_trip("route6 add to ::/0 via fe80::1%eth0 dev eth0"),
+            "route6 ::/0 via fe80::1 dev eth0"
+        );
+
+        assert!(matches!(
+            parse_err("route6 add to ::/0 via fe80::1%eth0 dev eth1"),
+            RouteParseError::ZoneMismatch(zone, link) if zone == "eth0" && link == "eth1"
+        ));
+        // Only link-local addresses are scoped to a link.
+        assert!(matches!(
+            parse_err("route6 add to ::/0 via 2001:db8::1%eth0"),
+            RouteParseError::InvalidZone(_)
+        ));
+    }
+
+    #[test]
+    fn link_local_gateways_need_dev() {
+        assert!(matches!(
+            parse_err("route6 add to ::/0 via fe80::1"),
+            RouteParseError::LinkLocalNoLink(rtr) if rtr == "fe80::1".parse::<Ipv6Addr>().unwrap()
+        ));
+        assert_eq!(
+            round_trip("route6 add to ::/0 via fe80::1 dev ppp0"),
+            "route6 ::/0 via fe80::1 dev ppp0"
+        );
+    }
+
+    #[test]
+    fn netns() {
+        assert_eq!(
+            round_trip("route4 add to 10.1.0.0/16 via 192.0.2.1 dev eth0 netns box"),
+            "route4 10.1.0.0/16 via 192.0.2.1 dev eth0 netns box"
+        );
+        // The watchers keeping these up to date only work in rtd's own namespace.
+        assert!(matches!(
+            parse_err("route4 add to vpn.example.com dev wg0 netns box"),
+            RouteParseError::Conflict("netns", "hostname")
+        ));
+    }
+
+    #[test]
+    fn netns_blocks() {
+        use crate::netns::BlockError;
+
+        let routes: Routes = "route4 add to 10.0.0.0/16 dev eth0\n\
+            netns box {\n\
+            \x20   route4 add to 10.1.0.0/16 dev eth0\n\
+            }\n"
+        .parse()
+        .unwrap();
+
+        let shown: Vec<String> = routes.routes.iter().map(Route::to_string).collect();
+        assert_eq!(
+            shown,
+            [
+                "route4 10.0.0.0/16 dev eth0",
+                "route4 10.1.0.0/16 dev eth0 netns box",
+            ]
+        );
+        assert_eq!(routes.routes[1].line, 3);
+
+        let block_err = |s: &str| match s.parse::<Routes>() {
+            Err(RouteParseError::Line(line, e)) => match *e {
+                RouteParseError::Netns(e) => (line, e),
+                e => panic!("{}: {}", s, e),
+            },
+            res => panic!("{}: {:?}", s, res.map(|routes| routes.routes)),
+        };
+        assert!(matches!(
+            block_err("netns box {\nroute4 add to 10.1.0.0/16 dev eth0\n"),
+            (1, BlockError::Unclosed(name)) if name == "box"
+        ));
+        assert!(matches!(block_err("}\n"), (1, BlockError::NotInBlock)));
+        assert!(matches!(
+            block_err("netns box {\nnetns other {\n"),
+            (2, BlockError::Nested)
+        ));
+        // The other entries only exist in rtd's own namespace.
+        assert!(matches!(
+            block_err("netns box {\nbogons add\n}\n"),
+            (2, BlockError::Unsupported(version)) if version == "bogons"
+        ));
+    }
+
+    #[test]
+    fn serde() {
+        let routes: Routes = "route4 add to 10.1.0.0/16 via 192.0.2.1 dev eth0 probe arp\n\
+            route4 add to 10.2.0.0/16 dev wg0 schedule 22:00-06:00 table 100\n\
+            route6 add to ::/0 via fe80::1%ppp0 balance wan weight 2\n"
+            .parse()
+            .unwrap();
+
+        for route in &routes.routes {
+            let json = serde_json::to_string(route).unwrap();
+            let stored: Route = serde_json::from_str(&json).unwrap();
+            assert_eq!(stored, *route);
+            assert_eq!(stored.line, route.line);
+        }
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            round_trip("route4 add to 10.1.0.0/16 via 192.0.2.1 dev eth0 # uplink #2"),
+            "route4 10.1.0.0/16 via 192.0.2.1 dev eth0 # uplink #2"
+        );
+        // Only a `#` starting a word starts a comment.
+        assert_eq!(
+            round_trip("route4 add to 10.1.0.0/16 dev eth#0"),
+            "route4 10.1.0.0/16 dev eth#0"
+        );
+        // Lines holding nothing but a comment are skipped.
+        let routes: Routes = "# uplink\nroute4 add to 10.1.0.0/16 dev eth0\n"
+            .parse()
+            .unwrap();
+        assert_eq!(routes.routes.len(), 1);
+        assert_eq!(routes.routes[0].line, 2);
+    }
+
+    #[test]
+    fn drift_policies() {
+        for drift in ["ignore", "log", "repair"] {
+            assert_eq!(
+                round_trip(&format!(
+                    "route4 add to 10.1.0.0/16 dev eth0 drift {}",
+                    drift
+                )),
+                format!("route4 10.1.0.0/16 dev eth0 drift {}", drift)
+            );
+        }
+        assert!(matches!(
+            parse_err("route4 add to 10.1.0.0/16 dev eth0 drift fix"),
+            RouteParseError::InvalidDrift(drift) if drift == "fix"
+        ));
+    }
+
+    #[test]
+    fn unmanaged() {
+        let route: Route = "route4 add to 10.1.0.0/16 dev eth0".parse().unwrap();
+        assert!(route.managed);
+        assert_eq!(
+            round_trip("route4 add to 10.1.0.0/16 dev eth0 managed false"),
+            "route4 10.1.0.0/16 dev eth0 managed false"
+        );
+
+        // Nothing may change an unmanaged route once it is installed.
+        assert!(matches!(
+            parse_err("route4 del to 10.1.0.0/16 dev eth0 managed false"),
+            RouteParseError::Conflict("managed false", "del")
+        ));
+        assert!(matches!(
+            parse_err("route4 add to 10.1.0.0/16 via 192.0.2.1 dev eth0 managed false probe arp"),
+            RouteParseError::Conflict("managed false", "probe")
+        ));
+    }
+
+    #[test]
+    fn encap() {
+        assert_eq!(
+            round_trip(
+                "route4 add to 10.1.0.0/16 dev tun0 encap ip encap-dst 192.0.2.9 encap-ttl 64"
+            ),
+            "route4 10.1.0.0/16 dev tun0 encap ip encap-dst 192.0.2.9 encap-ttl 64"
+        );
+        assert_eq!(
+            round_trip(
+                "route6 add to 2001:db8:1::/48 dev tun0 encap-id 7 encap ip6 encap-dst 2001:db8::9"
+            ),
+            "route6 2001:db8:1::/48 dev tun0 encap ip6 encap-id 7 encap-dst 2001:db8::9"
+        );
+
+        // The tunnel parameters only make sense along with the type.
+        assert!(matches!(
+            parse_err("route4 add to 10.1.0.0/16 dev tun0 encap-id 7"),
+            RouteParseError::InvalidAttr(attr) if attr == "encap-id"
+        ));
+        assert!(matches!(
+            parse_err("route4 add to 10.1.0.0/16 dev tun0 encap gre"),
+            RouteParseError::InvalidEncap(ty) if ty == "gre"
+        ));
+        assert!(matches!(
+            parse_err("route4 add to 10.1.0.0/16 dev tun0 encap ip encap-dst 2001:db8::9"),
+            RouteParseError::EncapMismatch(_)
+        ));
+    }
+
+    #[test]
+    fn src_auto() {
+        let route: Route = "route4 add to 10.1.0.0/16 dev eth0 src auto"
+            .parse()
+            .unwrap();
+        assert!(route.src_auto);
+        assert_eq!(
+            round_trip("route4 add to 10.1.0.0/16 dev eth0 src auto"),
+            "route4 10.1.0.0/16 dev eth0 src auto"
+        );
+        assert_eq!(
+            round_trip("route4 add to 10.1.0.0/16 dev eth0 src 192.0.2.5"),
+            "route4 10.1.0.0/16 dev eth0 src 192.0.2.5"
+        );
+
+        // Copies are installed without the source.
+        assert!(matches!(
+            parse_err("route4 add to 10.1.0.0/16 dev eth0 src auto mirror 200"),
+            RouteParseError::Conflict("src", "mirror")
+        ));
+    }
+
+    #[test]
+    fn link_patterns() {
+        for link in ["ppp+", "wg*", "eth?"] {
+            assert_eq!(
+                round_trip(&format!("route4 add to 10.1.0.0/16 dev {}", link)),
+                format!("route4 10.1.0.0/16 dev {}", link)
+            );
+        }
+
+        assert!(link_matches("ppp+", "ppp0"));
+        assert!(link_matches("ppp+", "ppp"));
+        assert!(!link_matches("ppp+", "eth0"));
+        assert!(link_matches("wg*0", "wg-home0"));
+        assert!(link_matches("eth?", "eth1"));
+        assert!(!link_matches("eth?", "eth10"));
+        assert!(link_matches("eth0", "eth0"));
+
+        assert!(matches!(
+            parse_err("route4 add to 10.1.0.0/16 dev pp+p+"),
+            RouteParseError::InvalidLinkPattern(_)
+        ));
+        // Links matching later only get the route if it is as simple as it gets.
+        assert!(matches!(
+            parse_err("route4 add to 10.1.0.0/16 dev ppp+ ttl 60"),
+            RouteParseError::Conflict("link pattern", "ttl")
+        ));
+    }
+
+    #[test]
+    fn several_links() {
+        assert_eq!(
+            round_trip("route4 add to 0.0.0.0/0 dev ppp0,ppp1 metric 5"),
+            "route4 0.0.0.0/0 metric 5 dev ppp0,ppp1"
+        );
+
+        assert!(matches!(
+            parse_err("route4 add to 0.0.0.0/0 dev ppp0,,ppp1"),
+            RouteParseError::InvalidLinks(_)
+        ));
+        assert!(matches!(
+            parse_err("route4 add to 0.0.0.0/0 dev ppp0,ppp+"),
+            RouteParseError::InvalidLinks(_)
+        ));
+        // The kernel only takes IPv6 nexthops with gateways.
+        assert!(matches!(
+            parse_err("route6 add to ::/0 dev ppp0,ppp1"),
+            RouteParseError::Conflict("several links", "route6")
+        ));
+        assert!(matches!(
+            parse_err("route4 add to 0.0.0.0/0 via 192.0.2.1 dev ppp0,ppp1"),
+            RouteParseError::Conflict("several links", "via")
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+//! Blackhole routes: the `bogons` preset and `rtbh` prefix lists,
+//! which are kept in sync with their files.
+//!
+//! The lists are polled for modifications. Prefixes that were removed
+//! from a list are deleted, new ones are added.
+
+use crate::audit::Source;
+use crate::{log, status};
+use crate::{outcome, report};
+
+use rsdsl_rtd::{Backend, Blackhole, Bogons, PrefixList};
+
+use std::collections::HashSet;
+use std::fs;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Installs (or removes) the routes of the bogon preset.
+pub fn apply_bogons(backend: &dyn Backend, source: Source, bogons: &Bogons) {
+    let mut res = Ok(());
+    for route in bogons.routes() {
+        let r = if bogons.delete {
+            report(source, "del", &route, backend.del_reject(&route))
+        } else {
+            report(source, "add", &route, backend.add_reject(&route))
+        };
+        res = res.and(r);
+    }
+
+    let success = if bogons.delete {
+        status::State::Removed
+    } else {
+        status::State::Applied
+    };
+    status::set(source, outcome(res, success));
+}
+
+/// Installs (or removes) the routes of a prefix list,
+/// returning the routes that are now installed.
+pub fn apply(backend: &dyn Backend, source: Source, list: &PrefixList) -> Vec<Blackhole> {
+    let blackholes = match list.load() {
+        Ok(blackholes) => blackholes,
+        Err(e) => {
+            log::error!(Parser, "read prefix list {}: {}", list.path.display(), e);
+            status::set(source, status::State::Failed(e.to_string()));
+            return Vec::new();
+        }
+    };
+
+    let mut res = Ok(());
+    for blackhole in &blackholes {
+        let r = if list.delete {
+            report(source, "del", blackhole, backend.del_reject(blackhole))
+        } else {
+            report(source, "add", blackhole, backend.add_reject(blackhole))
+        };
+        res = res.and(r);
+    }
+
+    if list.delete {
+        status::set(source, outcome(res, status::State::Removed));
+        Vec::new()
+    } else {
+        status::set(source, outcome(res, status::State::Applied));
+        blackholes
+    }
+}
+
+/// Re-applies the given prefix lists whenever their files change.
+pub fn watch(backend: &dyn Backend, lists: Vec<(Source, PrefixList, Vec<Blackhole>)>) {
+    let mut lists: Vec<_> = lists
+        .into_iter()
+        .filter(|(_, list, _)| !list.delete)
+        .map(|(source, list, installed)| {
+            let modified = modified(&list);
+            (source, list, installed, modified)
+        })
+        .collect();
+
+    if lists.is_empty() {
+        return;
+    }
+
+    let backend = match backend.connect() {
+        Ok(backend) => backend,
+        Err(e) => {
+            log::error!(Netlink, "connect for prefix lists: {}", e);
+            return;
+        }
+    };
+
+    thread::spawn(move || loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let mut changed = false;
+
+        for (source, list, installed, last_modified) in &mut lists {
+            let current = modified(list);
+            if current == *last_modified {
+                continue;
+            }
+            *last_modified = current;
+
+            log::info!(
+                General,
+                "prefix list {} changed, reload",
+                list.path.display()
+            );
+
+            let blackholes = match list.load() {
+                Ok(blackholes) => blackholes,
+                Err(e) => {
+                    // Keep the previous routes rather than dropping them all.
+                    log::error!(Parser, "read prefix list {}: {}", list.path.display(), e);
+                    status::set(*source, status::State::Failed(e.to_string()));
+                    continue;
+                }
+            };
+
+            let old: HashSet<&Blackhole> = installed.iter().collect();
+            let new: HashSet<&Blackhole> = blackholes.iter().collect();
+
+            let mut res = Ok(());
+            for blackhole in installed.iter().filter(|b| !new.contains(b)) {
+                let _ = report(*source, "del", blackhole, backend.del_reject(blackhole));
+            }
+            for blackhole in blackholes.iter().filter(|b| !old.contains(b)) {
+                res = res.and(report(
+                    *source,
+                    "add",
+                    blackhole,
+                    backend.add_reject(blackhole),
+                ));
+            }
+            status::set(*source, outcome(res, status::State::Applied));
+
+            *installed = blackholes;
+            changed = true;
+        }
+
+        if changed {
+            status::applied();
+        }
+    });
+}
+
+fn modified(list: &PrefixList) -> Option<SystemTime> {
+    fs::metadata(&list.path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,7 @@
+//! `rtdctl`: the command line tool for the configuration files,
+//! `write`, `edit` and `completion`.
+//!
+//! It is built from rtd's own code so that it validates files with the
+//! same parser and takes the same lock, `main` limits it to these commands.
+
+include!("main.rs");
@@ -0,0 +1,1084 @@
+//! Minimal rtnetlink client for the requests rsdsl_netlinklib doesn't cover
+//! (route lookups and dumps, address queries, veth creation, neighbors).
+
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::Mutex;
+
+const NLMSG_HDRLEN: usize = 16;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_REPLACE: u16 = 0x100;
+const NLM_F_EXCL: u16 = 0x200;
+const NLM_F_CREATE: u16 = 0x400;
+pub const NLM_F_DUMP: u16 = 0x300;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+pub const RTM_GETADDR: u16 = 22;
+pub const RTM_NEWROUTE: u16 = 24;
+pub const RTM_DELROUTE: u16 = 25;
+pub const RTM_GETROUTE: u16 = 26;
+const RTM_NEWNEIGH: u16 = 28;
+const RTM_DELNEIGH: u16 = 29;
+const RTM_GETNEIGH: u16 = 30;
+pub const RTM_NEWRULE: u16 = 32;
+pub const RTM_DELRULE: u16 = 33;
+pub const RTM_GETRULE: u16 = 34;
+
+pub const RTA_DST: u16 = 1;
+pub const RTA_SRC: u16 = 2;
+pub const RTA_IIF: u16 = 3;
+pub const RTA_OIF: u16 = 4;
+pub const RTA_GATEWAY: u16 = 5;
+pub const RTA_PRIORITY: u16 = 6;
+pub const RTA_PREFSRC: u16 = 7;
+const RTA_MULTIPATH: u16 = 9;
+pub const RTA_TABLE: u16 = 15;
+pub const RTA_MARK: u16 = 16;
+pub const RTA_ENCAP_TYPE: u16 = 21;
+pub const RTA_ENCAP: u16 = 22;
+
+pub const LWTUNNEL_ENCAP_IP: u16 = 2;
+pub const LWTUNNEL_ENCAP_IP6: u16 = 4;
+
+pub const LWTUNNEL_IP_ID: u16 = 1;
+pub const LWTUNNEL_IP_DST: u16 = 2;
+pub const LWTUNNEL_IP_SRC: u16 = 3;
+pub const LWTUNNEL_IP_TTL: u16 = 4;
+
+pub const RT_TABLE_DEFAULT: u32 = 253;
+pub const RT_TABLE_MAIN: u32 = 254;
+pub const RT_TABLE_LOCAL: u32 = 255;
+
+pub const RTPROT_UNSPEC: u8 = 0;
+pub const RTPROT_KERNEL: u8 = 2;
+pub const RTPROT_BOOT: u8 = 3;
+pub const RTPROT_STATIC: u8 = 4;
+pub const RTPROT_RA: u8 = 9;
+/// The protocol rtd tags its rules with. It isn't registered with iproute2,
+/// add `200 rtd` to `/etc/iproute2/rt_protos` to have `ip rule` show its name.
+pub const RTPROT_RTD: u8 = 200;
+
+/// The metric the kernel gives IPv6 routes without one.
+pub const IP6_RT_PRIO_USER: u32 = 1024;
+
+pub const RT_SCOPE_UNIVERSE: u8 = 0;
+pub const RT_SCOPE_LINK: u8 = 253;
+pub const RT_SCOPE_NOWHERE: u8 = 255;
+
+pub const RTNLGRP_IPV4_ROUTE: u32 = 7;
+pub const RTNLGRP_IPV4_RULE: u32 = 8;
+pub const RTNLGRP_IPV6_ROUTE: u32 = 11;
+pub const RTNLGRP_IPV6_RULE: u32 = 19;
+
+pub const RTNH_F_ONLINK: u8 = 0x4;
+const RTNH_LEN: usize = 8;
+
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MASTER: u16 = 10;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_INFO_KIND: u16 = 1;
+const IFLA_INFO_DATA: u16 = 2;
+const VETH_INFO_PEER: u16 = 1;
+const IFLA_VRF_TABLE: u16 = 1;
+
+const IFF_UP: u32 = 0x1;
+
+const IFINFOMSG_LEN: usize = 16;
+
+const NDA_DST: u16 = 1;
+const NDA_LLADDR: u16 = 2;
+
+const NDMSG_LEN: usize = 12;
+
+pub const NUD_INCOMPLETE: u16 = 0x01;
+pub const NUD_FAILED: u16 = 0x20;
+const NUD_PERMANENT: u16 = 0x80;
+
+const NTF_PROXY: u8 = 0x8;
+
+pub const IFA_ADDRESS: u16 = 1;
+pub const IFA_LOCAL: u16 = 2;
+
+const IFA_F_SECONDARY: u8 = 0x01;
+const IFA_F_DADFAILED: u8 = 0x08;
+const IFA_F_DEPRECATED: u8 = 0x20;
+const IFA_F_TENTATIVE: u8 = 0x40;
+
+pub const FRA_DST: u16 = 1;
+pub const FRA_SRC: u16 = 2;
+pub const FRA_IIFNAME: u16 = 3;
+pub const FRA_PRIORITY: u16 = 6;
+pub const FRA_FWMARK: u16 = 10;
+pub const FRA_TABLE: u16 = 15;
+pub const FRA_FWMASK: u16 = 16;
+pub const FRA_OIFNAME: u16 = 17;
+pub const FRA_PROTOCOL: u16 = 21;
+
+pub const FR_ACT_TO_TBL: u8 = 1;
+pub const FR_ACT_GOTO: u8 = 2;
+pub const FR_ACT_NOP: u8 = 3;
+pub const FR_ACT_BLACKHOLE: u8 = 6;
+pub const FR_ACT_UNREACHABLE: u8 = 7;
+pub const FR_ACT_PROHIBIT: u8 = 8;
+
+pub const FIB_RULE_INVERT: u32 = 0x2;
+
+pub const RTM_F_LOOKUP_TABLE: u32 = 0x1000;
+pub const RTM_F_FIB_MATCH: u32 = 0x2000;
+
+pub const RTN_UNICAST: u8 = 1;
+pub const RTN_LOCAL: u8 = 2;
+pub const RTN_BROADCAST: u8 = 3;
+pub const RTN_ANYCAST: u8 = 4;
+pub const RTN_MULTICAST: u8 = 5;
+pub const RTN_BLACKHOLE: u8 = 6;
+pub const RTN_UNREACHABLE: u8 = 7;
+pub const RTN_PROHIBIT: u8 = 8;
+pub const RTN_THROW: u8 = 9;
+
+const NLA_TYPE_MASK: u16 = 0x3fff;
+pub const NLA_F_NESTED: u16 = 0x8000;
+
+/// The number of ports `is_own_port` remembers.
+const OWN_PORTS: usize = 256;
+
+/// The ports of the most recently created sockets.
+static RECENT_PORTS: Mutex<VecDeque<u32>> = Mutex::new(VecDeque::new());
+
+#[derive(Debug)]
+pub struct Socket {
+    fd: OwnedFd,
+    seq: u32,
+}
+
+impl Socket {
+    pub fn new() -> io::Result<Self> {
+        // SAFETY: Plain socket(2) call, the result is checked below.
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: fd is a freshly created socket that nothing else owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // SAFETY: sockaddr_nl is plain old data, all zeroes is a valid value.
+        let mut sa: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        sa.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+
+        // SAFETY: sa is a valid sockaddr_nl of the advertised size.
+        let res = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &sa as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut len = mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+        // SAFETY: sa and len describe a valid, writable sockaddr_nl.
+        let res = unsafe {
+            libc::getsockname(
+                fd.as_raw_fd(),
+                &mut sa as *mut libc::sockaddr_nl as *mut libc::sockaddr,
+                &mut len,
+            )
+        };
+        if res == 0 {
+            let mut ports = RECENT_PORTS.lock().unwrap_or_else(|e| e.into_inner());
+            if ports.len() == OWN_PORTS {
+                ports.pop_front();
+            }
+            ports.push_back(sa.nl_pid);
+        }
+
+        Ok(Self { fd, seq: 0 })
+    }
+
+    /// Sends a request and collects the payloads of all replies
+    /// until the kernel acknowledges it or finishes the dump.
+    pub fn request(&mut self, ty: u16, flags: u16, payload: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        self.seq = self.seq.wrapping_add(1);
+
+        let dump = flags & NLM_F_DUMP == NLM_F_DUMP;
+        let flags = if dump {
+            flags | NLM_F_REQUEST
+        } else {
+            flags | NLM_F_REQUEST | NLM_F_ACK
+        };
+
+        let len = NLMSG_HDRLEN + payload.len();
+        let mut buf = Vec::with_capacity(len);
+        buf.extend_from_slice(&(len as u32).to_ne_bytes());
+        buf.extend_from_slice(&ty.to_ne_bytes());
+        buf.extend_from_slice(&flags.to_ne_bytes());
+        buf.extend_from_slice(&self.seq.to_ne_bytes());
+        buf.extend_from_slice(&0u32.to_ne_bytes());
+        buf.extend_from_slice(payload);
+
+        // SAFETY: buf is a valid, initialized buffer of the given length.
+        let n = unsafe {
+            libc::send(
+                self.fd.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut msgs = Vec::new();
+        let mut rbuf = vec![0u8; 65536];
+        loop {
+            // SAFETY: rbuf is a valid, writable buffer of the given length.
+            let n = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    rbuf.as_mut_ptr() as *mut libc::c_void,
+                    rbuf.len(),
+                    0,
+                )
+            };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut data = &rbuf[..n as usize];
+            while data.len() >= NLMSG_HDRLEN {
+                let msg_len = u32_at(data, 0) as usize;
+                let msg_ty = u16::from_ne_bytes([data[4], data[5]]);
+                let msg_seq = u32_at(data, 8);
+
+                if msg_len < NLMSG_HDRLEN || msg_len > data.len() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "truncated netlink message",
+                    ));
+                }
+
+                let payload = &data[NLMSG_HDRLEN..msg_len];
+                data = &data[align(msg_len).min(data.len())..];
+
+                if msg_seq != self.seq {
+                    continue;
+                }
+
+                match msg_ty {
+                    NLMSG_DONE => return Ok(msgs),
+                    NLMSG_ERROR => {
+                        let errno = payload
+                            .get(..4)
+                            .map(|b| i32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+                            .unwrap_or(-libc::EIO);
+
+                        return if errno == 0 {
+                            Ok(msgs)
+                        } else {
+                            Err(io::Error::from_raw_os_error(-errno))
+                        };
+                    }
+                    _ => msgs.push(payload.to_vec()),
+                }
+            }
+        }
+    }
+
+    /// Joins multicast groups (`RTNLGRP_*`) to receive notifications
+    /// about changes via `recv_events`.
+    pub fn subscribe(&mut self, groups: &[u32]) -> io::Result<()> {
+        for group in groups {
+            // SAFETY: group is a valid u32 of the advertised size.
+            let res = unsafe {
+                libc::setsockopt(
+                    self.fd.as_raw_fd(),
+                    libc::SOL_NETLINK,
+                    libc::NETLINK_ADD_MEMBERSHIP,
+                    group as *const u32 as *const libc::c_void,
+                    mem::size_of::<u32>() as libc::socklen_t,
+                )
+            };
+            if res < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Blocks until notifications arrive and returns them.
+    /// Fails with `ENOBUFS` if some of them were lost.
+    pub fn recv_events(&mut self) -> io::Result<Vec<Event>> {
+        let mut rbuf = vec![0u8; 65536];
+        // SAFETY: rbuf is a valid, writable buffer of the given length.
+        let n = unsafe {
+            libc::recv(
+                self.fd.as_raw_fd(),
+                rbuf.as_mut_ptr() as *mut libc::c_void,
+                rbuf.len(),
+                0,
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut events = Vec::new();
+        let mut data = &rbuf[..n as usize];
+        while data.len() >= NLMSG_HDRLEN {
+            let msg_len = u32_at(data, 0) as usize;
+            if msg_len < NLMSG_HDRLEN || msg_len > data.len() {
+                break;
+            }
+
+            events.push(Event {
+                ty: u16::from_ne_bytes([data[4], data[5]]),
+                portid: u32_at(data, 12),
+                payload: data[NLMSG_HDRLEN..msg_len].to_vec(),
+            });
+            data = &data[align(msg_len).min(data.len())..];
+        }
+
+        Ok(events)
+    }
+
+    /// Returns all routes of the given address family (`AF_UNSPEC` for all).
+    pub fn dump_routes(&mut self, family: u8) -> io::Result<Vec<RouteMsg>> {
+        Ok(self
+            .request(RTM_GETROUTE, NLM_F_DUMP, &rtmsg(family, 0, 0, 0, 0, 0))?
+            .iter()
+            .filter_map(|msg| RouteMsg::parse(msg))
+            .collect())
+    }
+
+    /// Returns all rules of the given address family (`AF_UNSPEC` for all).
+    pub fn dump_rules(&mut self, family: u8) -> io::Result<Vec<RuleMsg>> {
+        Ok(self
+            .request(RTM_GETRULE, NLM_F_DUMP, &rtmsg(family, 0, 0, 0, 0, 0))?
+            .iter()
+            .filter_map(|msg| RuleMsg::parse(msg))
+            .collect())
+    }
+
+    /// Removes the routes `flush` selects, returning them.
+    /// Routes that disappear in the meantime count as removed.
+    pub fn flush_routes(&mut self, flush: impl Fn(&RouteMsg) -> bool) -> io::Result<Vec<RouteMsg>> {
+        let msgs = self.request(RTM_GETROUTE, NLM_F_DUMP, &rtmsg(0, 0, 0, 0, 0, 0))?;
+
+        let mut flushed = Vec::new();
+        for msg in msgs {
+            let Some(route) = RouteMsg::parse(&msg).filter(&flush) else {
+                continue;
+            };
+
+            match self.request(RTM_DELROUTE, 0, &msg) {
+                Ok(_) => {}
+                Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {}
+                Err(e) => return Err(e),
+            }
+            flushed.push(route);
+        }
+
+        Ok(flushed)
+    }
+
+    /// Removes the rules `flush` selects, returning them.
+    /// Rules that disappear in the meantime count as removed.
+    pub fn flush_rules(&mut self, flush: impl Fn(&RuleMsg) -> bool) -> io::Result<Vec<RuleMsg>> {
+        let msgs = self.request(RTM_GETRULE, NLM_F_DUMP, &rtmsg(0, 0, 0, 0, 0, 0))?;
+
+        let mut flushed = Vec::new();
+        for msg in msgs {
+            let Some(rule) = RuleMsg::parse(&msg).filter(&flush) else {
+                continue;
+            };
+
+            match self.request(RTM_DELRULE, 0, &msg) {
+                Ok(_) => {}
+                Err(e) if e.raw_os_error() == Some(libc::ENOENT) => {}
+                Err(e) => return Err(e),
+            }
+            flushed.push(rule);
+        }
+
+        Ok(flushed)
+    }
+
+    /// Installs a multipath route, replacing any existing route
+    /// with the same destination, table and metric.
+    pub fn replace_multipath(
+        &mut self,
+        dst: IpAddr,
+        prefix_len: u8,
+        table: Option<u32>,
+        metric: Option<u32>,
+        nexthops: &[Nexthop],
+    ) -> io::Result<()> {
+        let mut multipath = Vec::new();
+        for nexthop in nexthops {
+            let mut attrs = Vec::new();
+            if let Some(gateway) = nexthop.gateway {
+                put_addr(&mut attrs, RTA_GATEWAY, gateway);
+            }
+
+            let flags = if nexthop.on_link { RTNH_F_ONLINK } else { 0 };
+            // The kernel stores weights as the number of additional hops.
+            let hops = (nexthop.weight.clamp(1, 256) - 1) as u8;
+
+            multipath.extend_from_slice(&((RTNH_LEN + attrs.len()) as u16).to_ne_bytes());
+            multipath.extend_from_slice(&[flags, hops]);
+            multipath.extend_from_slice(&nexthop.index.to_ne_bytes());
+            multipath.extend_from_slice(&attrs);
+        }
+
+        let mut req = route_req(dst, prefix_len, table, metric, RTN_UNICAST);
+        put_attr(&mut req, RTA_MULTIPATH, &multipath);
+
+        self.request(RTM_NEWROUTE, NLM_F_CREATE | NLM_F_REPLACE, &req)?;
+        Ok(())
+    }
+
+    /// Removes the route with the given destination, table and metric,
+    /// including all of its nexthops.
+    pub fn del_route(
+        &mut self,
+        dst: IpAddr,
+        prefix_len: u8,
+        table: Option<u32>,
+        metric: Option<u32>,
+    ) -> io::Result<()> {
+        // Leave the type and protocol unspecified so that routes of any type match,
+        // no matter who installed them. The kernel only ignores the scope if it's "nowhere".
+        let mut req = route_req(dst, prefix_len, table, metric, 0);
+        req[5] = 0;
+        req[6] = RT_SCOPE_NOWHERE;
+
+        self.request(RTM_DELROUTE, 0, &req)?;
+        Ok(())
+    }
+
+    /// Adds a route that discards matching packets, `ty` is one of
+    /// `RTN_BLACKHOLE`, `RTN_UNREACHABLE` or `RTN_PROHIBIT`.
+    pub fn add_reject(
+        &mut self,
+        dst: IpAddr,
+        prefix_len: u8,
+        table: Option<u32>,
+        metric: Option<u32>,
+        ty: u8,
+    ) -> io::Result<()> {
+        let req = route_req(dst, prefix_len, table, metric, ty);
+
+        self.request(RTM_NEWROUTE, NLM_F_CREATE | NLM_F_REPLACE, &req)?;
+        Ok(())
+    }
+
+    /// Adds a route from a complete request, i.e. an `rtmsg`
+    /// followed by its attributes.
+    pub fn add_route(&mut self, req: &[u8]) -> io::Result<()> {
+        self.request(RTM_NEWROUTE, NLM_F_CREATE | NLM_F_EXCL, req)?;
+        Ok(())
+    }
+
+    /// Adds a rule from a complete request, i.e. a `fib_rule_hdr`
+    /// followed by its attributes.
+    pub fn add_rule(&mut self, req: &[u8]) -> io::Result<()> {
+        self.request(RTM_NEWRULE, NLM_F_CREATE | NLM_F_EXCL, req)?;
+        Ok(())
+    }
+
+    /// Adds a rule looking up `table` for traffic received on `iif`,
+    /// tagged as rtd's.
+    pub fn add_iif_rule(&mut self, family: u8, iif: &str, table: u32) -> io::Result<()> {
+        let mut req = iif_rule_req(family, iif, table);
+        put_attr(&mut req, FRA_PROTOCOL, &[RTPROT_RTD]);
+
+        self.request(RTM_NEWRULE, NLM_F_CREATE | NLM_F_EXCL, &req)?;
+        Ok(())
+    }
+
+    /// Removes a rule added by `add_iif_rule`.
+    pub fn del_iif_rule(&mut self, family: u8, iif: &str, table: u32) -> io::Result<()> {
+        let req = iif_rule_req(family, iif, table);
+
+        self.request(RTM_DELRULE, 0, &req)?;
+        Ok(())
+    }
+
+    /// Adds a rule looking up `table` for all traffic at the given priority,
+    /// like the ones the kernel starts with.
+    pub fn add_table_rule(&mut self, family: u8, table: u32, priority: u32) -> io::Result<()> {
+        let req = table_rule_req(family, table, priority);
+
+        self.request(RTM_NEWRULE, NLM_F_CREATE | NLM_F_EXCL, &req)?;
+        Ok(())
+    }
+
+    /// Removes a rule added by `add_table_rule`.
+    pub fn del_table_rule(&mut self, family: u8, table: u32, priority: u32) -> io::Result<()> {
+        let req = table_rule_req(family, table, priority);
+
+        self.request(RTM_DELRULE, 0, &req)?;
+        Ok(())
+    }
+
+    /// Adds or replaces a permanent neighbor entry,
+    /// or a proxy entry if no link-layer address is given.
+    pub fn add_neigh(&mut self, index: u32, addr: IpAddr, lladdr: Option<&[u8]>) -> io::Result<()> {
+        let flags = if lladdr.is_some() { 0 } else { NTF_PROXY };
+
+        let mut req = ndmsg(family(addr), index, NUD_PERMANENT, flags);
+        put_addr(&mut req, NDA_DST, addr);
+        if let Some(lladdr) = lladdr {
+            put_attr(&mut req, NDA_LLADDR, lladdr);
+        }
+
+        self.request(RTM_NEWNEIGH, NLM_F_CREATE | NLM_F_REPLACE, &req)?;
+        Ok(())
+    }
+
+    /// Returns the state (`NUD_*`) of the neighbor entry for `addr` on a link.
+    pub fn neigh_state(&mut self, index: u32, addr: IpAddr) -> io::Result<Option<u16>> {
+        let msgs = self.request(RTM_GETNEIGH, NLM_F_DUMP, &ndmsg(family(addr), 0, 0, 0))?;
+
+        Ok(msgs.iter().find_map(|msg| {
+            if msg.len() < NDMSG_LEN {
+                return None;
+            }
+
+            let dst = attrs(&msg[NDMSG_LEN..])
+                .find(|(ty, _)| *ty == NDA_DST)
+                .and_then(|(_, value)| attr_addr(value));
+            let state = u16::from_ne_bytes([msg[8], msg[9]]);
+
+            (u32_at(msg, 4) == index && dst == Some(addr)).then_some(state)
+        }))
+    }
+
+    /// Removes a neighbor or proxy entry.
+    pub fn del_neigh(&mut self, index: u32, addr: IpAddr, proxy: bool) -> io::Result<()> {
+        let flags = if proxy { NTF_PROXY } else { 0 };
+
+        let mut req = ndmsg(family(addr), index, 0, flags);
+        put_addr(&mut req, NDA_DST, addr);
+
+        self.request(RTM_DELNEIGH, 0, &req)?;
+        Ok(())
+    }
+
+    /// Creates a pair of connected veth devices.
+    pub fn add_veth(&mut self, name: &str, peer: &str) -> io::Result<()> {
+        let mut peer_info = vec![0; IFINFOMSG_LEN];
+        put_attr(&mut peer_info, IFLA_IFNAME, &c_str(peer));
+
+        let mut info_data = Vec::new();
+        put_attr(&mut info_data, VETH_INFO_PEER, &peer_info);
+
+        let mut link_info = Vec::new();
+        put_attr(&mut link_info, IFLA_INFO_KIND, b"veth");
+        put_attr(&mut link_info, IFLA_INFO_DATA, &info_data);
+
+        let mut req = vec![0; IFINFOMSG_LEN];
+        put_attr(&mut req, IFLA_IFNAME, &c_str(name));
+        put_attr(&mut req, IFLA_LINKINFO, &link_info);
+
+        self.request(RTM_NEWLINK, NLM_F_CREATE | NLM_F_EXCL, &req)?;
+        Ok(())
+    }
+
+    /// Creates a VRF device using the given routing table.
+    pub fn add_vrf(&mut self, name: &str, table: u32) -> io::Result<()> {
+        let mut info_data = Vec::new();
+        put_attr(&mut info_data, IFLA_VRF_TABLE, &table.to_ne_bytes());
+
+        let mut link_info = Vec::new();
+        put_attr(&mut link_info, IFLA_INFO_KIND, b"vrf");
+        put_attr(&mut link_info, IFLA_INFO_DATA, &info_data);
+
+        let mut req = vec![0; IFINFOMSG_LEN];
+        put_attr(&mut req, IFLA_IFNAME, &c_str(name));
+        put_attr(&mut req, IFLA_LINKINFO, &link_info);
+
+        self.request(RTM_NEWLINK, NLM_F_CREATE | NLM_F_EXCL, &req)?;
+        Ok(())
+    }
+
+    /// Brings a link up.
+    pub fn link_up(&mut self, index: u32) -> io::Result<()> {
+        let req = ifinfomsg(index, IFF_UP, IFF_UP);
+
+        self.request(RTM_NEWLINK, 0, &req)?;
+        Ok(())
+    }
+
+    /// Deletes a link.
+    pub fn del_link(&mut self, index: u32) -> io::Result<()> {
+        let req = ifinfomsg(index, 0, 0);
+
+        self.request(RTM_DELLINK, 0, &req)?;
+        Ok(())
+    }
+
+    /// Enslaves a link to a master device such as a VRF.
+    pub fn set_master(&mut self, index: u32, master: u32) -> io::Result<()> {
+        let mut req = ifinfomsg(index, 0, 0);
+        put_attr(&mut req, IFLA_MASTER, &master.to_ne_bytes());
+
+        self.request(RTM_NEWLINK, 0, &req)?;
+        Ok(())
+    }
+}
+
+/// A nexthop of a multipath route.
+#[derive(Clone, Debug)]
+pub struct Nexthop {
+    pub index: u32,
+    pub gateway: Option<IpAddr>,
+    pub on_link: bool,
+    /// Relative share of the traffic, 1 to 256.
+    pub weight: u16,
+}
+
+/// Builds a static route request without nexthop information.
+fn route_req(
+    dst: IpAddr,
+    prefix_len: u8,
+    table: Option<u32>,
+    metric: Option<u32>,
+    ty: u8,
+) -> Vec<u8> {
+    let mut req = rtmsg(family(dst), prefix_len, 0, 0, ty, 0);
+    req[5] = RTPROT_STATIC;
+
+    put_addr(&mut req, RTA_DST, dst);
+    put_attr(
+        &mut req,
+        RTA_TABLE,
+        &table.unwrap_or(RT_TABLE_MAIN).to_ne_bytes(),
+    );
+    if let Some(metric) = metric {
+        put_attr(&mut req, RTA_PRIORITY, &metric.to_ne_bytes());
+    }
+
+    req
+}
+
+/// Builds the fixed ifinfomsg header.
+fn ifinfomsg(index: u32, flags: u32, change: u32) -> Vec<u8> {
+    let mut buf = vec![0; 4];
+    buf.extend_from_slice(&index.to_ne_bytes());
+    buf.extend_from_slice(&flags.to_ne_bytes());
+    buf.extend_from_slice(&change.to_ne_bytes());
+    buf
+}
+
+/// Builds the fixed ndmsg header.
+fn ndmsg(family: u8, index: u32, state: u16, flags: u8) -> Vec<u8> {
+    let mut buf = vec![family, 0, 0, 0];
+    buf.extend_from_slice(&index.to_ne_bytes());
+    buf.extend_from_slice(&state.to_ne_bytes());
+    buf.extend_from_slice(&[flags, 0]);
+    buf
+}
+
+fn iif_rule_req(family: u8, iif: &str, table: u32) -> Vec<u8> {
+    let mut req = rtmsg(family, 0, 0, 0, FR_ACT_TO_TBL, 0);
+
+    put_attr(&mut req, FRA_IIFNAME, &c_str(iif));
+    put_attr(&mut req, FRA_TABLE, &table.to_ne_bytes());
+
+    req
+}
+
+fn table_rule_req(family: u8, table: u32, priority: u32) -> Vec<u8> {
+    let mut req = rtmsg(family, 0, 0, 0, FR_ACT_TO_TBL, 0);
+
+    put_attr(&mut req, FRA_TABLE, &table.to_ne_bytes());
+    put_attr(&mut req, FRA_PRIORITY, &priority.to_ne_bytes());
+
+    req
+}
+
+fn family(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => libc::AF_INET as u8,
+        IpAddr::V6(_) => libc::AF_INET6 as u8,
+    }
+}
+
+fn c_str(s: &str) -> Vec<u8> {
+    let mut buf = s.as_bytes().to_vec();
+    buf.push(0);
+    buf
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Appends a netlink attribute (including padding) to `buf`.
+pub fn put_attr(buf: &mut Vec<u8>, ty: u16, data: &[u8]) {
+    let len = 4 + data.len();
+    buf.extend_from_slice(&(len as u16).to_ne_bytes());
+    buf.extend_from_slice(&ty.to_ne_bytes());
+    buf.extend_from_slice(data);
+    buf.resize(buf.len() + align(len) - len, 0);
+}
+
+/// Appends an address attribute in network byte order.
+pub fn put_addr(buf: &mut Vec<u8>, ty: u16, addr: IpAddr) {
+    match addr {
+        IpAddr::V4(addr) => put_attr(buf, ty, &addr.octets()),
+        IpAddr::V6(addr) => put_attr(buf, ty, &addr.octets()),
+    }
+}
+
+/// Iterates over the attributes in `buf` as (type, value) pairs.
+pub fn attrs(mut buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if buf.len() < 4 {
+            return None;
+        }
+
+        let len = u16::from_ne_bytes([buf[0], buf[1]]) as usize;
+        let ty = u16::from_ne_bytes([buf[2], buf[3]]) & NLA_TYPE_MASK;
+        if len < 4 || len > buf.len() {
+            return None;
+        }
+
+        let value = &buf[4..len];
+        buf = &buf[align(len).min(buf.len())..];
+
+        Some((ty, value))
+    })
+}
+
+pub fn attr_u32(value: &[u8]) -> Option<u32> {
+    (value.len() >= 4).then(|| u32_at(value, 0))
+}
+
+pub fn attr_addr(value: &[u8]) -> Option<IpAddr> {
+    match value.len() {
+        4 => {
+            let octets: [u8; 4] = value.try_into().ok()?;
+            Some(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        16 => {
+            let octets: [u8; 16] = value.try_into().ok()?;
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+pub fn attr_str(value: &[u8]) -> String {
+    let end = value.iter().position(|b| *b == 0).unwrap_or(value.len());
+    String::from_utf8_lossy(&value[..end]).into_owned()
+}
+
+/// Builds the fixed rtmsg / fib_rule_hdr header, which share a layout.
+pub fn rtmsg(family: u8, dst_len: u8, src_len: u8, table: u8, ty: u8, flags: u32) -> Vec<u8> {
+    let mut buf = vec![family, dst_len, src_len, 0, table, 0, 0, ty];
+    buf.extend_from_slice(&flags.to_ne_bytes());
+    buf
+}
+
+/// Reports whether a port (see `Event::portid`) belongs to one of the last
+/// sockets of this type created by this process. Short-lived sockets
+/// are usually closed by the time their changes are noticed.
+pub fn is_own_port(port: u32) -> bool {
+    RECENT_PORTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .contains(&port)
+}
+
+/// A notification received on a subscribed socket.
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// The message type, e.g. `RTM_NEWROUTE`.
+    pub ty: u16,
+    /// The netlink port of the socket that caused the change, 0 for the kernel.
+    pub portid: u32,
+    pub payload: Vec<u8>,
+}
+
+/// A route as reported by the kernel.
+#[derive(Clone, Debug, Default)]
+pub struct RouteMsg {
+    pub family: u8,
+    pub dst_len: u8,
+    pub table: u32,
+    pub protocol: u8,
+    pub ty: u8,
+    pub on_link: bool,
+    pub dst: Option<IpAddr>,
+    pub gateway: Option<IpAddr>,
+    pub prefsrc: Option<IpAddr>,
+    pub oif: Option<u32>,
+    pub metric: Option<u32>,
+}
+
+impl RouteMsg {
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < 12 {
+            return None;
+        }
+
+        let mut route = Self {
+            family: payload[0],
+            dst_len: payload[1],
+            table: payload[4].into(),
+            protocol: payload[5],
+            ty: payload[7],
+            on_link: u32_at(payload, 8) & u32::from(RTNH_F_ONLINK) != 0,
+            ..Default::default()
+        };
+
+        for (ty, value) in attrs(&payload[12..]) {
+            match ty {
+                RTA_DST => route.dst = attr_addr(value),
+                RTA_GATEWAY => route.gateway = attr_addr(value),
+                RTA_PREFSRC => route.prefsrc = attr_addr(value),
+                RTA_OIF => route.oif = attr_u32(value),
+                RTA_PRIORITY => route.metric = attr_u32(value),
+                RTA_TABLE => route.table = attr_u32(value).unwrap_or(route.table),
+                _ => {}
+            }
+        }
+
+        Some(route)
+    }
+}
+
+/// An interface address as reported by the kernel.
+#[derive(Clone, Debug, Default)]
+pub struct AddrMsg {
+    pub family: u8,
+    pub flags: u8,
+    pub scope: u8,
+    pub index: u32,
+    pub address: Option<IpAddr>,
+    pub local: Option<IpAddr>,
+}
+
+impl AddrMsg {
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < 8 {
+            return None;
+        }
+
+        let mut addr = Self {
+            family: payload[0],
+            flags: payload[2],
+            scope: payload[3],
+            index: u32_at(payload, 4),
+            ..Default::default()
+        };
+
+        for (ty, value) in attrs(&payload[8..]) {
+            match ty {
+                IFA_ADDRESS => addr.address = attr_addr(value),
+                IFA_LOCAL => addr.local = attr_addr(value),
+                _ => {}
+            }
+        }
+
+        Some(addr)
+    }
+
+    /// Returns the address of the remote end of a point-to-point link.
+    /// For other links the kernel reports identical local and peer addresses.
+    pub fn peer(&self) -> Option<IpAddr> {
+        match (self.local, self.address) {
+            (Some(local), Some(peer)) if local != peer => Some(peer),
+            _ => None,
+        }
+    }
+
+    /// Returns the address if it is the kind the kernel picks as a source
+    /// by itself: global, usable and not a secondary address of its subnet.
+    pub fn primary(&self) -> Option<IpAddr> {
+        let unusable = IFA_F_SECONDARY | IFA_F_DADFAILED | IFA_F_DEPRECATED | IFA_F_TENTATIVE;
+        if self.scope != RT_SCOPE_UNIVERSE || self.flags & unusable != 0 {
+            return None;
+        }
+
+        self.local.or(self.address)
+    }
+}
+
+/// A routing policy rule as reported by the kernel.
+#[derive(Clone, Debug, Default)]
+pub struct RuleMsg {
+    pub family: u8,
+    pub dst_len: u8,
+    pub src_len: u8,
+    pub action: u8,
+    pub flags: u32,
+    pub table: u32,
+    pub priority: u32,
+    pub protocol: u8,
+    pub fwmark: Option<u32>,
+    pub fwmask: Option<u32>,
+    pub dst: Option<IpAddr>,
+    pub src: Option<IpAddr>,
+    pub iifname: Option<String>,
+    pub oifname: Option<String>,
+}
+
+impl RuleMsg {
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < 12 {
+            return None;
+        }
+
+        let mut rule = Self {
+            family: payload[0],
+            dst_len: payload[1],
+            src_len: payload[2],
+            table: payload[4].into(),
+            action: payload[7],
+            flags: u32_at(payload, 8),
+            ..Default::default()
+        };
+
+        for (ty, value) in attrs(&payload[12..]) {
+            match ty {
+                FRA_DST => rule.dst = attr_addr(value),
+                FRA_SRC => rule.src = attr_addr(value),
+                FRA_IIFNAME => rule.iifname = Some(attr_str(value)),
+                FRA_OIFNAME => rule.oifname = Some(attr_str(value)),
+                FRA_PRIORITY => rule.priority = attr_u32(value).unwrap_or_default(),
+                FRA_FWMARK => rule.fwmark = attr_u32(value),
+                FRA_FWMASK => rule.fwmask = attr_u32(value),
+                FRA_TABLE => rule.table = attr_u32(value).unwrap_or(rule.table),
+                FRA_PROTOCOL => rule.protocol = value.first().copied().unwrap_or_default(),
+                _ => {}
+            }
+        }
+
+        Some(rule)
+    }
+}
+
+/// Returns the name of a route or rule protocol (`RTPROT_*`),
+/// see `/etc/iproute2/rt_protos`.
+pub fn protocol_name(protocol: u8) -> String {
+    match protocol {
+        RTPROT_UNSPEC => "unspec".to_string(),
+        1 => "redirect".to_string(),
+        RTPROT_KERNEL => "kernel".to_string(),
+        RTPROT_BOOT => "boot".to_string(),
+        RTPROT_STATIC => "static".to_string(),
+        RTPROT_RA => "ra".to_string(),
+        11 => "zebra".to_string(),
+        12 => "bird".to_string(),
+        16 => "dhcp".to_string(),
+        RTPROT_RTD => "rtd".to_string(),
+        p => p.to_string(),
+    }
+}
+
+/// Parses a protocol given by its name (as returned by `protocol_name`) or number.
+pub fn parse_protocol(s: &str) -> Option<u8> {
+    (0..=u8::MAX)
+        .find(|protocol| protocol_name(*protocol) == s)
+        .or_else(|| s.parse().ok())
+}
+
+/// Returns the interface index of the named link.
+pub fn link_index(link: &str) -> io::Result<u32> {
+    let name = CString::new(link).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    // SAFETY: name is a valid NUL-terminated string.
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+/// Returns the names of all links.
+pub fn link_names() -> io::Result<Vec<String>> {
+    // SAFETY: if_nameindex(3) has no preconditions.
+    let list = unsafe { libc::if_nameindex() };
+    if list.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut names = Vec::new();
+    // SAFETY: the list ends with an entry of index 0
+    // and stays valid until it is freed below.
+    unsafe {
+        let mut entry = list;
+        while (*entry).if_index != 0 {
+            names.push(
+                CStr::from_ptr((*entry).if_name)
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+            entry = entry.add(1);
+        }
+        libc::if_freenameindex(list);
+    }
+
+    Ok(names)
+}
+
+/// Returns the name of the link with the given interface index.
+pub fn link_name(index: u32) -> Option<String> {
+    let mut buf = [0u8; libc::IF_NAMESIZE];
+
+    // SAFETY: buf is IF_NAMESIZE bytes long as required by if_indextoname(3).
+    let res = unsafe { libc::if_indextoname(index, buf.as_mut_ptr() as *mut libc::c_char) };
+    if res.is_null() {
+        None
+    } else {
+        Some(attr_str(&buf))
+    }
+}
+
+/// Checks whether `addr` lies within `prefix`/`len`.
+pub fn prefix_contains(prefix: IpAddr, len: u8, addr: IpAddr) -> bool {
+    match (prefix, addr) {
+        (IpAddr::V4(prefix), IpAddr::V4(addr)) => {
+            let mask = u32::MAX.checked_shl(32 - len.min(32) as u32).unwrap_or(0);
+            u32::from(prefix) & mask == u32::from(addr) & mask
+        }
+        (IpAddr::V6(prefix), IpAddr::V6(addr)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - len.min(128) as u32)
+                .unwrap_or(0);
+            u128::from(prefix) & mask == u128::from(addr) & mask
+        }
+        _ => false,
+    }
+}
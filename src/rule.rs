@@ -0,0 +1,1218 @@
+//! Routing policy rules (`/data/policies.rl`).
+
+use crate::{rtnl, vars, Drift, KernelRule, SetupError};
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use rsdsl_netlinklib::blocking::Connection;
+use rsdsl_netlinklib::rule::RuleAction;
+use serde::{Deserialize, Serialize};
+
+/// An error parsing a rule configuration line or file.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RuleParseError {
+    Conflict(&'static str, &'static str),
+    DstIllegal,
+    DstNotIpv4,
+    DstNotIpv6,
+    DuplicateAttr(String),
+    InvalidAction(String),
+    InvalidAttr(String),
+    InvalidCidr(String),
+    InvalidCmd(String),
+    InvalidDrift(String),
+    InvalidKernelTable(u32),
+    InvalidNetns(String),
+    InvalidProtocol(String),
+    InvalidVersion(String),
+    Line(usize, Box<RuleParseError>),
+    Netns(crate::netns::BlockError),
+    NoAction,
+    NoAttrValue(String),
+    NoCmd,
+    NoKernelTable,
+    NoPriority,
+    NoTable,
+    NoVersion,
+    ParseAddr(std::net::AddrParseError),
+    ParseBool(std::str::ParseBoolError),
+    ParseInt(std::num::ParseIntError),
+    SrcIllegal,
+    SrcNotIpv4,
+    SrcNotIpv6,
+    Var(vars::VarError),
+}
+
+impl fmt::Display for RuleParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Conflict(a, b) => write!(f, "{} can't be combined with {}", a, b)?,
+            Self::DstIllegal => write!(f, "protocol-agnostic rule with destination prefix")?,
+            Self::DstNotIpv4 => write!(f, "rule4 with non-IPv4 destination")?,
+            Self::DstNotIpv6 => write!(f, "rule6 with non-IPv6 destination")?,
+            Self::DuplicateAttr(a) => write!(f, "duplicate attribute {}", a)?,
+            Self::InvalidAction(a) => write!(f, "invalid action {}", a)?,
+            Self::InvalidAttr(a) => write!(f, "invalid attribute {}", a)?,
+            Self::InvalidCidr(c) => write!(f, "invalid CIDR {} (want exactly 1 /)", c)?,
+            Self::InvalidCmd(c) => write!(
+                f,
+                "invalid command {} (want \"add\" or \"del\", \"set\" or \"del\" for kernel)",
+                c
+            )?,
+            Self::InvalidDrift(d) => write!(
+                f,
+                "invalid drift policy {} (want \"ignore\", \"log\" or \"repair\")",
+                d
+            )?,
+            Self::InvalidKernelTable(t) => write!(
+                f,
+                "no kernel rule for table {} (want local, main or IPv4 default)",
+                t
+            )?,
+            Self::InvalidNetns(n) => write!(
+                f,
+                "invalid network namespace {} (want name as in \"ip netns\")",
+                n
+            )?,
+            Self::InvalidProtocol(p) => write!(
+                f,
+                "invalid protocol {} (want name as in /etc/iproute2/rt_protos or 0-255)",
+                p
+            )?,
+            Self::InvalidVersion(v) => write!(
+                f,
+                "invalid version: {} (want \"rule\", \"rule4\", \"rule6\" or \"kernel\")",
+                v
+            )?,
+            Self::NoAction => write!(f, "missing action (\"action\" attribute)")?,
+            Self::Line(line, e) => write!(f, "line {}: {}", line, e)?,
+            Self::Netns(e) => write!(f, "{}", e)?,
+            Self::NoAttrValue(a) => write!(f, "missing value for attribute {}", a)?,
+            Self::NoCmd => write!(f, "missing command (want \"add\" or \"del\")")?,
+            Self::NoKernelTable => write!(f, "missing routing table (\"table\" attribute)")?,
+            Self::NoPriority => write!(f, "missing priority (\"pref\" attribute)")?,
+            Self::NoTable => write!(
+                f,
+                "action to_table without routing table (\"table\" attribute)"
+            )?,
+            Self::NoVersion => write!(
+                f,
+                "missing version (want \"rule\", \"rule4\", \"rule6\" or \"kernel\")"
+            )?,
+            Self::ParseAddr(e) => write!(f, "parse network address: {}", e)?,
+            Self::ParseBool(e) => write!(f, "parse bool: {}", e)?,
+            Self::ParseInt(e) => write!(f, "parse integer: {}", e)?,
+            Self::SrcIllegal => write!(f, "protocol-agnostic rule with source prefix")?,
+            Self::SrcNotIpv4 => write!(f, "rule4 with non-IPv4 source")?,
+            Self::SrcNotIpv6 => write!(f, "rule6 with non-IPv6 source")?,
+            Self::Var(e) => write!(f, "variable: {}", e)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl From<std::net::AddrParseError> for RuleParseError {
+    fn from(e: std::net::AddrParseError) -> RuleParseError {
+        RuleParseError::ParseAddr(e)
+    }
+}
+
+impl From<std::str::ParseBoolError> for RuleParseError {
+    fn from(e: std::str::ParseBoolError) -> RuleParseError {
+        RuleParseError::ParseBool(e)
+    }
+}
+
+impl From<std::num::ParseIntError> for RuleParseError {
+    fn from(e: std::num::ParseIntError) -> RuleParseError {
+        RuleParseError::ParseInt(e)
+    }
+}
+
+impl From<crate::netns::BlockError> for RuleParseError {
+    fn from(e: crate::netns::BlockError) -> RuleParseError {
+        RuleParseError::Netns(e)
+    }
+}
+
+impl From<crate::AttrError> for RuleParseError {
+    fn from(e: crate::AttrError) -> RuleParseError {
+        match e {
+            crate::AttrError::Duplicate(attr) => RuleParseError::DuplicateAttr(attr),
+            crate::AttrError::NoValue(attr) => RuleParseError::NoAttrValue(attr),
+        }
+    }
+}
+
+impl From<vars::VarError> for RuleParseError {
+    fn from(e: vars::VarError) -> RuleParseError {
+        RuleParseError::Var(e)
+    }
+}
+
+impl RuleParseError {
+    /// Returns the (1-based) line the error occurred on if parsing a whole file.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Self::Line(line, _) => Some(*line),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the offending attribute if the error is specific to one.
+    pub fn attr(&self) -> Option<&str> {
+        match self {
+            Self::DuplicateAttr(a) | Self::InvalidAttr(a) | Self::NoAttrValue(a) => Some(a),
+            Self::Line(_, e) => e.attr(),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for RuleParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Line(_, e) => Some(e.as_ref()),
+            Self::ParseAddr(e) => Some(e),
+            Self::ParseBool(e) => Some(e),
+            Self::ParseInt(e) => Some(e),
+            Self::Var(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleVersion {
+    #[default]
+    Both,
+    Ipv4,
+    Ipv6,
+}
+
+/// A single line of the policy rule configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Rule {
+    pub delete: bool,
+    pub version: RuleVersion,
+    pub invert: bool,
+    pub fwmark: Option<u32>,
+    pub dst: Option<(IpAddr, u8)>,
+    pub src: Option<(IpAddr, u8)>,
+    #[serde(with = "action")]
+    pub action: RuleAction,
+    pub table: u32,
+    /// The network namespace the rule is installed in, rtd's own if unset.
+    pub netns: Option<String>,
+    /// The protocol (`FRA_PROTOCOL`) the rule is tagged with to tell
+    /// who installed it, [`rtnl::RTPROT_RTD`] unless configured otherwise.
+    #[serde(default = "own_protocol")]
+    pub protocol: u8,
+    /// What to do when the rule drifts from the configuration,
+    /// the `drift` setting if unset.
+    #[serde(default)]
+    pub drift: Option<Drift>,
+    /// Whether rtd keeps the rule as configured. An unmanaged rule
+    /// is installed once, it is never deleted, restored or reloaded after that.
+    #[serde(default = "crate::managed_by_default")]
+    pub managed: bool,
+    #[serde(default)]
+    pub line: usize,
+    pub template: Option<String>,
+    /// The trailing comment of the configuration line, shown in the log.
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+fn own_protocol() -> u8 {
+    rtnl::RTPROT_RTD
+}
+
+impl Rule {
+    /// Returns the IPv4 half of a protocol-agnostic rule.
+    fn both_v4(&self) -> rsdsl_netlinklib::rule::Rule<Ipv4Addr> {
+        rsdsl_netlinklib::rule::Rule {
+            invert: self.invert,
+            fwmark: self.fwmark,
+            dst: None,
+            src: None,
+            action: self.action,
+            table: self.table,
+        }
+    }
+
+    /// Returns the IPv6 half of a protocol-agnostic rule.
+    fn both_v6(&self) -> rsdsl_netlinklib::rule::Rule<Ipv6Addr> {
+        rsdsl_netlinklib::rule::Rule {
+            invert: self.invert,
+            fwmark: self.fwmark,
+            dst: None,
+            src: None,
+            action: self.action,
+            table: self.table,
+        }
+    }
+
+    /// Splits a protocol-agnostic rule into its IPv4 and IPv6 halves,
+    /// returning `None` for rules restricted to one address family.
+    pub fn halves(&self) -> Option<(Rule, Rule)> {
+        match self.version {
+            RuleVersion::Both => Some((
+                Rule {
+                    version: RuleVersion::Ipv4,
+                    ..self.clone()
+                },
+                Rule {
+                    version: RuleVersion::Ipv6,
+                    ..self.clone()
+                },
+            )),
+            RuleVersion::Ipv4 | RuleVersion::Ipv6 => None,
+        }
+    }
+
+    /// Installs the rule, for both address families unless restricted to one.
+    /// Both halves of a protocol-agnostic rule are installed or neither is.
+    ///
+    /// Tagged rules are installed via [`rtnl`] as netlinklib can't set
+    /// their protocol, in the network namespace of the calling thread
+    /// rather than that of `c`.
+    pub fn blocking_add(self, c: &Connection) -> Result<(), SetupError> {
+        if self.protocol != rtnl::RTPROT_UNSPEC {
+            return self.blocking_add_tagged(c);
+        }
+
+        match self.version {
+            RuleVersion::Both => {
+                self.both_v4().blocking_add(c)?;
+
+                // Never leave half a policy behind.
+                if let Err(e) = self.both_v6().blocking_add(c) {
+                    return match self.both_v4().blocking_del(c) {
+                        Ok(()) => Err(e.into()),
+                        Err(_) => Err(SetupError::HalfApplied(Box::new(e.into()))),
+                    };
+                }
+            }
+            RuleVersion::Ipv4 => {
+                let rule = rsdsl_netlinklib::rule::Rule::<Ipv4Addr> {
+                    invert: self.invert,
+                    fwmark: self.fwmark,
+                    dst: self.dst.map(|dst| {
+                        if let (IpAddr::V4(addr), cidr) = dst {
+                            (addr, cidr)
+                        } else {
+                            unreachable!()
+                        }
+                    }),
+                    src: self.src.map(|src| {
+                        if let (IpAddr::V4(addr), cidr) = src {
+                            (addr, cidr)
+                        } else {
+                            unreachable!()
+                        }
+                    }),
+                    action: self.action,
+                    table: self.table,
+                };
+                crate::retry(|| Ok(rule.clone().blocking_add(c)?))?;
+            }
+            RuleVersion::Ipv6 => {
+                let rule = rsdsl_netlinklib::rule::Rule::<Ipv6Addr> {
+                    invert: self.invert,
+                    fwmark: self.fwmark,
+                    dst: self.dst.map(|dst| {
+                        if let (IpAddr::V6(addr), cidr) = dst {
+                            (addr, cidr)
+                        } else {
+                            unreachable!()
+                        }
+                    }),
+                    src: self.src.map(|src| {
+                        if let (IpAddr::V6(addr), cidr) = src {
+                            (addr, cidr)
+                        } else {
+                            unreachable!()
+                        }
+                    }),
+                    action: self.action,
+                    table: self.table,
+                };
+                crate::retry(|| Ok(rule.clone().blocking_add(c)?))?;
+            }
+        };
+
+        Ok(())
+    }
+
+    fn blocking_add_tagged(self, c: &Connection) -> Result<(), SetupError> {
+        let add = |family: i32| {
+            let req = self.request(family as u8);
+            crate::retry(|| Ok(rtnl::Socket::new()?.add_rule(&req)?))
+        };
+
+        match self.version {
+            RuleVersion::Both => {
+                add(libc::AF_INET)?;
+
+                // Never leave half a policy behind.
+                if let Err(e) = add(libc::AF_INET6) {
+                    return match self.both_v4().blocking_del(c) {
+                        Ok(()) => Err(e),
+                        Err(_) => Err(SetupError::HalfApplied(Box::new(e))),
+                    };
+                }
+
+                Ok(())
+            }
+            RuleVersion::Ipv4 => add(libc::AF_INET),
+            RuleVersion::Ipv6 => add(libc::AF_INET6),
+        }
+    }
+
+    /// Builds the `RTM_NEWRULE` request for one address family of the rule.
+    fn request(&self, family: u8) -> Vec<u8> {
+        let flags = if self.invert {
+            rtnl::FIB_RULE_INVERT
+        } else {
+            0
+        };
+        let dst_len = self.dst.map(|(_, len)| len).unwrap_or_default();
+        let src_len = self.src.map(|(_, len)| len).unwrap_or_default();
+        let action = crate::backend::action_type(self.action);
+        let mut req = rtnl::rtmsg(family, dst_len, src_len, 0, action, flags);
+
+        if let Some(fwmark) = self.fwmark {
+            rtnl::put_attr(&mut req, rtnl::FRA_FWMARK, &fwmark.to_ne_bytes());
+        }
+        if let Some((addr, _)) = self.dst {
+            rtnl::put_addr(&mut req, rtnl::FRA_DST, addr);
+        }
+        if let Some((addr, _)) = self.src {
+            rtnl::put_addr(&mut req, rtnl::FRA_SRC, addr);
+        }
+        if let Some(table) = self.lookup() {
+            rtnl::put_attr(&mut req, rtnl::FRA_TABLE, &table.to_ne_bytes());
+        }
+        rtnl::put_attr(&mut req, rtnl::FRA_PROTOCOL, &[self.protocol]);
+
+        req
+    }
+
+    /// Removes the rule, for both address families unless restricted to one.
+    pub fn blocking_del(self, c: &Connection) -> Result<(), SetupError> {
+        match self.version {
+            RuleVersion::Both => {
+                // Remove whatever half exists.
+                let v4 = self.both_v4().blocking_del(c);
+                let v6 = self.both_v6().blocking_del(c);
+                v4.and(v6)?;
+            }
+            RuleVersion::Ipv4 => {
+                let rule = rsdsl_netlinklib::rule::Rule::<Ipv4Addr> {
+                    invert: self.invert,
+                    fwmark: self.fwmark,
+                    dst: self.dst.map(|dst| {
+                        if let (IpAddr::V4(addr), cidr) = dst {
+                            (addr, cidr)
+                        } else {
+                            unreachable!()
+                        }
+                    }),
+                    src: self.src.map(|src| {
+                        if let (IpAddr::V4(addr), cidr) = src {
+                            (addr, cidr)
+                        } else {
+                            unreachable!()
+                        }
+                    }),
+                    action: self.action,
+                    table: self.table,
+                };
+                crate::retry(|| Ok(rule.clone().blocking_del(c)?))?;
+            }
+            RuleVersion::Ipv6 => {
+                let rule = rsdsl_netlinklib::rule::Rule::<Ipv6Addr> {
+                    invert: self.invert,
+                    fwmark: self.fwmark,
+                    dst: self.dst.map(|dst| {
+                        if let (IpAddr::V6(addr), cidr) = dst {
+                            (addr, cidr)
+                        } else {
+                            unreachable!()
+                        }
+                    }),
+                    src: self.src.map(|src| {
+                        if let (IpAddr::V6(addr), cidr) = src {
+                            (addr, cidr)
+                        } else {
+                            unreachable!()
+                        }
+                    }),
+                    action: self.action,
+                    table: self.table,
+                };
+                crate::retry(|| Ok(rule.clone().blocking_del(c)?))?;
+            }
+        };
+
+        Ok(())
+    }
+}
+
+impl Rule {
+    /// Describes the entry as configured, i.e. with placeholders intact.
+    pub fn label(&self) -> String {
+        match &self.template {
+            Some(template) => template.clone(),
+            None => self.to_string(),
+        }
+    }
+
+    /// Returns the rule with its line number cleared and the table
+    /// of actions other than `to_table` zeroed, so that equal rules
+    /// are identical field by field.
+    pub fn canonical(&self) -> Rule {
+        Rule {
+            table: self.lookup().unwrap_or(0),
+            line: 0,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the table the rule looks up, if that is its action.
+    fn lookup(&self) -> Option<u32> {
+        (self.action == RuleAction::ToTable).then_some(self.table)
+    }
+
+    /// Returns everything but the line number and unused tables,
+    /// neither of which changes what a rule does.
+    #[allow(clippy::type_complexity)]
+    fn key(
+        &self,
+    ) -> (
+        bool,
+        &RuleVersion,
+        bool,
+        Option<u32>,
+        Option<(IpAddr, u8)>,
+        Option<(IpAddr, u8)>,
+        RuleAction,
+        Option<u32>,
+        &Option<String>,
+        u8,
+        (Option<Drift>, bool),
+        &Option<String>,
+    ) {
+        (
+            self.delete,
+            &self.version,
+            self.invert,
+            self.fwmark,
+            self.dst,
+            self.src,
+            self.action,
+            self.lookup(),
+            &self.netns,
+            self.protocol,
+            (self.drift, self.managed),
+            &self.template,
+        )
+    }
+}
+
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Rule {}
+
+impl Hash for Rule {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// (De)serialization of [`RuleAction`]s by their configuration names.
+mod action {
+    use rsdsl_netlinklib::rule::RuleAction;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn name(action: RuleAction) -> String {
+        match action {
+            RuleAction::Unspec => "unspec".to_string(),
+            RuleAction::ToTable => "to_table".to_string(),
+            RuleAction::Goto => "goto".to_string(),
+            RuleAction::Nop => "nop".to_string(),
+            RuleAction::Blackhole => "blackhole".to_string(),
+            RuleAction::Unreachable => "unreachable".to_string(),
+            RuleAction::Prohibit => "prohibit".to_string(),
+            RuleAction::Other(a) => a.to_string(),
+            _ => "?".to_string(),
+        }
+    }
+
+    pub fn serialize<S: Serializer>(action: &RuleAction, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&name(*action))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RuleAction, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        match name.as_str() {
+            "unspec" => Ok(RuleAction::Unspec),
+            "to_table" => Ok(RuleAction::ToTable),
+            "goto" => Ok(RuleAction::Goto),
+            "nop" => Ok(RuleAction::Nop),
+            "blackhole" => Ok(RuleAction::Blackhole),
+            "unreachable" => Ok(RuleAction::Unreachable),
+            "prohibit" => Ok(RuleAction::Prohibit),
+            _ => name
+                .parse()
+                .map(RuleAction::Other)
+                .map_err(|_| de::Error::custom(format!("invalid action {}", name))),
+        }
+    }
+}
+
+/// The alternate form (`{:#}`) includes the comment of the configuration line.
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.version {
+            RuleVersion::Both => write!(f, "rule")?,
+            RuleVersion::Ipv4 => write!(f, "rule4")?,
+            RuleVersion::Ipv6 => write!(f, "rule6")?,
+        }
+        if self.invert {
+            write!(f, " invert true")?;
+        }
+        if let Some(fwmark) = self.fwmark {
+            write!(f, " fwmark {}", fwmark)?;
+        }
+        if let Some(dst) = self.dst {
+            write!(f, " dst {}/{}", dst.0, dst.1)?;
+        }
+        if let Some(src) = self.src {
+            write!(f, " src {}/{}", src.0, src.1)?;
+        }
+        write!(f, " action {}", action::name(self.action))?;
+        if self.action == RuleAction::ToTable {
+            write!(f, " table {}", self.table)?;
+        }
+        if let Some(netns) = &self.netns {
+            write!(f, " netns {}", netns)?;
+        }
+        if self.protocol != rtnl::RTPROT_RTD {
+            write!(f, " protocol {}", rtnl::protocol_name(self.protocol))?;
+        }
+        if let Some(drift) = self.drift {
+            write!(f, " drift {}", drift)?;
+        }
+        if !self.managed {
+            write!(f, " managed false")?;
+        }
+        if let Some(comment) = self.comment.as_ref().filter(|_| f.alternate()) {
+            write!(f, " # {}", comment)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Constructs a rule programmatically,
+/// applying the same checks as the configuration parser.
+#[derive(Clone, Debug)]
+pub struct RuleBuilder {
+    version: RuleVersion,
+    delete: bool,
+    invert: bool,
+    fwmark: Option<u32>,
+    dst: Option<(IpAddr, u8)>,
+    src: Option<(IpAddr, u8)>,
+    action: Option<RuleAction>,
+    table: Option<u32>,
+    netns: Option<String>,
+    protocol: Option<u8>,
+    drift: Option<Drift>,
+    managed: bool,
+}
+
+impl RuleBuilder {
+    fn new(version: RuleVersion) -> Self {
+        Self {
+            version,
+            delete: false,
+            invert: false,
+            fwmark: None,
+            dst: None,
+            src: None,
+            action: None,
+            table: None,
+            netns: None,
+            protocol: None,
+            drift: None,
+            managed: true,
+        }
+    }
+
+    /// Starts a rule for both IPv4 and IPv6.
+    pub fn both() -> Self {
+        Self::new(RuleVersion::Both)
+    }
+
+    /// Starts an IPv4 rule.
+    pub fn v4() -> Self {
+        Self::new(RuleVersion::Ipv4)
+    }
+
+    /// Starts an IPv6 rule.
+    pub fn v6() -> Self {
+        Self::new(RuleVersion::Ipv6)
+    }
+
+    /// Makes the entry remove the rule instead of installing it.
+    pub fn delete(mut self) -> Self {
+        self.delete = true;
+        self
+    }
+
+    pub fn invert(mut self, invert: bool) -> Self {
+        self.invert = invert;
+        self
+    }
+
+    pub fn fwmark(mut self, fwmark: u32) -> Self {
+        self.fwmark = Some(fwmark);
+        self
+    }
+
+    pub fn dst(mut self, addr: impl Into<IpAddr>, prefix_len: u8) -> Self {
+        self.dst = Some((addr.into(), prefix_len));
+        self
+    }
+
+    pub fn src(mut self, addr: impl Into<IpAddr>, prefix_len: u8) -> Self {
+        self.src = Some((addr.into(), prefix_len));
+        self
+    }
+
+    pub fn action(mut self, action: RuleAction) -> Self {
+        self.action = Some(action);
+        self
+    }
+
+    pub fn table(mut self, table: u32) -> Self {
+        self.table = Some(table);
+        self
+    }
+
+    /// Installs the rule in another network namespace, see [`crate::netns`].
+    pub fn netns(mut self, name: impl Into<String>) -> Self {
+        self.netns = Some(name.into());
+        self
+    }
+
+    /// Tags the rule with another protocol than rtd's own,
+    /// [`rtnl::RTPROT_UNSPEC`] for none.
+    pub fn protocol(mut self, protocol: u8) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Overrides the `drift` setting for the rule.
+    pub fn drift(mut self, drift: Drift) -> Self {
+        self.drift = Some(drift);
+        self
+    }
+
+    /// Hands the rule over to someone else once it is installed.
+    pub fn managed(mut self, managed: bool) -> Self {
+        self.managed = managed;
+        self
+    }
+
+    pub fn build(self) -> Result<Rule, RuleParseError> {
+        let (dst, src) = match self.version {
+            RuleVersion::Both => (
+                if self.dst.is_some() {
+                    return Err(RuleParseError::DstIllegal);
+                } else {
+                    None
+                },
+                if self.src.is_some() {
+                    return Err(RuleParseError::SrcIllegal);
+                } else {
+                    None
+                },
+            ),
+            RuleVersion::Ipv4 => (
+                match self.dst {
+                    Some((IpAddr::V4(dst), cidr)) => Some((IpAddr::V4(dst), cidr)),
+                    Some(_) => return Err(RuleParseError::DstNotIpv4),
+                    None => None,
+                },
+                match self.src {
+                    Some((IpAddr::V4(src), cidr)) => Some((IpAddr::V4(src), cidr)),
+                    Some(_) => return Err(RuleParseError::SrcNotIpv4),
+                    None => None,
+                },
+            ),
+            RuleVersion::Ipv6 => (
+                match self.dst {
+                    Some((IpAddr::V6(dst), cidr)) => Some((IpAddr::V6(dst), cidr)),
+                    Some(_) => return Err(RuleParseError::DstNotIpv6),
+                    None => None,
+                },
+                match self.src {
+                    Some((IpAddr::V6(src), cidr)) => Some((IpAddr::V6(src), cidr)),
+                    Some(_) => return Err(RuleParseError::SrcNotIpv6),
+                    None => None,
+                },
+            ),
+        };
+
+        // Nothing may change an unmanaged rule once it is installed.
+        if self.delete && !self.managed {
+            return Err(RuleParseError::Conflict("managed false", "del"));
+        }
+
+        // Table 0 means unspecified to the kernel.
+        let action = self.action.ok_or(RuleParseError::NoAction)?;
+        if action == RuleAction::ToTable && self.table.is_none_or(|table| table == 0) {
+            return Err(RuleParseError::NoTable);
+        }
+
+        Ok(Rule {
+            delete: self.delete,
+            version: self.version,
+            invert: self.invert,
+            fwmark: self.fwmark,
+            dst,
+            src,
+            action,
+            table: self.table.unwrap_or_default(),
+            netns: self.netns,
+            protocol: self.protocol.unwrap_or(rtnl::RTPROT_RTD),
+            drift: self.drift,
+            managed: self.managed,
+            line: 0,
+            template: None,
+            comment: None,
+        })
+    }
+}
+
+impl FromStr for Rule {
+    type Err = RuleParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // A leading "not" (as in iproute2) inverts the rule, the attributes follow it.
+        let not = s
+            .split_whitespace()
+            .nth(2)
+            .is_some_and(|word| word.eq_ignore_ascii_case("not"));
+        let s = crate::lowercase_keywords(s, if not { 3 } else { 2 });
+        let mut words = s.split_whitespace();
+
+        let version_str = words.next().ok_or(RuleParseError::NoVersion)?;
+        let mut builder = match version_str {
+            "rule" => RuleBuilder::both(),
+            "rule4" => RuleBuilder::v4(),
+            "rule6" => RuleBuilder::v6(),
+            _ => return Err(RuleParseError::InvalidVersion(version_str.to_string())),
+        };
+
+        let cmd = words.next().ok_or(RuleParseError::NoCmd)?;
+        match cmd {
+            "add" => {}
+            "del" => builder = builder.delete(),
+            _ => return Err(RuleParseError::InvalidCmd(cmd.to_string())),
+        }
+
+        if not {
+            words.next();
+        }
+
+        let mut attrs = crate::attr_pairs(words.clone(), |attr| match attr {
+            "lookup" => "table",
+            attr => attr,
+        })?;
+        if not {
+            if attrs.iter().any(|(a, _)| *a == "invert") {
+                return Err(RuleParseError::DuplicateAttr("invert".to_string()));
+            }
+            attrs.insert(0, ("invert", "true"));
+        }
+
+        // Looking up a table implies the action, as in iproute2.
+        let lookup = words.step_by(2).any(|attr| attr == "lookup");
+        if lookup && !attrs.iter().any(|(a, _)| *a == "action") {
+            attrs.push(("action", "to_table"));
+        }
+
+        for (attr, value) in attrs {
+            builder = match attr {
+                "invert" => builder.invert(value.parse()?),
+                "fwmark" => builder.fwmark(crate::parse_u32(value)?),
+                "dst" => {
+                    let (addr, cidr) = parse_cidr(value)?;
+                    builder.dst(addr, cidr)
+                }
+                "src" => {
+                    let (addr, cidr) = parse_cidr(value)?;
+                    builder.src(addr, cidr)
+                }
+                "action" => match value {
+                    "to_table" => builder.action(RuleAction::ToTable),
+                    "blackhole" => builder.action(RuleAction::Blackhole),
+                    "unreachable" => builder.action(RuleAction::Unreachable),
+                    "prohibit" => builder.action(RuleAction::Prohibit),
+                    a => return Err(RuleParseError::InvalidAction(a.to_string())),
+                },
+                "table" => builder.table(crate::parse_table(value)?),
+                "netns" if !crate::netns::is_valid_name(value) => {
+                    return Err(RuleParseError::InvalidNetns(value.to_string()))
+                }
+                "netns" => builder.netns(value),
+                "protocol" => builder.protocol(
+                    rtnl::parse_protocol(value)
+                        .ok_or(RuleParseError::InvalidProtocol(value.to_string()))?,
+                ),
+                "drift" => builder.drift(
+                    value
+                        .parse()
+                        .map_err(|_| RuleParseError::InvalidDrift(value.to_string()))?,
+                ),
+                "managed" => builder.managed(value.parse()?),
+                _ => return Err(RuleParseError::InvalidAttr(attr.to_string())),
+            };
+        }
+
+        builder.build()
+    }
+}
+
+fn parse_cidr(value: &str) -> Result<(IpAddr, u8), RuleParseError> {
+    let mut prefix = value.split('/');
+
+    let addr = prefix
+        .next()
+        .ok_or(RuleParseError::InvalidCidr(value.to_string()))?;
+    let cidr = prefix
+        .next()
+        .ok_or(RuleParseError::InvalidCidr(value.to_string()))?;
+
+    if prefix.next().is_some() {
+        return Err(RuleParseError::InvalidCidr(value.to_string()));
+    }
+
+    Ok((addr.parse()?, cidr.parse()?))
+}
+
+/// A parsed policy rule configuration file.
+#[derive(Debug, Default)]
+pub struct Rules {
+    pub rules: Vec<Rule>,
+    pub kernel_rules: Vec<KernelRule>,
+}
+
+impl FromStr for Rules {
+    type Err = RuleParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lines = crate::netns::lines(s)
+            .map_err(|(line, e)| RuleParseError::Line(line, Box::new(e.into())))?;
+        let (kernel_lines, rule_lines): (Vec<_>, Vec<_>) = lines
+            .into_iter()
+            .partition(|line| line.version().is_some_and(|v| v.starts_with("kernel")));
+
+        let kernel_rules = kernel_lines
+            .into_iter()
+            .map(|line| {
+                let i = line.number - 1;
+                // Kernel rules only exist in rtd's own namespace.
+                if let Some(version) = line.version().filter(|_| line.netns.is_some()) {
+                    let e = crate::netns::BlockError::Unsupported(version.into_owned());
+                    return Err(RuleParseError::Line(i + 1, Box::new(e.into())));
+                }
+
+                line.text
+                    .parse::<KernelRule>()
+                    .map(|rule| KernelRule {
+                        line: i + 1,
+                        ..rule
+                    })
+                    .map_err(|e| RuleParseError::Line(i + 1, Box::new(e)))
+            })
+            .collect::<Result<Vec<_>, Self::Err>>()?;
+
+        let rules = rule_lines
+            .into_iter()
+            .map(|line| {
+                let i = line.number - 1;
+                let scoped = line.scoped();
+                let l = &*scoped;
+
+                // Lines with placeholders are resolved at apply time,
+                // check their syntax using stand-in values for now.
+                let template = vars::has_vars(l).then(|| l.to_string());
+                let parsed = match template {
+                    Some(_) => vars::expand(l, &vars::Vars::placeholders())
+                        .map_err(RuleParseError::from)
+                        .and_then(|l| l.parse::<Rule>()),
+                    None => l.parse::<Rule>(),
+                };
+
+                parsed
+                    .and_then(|rule| {
+                        // Resolved values are only kept up to date in rtd's own namespace.
+                        if template.is_some() && rule.netns.is_some() {
+                            return Err(RuleParseError::Conflict("netns", "placeholders"));
+                        }
+
+                        Ok(Rule {
+                            line: i + 1,
+                            template,
+                            comment: line.comment.map(String::from),
+                            ..rule
+                        })
+                    })
+                    .map_err(|e| RuleParseError::Line(i + 1, Box::new(e)))
+            })
+            .collect::<Result<Vec<Rule>, Self::Err>>()?;
+
+        Ok(Self {
+            rules,
+            kernel_rules,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a single configuration line, checking that it parses
+    /// the same when written the way it is shown, and returns that.
+    fn round_trip(line: &str) -> String {
+        let rules: Rules = line.parse().unwrap_or_else(|e| panic!("{}: {}", line, e));
+        let shown = format!("{:#}", rules.rules[0]);
+
+        let (version, attrs) = shown.split_once(' ').unwrap();
+        let again: Rules = format!("{} add {}", version, attrs)
+            .parse()
+            .unwrap_or_else(|e| panic!("{}: {}", shown, e));
+        assert_eq!(format!("{:#}", again.rules[0]), shown);
+
+        shown
+    }
+
+    fn parse_err(line: &str) -> RuleParseError {
+        match line.parse::<Rule>() {
+            Ok(rule) => panic!("{}: parsed as {}", line, rule),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn to_table_needs_table() {
+        assert_eq!(
+            round_trip("rule4 add fwmark 5 action to_table table 100"),
+            "rule4 fwmark 5 action to_table table 100"
+        );
+        assert_eq!(
+            round_trip("rule4 add fwmark 5 action blackhole"),
+            "rule4 fwmark 5 action blackhole"
+        );
+        assert!(matches!(
+            parse_err("rule4 add fwmark 5 action to_table"),
+            RuleParseError::NoTable
+        ));
+        // Table 0 means unspecified to the kernel.
+        assert!(matches!(
+            parse_err("rule4 add fwmark 5 action to_table table 0"),
+            RuleParseError::NoTable
+        ));
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive() {
+        assert_eq!(
+            round_trip("RULE4 Add FwMark 5 ACTION to_table Table 100"),
+            "rule4 fwmark 5 action to_table table 100"
+        );
+    }
+
+    #[test]
+    fn lookup_and_not() {
+        assert_eq!(
+            round_trip("rule6 add not src 2001:db8::/32 lookup 100"),
+            "rule6 invert true src 2001:db8::/32 action to_table table 100"
+        );
+        assert_eq!(
+            round_trip("rule4 add fwmark 5 lookup 100"),
+            round_trip("rule4 add fwmark 5 action to_table table 100")
+        );
+
+        // `lookup` is the table, `not` the inversion.
+        assert!(matches!(
+            parse_err("rule4 add fwmark 5 lookup 100 table 200"),
+            RuleParseError::DuplicateAttr(attr) if attr == "table"
+        ));
+        assert!(matches!(
+            parse_err("rule4 add not fwmark 5 invert true lookup 100"),
+            RuleParseError::DuplicateAttr(attr) if attr == "invert"
+        ));
+        assert!(matches!(
+            parse_err("rule4 add fwmark 5 lookup"),
+            RuleParseError::NoAttrValue(attr) if attr == "table"
+        ));
+    }
+
+    #[test]
+    fn hex_marks() {
+        for fwmark in ["0x10", "0X10", "16"] {
+            assert_eq!(
+                round_trip(&format!(
+                    "rule4 add fwmark {} table 0x64 action to_table",
+                    fwmark
+                )),
+                "rule4 fwmark 16 action to_table table 100"
+            );
+        }
+        assert!(matches!(
+            parse_err("rule4 add fwmark 0xg action to_table table 100"),
+            RuleParseError::ParseInt(_)
+        ));
+    }
+
+    #[test]
+    fn table_names() {
+        for (name, table) in [("main", 254), ("local", 255), ("default", 253)] {
+            assert_eq!(
+                round_trip(&format!("rule add fwmark 5 lookup {}", name)),
+                format!("rule fwmark 5 action to_table table {}", table)
+            );
+        }
+        assert!(matches!(
+            parse_err("rule add fwmark 5 lookup nonexistent"),
+            RuleParseError::ParseInt(_)
+        ));
+    }
+
+    #[test]
+    fn registered_table_names() {
+        crate::register_table_names([("vpn".to_string(), 100)]);
+
+        assert_eq!(
+            round_trip("rule add fwmark 5 lookup vpn"),
+            "rule fwmark 5 action to_table table 100"
+        );
+        assert_eq!(
+            "route4 add to 10.1.0.0/16 dev wg0 table vpn"
+                .parse::<crate::Route>()
+                .unwrap()
+                .to_string(),
+            "route4 10.1.0.0/16 table 100 dev wg0"
+        );
+    }
+
+    #[test]
+    fn netns() {
+        assert_eq!(
+            round_trip("rule4 add fwmark 5 lookup 100 netns box"),
+            "rule4 fwmark 5 action to_table table 100 netns box"
+        );
+    }
+
+    #[test]
+    fn netns_blocks() {
+        let rules: Rules = "netns box {\n\
+            \x20   rule4 add fwmark 5 lookup 100\n\
+            }\n\
+            rule4 add fwmark 6 lookup 100\n"
+            .parse()
+            .unwrap();
+
+        let shown: Vec<String> = rules.rules.iter().map(Rule::to_string).collect();
+        assert_eq!(
+            shown,
+            [
+                "rule4 fwmark 5 action to_table table 100 netns box",
+                "rule4 fwmark 6 action to_table table 100",
+            ]
+        );
+    }
+
+    #[test]
+    fn serde() {
+        let rules: Rules = "rule6 add not src 2001:db8::/32 lookup 100\n\
+            rule add fwmark 5 action blackhole\n"
+            .parse()
+            .unwrap();
+
+        for rule in &rules.rules {
+            let json = serde_json::to_string(rule).unwrap();
+            let stored: Rule = serde_json::from_str(&json).unwrap();
+            assert_eq!(stored, *rule);
+        }
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            round_trip("rule4 add fwmark 5 lookup 100 # vpn #1"),
+            "rule4 fwmark 5 action to_table table 100 # vpn #1"
+        );
+    }
+
+    #[test]
+    fn protocols() {
+        let rule: Rule = "rule4 add fwmark 5 lookup 100".parse().unwrap();
+        assert_eq!(rule.protocol, rtnl::RTPROT_RTD);
+
+        assert_eq!(
+            round_trip("rule4 add fwmark 5 lookup 100 protocol 99"),
+            "rule4 fwmark 5 action to_table table 100 protocol 99"
+        );
+        assert_eq!(
+            round_trip("rule4 add fwmark 5 lookup 100 protocol static"),
+            "rule4 fwmark 5 action to_table table 100 protocol static"
+        );
+        assert!(matches!(
+            parse_err("rule4 add fwmark 5 lookup 100 protocol 256"),
+            RuleParseError::InvalidProtocol(protocol) if protocol == "256"
+        ));
+    }
+
+    #[test]
+    fn drift_policies() {
+        assert_eq!(
+            round_trip("rule4 add fwmark 5 lookup 100 drift repair"),
+            "rule4 fwmark 5 action to_table table 100 drift repair"
+        );
+        assert!(matches!(
+            parse_err("rule4 add fwmark 5 lookup 100 drift fix"),
+            RuleParseError::InvalidDrift(drift) if drift == "fix"
+        ));
+    }
+
+    #[test]
+    fn unmanaged() {
+        assert_eq!(
+            round_trip("rule4 add fwmark 5 lookup 100 managed false"),
+            "rule4 fwmark 5 action to_table table 100 managed false"
+        );
+        assert!(matches!(
+            parse_err("rule4 del fwmark 5 lookup 100 managed false"),
+            RuleParseError::Conflict("managed false", "del")
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+//! Daily time windows during which an entry is installed.
+
+use crate::RouteParseError;
+
+use std::fmt;
+use std::mem;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// The `schedule` attribute: a comma separated list of daily windows
+/// in local time, e.g. `01:00-05:00` or `22:00-06:00,12:00-13:00`.
+/// Windows ending before they start extend past midnight.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Schedule {
+    /// Start and end of the windows in minutes since midnight.
+    windows: Vec<(u16, u16)>,
+}
+
+impl Schedule {
+    /// Reports whether the given time (in minutes since midnight)
+    /// falls into any of the windows.
+    pub fn is_active_at(&self, minute: u16) -> bool {
+        self.windows.iter().any(|(start, end)| {
+            if start <= end {
+                (*start..*end).contains(&minute)
+            } else {
+                minute >= *start || minute < *end
+            }
+        })
+    }
+
+    /// Reports whether the current local time falls into any of the windows.
+    pub fn is_active(&self) -> bool {
+        self.is_active_at(local_minute())
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (start, end)) in self.windows.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(
+                f,
+                "{:02}:{:02}-{:02}:{:02}",
+                start / 60,
+                start % 60,
+                end / 60,
+                end % 60
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for Schedule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::text::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Schedule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::text::deserialize(deserializer)
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || RouteParseError::InvalidSchedule(s.to_string());
+
+        let windows = s
+            .split(',')
+            .map(|window| {
+                let (start, end) = window.split_once('-').ok_or_else(invalid)?;
+                let start = parse_time(start).ok_or_else(invalid)?;
+                let end = parse_time(end).ok_or_else(invalid)?;
+
+                if start == end {
+                    return Err(invalid());
+                }
+
+                Ok((start, end))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { windows })
+    }
+}
+
+/// Parses `HH:MM` into minutes since midnight.
+fn parse_time(s: &str) -> Option<u16> {
+    let (hour, minute) = s.split_once(':')?;
+    let hour: u16 = hour.parse().ok()?;
+    let minute: u16 = minute.parse().ok()?;
+
+    (hour < 24 && minute < 60).then_some(hour * 60 + minute)
+}
+
+/// Returns the current local time in minutes since midnight.
+fn local_minute() -> u16 {
+    // SAFETY: time(2) accepts a null pointer.
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+
+    // SAFETY: tm is plain old data, all zeroes is a valid value.
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    // SAFETY: now and tm are valid for the duration of the call.
+    if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+        // Fall back to UTC, which is what most routers use anyway.
+        return ((now % 86400) / 60) as u16 % MINUTES_PER_DAY;
+    }
+
+    (tm.tm_hour * 60 + tm.tm_min) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows() {
+        let schedule: Schedule = "22:00-06:00,12:00-13:30".parse().unwrap();
+        assert_eq!(schedule.to_string(), "22:00-06:00,12:00-13:30");
+
+        // Windows ending before they start span midnight.
+        assert!(schedule.is_active_at(23 * 60));
+        assert!(schedule.is_active_at(5 * 60 + 59));
+        assert!(!schedule.is_active_at(6 * 60));
+        assert!(schedule.is_active_at(13 * 60 + 29));
+        assert!(!schedule.is_active_at(13 * 60 + 30));
+
+        for invalid in ["24:00-06:00", "22:00", "22:00-22:00", "8:60-9:00"] {
+            assert!(matches!(
+                invalid.parse::<Schedule>(),
+                Err(RouteParseError::InvalidSchedule(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! `self-test`: applies a built-in sample configuration inside a throwaway
+//! network namespace and checks the result against the kernel's tables.
+
+use crate::log;
+
+use rsdsl_rtd::rtnl::{self, RouteMsg, RuleMsg};
+use rsdsl_rtd::{
+    RouteDef, RouteParseError, Routes, Rule, RuleAction, RuleParseError, RuleVersion, Rules,
+    SetupError,
+};
+
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+
+use rsdsl_netlinklib::blocking::Connection;
+
+const LINK: &str = "rtdtest0";
+const PEER: &str = "rtdtest1";
+
+const MAIN_TABLE: u32 = 254;
+
+const SAMPLE_ROUTES: &str = "\
+route4 add to 198.51.100.0/24 dev rtdtest0
+route4 add to 203.0.113.0/24 via 192.0.2.1 onlink true table 100 metric 10 dev rtdtest0
+route6 add to 2001:db8:1::/48 via fe80::1 onlink true dev rtdtest0
+route6 add to 2001:db8:2::/48 table 100 dev rtdtest0";
+
+const SAMPLE_RULES: &str = "\
+rule4 add fwmark 1 action to_table table 100
+rule6 add src 2001:db8:3::/48 action blackhole
+rule add fwmark 2 invert true action unreachable";
+
+#[derive(Debug)]
+pub enum SelfTestError {
+    Netlink(io::Error),
+    Netns(io::Error),
+    ParseRoutes(RouteParseError),
+    ParseRules(RuleParseError),
+    Setup(SetupError),
+}
+
+impl fmt::Display for SelfTestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Netlink(e) => write!(f, "netlink: {}", e)?,
+            Self::Netns(e) => write!(f, "create network namespace: {}", e)?,
+            Self::ParseRoutes(e) => write!(f, "parse sample routes: {}", e)?,
+            Self::ParseRules(e) => write!(f, "parse sample rules: {}", e)?,
+            Self::Setup(e) => write!(f, "set up test links: {}", e)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl From<io::Error> for SelfTestError {
+    fn from(e: io::Error) -> SelfTestError {
+        SelfTestError::Netlink(e)
+    }
+}
+
+impl From<RouteParseError> for SelfTestError {
+    fn from(e: RouteParseError) -> SelfTestError {
+        SelfTestError::ParseRoutes(e)
+    }
+}
+
+impl From<RuleParseError> for SelfTestError {
+    fn from(e: RuleParseError) -> SelfTestError {
+        SelfTestError::ParseRules(e)
+    }
+}
+
+impl From<rsdsl_netlinklib::Error> for SelfTestError {
+    fn from(e: rsdsl_netlinklib::Error) -> SelfTestError {
+        SelfTestError::Setup(e.into())
+    }
+}
+
+impl std::error::Error for SelfTestError {}
+
+/// Runs the self-test, reporting whether all sample entries
+/// ended up in the kernel as configured.
+pub fn self_test() -> Result<bool, SelfTestError> {
+    // SAFETY: unshare(2) has no memory safety requirements.
+    if unsafe { libc::unshare(libc::CLONE_NEWNET) } < 0 {
+        return Err(SelfTestError::Netns(io::Error::last_os_error()));
+    }
+
+    let mut sock = rtnl::Socket::new()?;
+    sock.add_veth(LINK, PEER)?;
+
+    let conn = Connection::new()?;
+    // IPv4 gateway validation needs the local table, i.e. a working loopback.
+    conn.link_set("lo".to_string(), true)?;
+    conn.link_set(LINK.to_string(), true)?;
+    conn.link_set(PEER.to_string(), true)?;
+
+    let index = rtnl::link_index(LINK)?;
+
+    let routes: Routes = SAMPLE_ROUTES.parse()?;
+    let rules: Rules = SAMPLE_RULES.parse()?;
+
+    let mut passed = true;
+
+    for route in &routes.routes {
+        if let Err(e) = route.def.clone().blocking_add(&conn) {
+            log::error!(Netlink, "self-test: add {}: {}", route, e);
+            passed = false;
+        }
+    }
+    for rule in &rules.rules {
+        if let Err(e) = rule.clone().blocking_add(&conn) {
+            log::error!(Netlink, "self-test: add {}: {}", rule, e);
+            passed = false;
+        }
+    }
+
+    let kernel_routes = sock.dump_routes(libc::AF_UNSPEC as u8)?;
+    let kernel_rules = sock.dump_rules(libc::AF_UNSPEC as u8)?;
+
+    for route in &routes.routes {
+        if kernel_routes
+            .iter()
+            .any(|msg| route_matches(&route.def, index, msg))
+        {
+            log::info!(Netlink, "self-test: ok {}", route);
+        } else {
+            log::error!(Netlink, "self-test: missing {}", route);
+            passed = false;
+        }
+    }
+
+    for rule in &rules.rules {
+        let missing = families(&rule.version).iter().any(|family| {
+            !kernel_rules
+                .iter()
+                .any(|msg| rule_matches(rule, *family, msg))
+        });
+
+        if missing {
+            log::error!(Netlink, "self-test: missing {}", rule);
+            passed = false;
+        } else {
+            log::info!(Netlink, "self-test: ok {}", rule);
+        }
+    }
+
+    Ok(passed)
+}
+
+fn route_matches(def: &RouteDef, index: u32, msg: &RouteMsg) -> bool {
+    let (family, dst, prefix_len, rtr, table, metric) = match def {
+        RouteDef::V4(r) => (
+            libc::AF_INET,
+            IpAddr::V4(r.dst),
+            r.prefix_len,
+            r.rtr.map(IpAddr::V4),
+            r.table,
+            r.metric,
+        ),
+        RouteDef::V6(r) => (
+            libc::AF_INET6,
+            IpAddr::V6(r.dst),
+            r.prefix_len,
+            r.rtr.map(IpAddr::V6),
+            r.table,
+            r.metric,
+        ),
+    };
+
+    // The kernel omits the destination of default routes.
+    let msg_dst = msg.dst.or((msg.dst_len == 0).then_some(dst));
+
+    i32::from(msg.family) == family
+        && msg.dst_len == prefix_len
+        && msg_dst == Some(dst)
+        && msg.gateway == rtr
+        && msg.table == table.unwrap_or(MAIN_TABLE)
+        && metric.is_none_or(|metric| msg.metric == Some(metric))
+        && msg.oif == Some(index)
+}
+
+fn rule_matches(rule: &Rule, family: i32, msg: &RuleMsg) -> bool {
+    let action = match rule.action {
+        RuleAction::ToTable => rtnl::FR_ACT_TO_TBL,
+        RuleAction::Goto => rtnl::FR_ACT_GOTO,
+        RuleAction::Nop => rtnl::FR_ACT_NOP,
+        RuleAction::Blackhole => rtnl::FR_ACT_BLACKHOLE,
+        RuleAction::Unreachable => rtnl::FR_ACT_UNREACHABLE,
+        RuleAction::Prohibit => rtnl::FR_ACT_PROHIBIT,
+        _ => return false,
+    };
+
+    i32::from(msg.family) == family
+        && msg.action == action
+        && (action != rtnl::FR_ACT_TO_TBL || msg.table == rule.table)
+        && msg.fwmark == rule.fwmark
+        && msg.dst.map(|dst| (dst, msg.dst_len)) == rule.dst
+        && msg.src.map(|src| (src, msg.src_len)) == rule.src
+        && (msg.flags & rtnl::FIB_RULE_INVERT != 0) == rule.invert
+}
+
+fn families(version: &RuleVersion) -> &'static [i32] {
+    match version {
+        RuleVersion::Both => &[libc::AF_INET, libc::AF_INET6],
+        RuleVersion::Ipv4 => &[libc::AF_INET],
+        RuleVersion::Ipv6 => &[libc::AF_INET6],
+    }
+}
@@ -0,0 +1,179 @@
+//! Daemon settings (`/data/rtd.conf`): how rtd behaves rather than
+//! what it configures. Each line sets an option, all of them are optional:
+//!
+//! ```text
+//! # <option> <value>
+//! log-level info,netlink=debug
+//! retries 5
+//! retry-delay 100
+//! apply-order rules-first
+//! missing-link skip
+//! reconcile-interval 60
+//! drift log
+//! apply-timeout 120
+//! ```
+//!
+//! * `log-level`: the level filter as for `--log-level`.
+//! * `retries`: how often transient netlink errors are retried.
+//! * `retry-delay`: the milliseconds before the first retry, doubling with every further one.
+//! * `apply-order`: `routes-first` (default) or `rules-first`.
+//! * `missing-link`: `wait` (default) for the links of entries to appear
+//!   or `skip` the entries whose links don't exist.
+//! * `reconcile-interval`: the seconds between checks for configured entries
+//!   that went missing from the kernel, which are added back. Off by default.
+//! * `drift`: what reconciling does about entries that differ from the kernel,
+//!   `repair` (default) them, only `log` them or `ignore` them.
+//!   Entries can override it with their `drift` attribute.
+//! * `apply-timeout`: the seconds after which the initial apply pass counts
+//!   as done even if entries are still pending, e.g. waiting for links.
+//!   They are applied in the background. Unlimited by default.
+//!
+//! Command-line options and environment variables take precedence.
+
+use rsdsl_rtd::Drift;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const SETTINGS_PATH: &str = "/data/rtd.conf";
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// Whether routes or rules are applied first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ApplyOrder {
+    /// Rules only take effect once their tables are filled.
+    #[default]
+    RoutesFirst,
+    /// Traffic is steered right away, falling through empty tables.
+    RulesFirst,
+}
+
+impl FromStr for ApplyOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "routes-first" => Ok(Self::RoutesFirst),
+            "rules-first" => Ok(Self::RulesFirst),
+            _ => Err(format!(
+                "invalid apply order {} (want \"routes-first\" or \"rules-first\")",
+                s
+            )),
+        }
+    }
+}
+
+/// What happens to entries whose link doesn't exist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissingLink {
+    /// Wait for the link, holding up the entries after it.
+    #[default]
+    Wait,
+    /// Fail the entry and go on with the next one.
+    Skip,
+}
+
+impl FromStr for MissingLink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wait" => Ok(Self::Wait),
+            "skip" => Ok(Self::Skip),
+            _ => Err(format!(
+                "invalid missing link policy {} (want \"wait\" or \"skip\")",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Settings {
+    pub log_level: Option<String>,
+    pub retries: Option<u32>,
+    pub retry_delay: Option<Duration>,
+    pub apply_order: ApplyOrder,
+    pub missing_link: MissingLink,
+    pub reconcile_interval: Option<Duration>,
+    pub drift: Drift,
+    pub apply_timeout: Option<Duration>,
+}
+
+impl Settings {
+    fn set(&mut self, option: &str, value: &str) -> Result<(), String> {
+        let invalid_num = |e: &dyn fmt::Display| format!("invalid {} {}: {}", option, value, e);
+
+        match option {
+            "log-level" => self.log_level = Some(value.to_string()),
+            "retries" => self.retries = Some(value.parse().map_err(|e| invalid_num(&e))?),
+            "retry-delay" => {
+                let ms = value.parse().map_err(|e| invalid_num(&e))?;
+                self.retry_delay = Some(Duration::from_millis(ms));
+            }
+            "apply-order" => self.apply_order = value.parse()?,
+            "missing-link" => self.missing_link = value.parse()?,
+            "reconcile-interval" => match value.parse().map_err(|e| invalid_num(&e))? {
+                0 => return Err(format!("invalid {} 0 (want at least 1)", option)),
+                secs => self.reconcile_interval = Some(Duration::from_secs(secs)),
+            },
+            "drift" => self.drift = value.parse()?,
+            "apply-timeout" => match value.parse().map_err(|e| invalid_num(&e))? {
+                0 => return Err(format!("invalid {} 0 (want at least 1)", option)),
+                secs => self.apply_timeout = Some(Duration::from_secs(secs)),
+            },
+            _ => return Err(format!(
+                "invalid option {} (want \"log-level\", \"retries\", \"retry-delay\", \"apply-order\", \"missing-link\", \"reconcile-interval\", \"drift\" or \"apply-timeout\")",
+                option
+            )),
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the settings file, which is optional. Returns what is wrong with it,
+/// to be logged once logging is set up. Invalid lines are ignored.
+pub fn init() -> Vec<String> {
+    let mut settings = Settings::default();
+    let mut warnings = Vec::new();
+
+    let s = match fs::read_to_string(SETTINGS_PATH) {
+        Ok(s) => s,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            warnings.push(format!("read settings ({}): {}", SETTINGS_PATH, e));
+            String::new()
+        }
+    };
+
+    for (i, line) in s.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let res = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [option, value] => settings.set(option, value),
+            _ => Err("invalid line (want \"<option> <value>\")".to_string()),
+        };
+        if let Err(e) = res {
+            warnings.push(format!("{}:{}: {}", SETTINGS_PATH, i + 1, e));
+        }
+    }
+
+    rsdsl_rtd::set_retry_policy(settings.retries, settings.retry_delay);
+
+    let _ = SETTINGS.set(settings);
+    warnings
+}
+
+/// Returns the settings, the defaults if they haven't been read.
+pub fn get() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::default)
+}
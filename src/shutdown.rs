@@ -0,0 +1,124 @@
+//! Orderly shutdown on SIGTERM and SIGINT, or for a restart.
+//!
+//! Most of the configuration outlives the daemon on purpose, routes stay
+//! in place across restarts. Changes that would leave the system in a state
+//! nobody configured (e.g. moved kernel rules) register hooks undoing them.
+
+use crate::log;
+
+use std::io;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+/// Why the daemon is shutting down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    Signal(libc::c_int),
+    Restart,
+    /// Restart after reverting an unconfirmed configuration.
+    Revert,
+}
+
+/// Written to the pipe instead of a signal number to request a restart.
+const RESTART: u8 = 0;
+/// Written to the pipe instead of a signal number to request a revert.
+const REVERT: u8 = u8::MAX;
+
+/// The pipe the signal handler wakes `wait` through.
+static READ_FD: AtomicI32 = AtomicI32::new(-1);
+static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+static HOOKS: Mutex<Vec<Hook>> = Mutex::new(Vec::new());
+
+/// Installs the signal handlers. Nothing happens on the signals
+/// until `wait` is called, it should be right away.
+pub fn init() -> io::Result<()> {
+    let mut fds = [0; 2];
+    // SAFETY: fds is a valid array of two file descriptors.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    READ_FD.store(fds[0], Ordering::Relaxed);
+    WRITE_FD.store(fds[1], Ordering::Relaxed);
+
+    for sig in [libc::SIGTERM, libc::SIGINT] {
+        // SAFETY: The handler only performs async-signal-safe operations.
+        let res = unsafe { libc::signal(sig, handle as extern "C" fn(libc::c_int) as usize) };
+        if res == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+extern "C" fn handle(sig: libc::c_int) {
+    wake(sig as u8);
+}
+
+/// Makes `wait` return `Reason::Restart`.
+pub fn restart() {
+    wake(RESTART);
+}
+
+/// Makes `wait` return `Reason::Revert`.
+pub fn revert() {
+    wake(REVERT);
+}
+
+fn wake(byte: u8) {
+    // SAFETY: write(2) is async-signal-safe and byte outlives the call.
+    unsafe {
+        libc::write(
+            WRITE_FD.load(Ordering::Relaxed),
+            &byte as *const u8 as *const libc::c_void,
+            1,
+        );
+    }
+}
+
+/// Registers a hook to run at shutdown. Hooks run in reverse order.
+pub fn defer(hook: impl FnOnce() + Send + 'static) {
+    HOOKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(Box::new(hook));
+}
+
+/// Blocks until a signal or `restart` requests shutdown.
+pub fn wait() -> Reason {
+    let mut byte = 0u8;
+    loop {
+        // SAFETY: byte is a valid, writable buffer of length 1.
+        let n = unsafe {
+            libc::read(
+                READ_FD.load(Ordering::Relaxed),
+                &mut byte as *mut u8 as *mut libc::c_void,
+                1,
+            )
+        };
+        match n {
+            1 if byte == RESTART => return Reason::Restart,
+            1 if byte == REVERT => return Reason::Revert,
+            1 => return Reason::Signal(byte.into()),
+            n if n < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+            _ => {
+                // Without a working pipe there's nothing to wait for.
+                log::error!(General, "wait for signals: {}", io::Error::last_os_error());
+                loop {
+                    std::thread::park();
+                }
+            }
+        }
+    }
+}
+
+/// Runs the registered hooks.
+pub fn run_hooks() {
+    let hooks = std::mem::take(&mut *HOOKS.lock().unwrap_or_else(|e| e.into_inner()));
+    for hook in hooks.into_iter().rev() {
+        hook();
+    }
+}
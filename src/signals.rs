@@ -0,0 +1,69 @@
+//! Signals asking the running daemon for something other than shutdown:
+//!
+//! * SIGUSR1 dumps the internal state to the log, e.g. to find out
+//!   which link an apply pass is waiting for.
+//! * SIGUSR2 re-applies the configuration, see [`resync`](crate::resync).
+
+use crate::{log, resync, status};
+
+use std::io;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+
+/// The pipe the signal handler wakes the dispatcher thread through.
+static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Installs the signal handlers and handles the signals in the background.
+pub fn spawn() -> io::Result<()> {
+    let mut fds = [0; 2];
+    // SAFETY: fds is a valid array of two file descriptors.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    WRITE_FD.store(fds[1], Ordering::Relaxed);
+
+    let read_fd = fds[0];
+    thread::spawn(move || dispatch(read_fd));
+
+    for sig in [libc::SIGUSR1, libc::SIGUSR2] {
+        // SAFETY: The handler only performs async-signal-safe operations.
+        let res = unsafe { libc::signal(sig, handle as extern "C" fn(libc::c_int) as usize) };
+        if res == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+extern "C" fn handle(sig: libc::c_int) {
+    let byte = sig as u8;
+    // SAFETY: write(2) is async-signal-safe and byte outlives the call.
+    unsafe {
+        libc::write(
+            WRITE_FD.load(Ordering::Relaxed),
+            &byte as *const u8 as *const libc::c_void,
+            1,
+        );
+    }
+}
+
+fn dispatch(read_fd: libc::c_int) {
+    let mut byte = 0u8;
+    loop {
+        // SAFETY: byte is a valid, writable buffer of length 1.
+        let n = unsafe { libc::read(read_fd, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+        match n {
+            1 => match libc::c_int::from(byte) {
+                libc::SIGUSR1 => status::dump(),
+                libc::SIGUSR2 => resync::run(),
+                _ => {}
+            },
+            n if n < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+            _ => {
+                log::error!(General, "wait for signals: {}", io::Error::last_os_error());
+                return;
+            }
+        }
+    }
+}
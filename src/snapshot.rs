@@ -0,0 +1,407 @@
+//! `snapshot save|restore`: copies the routes of kernel tables to a file
+//! and makes the tables match it again later, e.g. to revert experiments.
+//!
+//! Snapshots use the syntax of the route configuration for regular routes
+//! and that of the log for routes discarding traffic. Routes the kernel
+//! maintains by itself (connected networks, router advertisements)
+//! are neither saved nor touched by a restore.
+
+use crate::log;
+
+use rsdsl_rtd::rtnl::{self, RouteMsg};
+use rsdsl_rtd::{Blackhole, RejectKind, Route, RouteBuilder, RouteDef, SetupError};
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use rsdsl_netlinklib::blocking::Connection;
+
+/// The header line listing the tables a snapshot covers.
+const HEADER: &str = "# rtd snapshot of tables";
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Failed(usize),
+    InvalidAttr(String),
+    InvalidCmd(String),
+    InvalidLine(usize, String),
+    Netlink(io::Error),
+    NoAttrValue(String),
+    NoCmd,
+    NoFile,
+    NoHeader,
+    ParseInt(std::num::ParseIntError),
+    ReadFile(io::Error),
+    Setup(SetupError),
+    WriteFile(io::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Failed(n) => write!(f, "{} routes could not be restored", n)?,
+            Self::InvalidAttr(a) => write!(f, "invalid attribute {} (want \"tables\")", a)?,
+            Self::InvalidCmd(c) => {
+                write!(f, "invalid command {} (want \"save\" or \"restore\")", c)?
+            }
+            Self::InvalidLine(line, l) => write!(f, "line {}: invalid route {}", line, l)?,
+            Self::Netlink(e) => write!(f, "netlink: {}", e)?,
+            Self::NoAttrValue(a) => write!(f, "missing value for attribute {}", a)?,
+            Self::NoCmd => write!(f, "missing command (want \"save\" or \"restore\")")?,
+            Self::NoFile => write!(f, "missing snapshot file")?,
+            Self::NoHeader => write!(f, "not a snapshot (missing \"{}\" line)", HEADER)?,
+            Self::ParseInt(e) => write!(f, "parse integer: {}", e)?,
+            Self::ReadFile(e) => write!(f, "read snapshot: {}", e)?,
+            Self::Setup(e) => write!(f, "{}", e)?,
+            Self::WriteFile(e) => write!(f, "write snapshot: {}", e)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> SnapshotError {
+        SnapshotError::Netlink(e)
+    }
+}
+
+impl From<std::num::ParseIntError> for SnapshotError {
+    fn from(e: std::num::ParseIntError) -> SnapshotError {
+        SnapshotError::ParseInt(e)
+    }
+}
+
+impl From<SetupError> for SnapshotError {
+    fn from(e: SetupError) -> SnapshotError {
+        SnapshotError::Setup(e)
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// A route of a snapshot.
+#[derive(Debug)]
+enum Entry {
+    Route(Box<Route>),
+    Reject(Blackhole),
+}
+
+impl Entry {
+    /// Converts a kernel route, `None` if snapshots don't cover it.
+    fn from_msg(msg: &RouteMsg) -> Option<Self> {
+        if matches!(msg.protocol, rtnl::RTPROT_KERNEL | rtnl::RTPROT_RA) {
+            return None;
+        }
+
+        let ipv6 = i32::from(msg.family) == libc::AF_INET6;
+        let dst = msg.dst.unwrap_or(if ipv6 {
+            Ipv6Addr::UNSPECIFIED.into()
+        } else {
+            Ipv4Addr::UNSPECIFIED.into()
+        });
+        let table = (msg.table != rtnl::RT_TABLE_MAIN).then_some(msg.table);
+
+        let kind = match msg.ty {
+            rtnl::RTN_UNICAST => None,
+            rtnl::RTN_BLACKHOLE => Some(RejectKind::Blackhole),
+            rtnl::RTN_UNREACHABLE => Some(RejectKind::Unreachable),
+            rtnl::RTN_PROHIBIT => Some(RejectKind::Prohibit),
+            _ => return None,
+        };
+        if let Some(kind) = kind {
+            return Some(Self::Reject(Blackhole {
+                dst,
+                prefix_len: msg.dst_len,
+                kind,
+                table,
+                metric: msg.metric,
+            }));
+        }
+
+        // Multipath routes don't have a single interface.
+        let Some(link) = msg.oif.and_then(rtnl::link_name) else {
+            log::warn!(
+                Netlink,
+                "skip route to {}/{} without interface",
+                dst,
+                msg.dst_len
+            );
+            return None;
+        };
+
+        let mut builder = if ipv6 {
+            RouteBuilder::v6()
+        } else {
+            RouteBuilder::v4()
+        }
+        .dst(dst, msg.dst_len)
+        .on_link(msg.on_link)
+        .dev(link);
+        if let Some(gateway) = msg.gateway {
+            builder = builder.via(gateway);
+        }
+        if let Some(table) = table {
+            builder = builder.table(table);
+        }
+        if let Some(metric) = msg.metric {
+            builder = builder.metric(metric);
+        }
+
+        builder
+            .build()
+            .ok()
+            .map(|route| Self::Route(Box::new(route)))
+    }
+
+    fn table(&self) -> u32 {
+        let table = match self {
+            Self::Route(route) => match &route.def {
+                RouteDef::V4(r) => r.table,
+                RouteDef::V6(r) => r.table,
+            },
+            Self::Reject(blackhole) => blackhole.table,
+        };
+
+        table.unwrap_or(rtnl::RT_TABLE_MAIN)
+    }
+
+    fn add(&self, conn: &Connection) -> Result<(), SetupError> {
+        match self {
+            Self::Route(route) => route.def.clone().blocking_add(conn),
+            Self::Reject(blackhole) => blackhole.blocking_add(),
+        }
+    }
+
+    fn del(&self, conn: &Connection) -> Result<(), SetupError> {
+        match self {
+            Self::Route(route) => route.def.clone().blocking_del(conn),
+            Self::Reject(blackhole) => blackhole.blocking_del(),
+        }
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Route(route) => {
+                let (version, dst, prefix_len, on_link, table, metric, link) = match &route.def {
+                    RouteDef::V4(r) => (
+                        "route4",
+                        IpAddr::from(r.dst),
+                        r.prefix_len,
+                        r.on_link,
+                        r.table,
+                        r.metric,
+                        &r.link,
+                    ),
+                    RouteDef::V6(r) => (
+                        "route6",
+                        IpAddr::from(r.dst),
+                        r.prefix_len,
+                        r.on_link,
+                        r.table,
+                        r.metric,
+                        &r.link,
+                    ),
+                };
+
+                write!(f, "{} add to {}/{}", version, dst, prefix_len)?;
+                if let Some(rtr) = route.def.rtr() {
+                    write!(f, " via {}", rtr)?;
+                }
+                if on_link {
+                    write!(f, " onlink true")?;
+                }
+                if let Some(table) = table {
+                    write!(f, " table {}", table)?;
+                }
+                if let Some(metric) = metric {
+                    write!(f, " metric {}", metric)?;
+                }
+                write!(f, " dev {}", link)
+            }
+            Self::Reject(blackhole) => blackhole.fmt(f),
+        }
+    }
+}
+
+impl std::str::FromStr for Entry {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let version = words.next().ok_or(())?;
+
+        let kind = match words.next() {
+            Some("add") => {
+                return s
+                    .parse()
+                    .map(|route| Self::Route(Box::new(route)))
+                    .map_err(|_| ())
+            }
+            Some(kind) => kind.parse::<RejectKind>().map_err(|_| ())?,
+            None => return Err(()),
+        };
+
+        let (dst, prefix_len) = words.next().and_then(|p| p.split_once('/')).ok_or(())?;
+        let dst: IpAddr = dst.parse().map_err(|_| ())?;
+        if dst.is_ipv6() != (version == "route6") {
+            return Err(());
+        }
+
+        let mut blackhole = Blackhole {
+            dst,
+            prefix_len: prefix_len.parse().map_err(|_| ())?,
+            kind,
+            table: None,
+            metric: None,
+        };
+        while let Some(attr) = words.next() {
+            let value = words.next().and_then(|v| v.parse().ok()).ok_or(())?;
+            match attr {
+                "table" => blackhole.table = Some(value),
+                "metric" => blackhole.metric = Some(value),
+                _ => return Err(()),
+            }
+        }
+
+        Ok(Self::Reject(blackhole))
+    }
+}
+
+pub fn snapshot(args: &[String]) -> Result<(), SnapshotError> {
+    let mut words = args.iter().map(String::as_str);
+
+    let cmd = words.next().ok_or(SnapshotError::NoCmd)?;
+    let path = words.next().ok_or(SnapshotError::NoFile)?;
+
+    match cmd {
+        "save" => {
+            let mut tables = vec![rtnl::RT_TABLE_MAIN];
+            while let Some(attr) = words.next() {
+                let value = words
+                    .next()
+                    .ok_or_else(|| SnapshotError::NoAttrValue(attr.to_string()))?;
+                match attr {
+                    "tables" => {
+                        tables = value
+                            .split(',')
+                            .map(rsdsl_rtd::parse_table)
+                            .collect::<Result<_, _>>()?
+                    }
+                    _ => return Err(SnapshotError::InvalidAttr(attr.to_string())),
+                }
+            }
+
+            save(path, &tables)
+        }
+        "restore" => match words.next() {
+            Some(attr) => Err(SnapshotError::InvalidAttr(attr.to_string())),
+            None => restore(path),
+        },
+        _ => Err(SnapshotError::InvalidCmd(cmd.to_string())),
+    }
+}
+
+/// Returns the routes of the given tables.
+fn current(tables: &[u32]) -> Result<Vec<Entry>, SnapshotError> {
+    Ok(rtnl::Socket::new()?
+        .dump_routes(libc::AF_UNSPEC as u8)?
+        .iter()
+        .filter(|msg| tables.contains(&msg.table))
+        .filter_map(Entry::from_msg)
+        .collect())
+}
+
+fn save(path: &str, tables: &[u32]) -> Result<(), SnapshotError> {
+    let entries = current(tables)?;
+
+    let tables: Vec<String> = tables.iter().map(u32::to_string).collect();
+    let mut s = format!("{} {}\n", HEADER, tables.join(","));
+    for entry in &entries {
+        s += &format!("{}\n", entry);
+    }
+
+    fs::write(path, s).map_err(SnapshotError::WriteFile)?;
+
+    log::info!(
+        General,
+        "saved {} routes of tables {} to {}",
+        entries.len(),
+        tables.join(","),
+        path
+    );
+    Ok(())
+}
+
+fn restore(path: &str) -> Result<(), SnapshotError> {
+    let s = fs::read_to_string(path).map_err(SnapshotError::ReadFile)?;
+
+    let tables: Vec<u32> = s
+        .lines()
+        .find_map(|line| line.strip_prefix(HEADER))
+        .ok_or(SnapshotError::NoHeader)?
+        .trim()
+        .split(',')
+        .map(str::parse)
+        .collect::<Result<_, _>>()?;
+
+    let mut entries = Vec::new();
+    for (i, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let entry: Entry = line
+            .parse()
+            .map_err(|_| SnapshotError::InvalidLine(i + 1, line.to_string()))?;
+        if tables.contains(&entry.table()) {
+            entries.push(entry);
+        }
+    }
+
+    let conn = Connection::new().map_err(SetupError::from)?;
+
+    let wanted: HashSet<String> = entries.iter().map(Entry::to_string).collect();
+    let installed: HashSet<String> = current(&tables)?
+        .into_iter()
+        .map(|entry| {
+            // Routes added since the snapshot go first to make room for the old ones.
+            if !wanted.contains(&entry.to_string()) {
+                let _ = report("del", &entry, entry.del(&conn));
+            }
+            entry.to_string()
+        })
+        .collect();
+
+    let mut failed = 0;
+    for entry in entries
+        .iter()
+        .filter(|entry| !installed.contains(&entry.to_string()))
+    {
+        if report("add", entry, entry.add(&conn)).is_err() {
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        return Err(SnapshotError::Failed(failed));
+    }
+
+    log::info!(General, "restored tables from {}", path);
+    Ok(())
+}
+
+fn report(action: &str, entry: &Entry, res: Result<(), SetupError>) -> Result<(), SetupError> {
+    match &res {
+        Ok(()) => log::info!(Netlink, "{} {}", action, entry),
+        Err(e) => log::error!(Netlink, "{} {}: {}", action, entry, e),
+    }
+
+    res
+}
@@ -0,0 +1,453 @@
+//! Machine-readable status of all configured entries,
+//! kept up to date in a JSON file for the web UI and scripts.
+
+use crate::audit::Source;
+use crate::{control, lock, log};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const STATUS_PATH: &str = "/run/rtd.status";
+
+static STATUS: Mutex<Status> = Mutex::new(Status::new());
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum State {
+    Pending,
+    WaitingForLink(String),
+    WaitingForVar(String),
+    WaitingForPeer(String),
+    WaitingForAddr(String),
+    Applied,
+    Removed,
+    Absent,
+    Withdrawn(String),
+    Inactive(String),
+    /// Differs from the kernel, which is left alone as configured.
+    Drifted(String),
+    /// Installed on an earlier run and left to whoever took it over since.
+    Unmanaged,
+    Failed(String),
+}
+
+impl State {
+    /// Returns what the entry is waiting for, if anything.
+    fn waiting_for(&self) -> Option<String> {
+        match self {
+            Self::WaitingForLink(link) => Some(format!("link {}", link)),
+            Self::WaitingForVar(var) => Some(format!("variable {}", var)),
+            Self::WaitingForPeer(link) => Some(format!("peer address of {}", link)),
+            Self::WaitingForAddr(link) => Some(format!("address of {}", link)),
+            _ => None,
+        }
+    }
+
+    /// Returns the details of the state, e.g. the error of a failure.
+    fn detail(&self) -> Option<&str> {
+        match self {
+            Self::WaitingForLink(detail)
+            | Self::WaitingForVar(detail)
+            | Self::WaitingForPeer(detail)
+            | Self::WaitingForAddr(detail)
+            | Self::Withdrawn(detail)
+            | Self::Inactive(detail)
+            | Self::Drifted(detail)
+            | Self::Failed(detail) => Some(detail),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pending => write!(f, "pending")?,
+            Self::WaitingForLink(_) => write!(f, "waiting_for_link")?,
+            Self::WaitingForVar(_) => write!(f, "waiting_for_var")?,
+            Self::WaitingForPeer(_) => write!(f, "waiting_for_peer")?,
+            Self::WaitingForAddr(_) => write!(f, "waiting_for_addr")?,
+            Self::Applied => write!(f, "applied")?,
+            Self::Removed => write!(f, "removed")?,
+            Self::Absent => write!(f, "absent")?,
+            Self::Withdrawn(_) => write!(f, "withdrawn")?,
+            Self::Inactive(_) => write!(f, "inactive")?,
+            Self::Drifted(_) => write!(f, "drifted")?,
+            Self::Unmanaged => write!(f, "unmanaged")?,
+            Self::Failed(_) => write!(f, "failed")?,
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    source: Source,
+    entry: String,
+    state: State,
+}
+
+impl Entry {
+    fn to_json(&self) -> serde_json::Value {
+        let mut obj = serde_json::json!({
+            "source": self.source.to_string(),
+            "entry": self.entry,
+            "state": self.state.to_string(),
+        });
+        match &self.state {
+            State::WaitingForLink(link)
+            | State::WaitingForPeer(link)
+            | State::WaitingForAddr(link) => obj["link"] = link.as_str().into(),
+            State::WaitingForVar(var) => obj["var"] = var.as_str().into(),
+            State::Withdrawn(probe) => obj["probe"] = probe.as_str().into(),
+            State::Inactive(condition) => obj["condition"] = condition.as_str().into(),
+            State::Drifted(drift) => obj["drift"] = drift.as_str().into(),
+            State::Failed(e) => obj["error"] = e.as_str().into(),
+            _ => {}
+        }
+
+        obj
+    }
+}
+
+/// The entries of a network namespace other than rtd's own,
+/// applied separately from the rest.
+#[derive(Debug)]
+struct Namespace {
+    sources: Vec<Source>,
+    last_apply: Option<String>,
+}
+
+/// What an apply pass did with the entries.
+#[derive(Debug, Default)]
+struct Summary {
+    parsed: usize,
+    added: usize,
+    deleted: usize,
+    /// Entries not (yet) in place by intention or because they wait for something.
+    skipped: usize,
+    failed: usize,
+    elapsed: Duration,
+}
+
+impl Summary {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "parsed": self.parsed,
+            "added": self.added,
+            "deleted": self.deleted,
+            "skipped": self.skipped,
+            "failed": self.failed,
+            "elapsed_ms": self.elapsed.as_millis() as u64,
+        })
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} entries parsed, {} added, {} deleted, {} skipped, {} failed in {:.1?}",
+            self.parsed, self.added, self.deleted, self.skipped, self.failed, self.elapsed
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Status {
+    last_apply: Option<String>,
+    errors: u64,
+    entries: Vec<Entry>,
+    namespaces: BTreeMap<String, Namespace>,
+    summary: Option<Summary>,
+}
+
+impl Status {
+    const fn new() -> Self {
+        Self {
+            last_apply: None,
+            errors: 0,
+            entries: Vec::new(),
+            namespaces: BTreeMap::new(),
+            summary: None,
+        }
+    }
+
+    fn failed(&self, sources: &[Source]) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| sources.contains(&entry.source))
+            .filter(|entry| matches!(entry.state, State::Failed(_)))
+            .count()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let entries: Vec<serde_json::Value> = self.entries.iter().map(Entry::to_json).collect();
+        let namespaces: serde_json::Map<String, serde_json::Value> = self
+            .namespaces
+            .iter()
+            .map(|(name, ns)| {
+                let obj = serde_json::json!({
+                    "last_apply": ns.last_apply,
+                    "failed": self.failed(&ns.sources),
+                });
+                (name.clone(), obj)
+            })
+            .collect();
+
+        serde_json::json!({
+            "last_apply": self.last_apply,
+            "errors": self.errors,
+            "failed": self
+                .entries
+                .iter()
+                .filter(|entry| matches!(entry.state, State::Failed(_)))
+                .count(),
+            "absent": self
+                .entries
+                .iter()
+                .filter(|entry| entry.state == State::Absent)
+                .count(),
+            "entries": entries,
+            "namespaces": namespaces,
+            "summary": self.summary.as_ref().map(Summary::to_json),
+        })
+    }
+
+    fn write(&self) {
+        let content = self.to_json().to_string() + "\n";
+
+        let res = lock::write_atomic(Path::new(STATUS_PATH), content.as_bytes());
+        if let Err(e) = res {
+            log::error!(General, "write status file ({}): {}", STATUS_PATH, e);
+        }
+    }
+}
+
+fn status() -> std::sync::MutexGuard<'static, Status> {
+    STATUS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Registers the entries of a new apply pass as pending,
+/// replacing the previous set.
+pub fn begin(entries: Vec<(Source, String)>) {
+    let mut status = status();
+
+    status.entries = entries
+        .into_iter()
+        .map(|(source, entry)| Entry {
+            source,
+            entry,
+            state: State::Pending,
+        })
+        .collect();
+    status.namespaces.clear();
+    status.summary = None;
+    status.write();
+}
+
+/// Registers the entries of a network namespace,
+/// they are applied independently of the others.
+pub fn begin_netns(name: &str, sources: Vec<Source>) {
+    let mut status = status();
+
+    status.namespaces.insert(
+        name.to_string(),
+        Namespace {
+            sources,
+            last_apply: None,
+        },
+    );
+    status.write();
+}
+
+/// Registers an entry that came into existence after the apply pass began,
+/// e.g. the copy of a route for a link that appeared. A known entry
+/// only has its description updated.
+pub fn add(source: Source, entry: String) {
+    let mut status = status();
+
+    match status.entries.iter_mut().find(|e| e.source == source) {
+        Some(known) => known.entry = entry,
+        None => status.entries.push(Entry {
+            source,
+            entry,
+            state: State::Pending,
+        }),
+    }
+    status.write();
+}
+
+/// Forgets an entry registered with `add`.
+pub fn remove(source: Source) {
+    let mut status = status();
+
+    status.entries.retain(|e| e.source != source);
+    status.write();
+}
+
+/// Updates the state of a single entry.
+pub fn set(source: Source, state: State) {
+    let mut status = status();
+
+    if matches!(state, State::Failed(_)) {
+        status.errors += 1;
+    }
+
+    if let Some(entry) = status.entries.iter_mut().find(|e| e.source == source) {
+        if entry.state != state {
+            let previous = std::mem::replace(&mut entry.state, state);
+
+            let mut event = entry.to_json();
+            event["event"] = "entry".into();
+            event["previous"] = previous.to_string().into();
+            control::publish(event);
+        }
+    }
+    status.write();
+}
+
+/// Returns the current state of a single entry.
+pub fn state(source: Source) -> Option<State> {
+    status()
+        .entries
+        .iter()
+        .find(|e| e.source == source)
+        .map(|e| e.state.clone())
+}
+
+/// Records the completion of an apply pass.
+pub fn applied() {
+    let mut status = status();
+
+    status.last_apply = Some(log::timestamp());
+    status.write();
+}
+
+/// Summarizes the initial apply pass, which took `elapsed`,
+/// in the log and the status file.
+pub fn summarize(elapsed: Duration) {
+    let mut status = status();
+
+    let mut summary = Summary {
+        parsed: status.entries.len(),
+        elapsed,
+        ..Default::default()
+    };
+    for entry in &status.entries {
+        match entry.state {
+            State::Applied => summary.added += 1,
+            State::Removed | State::Absent => summary.deleted += 1,
+            State::Failed(_) => summary.failed += 1,
+            _ => summary.skipped += 1,
+        }
+    }
+
+    log::info!(General, "apply: {}", summary);
+    status.summary = Some(summary);
+    status.write();
+}
+
+/// Records the completion of an apply pass in a network namespace.
+pub fn applied_netns(name: &str) {
+    let mut status = status();
+
+    if let Some(ns) = status.namespaces.get_mut(name) {
+        ns.last_apply = Some(log::timestamp());
+    }
+    status.write();
+}
+
+/// Writes the current state of all entries to the log,
+/// ending with what the daemon is waiting for.
+pub fn dump() {
+    let status = status();
+
+    log::info!(
+        General,
+        "dump state: {} entries, last apply {}, {} errors",
+        status.entries.len(),
+        status.last_apply.as_deref().unwrap_or("never"),
+        status.errors
+    );
+    for entry in &status.entries {
+        match entry.state.detail() {
+            Some(detail) => log::info!(
+                General,
+                "{}: {}: {} ({})",
+                entry.source,
+                entry.entry,
+                entry.state,
+                detail
+            ),
+            None => log::info!(
+                General,
+                "{}: {}: {}",
+                entry.source,
+                entry.entry,
+                entry.state
+            ),
+        }
+    }
+    for (name, ns) in &status.namespaces {
+        log::info!(
+            General,
+            "netns {}: last apply {}, {} failed",
+            name,
+            ns.last_apply.as_deref().unwrap_or("never"),
+            status.failed(&ns.sources)
+        );
+    }
+
+    let waits: Vec<String> = status
+        .entries
+        .iter()
+        .filter_map(|entry| {
+            let what = entry.state.waiting_for()?;
+            Some(format!("{} for {}", entry.source, what))
+        })
+        .collect();
+    match waits.is_empty() {
+        true => log::info!(General, "no pending waits"),
+        false => log::info!(General, "pending waits: {}", waits.join(", ")),
+    }
+}
+
+/// Returns the entries that haven't been dealt with yet,
+/// along with what they are waiting for if anything.
+pub fn pending() -> Vec<String> {
+    status()
+        .entries
+        .iter()
+        .filter(|entry| entry.state == State::Pending || entry.state.waiting_for().is_some())
+        .map(|entry| match entry.state.waiting_for() {
+            Some(what) => format!("{}: {} (waiting for {})", entry.source, entry.entry, what),
+            None => format!("{}: {}", entry.source, entry.entry),
+        })
+        .collect()
+}
+
+/// Reports whether all entries have been applied successfully,
+/// along with the current status as JSON.
+/// Routes withdrawn by the failover logic or whose conditions
+/// don't hold are working as intended.
+pub fn health() -> (bool, serde_json::Value) {
+    let status = status();
+
+    let healthy = status.last_apply.is_some()
+        && status.entries.iter().all(|entry| {
+            matches!(
+                entry.state,
+                State::Applied
+                    | State::Removed
+                    | State::Absent
+                    | State::Withdrawn(_)
+                    | State::Inactive(_)
+            )
+        });
+
+    (healthy, status.to_json())
+}
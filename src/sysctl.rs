@@ -0,0 +1,181 @@
+//! Routing-related interface settings (`sysctl` lines of the route configuration).
+//!
+//! Forwarding or reverse path filtering being off breaks routes that exist
+//! just fine, so these are managed alongside the routes that depend on them.
+
+use crate::{RouteParseError, SetupError};
+
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+/// A setting that can be managed per interface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SysctlKey {
+    IpForward,
+    Ip6Forward,
+    RpFilter,
+    AcceptRa,
+    ProxyArp,
+    ProxyNdp,
+}
+
+impl SysctlKey {
+    /// Returns the path of the setting for the given link,
+    /// which may also be `all` or `default`.
+    pub fn path(&self, link: &str) -> String {
+        let (family, name) = match self {
+            Self::IpForward => ("ipv4", "forwarding"),
+            Self::Ip6Forward => ("ipv6", "forwarding"),
+            Self::RpFilter => ("ipv4", "rp_filter"),
+            Self::AcceptRa => ("ipv6", "accept_ra"),
+            Self::ProxyArp => ("ipv4", "proxy_arp"),
+            Self::ProxyNdp => ("ipv6", "proxy_ndp"),
+        };
+
+        format!("/proc/sys/net/{}/conf/{}/{}", family, link, name)
+    }
+}
+
+impl fmt::Display for SysctlKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IpForward => write!(f, "ip_forward"),
+            Self::Ip6Forward => write!(f, "ip6_forward"),
+            Self::RpFilter => write!(f, "rp_filter"),
+            Self::AcceptRa => write!(f, "accept_ra"),
+            Self::ProxyArp => write!(f, "proxy_arp"),
+            Self::ProxyNdp => write!(f, "proxy_ndp"),
+        }
+    }
+}
+
+impl FromStr for SysctlKey {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ip_forward" => Ok(Self::IpForward),
+            "ip6_forward" => Ok(Self::Ip6Forward),
+            "rp_filter" => Ok(Self::RpFilter),
+            "accept_ra" => Ok(Self::AcceptRa),
+            "proxy_arp" => Ok(Self::ProxyArp),
+            "proxy_ndp" => Ok(Self::ProxyNdp),
+            _ => Err(RouteParseError::InvalidAttr(s.to_string())),
+        }
+    }
+}
+
+/// A `sysctl` line of the route configuration.
+#[derive(Clone, Debug)]
+pub struct Sysctl {
+    /// The interface, `all` or `default`.
+    pub link: String,
+    pub settings: Vec<(SysctlKey, u32)>,
+    pub line: usize,
+}
+
+impl Sysctl {
+    /// Reports whether the settings apply to a specific interface
+    /// rather than all or future ones.
+    pub fn is_link_specific(&self) -> bool {
+        self.link != "all" && self.link != "default"
+    }
+
+    /// Writes the settings.
+    pub fn blocking_apply(&self) -> Result<(), SetupError> {
+        for (key, value) in &self.settings {
+            fs::write(key.path(&self.link), value.to_string())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Sysctl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sysctl dev {}", self.link)?;
+        for (key, value) in &self.settings {
+            write!(f, " {} {}", key, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Sysctl {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = crate::lowercase_keywords(s, 2);
+        let mut words = s.split_whitespace();
+
+        let version_str = words.next().ok_or(RouteParseError::NoVersion)?;
+        if version_str != "sysctl" {
+            return Err(RouteParseError::InvalidVersion(version_str.to_string()));
+        }
+
+        let cmd = words.next().ok_or(RouteParseError::NoCmd)?;
+        if cmd != "set" {
+            return Err(RouteParseError::InvalidCmd(cmd.to_string()));
+        }
+
+        let attrs = crate::attr_pairs(words, |attr| attr)?;
+
+        let link = attrs
+            .iter()
+            .find(|(attr, _)| *attr == "dev")
+            .map(|(_, link)| *link)
+            .ok_or(RouteParseError::NoLink)?;
+
+        // Keep the order of the configuration so that it is applied as written.
+        let settings = attrs
+            .into_iter()
+            .filter(|(attr, _)| *attr != "dev")
+            .map(|(attr, value)| Ok((attr.parse()?, value.parse()?)))
+            .collect::<Result<Vec<_>, RouteParseError>>()?;
+
+        if settings.is_empty() {
+            return Err(RouteParseError::NoSysctl);
+        }
+
+        Ok(Self {
+            link: link.to_string(),
+            settings,
+            line: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::round_trip;
+
+    #[test]
+    fn settings() {
+        // The settings are applied in the order they are written.
+        assert_eq!(
+            round_trip::<Sysctl>("sysctl set dev eth0 rp_filter 2 proxy_arp 1"),
+            "sysctl dev eth0 rp_filter 2 proxy_arp 1"
+        );
+        assert_eq!(
+            round_trip::<Sysctl>("sysctl set proxy_ndp 1 dev all"),
+            "sysctl dev all proxy_ndp 1"
+        );
+
+        assert!(matches!(
+            "sysctl set proxy_arp 1".parse::<Sysctl>(),
+            Err(RouteParseError::NoLink)
+        ));
+        assert!(matches!(
+            "sysctl set dev eth0".parse::<Sysctl>(),
+            Err(RouteParseError::NoSysctl)
+        ));
+        assert!(matches!(
+            "sysctl set dev eth0 mtu 1500".parse::<Sysctl>(),
+            Err(RouteParseError::InvalidAttr(attr)) if attr == "mtu"
+        ));
+    }
+}
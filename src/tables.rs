@@ -0,0 +1,476 @@
+//! The table registry (`/data/rtd.tables`): which routing table belongs to whom.
+//!
+//! Several rsdsl daemons put routes into tables of their own, two of them
+//! using the same table leads to routing that makes no sense. Each line
+//! of the registry assigns a table to its owner and may name it:
+//!
+//! ```text
+//! # <table> rtd|pppoe|wg|user [name] [exclusive]
+//! 100 pppoe
+//! 1000 rtd lte exclusive
+//! auto vpn
+//! ```
+//!
+//! `auto <name>` allocates a free table to rtd and writes its number back
+//! so that it stays the same. The configuration files may refer to named
+//! tables by their name. rtd warns about tables that are assigned twice
+//! and about tables it uses that belong to someone else.
+//!
+//! Tables of rtd marked `exclusive` contain exactly the configured routes:
+//! any other route is removed when applying the configuration
+//! and, with `--monitor`, as soon as it shows up.
+
+use crate::lookup::DisplayRoute;
+use crate::{lock, log};
+use crate::{ROUTES_PATH, RULES_PATH};
+
+use rsdsl_rtd::rtnl::{self, RouteMsg};
+use rsdsl_rtd::{RouteDef, Routes, RuleAction, Rules};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+
+const REGISTRY_PATH: &str = "/data/rtd.tables";
+/// The tables `auto` allocates from, well clear of those usually picked by hand.
+const AUTO_TABLES: RangeInclusive<u32> = 1000..=1999;
+
+/// The exclusive tables and what the configuration puts into them.
+static CLAIMED: Mutex<Claimed> = Mutex::new(Claimed {
+    tables: Vec::new(),
+    routes: Vec::new(),
+    reject_tables: Vec::new(),
+});
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Owner {
+    Rtd,
+    Pppoe,
+    Wg,
+    User,
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rtd => write!(f, "rtd"),
+            Self::Pppoe => write!(f, "pppoe"),
+            Self::Wg => write!(f, "wg"),
+            Self::User => write!(f, "user"),
+        }
+    }
+}
+
+impl FromStr for Owner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rtd" => Ok(Self::Rtd),
+            "pppoe" => Ok(Self::Pppoe),
+            "wg" => Ok(Self::Wg),
+            "user" => Ok(Self::User),
+            _ => Err(format!(
+                "invalid owner {} (want \"rtd\", \"pppoe\", \"wg\" or \"user\")",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    table: u32,
+    owner: Owner,
+    name: Option<String>,
+    exclusive: bool,
+    line: usize,
+}
+
+#[derive(Debug)]
+struct Claimed {
+    tables: Vec<u32>,
+    routes: Vec<OwnRoute>,
+    /// The tables prefix lists or bogons put discarding routes into.
+    reject_tables: Vec<u32>,
+}
+
+/// A configured route, the destination is `None` if it's only known
+/// at apply time (placeholders, hostnames).
+#[derive(Debug)]
+struct OwnRoute {
+    family: u8,
+    dst: Option<IpAddr>,
+    prefix_len: u8,
+    table: u32,
+    metric: Option<u32>,
+}
+
+impl OwnRoute {
+    fn new(def: &RouteDef, dynamic: bool) -> Self {
+        Self {
+            family: family(def.dst()),
+            dst: (!dynamic).then(|| def.dst()),
+            prefix_len: def.prefix_len(),
+            table: def.table(),
+            metric: def.metric(),
+        }
+    }
+
+    fn matches(&self, route: &RouteMsg) -> bool {
+        route.family == self.family
+            && route.dst_len == self.prefix_len
+            && route.table == self.table
+            && self.dst.is_none_or(|dst| {
+                let other = route.dst.unwrap_or(unspecified(self.family));
+                rtnl::prefix_contains(dst, self.prefix_len, other)
+            })
+            && self
+                .metric
+                .is_none_or(|metric| route.metric.unwrap_or(0) == metric)
+    }
+}
+
+/// The tables of the registry.
+#[derive(Debug, Default)]
+pub struct Registry {
+    entries: Vec<Entry>,
+}
+
+/// Reads the registry, allocates the tables requested with `auto`
+/// and makes the configuration parsers accept the names.
+/// Problems are logged, a missing registry is empty.
+pub fn load() -> Registry {
+    let s = match fs::read_to_string(REGISTRY_PATH) {
+        Ok(s) => s,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            log::warn!(Parser, "read table registry ({}): {}", REGISTRY_PATH, e);
+            String::new()
+        }
+    };
+
+    let mut registry = Registry::default();
+    let mut auto = Vec::new();
+    for (i, line) in s.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        let exclusive = words.len() > 2 && words.last() == Some(&"exclusive");
+        if exclusive {
+            words.pop();
+        }
+
+        match words.as_slice() {
+            ["auto", name] => auto.push((i, name.to_string(), exclusive)),
+            [table, owner] | [table, owner, _] => match parse_entry(table, owner, exclusive) {
+                Ok((table, owner)) => registry.entries.push(Entry {
+                    table,
+                    owner,
+                    name: words.get(2).map(|name| name.to_string()),
+                    exclusive,
+                    line: i + 1,
+                }),
+                Err(e) => log::warn!(Parser, "{}:{}: {}", REGISTRY_PATH, i + 1, e),
+            },
+            _ => log::warn!(
+                Parser,
+                "{}:{}: invalid line (want \"<table> <owner> [name] [exclusive]\" or \"auto <name> [exclusive]\")",
+                REGISTRY_PATH,
+                i + 1
+            ),
+        }
+    }
+
+    if !auto.is_empty() {
+        registry.allocate(&s, auto);
+    }
+
+    registry.check_collisions();
+    rsdsl_rtd::register_table_names(
+        registry
+            .entries
+            .iter()
+            .filter_map(|entry| Some((entry.name.clone()?, entry.table))),
+    );
+
+    registry
+}
+
+fn parse_entry(table: &str, owner: &str, exclusive: bool) -> Result<(u32, Owner), String> {
+    let table =
+        rsdsl_rtd::parse_u32(table).map_err(|e| format!("invalid table {}: {}", table, e))?;
+    let owner = owner.parse()?;
+    if exclusive && owner != Owner::Rtd {
+        return Err(format!(
+            "only tables of rtd can be exclusive, not {}",
+            owner
+        ));
+    }
+
+    Ok((table, owner))
+}
+
+impl Registry {
+    /// Assigns free tables to the `auto` lines and writes them back
+    /// to the registry. The tables are used even if that fails.
+    fn allocate(&mut self, s: &str, auto: Vec<(usize, String, bool)>) {
+        let mut lines: Vec<String> = s.lines().map(String::from).collect();
+
+        for (i, name, exclusive) in auto {
+            let Some(table) = AUTO_TABLES
+                .clone()
+                .find(|table| !self.is_registered(*table))
+            else {
+                log::error!(
+                    Parser,
+                    "{}:{}: no free table left for {}",
+                    REGISTRY_PATH,
+                    i + 1,
+                    name
+                );
+                continue;
+            };
+
+            log::info!(Parser, "allocated table {} for {}", table, name);
+            lines[i] = format!("{} {} {}", table, Owner::Rtd, name);
+            if exclusive {
+                lines[i] += " exclusive";
+            }
+            self.entries.push(Entry {
+                table,
+                owner: Owner::Rtd,
+                name: Some(name),
+                exclusive,
+                line: i + 1,
+            });
+        }
+
+        let mut content = lines.join("\n");
+        content.push('\n');
+        let res = lock::exclusive()
+            .and_then(|_lock| lock::write_atomic(Path::new(REGISTRY_PATH), content.as_bytes()));
+        if let Err(e) = res {
+            log::warn!(Parser, "write table registry ({}): {}", REGISTRY_PATH, e);
+        }
+    }
+
+    /// Returns the owner the registry assigns a table to, if any.
+    pub fn owner(&self, table: u32) -> Option<Owner> {
+        let entry = self.entries.iter().find(|entry| entry.table == table)?;
+        Some(entry.owner)
+    }
+
+    /// Returns the name the registry gives a table, if any.
+    pub fn name(&self, table: u32) -> Option<&str> {
+        self.entries
+            .iter()
+            .find_map(|entry| entry.name.as_deref().filter(|_| entry.table == table))
+    }
+
+    fn is_registered(&self, table: u32) -> bool {
+        self.entries.iter().any(|entry| entry.table == table)
+    }
+
+    fn check_collisions(&self) {
+        for (i, entry) in self.entries.iter().enumerate() {
+            for other in &self.entries[..i] {
+                if other.table == entry.table && other.owner != entry.owner {
+                    log::warn!(
+                        Parser,
+                        "{}:{}: table {} assigned to {}, but line {} assigns it to {}",
+                        REGISTRY_PATH,
+                        entry.line,
+                        entry.table,
+                        entry.owner,
+                        other.line,
+                        other.owner
+                    );
+                }
+                if other.name.is_some() && other.name == entry.name && other.table != entry.table {
+                    log::warn!(
+                        Parser,
+                        "{}:{}: name {} already used by table {} (line {})",
+                        REGISTRY_PATH,
+                        entry.line,
+                        entry.name.as_deref().unwrap_or_default(),
+                        other.table,
+                        other.line
+                    );
+                }
+            }
+        }
+    }
+
+    /// Warns about the tables the configuration uses
+    /// that the registry assigns to another daemon or the user.
+    pub fn check(&self, routes: &Routes, rules: &Rules) {
+        // Other network namespaces have tables of their own.
+        let mut used = BTreeMap::new();
+        for route in routes
+            .routes
+            .iter()
+            .filter(|route| !route.delete && route.netns.is_none())
+        {
+            let tables = [Some(route.def.table()), route.mirror];
+            for table in tables.into_iter().flatten() {
+                used.entry(table).or_insert((ROUTES_PATH, route.line));
+            }
+        }
+        for rule in rules
+            .rules
+            .iter()
+            .filter(|rule| !rule.delete && rule.netns.is_none())
+        {
+            if rule.action == RuleAction::ToTable {
+                used.entry(rule.table).or_insert((RULES_PATH, rule.line));
+            }
+        }
+
+        for (table, (path, line)) in used {
+            if matches!(
+                table,
+                rtnl::RT_TABLE_MAIN | rtnl::RT_TABLE_LOCAL | rtnl::RT_TABLE_DEFAULT
+            ) {
+                continue;
+            }
+
+            let foreign = self
+                .entries
+                .iter()
+                .find(|entry| entry.table == table && entry.owner != Owner::Rtd);
+            if let Some(entry) = foreign {
+                log::warn!(
+                    Parser,
+                    "{}:{}: table {} belongs to {} ({}:{})",
+                    path,
+                    line,
+                    table,
+                    entry.owner,
+                    REGISTRY_PATH,
+                    entry.line
+                );
+            }
+        }
+    }
+
+    /// Records the routes the configuration puts into the exclusive tables,
+    /// all others are foreign from now on.
+    pub fn claim(&self, routes: &Routes) {
+        let tables: Vec<u32> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.exclusive)
+            .map(|entry| entry.table)
+            .collect();
+
+        let mut own = Vec::new();
+        for route in routes
+            .routes
+            .iter()
+            .filter(|route| !route.delete && route.netns.is_none())
+        {
+            let dynamic = route.template.is_some() || route.host.is_some();
+            own.push(OwnRoute::new(&route.def, dynamic));
+            if let Some(mirror) = route.mirror_def() {
+                own.push(OwnRoute::new(&mirror, dynamic));
+            }
+        }
+
+        let reject_tables = routes
+            .prefix_lists
+            .iter()
+            .filter(|list| !list.delete)
+            .map(|list| list.table.unwrap_or(rtnl::RT_TABLE_MAIN))
+            .chain(
+                routes
+                    .bogons
+                    .iter()
+                    .filter(|bogons| !bogons.delete)
+                    .map(|bogons| bogons.table.unwrap_or(rtnl::RT_TABLE_MAIN)),
+            )
+            .collect();
+
+        *claimed() = Claimed {
+            tables,
+            routes: own,
+            reject_tables,
+        };
+    }
+}
+
+/// Removes the foreign routes from the exclusive tables.
+pub fn enforce() {
+    if claimed().tables.is_empty() {
+        return;
+    }
+
+    let res = rtnl::Socket::new().and_then(|mut sock| sock.flush_routes(is_foreign));
+    match res {
+        Ok(routes) => {
+            for route in routes {
+                log::warn!(
+                    Netlink,
+                    "removed foreign {} from exclusive table {}",
+                    DisplayRoute(&route),
+                    route.table
+                );
+            }
+        }
+        Err(e) => log::error!(Netlink, "clean exclusive tables: {}", e),
+    }
+}
+
+/// Reports whether a route is in an exclusive table
+/// without the configuration putting it there.
+pub fn is_foreign(route: &RouteMsg) -> bool {
+    let claimed = claimed();
+    if !claimed.tables.contains(&route.table) {
+        return false;
+    }
+
+    // The kernel adds these for the addresses of VRF members.
+    if route.protocol == rtnl::RTPROT_KERNEL {
+        return false;
+    }
+    if route.ty != rtnl::RTN_UNICAST && claimed.reject_tables.contains(&route.table) {
+        return false;
+    }
+
+    !claimed.routes.iter().any(|own| own.matches(route))
+}
+
+/// Removes a single foreign route.
+pub fn evict(route: &RouteMsg) -> io::Result<()> {
+    let dst = route.dst.unwrap_or(unspecified(route.family));
+
+    rtnl::Socket::new()?.del_route(dst, route.dst_len, Some(route.table), route.metric)
+}
+
+fn claimed() -> MutexGuard<'static, Claimed> {
+    CLAIMED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn family(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => libc::AF_INET as u8,
+        IpAddr::V6(_) => libc::AF_INET6 as u8,
+    }
+}
+
+fn unspecified(family: u8) -> IpAddr {
+    if family == libc::AF_INET as u8 {
+        IpAddr::from([0; 4])
+    } else {
+        IpAddr::from([0; 16])
+    }
+}
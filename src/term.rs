@@ -0,0 +1,48 @@
+//! Colors for output to terminals, left out when the output goes
+//! anywhere else or `NO_COLOR` is set.
+
+use std::fmt;
+use std::io::{self, IsTerminal};
+
+const NO_COLOR_VAR: &str = "NO_COLOR";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Gray,
+}
+
+impl Color {
+    fn code(self) -> u8 {
+        match self {
+            Self::Red => 31,
+            Self::Green => 32,
+            Self::Yellow => 33,
+            Self::Gray => 90,
+        }
+    }
+}
+
+/// A value that is displayed in a color if enabled.
+pub struct Paint<T>(pub Option<Color>, pub T);
+
+impl<T: fmt::Display> fmt::Display for Paint<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(color) => write!(f, "\x1b[{}m{}\x1b[0m", color.code(), self.1),
+            None => write!(f, "{}", self.1),
+        }
+    }
+}
+
+/// Reports whether standard output is a terminal that should get colors.
+pub fn stdout() -> bool {
+    io::stdout().is_terminal() && std::env::var_os(NO_COLOR_VAR).is_none()
+}
+
+/// Reports whether standard error is a terminal that should get colors.
+pub fn stderr() -> bool {
+    io::stderr().is_terminal() && std::env::var_os(NO_COLOR_VAR).is_none()
+}
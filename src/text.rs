@@ -0,0 +1,87 @@
+//! The configuration syntax: the attributes of a line and the
+//! (de)serialization of values as they are written,
+//! e.g. `01:00-05:00` for a schedule rather than its list of windows.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer, Serializer};
+
+/// A malformed list of attributes, see [`attr_pairs`].
+#[derive(Debug)]
+pub enum AttrError {
+    /// The attribute is given more than once.
+    Duplicate(String),
+    /// The attribute ends the line without a value.
+    NoValue(String),
+}
+
+/// Pairs the attributes of a configuration line up with their values
+/// in order, each at most once. `alias` maps the names of attributes
+/// that have several of them to the one they are parsed by.
+pub fn attr_pairs<'a>(
+    words: impl IntoIterator<Item = &'a str>,
+    alias: impl Fn(&'a str) -> &'a str,
+) -> Result<Vec<(&'a str, &'a str)>, AttrError> {
+    let mut attrs = Vec::<(&str, &str)>::new();
+    let mut current_attr: Option<&str> = None;
+    for word in words {
+        if let Some(attr) = current_attr {
+            if attrs.iter().any(|(a, _)| *a == attr) {
+                return Err(AttrError::Duplicate(attr.to_string()));
+            }
+            attrs.push((attr, word));
+            current_attr = None;
+        } else {
+            current_attr = Some(alias(word));
+        }
+    }
+
+    if let Some(attr) = current_attr {
+        return Err(AttrError::NoValue(attr.to_string()));
+    }
+
+    Ok(attrs)
+}
+
+/// Serializes a value as the string it is written as in the configuration.
+pub(crate) fn serialize<T: fmt::Display, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+/// Deserializes a value from the string it is written as in the configuration.
+pub(crate) fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+/// Parses a single configuration line, checking that it parses the same
+/// when written the way it is shown (with the command of the line put back),
+/// and returns that.
+#[cfg(test)]
+pub(crate) fn round_trip<T>(line: &str) -> String
+where
+    T: FromStr + fmt::Display,
+    T::Err: fmt::Display,
+{
+    let parsed: T = line.parse().unwrap_or_else(|e| panic!("{}: {}", line, e));
+    let shown = parsed.to_string();
+
+    let cmd = line.split_whitespace().nth(1).unwrap();
+    let (keyword, attrs) = shown.split_once(' ').unwrap();
+    let again: T = format!("{} {} {}", keyword, cmd, attrs)
+        .parse()
+        .unwrap_or_else(|e| panic!("{}: {}", shown, e));
+    assert_eq!(again.to_string(), shown);
+
+    shown
+}
@@ -0,0 +1,140 @@
+//! Durations of the operations of a backend in the debug output,
+//! to tell which link or entry holds up the apply pass.
+
+use crate::log;
+
+use rsdsl_rtd::rtnl;
+use rsdsl_rtd::{
+    Backend, Balance, Blackhole, Isolate, KernelRule, Neighbor, RouteAttrs, RouteDef, Rule,
+    SetupError, Sysctl, Vrf,
+};
+
+use std::ops::Deref;
+use std::time::Instant;
+
+/// A backend that logs how long each of its operations takes.
+pub struct Timed<B>(pub B);
+
+impl<B> Timed<B> {
+    fn time<T>(&self, what: &str, entry: &dyn std::fmt::Display, op: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let res = op();
+        log::debug!(Netlink, "{} {} took {:.1?}", what, entry, start.elapsed());
+
+        res
+    }
+}
+
+impl<B, T> Backend for Timed<B>
+where
+    B: Deref<Target = T>,
+    T: Backend + ?Sized,
+{
+    fn add_route(&self, route: &RouteDef) -> Result<(), SetupError> {
+        self.time("add", route, || self.0.add_route(route))
+    }
+
+    fn add_route_attrs(&self, route: &RouteDef, attrs: &RouteAttrs) -> Result<(), SetupError> {
+        self.time("add", route, || self.0.add_route_attrs(route, attrs))
+    }
+
+    fn del_route(&self, route: &RouteDef) -> Result<(), SetupError> {
+        self.time("del", route, || self.0.del_route(route))
+    }
+
+    fn add_rule(&self, rule: &Rule) -> Result<(), SetupError> {
+        self.time("add", rule, || self.0.add_rule(rule))
+    }
+
+    fn del_rule(&self, rule: &Rule) -> Result<(), SetupError> {
+        self.time("del", rule, || self.0.del_rule(rule))
+    }
+
+    fn replace_balance(&self, balance: &Balance) -> Result<(), SetupError> {
+        self.time("replace", balance, || self.0.replace_balance(balance))
+    }
+
+    fn del_balance(&self, balance: &Balance) -> Result<(), SetupError> {
+        self.time("del", balance, || self.0.del_balance(balance))
+    }
+
+    fn apply_kernel_rule(&self, kernel_rule: &KernelRule) -> Result<(), SetupError> {
+        self.time("apply", kernel_rule, || {
+            self.0.apply_kernel_rule(kernel_rule)
+        })
+    }
+
+    fn restore_kernel_rule(&self, kernel_rule: &KernelRule) -> Result<(), SetupError> {
+        self.time("restore", kernel_rule, || {
+            self.0.restore_kernel_rule(kernel_rule)
+        })
+    }
+
+    fn add_reject(&self, route: &Blackhole) -> Result<(), SetupError> {
+        self.time("add", route, || self.0.add_reject(route))
+    }
+
+    fn del_reject(&self, route: &Blackhole) -> Result<(), SetupError> {
+        self.time("del", route, || self.0.del_reject(route))
+    }
+
+    fn add_isolate_rules(&self, isolate: &Isolate) -> Result<(), SetupError> {
+        self.time("add rules of", isolate, || {
+            self.0.add_isolate_rules(isolate)
+        })
+    }
+
+    fn del_isolate_rules(&self, isolate: &Isolate) -> Result<(), SetupError> {
+        self.time("del rules of", isolate, || {
+            self.0.del_isolate_rules(isolate)
+        })
+    }
+
+    fn add_neighbor(&self, neighbor: &Neighbor) -> Result<(), SetupError> {
+        self.time("add", neighbor, || self.0.add_neighbor(neighbor))
+    }
+
+    fn del_neighbor(&self, neighbor: &Neighbor) -> Result<(), SetupError> {
+        self.time("del", neighbor, || self.0.del_neighbor(neighbor))
+    }
+
+    fn add_vrf(&self, vrf: &Vrf) -> Result<(), SetupError> {
+        self.time("add", vrf, || self.0.add_vrf(vrf))
+    }
+
+    fn bind_vrf(&self, vrf: &Vrf, member: &str) -> Result<(), SetupError> {
+        self.time("bind", &member, || self.0.bind_vrf(vrf, member))
+    }
+
+    fn del_vrf(&self, vrf: &Vrf) -> Result<(), SetupError> {
+        self.time("del", vrf, || self.0.del_vrf(vrf))
+    }
+
+    fn apply_sysctl(&self, sysctl: &Sysctl) -> Result<(), SetupError> {
+        self.time("set", sysctl, || self.0.apply_sysctl(sysctl))
+    }
+
+    fn link_exists(&self, link: &str) -> Result<bool, SetupError> {
+        self.time("look up link", &link, || self.0.link_exists(link))
+    }
+
+    fn link_wait_exists(&self, link: &str) -> Result<(), SetupError> {
+        self.time("wait for link", &link, || self.0.link_wait_exists(link))
+    }
+
+    fn link_wait_up(&self, link: &str) -> Result<(), SetupError> {
+        self.time("wait for link up", &link, || self.0.link_wait_up(link))
+    }
+
+    fn dump_routes(&self, family: u8) -> Result<Vec<rtnl::RouteMsg>, SetupError> {
+        self.time("dump", &"routes", || self.0.dump_routes(family))
+    }
+
+    fn dump_rules(&self, family: u8) -> Result<Vec<rtnl::RuleMsg>, SetupError> {
+        self.time("dump", &"rules", || self.0.dump_rules(family))
+    }
+
+    fn connect(&self) -> Result<Box<dyn Backend + Send>, SetupError> {
+        Ok(Box::new(Timed(self.0.connect()?)))
+    }
+}
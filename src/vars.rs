@@ -0,0 +1,315 @@
+//! `$NAME` placeholders in config lines, resolved at apply time
+//! from the state files other rsdsl daemons maintain.
+//!
+//! `$PDPREFIX` expands to the delegated prefix, `$PDPREFIX[n]`
+//! to the n-th /64 subnet of it.
+
+use crate::rtnl;
+
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const IP_CONFIG_PATH: &str = "/data/pppoe.ip_config";
+const IP6_CONFIG_PATH: &str = "/data/pppoe.ip6_config";
+const PD_CONFIG_PATH: &str = "/data/dhcp6.pd_config";
+
+const SUBNET_LEN: u8 = 64;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum VarError {
+    IndexOutOfRange(String, u64),
+    InvalidIndex(String),
+    UnexpectedIndex(String),
+    Unavailable(String, &'static str),
+    Unknown(String),
+}
+
+impl fmt::Display for VarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IndexOutOfRange(v, n) => write!(f, "${}[{}] exceeds the delegated prefix", v, n)?,
+            Self::InvalidIndex(v) => write!(f, "invalid index for ${} (want [n])", v)?,
+            Self::UnexpectedIndex(v) => write!(f, "${} can't be indexed", v)?,
+            Self::Unavailable(v, path) => write!(f, "${} unavailable (read from {})", v, path)?,
+            Self::Unknown(v) => write!(
+                f,
+                "unknown variable ${} (want WAN_ADDR, WAN_GW, WAN_ADDR6, WAN_GW6 or PDPREFIX)",
+                v
+            )?,
+        }
+
+        Ok(())
+    }
+}
+
+impl VarError {
+    /// Returns the name of the variable the error refers to.
+    pub fn var(&self) -> &str {
+        match self {
+            Self::IndexOutOfRange(v, _)
+            | Self::InvalidIndex(v)
+            | Self::UnexpectedIndex(v)
+            | Self::Unavailable(v, _)
+            | Self::Unknown(v) => v,
+        }
+    }
+}
+
+impl std::error::Error for VarError {}
+
+/// The current values of all variables, `None` if not (yet) known.
+#[derive(Debug, Default)]
+pub struct Vars {
+    wan_addr: Option<Ipv4Addr>,
+    wan_gw: Option<Ipv4Addr>,
+    wan_addr6: Option<Ipv6Addr>,
+    wan_gw6: Option<Ipv6Addr>,
+    pd_prefix: Option<(Ipv6Addr, u8)>,
+}
+
+impl Vars {
+    /// Reads the current values from the PPPoE and DHCPv6 state files.
+    /// Missing files or fields leave the affected variables unset.
+    pub fn load() -> Self {
+        let ip_config = read_json(IP_CONFIG_PATH);
+        let ip6_config = read_json(IP6_CONFIG_PATH);
+        let pd_config = read_json(PD_CONFIG_PATH);
+
+        Self {
+            wan_addr: field(&ip_config, "addr"),
+            wan_gw: field(&ip_config, "rtr"),
+            wan_addr6: field(&ip6_config, "laddr"),
+            wan_gw6: field(&ip6_config, "raddr"),
+            pd_prefix: field(&pd_config, "prefix").zip(
+                pd_config
+                    .as_ref()
+                    .and_then(|pd| pd.get("len")?.as_u64()?.try_into().ok())
+                    .filter(|len| *len <= SUBNET_LEN),
+            ),
+        }
+    }
+
+    /// Returns the delegated prefix, if known.
+    pub fn pd_prefix(&self) -> Option<(Ipv6Addr, u8)> {
+        self.pd_prefix
+    }
+
+    /// Returns stand-in values that allow checking the syntax
+    /// of a line before the real values are known.
+    pub fn placeholders() -> Self {
+        Self {
+            wan_addr: Some(Ipv4Addr::UNSPECIFIED),
+            wan_gw: Some(Ipv4Addr::UNSPECIFIED),
+            wan_addr6: Some(Ipv6Addr::UNSPECIFIED),
+            wan_gw6: Some(Ipv6Addr::UNSPECIFIED),
+            pd_prefix: Some((Ipv6Addr::UNSPECIFIED, 0)),
+        }
+    }
+
+    fn get(&self, name: &str, index: Option<u64>) -> Result<String, VarError> {
+        let unavailable = |path| VarError::Unavailable(name.to_string(), path);
+
+        if name == "PDPREFIX" {
+            let (prefix, len) = self.pd_prefix.ok_or(unavailable(PD_CONFIG_PATH))?;
+
+            return match index {
+                Some(n) => {
+                    let subnets = 1u128 << (SUBNET_LEN - len);
+                    if u128::from(n) >= subnets {
+                        return Err(VarError::IndexOutOfRange(name.to_string(), n));
+                    }
+
+                    let subnet = u128::from(prefix) | (u128::from(n) << (128 - SUBNET_LEN));
+                    Ok(format!("{}/{}", Ipv6Addr::from(subnet), SUBNET_LEN))
+                }
+                None => Ok(format!("{}/{}", prefix, len)),
+            };
+        }
+
+        if index.is_some() {
+            return Err(VarError::UnexpectedIndex(name.to_string()));
+        }
+
+        match name {
+            "WAN_ADDR" => self
+                .wan_addr
+                .map(|addr| addr.to_string())
+                .ok_or(unavailable(IP_CONFIG_PATH)),
+            "WAN_GW" => self
+                .wan_gw
+                .map(|addr| addr.to_string())
+                .ok_or(unavailable(IP_CONFIG_PATH)),
+            "WAN_ADDR6" => self
+                .wan_addr6
+                .map(|addr| addr.to_string())
+                .ok_or(unavailable(IP6_CONFIG_PATH)),
+            "WAN_GW6" => self
+                .wan_gw6
+                .map(|addr| addr.to_string())
+                .ok_or(unavailable(IP6_CONFIG_PATH)),
+            _ => Err(VarError::Unknown(name.to_string())),
+        }
+    }
+}
+
+fn read_json(path: &str) -> Option<serde_json::Value> {
+    let s = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&s).ok()
+}
+
+fn field<T: std::str::FromStr>(obj: &Option<serde_json::Value>, name: &str) -> Option<T> {
+    obj.as_ref()?.get(name)?.as_str()?.parse().ok()
+}
+
+/// Reports whether a line contains any placeholders.
+pub fn has_vars(line: &str) -> bool {
+    line.contains('$')
+}
+
+/// Replaces all `$NAME` placeholders in a line with their current values.
+pub fn expand(line: &str, vars: &Vars) -> Result<String, VarError> {
+    let mut expanded = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let name = &rest[..end];
+        rest = &rest[end..];
+
+        let index = match rest.strip_prefix('[') {
+            Some(indexed) => {
+                let (index, after) = indexed
+                    .split_once(']')
+                    .ok_or(VarError::InvalidIndex(name.to_string()))?;
+                let index = index
+                    .parse()
+                    .map_err(|_| VarError::InvalidIndex(name.to_string()))?;
+
+                rest = after;
+                Some(index)
+            }
+            None => None,
+        };
+
+        expanded.push_str(&vars.get(name, index)?);
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Returns the address of the remote end of a PPP link.
+/// The kernel's view is preferred, the PPPoE state files
+/// are consulted if the link has no peer address (e.g. IPv6).
+pub fn peer(link: &str, ipv6: bool) -> Option<IpAddr> {
+    kernel_peer(link, ipv6).or_else(|| {
+        let vars = Vars::load();
+        if ipv6 {
+            vars.wan_gw6.map(IpAddr::V6)
+        } else {
+            vars.wan_gw.map(IpAddr::V4)
+        }
+    })
+}
+
+fn kernel_peer(link: &str, ipv6: bool) -> Option<IpAddr> {
+    link_addrs(link, ipv6)?.iter().find_map(|addr| addr.peer())
+}
+
+/// Returns the address of the link the kernel would use as the source
+/// of its own packets, i.e. the first global one of the given family.
+pub fn primary_addr(link: &str, ipv6: bool) -> Option<IpAddr> {
+    link_addrs(link, ipv6)?
+        .iter()
+        .find_map(|addr| addr.primary())
+}
+
+/// Returns the addresses of one family the link currently has.
+fn link_addrs(link: &str, ipv6: bool) -> Option<Vec<rtnl::AddrMsg>> {
+    let index = rtnl::link_index(link).ok()?;
+    let family = if ipv6 { libc::AF_INET6 } else { libc::AF_INET } as u8;
+
+    let mut sock = rtnl::Socket::new().ok()?;
+    let replies = sock
+        .request(
+            rtnl::RTM_GETADDR,
+            rtnl::NLM_F_DUMP,
+            &[family, 0, 0, 0, 0, 0, 0, 0],
+        )
+        .ok()?;
+
+    Some(
+        replies
+            .iter()
+            .filter_map(|payload| rtnl::AddrMsg::parse(payload))
+            .filter(|addr| addr.family == family && addr.index == index)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Route;
+
+    /// Expands a route line and returns how the resulting route is shown.
+    fn expand_route(line: &str, vars: &Vars) -> String {
+        let expanded = expand(line, vars).unwrap_or_else(|e| panic!("{}: {}", line, e));
+        let route: Route = expanded.parse().unwrap();
+
+        route.to_string()
+    }
+
+    #[test]
+    fn gateway() {
+        let vars = Vars {
+            wan_gw: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            ..Vars::default()
+        };
+
+        assert_eq!(
+            expand_route("route4 add to 10.1.0.0/16 via $WAN_GW dev ppp0", &vars),
+            "route4 10.1.0.0/16 via 192.0.2.1 dev ppp0"
+        );
+        assert!(matches!(
+            expand("route4 add to 10.1.0.0/16 via $WAN_GW6 dev ppp0", &vars),
+            Err(VarError::Unavailable(var, IP6_CONFIG_PATH)) if var == "WAN_GW6"
+        ));
+        assert!(matches!(
+            expand("route4 add to 10.1.0.0/16 via $WAN_GW[1] dev ppp0", &vars),
+            Err(VarError::UnexpectedIndex(var)) if var == "WAN_GW"
+        ));
+    }
+
+    #[test]
+    fn delegated_prefix() {
+        let vars = Vars {
+            pd_prefix: Some(("2001:db8:100::".parse().unwrap(), 56)),
+            ..Vars::default()
+        };
+
+        assert_eq!(
+            expand_route("route6 add to $PDPREFIX dev lan0 table 100", &vars),
+            "route6 2001:db8:100::/56 table 100 dev lan0"
+        );
+        assert_eq!(
+            expand_route("route6 add to $PDPREFIX[1] dev lan0", &vars),
+            "route6 2001:db8:100:1::/64 dev lan0"
+        );
+        assert!(matches!(
+            expand("route6 add to $PDPREFIX[256] dev lan0", &vars),
+            Err(VarError::IndexOutOfRange(var, 256)) if var == "PDPREFIX"
+        ));
+        assert!(matches!(
+            expand("route6 add to $PDPREFIX[x] dev lan0", &vars),
+            Err(VarError::InvalidIndex(var)) if var == "PDPREFIX"
+        ));
+    }
+}
@@ -0,0 +1,48 @@
+//! VPN bypass presets.
+
+use crate::audit::Source;
+use crate::{log, status};
+use crate::{outcome, report};
+
+use rsdsl_rtd::{Backend, Bypass};
+
+/// Installs (or removes) the rule, routes and settings of a bypass preset.
+pub fn apply(backend: &dyn Backend, source: Source, bypass: &Bypass) {
+    let rule = bypass.rule();
+
+    let _ = report(source, "del", &rule, backend.del_rule(&rule));
+    if bypass.delete {
+        for route in bypass.default_routes() {
+            let _ = report(source, "del", &route, backend.del_route(&route));
+        }
+
+        status::set(source, status::State::Removed);
+        return;
+    }
+
+    status::set(source, status::State::WaitingForLink(bypass.wan.clone()));
+    log::info!(Netlink, "wait for link {}", bypass.wan);
+    if let Err(e) = backend.link_wait_exists(&bypass.wan) {
+        log::error!(Netlink, "wait for link {}: {}", bypass.wan, e);
+        status::set(source, status::State::Failed(e.to_string()));
+        return;
+    }
+
+    // Only start redirecting marked traffic once the table is complete.
+    let mut res = Ok(());
+    for route in bypass.default_routes() {
+        let _ = backend.del_route(&route);
+        res = res.and(report(source, "add", &route, backend.add_route(&route)));
+    }
+
+    let sysctl = bypass.sysctl();
+    res = res.and(report(
+        source,
+        "set",
+        &sysctl,
+        backend.apply_sysctl(&sysctl),
+    ));
+    res = res.and(report(source, "add", &rule, backend.add_rule(&rule)));
+
+    status::set(source, outcome(res, status::State::Applied));
+}
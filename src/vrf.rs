@@ -0,0 +1,152 @@
+//! VRF devices (`vrf` lines of the route configuration).
+//!
+//! A VRF binds interfaces to a routing table of their own, e.g. to keep
+//! a management network apart from the customer traffic. The kernel adds
+//! the rule directing traffic of the enslaved interfaces to the table itself.
+
+use crate::{rtnl, RouteParseError, SetupError};
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// A `vrf` line of the route configuration.
+///
+/// Routes following it in the configuration that don't specify
+/// a table are installed to the table of the VRF.
+#[derive(Clone, Debug)]
+pub struct Vrf {
+    pub delete: bool,
+    pub name: String,
+    pub table: u32,
+    /// The interfaces to bind to the VRF.
+    pub members: Vec<String>,
+    pub line: usize,
+}
+
+impl Vrf {
+    /// Creates the VRF device if it doesn't exist yet and brings it up.
+    /// The members are bound separately since they may not exist yet.
+    pub fn blocking_add(&self) -> Result<(), SetupError> {
+        let mut sock = rtnl::Socket::new()?;
+
+        let index = match rtnl::link_index(&self.name) {
+            Ok(index) => index,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                sock.add_vrf(&self.name, self.table)?;
+                rtnl::link_index(&self.name)?
+            }
+            Err(e) => return Err(e.into()),
+        };
+        sock.link_up(index)?;
+
+        Ok(())
+    }
+
+    /// Binds an interface to the VRF.
+    pub fn blocking_bind(&self, member: &str) -> Result<(), SetupError> {
+        let index = rtnl::link_index(member)?;
+        let master = rtnl::link_index(&self.name)?;
+
+        rtnl::Socket::new()?.set_master(index, master)?;
+        Ok(())
+    }
+
+    /// Removes the VRF device, releasing its members.
+    pub fn blocking_del(&self) -> Result<(), SetupError> {
+        let index = match rtnl::link_index(&self.name) {
+            Ok(index) => index,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        rtnl::Socket::new()?.del_link(index)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Vrf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "vrf name {} table {}", self.name, self.table)?;
+        if !self.members.is_empty() {
+            write!(f, " members {}", self.members.join(","))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Vrf {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = crate::lowercase_keywords(s, 2);
+        let mut words = s.split_whitespace();
+
+        let version_str = words.next().ok_or(RouteParseError::NoVersion)?;
+        if version_str != "vrf" {
+            return Err(RouteParseError::InvalidVersion(version_str.to_string()));
+        }
+
+        let cmd = words.next().ok_or(RouteParseError::NoCmd)?;
+        let delete = match cmd {
+            "add" => false,
+            "del" => true,
+            _ => return Err(RouteParseError::InvalidCmd(cmd.to_string())),
+        };
+
+        let attrs = crate::attr_pairs(words, |attr| attr)?;
+
+        let mut vrf = Vrf {
+            delete,
+            name: String::new(),
+            table: 0,
+            members: Vec::new(),
+            line: 0,
+        };
+        let mut table = None;
+
+        for (attr, value) in attrs {
+            match attr {
+                "name" => vrf.name = value.to_string(),
+                "table" => table = Some(crate::parse_u32(value)?),
+                "members" => vrf.members = value.split(',').map(String::from).collect(),
+                _ => return Err(RouteParseError::InvalidAttr(attr.to_string())),
+            }
+        }
+
+        if vrf.name.is_empty() {
+            return Err(RouteParseError::NoName);
+        }
+        vrf.table = table.ok_or(RouteParseError::NoTable)?;
+
+        Ok(vrf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::round_trip;
+
+    #[test]
+    fn devices() {
+        assert_eq!(
+            round_trip::<Vrf>("vrf add name mgmt table 10 members eth1,eth2"),
+            "vrf name mgmt table 10 members eth1,eth2"
+        );
+        assert_eq!(
+            round_trip::<Vrf>("vrf del name mgmt table 10"),
+            "vrf name mgmt table 10"
+        );
+
+        assert!(matches!(
+            "vrf add table 10".parse::<Vrf>(),
+            Err(RouteParseError::NoName)
+        ));
+        assert!(matches!(
+            "vrf add name mgmt".parse::<Vrf>(),
+            Err(RouteParseError::NoTable)
+        ));
+    }
+}
@@ -0,0 +1,306 @@
+//! Routes through every link matching a pattern (`dev ppp+`, `dev wg*`),
+//! e.g. per-peer PPP or WireGuard links whose names vary.
+//!
+//! Each matching link gets a copy of the route. The kernel only keeps one
+//! route per destination, table and metric, so the copies are told apart
+//! by their metric: that of the route plus the slot of the copy, the lowest
+//! one free when its link started matching. Traffic prefers the link that
+//! matched first, the others take over when it disappears.
+//!
+//! The links are polled. Copies on links that disappear go with them,
+//! new matches get theirs as soon as they show up. Copies that failed
+//! are retried whenever the matching links change. `del` lines remove
+//! the copies from all matching links, whatever their metric.
+//!
+//! Each copy has a status entry of its own, the configured line
+//! sums them up. Copies aren't reconciled, they are up to this watcher.
+
+use crate::audit::Source;
+use crate::reload::current_route;
+use crate::{guard, log, status};
+use crate::{outcome, removal, report};
+
+use rsdsl_rtd::{rtnl, Backend, Route, SetupError};
+
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A route with a link pattern and its copies.
+#[derive(Debug)]
+pub struct Pattern {
+    source: Source,
+    route: Route,
+    /// The copies by slot, `None` for free slots.
+    copies: Vec<Option<LinkCopy>>,
+}
+
+/// The copy of a route for one of the links matching its pattern.
+#[derive(Debug)]
+struct LinkCopy {
+    link: String,
+    state: CopyState,
+}
+
+#[derive(Debug)]
+enum CopyState {
+    /// Waiting for values like the peer address of the link.
+    Waiting,
+    Failed(String),
+    /// In place as the given route.
+    Applied(Box<Route>),
+}
+
+impl Pattern {
+    fn copy_source(&self, slot: usize) -> Source {
+        Source::Copy {
+            path: self.source.path(),
+            line: self.route.line,
+            slot: slot as u32,
+        }
+    }
+
+    /// Brings the copies in line with the current links,
+    /// reporting whether any of them changed.
+    fn sync(&mut self, backend: &dyn Backend, links: &[String]) -> bool {
+        let mut links_changed = false;
+
+        // Copies on links that disappeared went with them.
+        for slot in 0..self.copies.len() {
+            let gone = self.copies[slot]
+                .as_ref()
+                .is_some_and(|copy| !links.contains(&copy.link));
+            if gone {
+                status::remove(self.copy_source(slot));
+                self.copies[slot] = None;
+                links_changed = true;
+            }
+        }
+        while self.copies.last().is_some_and(Option::is_none) {
+            self.copies.pop();
+        }
+
+        for link in links.iter().filter(|link| self.route.matches_link(link)) {
+            let known = self.copies.iter().flatten().any(|copy| copy.link == *link);
+            if known {
+                continue;
+            }
+
+            let slot = match self.copies.iter().position(Option::is_none) {
+                Some(slot) => slot,
+                None => {
+                    self.copies.push(None);
+                    self.copies.len() - 1
+                }
+            };
+            log::info!(Events, "link {} matches {}, add", link, self.source);
+
+            let copy = self.route.link_copy(link, slot as u32);
+            status::add(self.copy_source(slot), copy.label());
+            self.copies[slot] = Some(LinkCopy {
+                link: link.clone(),
+                state: CopyState::Waiting,
+            });
+            links_changed = true;
+        }
+
+        let mut changed = links_changed;
+        for slot in 0..self.copies.len() {
+            let retry = match self.copies[slot].as_ref().map(|copy| &copy.state) {
+                Some(CopyState::Waiting | CopyState::Applied(_)) => true,
+                Some(CopyState::Failed(_)) => links_changed,
+                None => false,
+            };
+            if retry {
+                changed |= self.install(backend, slot);
+            }
+        }
+
+        self.summarize();
+        changed
+    }
+
+    /// Installs the copy in a slot unless it is in place already,
+    /// replacing it if its values changed. Reports whether anything was done.
+    fn install(&mut self, backend: &dyn Backend, slot: usize) -> bool {
+        let source = self.copy_source(slot);
+        let Some(copy) = self.copies[slot].as_mut() else {
+            return false;
+        };
+
+        // Peer and source addresses may take a moment to be assigned.
+        let Some(current) = current_route(&self.route.link_copy(&copy.link, slot as u32)) else {
+            let state = if self.route.via_peer {
+                status::State::WaitingForPeer(copy.link.clone())
+            } else if self.route.src_auto {
+                status::State::WaitingForAddr(copy.link.clone())
+            } else {
+                status::State::Pending
+            };
+            status::set(source, state);
+            copy.state = CopyState::Waiting;
+            return false;
+        };
+
+        match &copy.state {
+            CopyState::Applied(installed) if **installed == current => return false,
+            CopyState::Applied(installed) => {
+                log::info!(Events, "values of {} changed, reload", source);
+                let _ = report(source, "del", installed, backend.del_route(&installed.def));
+            }
+            // Replace a stale version.
+            CopyState::Waiting | CopyState::Failed(_) => {
+                let _ = backend.del_route(&current.def);
+            }
+        }
+
+        if !guard::allow_route(source, &current) {
+            copy.state = CopyState::Failed("protected".to_string());
+            return true;
+        }
+
+        let res = report(source, "add", &current, current.add(backend));
+        copy.state = match &res {
+            Ok(()) => CopyState::Applied(Box::new(current)),
+            Err(e) => CopyState::Failed(e.to_string()),
+        };
+        status::set(source, outcome(res, status::State::Applied));
+        true
+    }
+
+    /// Sums the state of the copies up in the status entry of the configured line.
+    fn summarize(&self) {
+        let copies: Vec<&LinkCopy> = self.copies.iter().flatten().collect();
+
+        let state = if copies.is_empty() {
+            status::State::WaitingForLink(self.route.def.link().to_string())
+        } else if copies
+            .iter()
+            .any(|copy| matches!(copy.state, CopyState::Applied(_)))
+        {
+            status::State::Applied
+        } else if let Some(e) = copies.iter().find_map(|copy| match &copy.state {
+            CopyState::Failed(e) => Some(e.clone()),
+            _ => None,
+        }) {
+            status::State::Failed(e)
+        } else {
+            status::State::Pending
+        };
+
+        if status::state(self.source) != Some(state.clone()) {
+            status::set(self.source, state);
+        }
+    }
+}
+
+/// Installs the copies of the routes with link patterns (or removes them
+/// for `del` lines), returning the other routes along with the patterns
+/// to watch for new matches.
+pub fn apply(
+    backend: &dyn Backend,
+    routes: Vec<Route>,
+    source: impl Fn(&Route) -> Source,
+) -> (Vec<Route>, Vec<Pattern>) {
+    let (patterned, routes): (Vec<_>, Vec<_>) =
+        routes.into_iter().partition(Route::has_link_pattern);
+    if patterned.is_empty() {
+        return (routes, Vec::new());
+    }
+
+    let links = match rtnl::link_names() {
+        Ok(links) => links,
+        Err(e) => {
+            log::error!(Netlink, "list links: {}", e);
+            Vec::new()
+        }
+    };
+
+    let mut patterns = Vec::new();
+    for route in patterned {
+        let source = source(&route);
+
+        if route.delete {
+            status::set(
+                source,
+                match remove(backend, &route, &links) {
+                    Ok(false) => status::State::Absent,
+                    res => removal(source, &route, res.map(drop)),
+                },
+            );
+            continue;
+        }
+
+        if !links.iter().any(|link| route.matches_link(link)) {
+            log::info!(Events, "no link matches {} yet", route.def.link());
+        }
+
+        let mut pattern = Pattern {
+            source,
+            route,
+            copies: Vec::new(),
+        };
+        pattern.sync(backend, &links);
+        patterns.push(pattern);
+    }
+
+    (routes, patterns)
+}
+
+/// Removes the copies of a route with a link pattern from all of the given links
+/// that match it, whatever their metric. Reports whether there were any.
+pub fn remove(backend: &dyn Backend, route: &Route, links: &[String]) -> Result<bool, SetupError> {
+    let mut res = Ok(());
+    let mut removed = false;
+    for link in links.iter().filter(|link| route.matches_link(link)) {
+        let mut copy = route.link_copy(link, 0);
+        copy.def.set_metric(None);
+
+        match backend.del_route(&copy.def) {
+            Err(e) if e.is_not_found() => {}
+            r => {
+                removed = true;
+                res = res.and(r);
+            }
+        }
+    }
+
+    res.map(|()| removed)
+}
+
+/// Keeps the copies of the routes in line with the links matching their patterns.
+pub fn watch(backend: &dyn Backend, mut patterns: Vec<Pattern>) {
+    if patterns.is_empty() {
+        return;
+    }
+
+    let backend = match backend.connect() {
+        Ok(backend) => backend,
+        Err(e) => {
+            log::error!(Netlink, "connect for link patterns: {}", e);
+            return;
+        }
+    };
+
+    thread::spawn(move || loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let links = match rtnl::link_names() {
+            Ok(links) => links,
+            Err(e) => {
+                log::error!(Netlink, "list links: {}", e);
+                continue;
+            }
+        };
+
+        let mut changed = false;
+        for pattern in &mut patterns {
+            changed |= pattern.sync(&*backend, &links);
+        }
+
+        if changed {
+            status::applied();
+        }
+    });
+}